@@ -0,0 +1,194 @@
+//! The hand-written lexer the nom one replaced, kept to compare against.
+//! Its tokens own their text, as they did before tokens borrowed the source.
+
+use shen_transpiler::parser::Span;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    OpenBrace,
+    CloseBrace,
+    Bar,
+    Semicolon,
+    Defun,
+    Lambda,
+    If,
+    Let,
+    List,
+    Identifier(String),
+    Literal(String),
+    Operator(String),
+    Number(f64),
+}
+
+type SpanError = (String, Span);
+
+/// Split `input` into tokens, each with the span it was read from. What
+/// cannot be read is skipped, with an error for each skipped span.
+pub fn tokenize(input: &str) -> (Vec<(Token, Span)>, Vec<SpanError>) {
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    let mut errors = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            '(' => {
+                tokens.push(Token::OpenParen);
+                chars.next();
+            },
+            ')' => {
+                tokens.push(Token::CloseParen);
+                chars.next();
+            },
+            '[' => {
+                tokens.push(Token::OpenBracket);
+                chars.next();
+            },
+            ']' => {
+                tokens.push(Token::CloseBracket);
+                chars.next();
+            },
+            '{' => {
+                tokens.push(Token::OpenBrace);
+                chars.next();
+            },
+            '}' => {
+                tokens.push(Token::CloseBrace);
+                chars.next();
+            },
+            '|' => {
+                tokens.push(Token::Bar);
+                chars.next();
+            },
+            ';' => {
+                tokens.push(Token::Semicolon);
+                chars.next();
+            },
+            '!' => {
+                // The Prolog cut
+                tokens.push(Token::Identifier("!".to_string()));
+                chars.next();
+            },
+            c if c.is_whitespace() => {
+                chars.next();
+            },
+            '\\' | 'λ' => {
+                tokens.push(Token::Lambda);
+                chars.next();
+            },
+            '/' if chars.clone().nth(1).map(|(_, c)| c) == Some('.') => {
+                // Shen's lambda syntax: (/. X Body)
+                tokens.push(Token::Lambda);
+                chars.next();
+                chars.next();
+            },
+            '<' if chars.clone().map(|(_, c)| c).take(3).eq("<!>".chars()) => {
+                // Shen-YACC's rest-of-input non-terminal
+                tokens.push(Token::Identifier("<!>".to_string()));
+                chars.nth(2);
+            },
+            ':' => {
+                chars.next();
+                if chars.peek().map(|&(_, c)| c) == Some('=') {
+                    chars.next();
+                    tokens.push(Token::Operator(":=".to_string()));
+                } else {
+                    tokens.push(Token::Operator(":".to_string()));
+                }
+            },
+            '+' | '-' | '*' | '/' | '=' | '<' | '>' => {
+                let mut op = String::new();
+                while let Some(&(_, next_ch)) = chars.peek() {
+                    if "+-*/=<>".contains(next_ch) {
+                        op.push(next_ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // Symbols such as *counter* or <-vector start with operator characters
+                if chars.peek().is_some_and(|(_, next_ch)| next_ch.is_alphanumeric()) {
+                    while let Some(&(_, next_ch)) = chars.peek() {
+                        if is_symbol_char(next_ch) {
+                            op.push(next_ch);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    tokens.push(Token::Identifier(op));
+                } else {
+                    tokens.push(Token::Operator(op));
+                }
+            },
+            c if c.is_alphabetic() || c == '_' || c == '@' => {
+                let mut identifier = String::new();
+                while let Some(&(_, next_ch)) = chars.peek() {
+                    if is_symbol_char(next_ch) {
+                        identifier.push(next_ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                
+                tokens.push(match identifier.as_str() {
+                    "defun" => Token::Defun,
+                    "lambda" | "fn" => Token::Lambda,
+                    "if" => Token::If,
+                    "let" => Token::Let,
+                    "list" => Token::List,  // Add list token recognition
+                    _ => Token::Identifier(identifier),
+                });
+            },
+            c if c.is_numeric() => {
+                let mut number = String::new();
+                while let Some(&(_, next_ch)) = chars.peek() {
+                    if next_ch.is_numeric() || next_ch == '.' {
+                        number.push(next_ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let end = chars.peek().map_or(input.len(), |&(end, _)| end);
+                match number.parse::<f64>() {
+                    Ok(parsed_number) => tokens.push(Token::Number(parsed_number)),
+                    Err(_) => errors.push((format!("Invalid number: {}", number), Span { start, end })),
+                }
+            },
+            '"' => {
+                chars.next(); // consume opening quote
+                let mut literal = String::new();
+                while let Some(&(_, next_ch)) = chars.peek() {
+                    if next_ch == '"' {
+                        chars.next(); // consume closing quote
+                        break;
+                    }
+                    literal.push(next_ch);
+                    chars.next();
+                }
+                tokens.push(Token::Literal(literal));
+            },
+            _ => {
+                chars.next();
+                let end = start + c.len_utf8();
+                errors.push((format!("Unexpected character: {}", c), Span { start, end }));
+            },
+        }
+        let end = chars.peek().map_or(input.len(), |&(end, _)| end);
+        spans.resize(tokens.len(), Span { start, end });
+    }
+
+    (tokens.into_iter().zip(spans).collect(), errors)
+}
+
+/// Characters allowed after the first character of a symbol, so that names
+/// like `error-to-string`, `cons?` and `string->n` lex as one identifier.
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || "_?!-*+<>=./@$%&~^#'".contains(c)
+}
@@ -0,0 +1,45 @@
+//! The nom lexer against the hand-written one it replaced, on a few
+//! megabytes of KLambda made by repeating the kernel fixtures.
+//!
+//! Run with `cargo bench --bench lexer`.
+
+mod baseline;
+
+use std::fs;
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use shen_transpiler::parser::tokenize;
+
+/// Roughly how much KLambda to lex, in bytes.
+const SIZE: usize = 4 * 1024 * 1024;
+
+fn kernel_source() -> String {
+    let mut kernel = String::new();
+    for file in ["core.kl", "sys.kl"] {
+        let path = format!("{}/tests/fixtures/kernel/{}", env!("CARGO_MANIFEST_DIR"), file);
+        kernel.push_str(&fs::read_to_string(&path).expect("kernel fixture"));
+        kernel.push('\n');
+    }
+    kernel.repeat(SIZE / kernel.len() + 1)
+}
+
+fn lexer(c: &mut Criterion) {
+    let source = kernel_source();
+    // Borrowed and owned tokens debug-print alike
+    assert_eq!(
+        format!("{:?}", tokenize(&source)),
+        format!("{:?}", baseline::tokenize(&source)),
+        "the lexers disagree"
+    );
+
+    let mut group = c.benchmark_group("lex kernel");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.sample_size(20);
+    group.bench_function("nom", |b| b.iter(|| tokenize(black_box(&source))));
+    group.bench_function("hand-written", |b| b.iter(|| baseline::tokenize(black_box(&source))));
+    group.finish();
+}
+
+criterion_group!(benches, lexer);
+criterion_main!(benches);
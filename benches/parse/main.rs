@@ -0,0 +1,43 @@
+//! Parsing a few megabytes of Shen: the REPL fixtures and `sys.kl`, which
+//! is KLambda and so also Shen, repeated. (`core.kl` uses `{` as a symbol,
+//! which KLambda allows and Shen does not.) And reading a few megabytes of
+//! the kernel fixtures as KLambda, as `load_kernel` does.
+//!
+//! Run with `cargo bench --bench parse`.
+
+use std::fs;
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use shen_transpiler::parser::{parse_klambda, parse_shen_program};
+
+/// Roughly how much source to parse, in bytes.
+const SIZE: usize = 4 * 1024 * 1024;
+
+fn fixtures(files: &[&str]) -> String {
+    let mut source = String::new();
+    for file in files {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), file);
+        source.push_str(&fs::read_to_string(&path).expect("fixture"));
+        source.push('\n');
+    }
+    source.repeat(SIZE / source.len() + 1)
+}
+
+fn parse(c: &mut Criterion) {
+    let source = fixtures(&["kernel/sys.kl", "repl/lists.shen", "repl/arithmetic.shen"]);
+    parse_shen_program(&source).expect("the fixtures parse");
+    let kernel = fixtures(&["kernel/core.kl", "kernel/sys.kl"]);
+    parse_klambda(&kernel).expect("the kernel fixtures read");
+
+    let mut group = c.benchmark_group("parse");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("program", |b| b.iter(|| parse_shen_program(black_box(&source))));
+    group.throughput(Throughput::Bytes(kernel.len() as u64));
+    group.bench_function("klambda", |b| b.iter(|| parse_klambda(black_box(&kernel))));
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
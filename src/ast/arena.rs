@@ -0,0 +1,508 @@
+//! An arena for whole-program passes
+//!
+//! An [`Ast`] holds every node of a program in one vector, and nodes refer
+//! to their children by [`NodeId`] instead of owning them. A subtree can then
+//! be shared by several parents simply by using its ID twice, and a pass can
+//! rewrite a node in place without rebuilding the path to it.
+//!
+//! What a pass learns about a node lives beside it in side tables indexed by
+//! the same ID: its type, which starts as the [`ShenNode::get_type`] of the
+//! node it was built from, and the [`Span`] of source it was parsed from.
+//!
+//! [`Ast::add`] and [`Ast::to_node`] convert from and to the owned
+//! [`ShenNode`] the rest of the transpiler uses.
+
+use crate::parser::Span;
+
+use super::{Pattern, ShenNode, ShenType, ShenValue, Symbol, YaccItem};
+
+/// A node in an [`Ast`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    /// The node's index in its arena.
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A [`ShenNode`] whose children are [`NodeId`]s. The types a `ShenNode`
+/// carries on its symbols, lambdas and operations are in the arena's type
+/// table instead.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Function {
+        name: Symbol,
+        args: Vec<(Symbol, ShenType)>,
+        body: NodeId,
+    },
+    Application {
+        func: NodeId,
+        args: Vec<NodeId>,
+    },
+    Literal {
+        value: ShenValue,
+    },
+    Symbol {
+        name: Symbol,
+    },
+    List {
+        elements: Vec<NodeId>,
+        element_type: ShenType,
+    },
+    Cons {
+        head: NodeId,
+        tail: NodeId,
+    },
+    Lambda {
+        arg: (Symbol, ShenType),
+        body: NodeId,
+    },
+    Conditional {
+        condition: NodeId,
+        true_branch: NodeId,
+        false_branch: Option<NodeId>,
+    },
+    Define {
+        name: Symbol,
+        rules: Vec<Rule>,
+    },
+    Defmacro {
+        name: Symbol,
+        rules: Vec<Rule>,
+    },
+    Defprolog {
+        name: Symbol,
+        clauses: Vec<PrologClause>,
+    },
+    Defcc {
+        name: Symbol,
+        rules: Vec<YaccRule>,
+    },
+    PrologQuery {
+        goals: Vec<NodeId>,
+    },
+    Package {
+        name: Symbol,
+        exports: Vec<Symbol>,
+        body: Vec<NodeId>,
+    },
+    Tuple {
+        first: NodeId,
+        second: NodeId,
+    },
+    Vector {
+        elements: Vec<NodeId>,
+    },
+    VectorCons {
+        head: NodeId,
+        tail: NodeId,
+    },
+    StringCons {
+        head: NodeId,
+        tail: NodeId,
+    },
+    TrapError {
+        body: NodeId,
+        handler: NodeId,
+    },
+    Freeze {
+        body: NodeId,
+    },
+    BinaryOperation {
+        operator: Symbol,
+        left: NodeId,
+        right: NodeId,
+    },
+    Nil,
+    Error {
+        message: String,
+    },
+}
+
+/// A [`super::Rule`] in an arena.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub patterns: Vec<Pattern>,
+    pub body: NodeId,
+    pub guard: Option<NodeId>,
+}
+
+/// A [`super::PrologClause`] in an arena.
+#[derive(Debug, Clone)]
+pub struct PrologClause {
+    pub head: Vec<Pattern>,
+    pub body: Vec<NodeId>,
+}
+
+/// A [`super::YaccRule`] in an arena.
+#[derive(Debug, Clone)]
+pub struct YaccRule {
+    pub items: Vec<YaccItem>,
+    pub action: Option<NodeId>,
+    pub guard: Option<NodeId>,
+}
+
+/// The nodes of a program, with their types and spans.
+#[derive(Debug, Clone, Default)]
+pub struct Ast {
+    nodes: Vec<Node>,
+    types: Vec<ShenType>,
+    spans: Vec<Option<Span>>,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Ast::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Add `node`, whose children must already be in this arena, with its
+    /// type.
+    pub fn push(&mut self, node: Node, ty: ShenType) -> NodeId {
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(node);
+        self.types.push(ty);
+        self.spans.push(None);
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.index()]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.index()]
+    }
+
+    pub fn ty(&self, id: NodeId) -> &ShenType {
+        &self.types[id.index()]
+    }
+
+    pub fn set_type(&mut self, id: NodeId, ty: ShenType) {
+        self.types[id.index()] = ty;
+    }
+
+    /// The source `id` was parsed from, if known.
+    pub fn span(&self, id: NodeId) -> Option<Span> {
+        self.spans[id.index()]
+    }
+
+    pub fn set_span(&mut self, id: NodeId, span: Span) {
+        self.spans[id.index()] = Some(span);
+    }
+
+    /// Every node, with its ID, in the order they were added; children come
+    /// before their parents.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes.iter().enumerate().map(|(index, node)| (NodeId(index as u32), node))
+    }
+
+    /// The children of `id`, in source order.
+    pub fn children(&self, id: NodeId) -> Vec<NodeId> {
+        match self.node(id) {
+            Node::Literal { .. } | Node::Symbol { .. } | Node::Nil | Node::Error { .. } => Vec::new(),
+            Node::Function { body, .. } | Node::Lambda { body, .. } | Node::Freeze { body } => {
+                vec![*body]
+            }
+            Node::Application { func, args } => {
+                std::iter::once(*func).chain(args.iter().copied()).collect()
+            }
+            Node::List { elements, .. } | Node::Vector { elements } => elements.clone(),
+            Node::PrologQuery { goals } => goals.clone(),
+            Node::Package { body, .. } => body.clone(),
+            Node::Cons { head, tail }
+            | Node::VectorCons { head, tail }
+            | Node::StringCons { head, tail }
+            | Node::Tuple { first: head, second: tail }
+            | Node::TrapError { body: head, handler: tail }
+            | Node::BinaryOperation { left: head, right: tail, .. } => vec![*head, *tail],
+            Node::Conditional {
+                condition,
+                true_branch,
+                false_branch,
+            } => [*condition, *true_branch].into_iter().chain(*false_branch).collect(),
+            Node::Define { rules, .. } | Node::Defmacro { rules, .. } => rules
+                .iter()
+                .flat_map(|rule| std::iter::once(rule.body).chain(rule.guard))
+                .collect(),
+            Node::Defprolog { clauses, .. } => clauses
+                .iter()
+                .flat_map(|clause| clause.body.iter().copied())
+                .collect(),
+            Node::Defcc { rules, .. } => rules
+                .iter()
+                .flat_map(|rule| rule.action.into_iter().chain(rule.guard))
+                .collect(),
+        }
+    }
+
+    /// Move `node` and everything under it into the arena.
+    pub fn add(&mut self, node: ShenNode) -> NodeId {
+        let ty = node.get_type();
+        let node = match node {
+            ShenNode::Function { name, args, body, .. } => Node::Function {
+                name,
+                args,
+                body: self.add(*body),
+            },
+            ShenNode::Application { func, args } => Node::Application {
+                func: self.add(*func),
+                args: self.add_all(args),
+            },
+            ShenNode::Literal { value } => Node::Literal { value },
+            ShenNode::Symbol { name, .. } => Node::Symbol { name },
+            ShenNode::List { elements, element_type } => Node::List {
+                elements: self.add_all(elements),
+                element_type,
+            },
+            ShenNode::Cons { head, tail } => Node::Cons {
+                head: self.add(*head),
+                tail: self.add(*tail),
+            },
+            ShenNode::Lambda { arg, body, .. } => Node::Lambda {
+                arg,
+                body: self.add(*body),
+            },
+            ShenNode::Conditional {
+                condition,
+                true_branch,
+                false_branch,
+            } => Node::Conditional {
+                condition: self.add(*condition),
+                true_branch: self.add(*true_branch),
+                false_branch: false_branch.map(|branch| self.add(*branch)),
+            },
+            ShenNode::Define { name, rules } => Node::Define {
+                name,
+                rules: self.add_rules(rules),
+            },
+            ShenNode::Defmacro { name, rules } => Node::Defmacro {
+                name,
+                rules: self.add_rules(rules),
+            },
+            ShenNode::Defprolog { name, clauses } => Node::Defprolog {
+                name,
+                clauses: clauses
+                    .into_iter()
+                    .map(|clause| PrologClause {
+                        head: clause.head,
+                        body: self.add_all(clause.body),
+                    })
+                    .collect(),
+            },
+            ShenNode::Defcc { name, rules } => Node::Defcc {
+                name,
+                rules: rules
+                    .into_iter()
+                    .map(|rule| YaccRule {
+                        items: rule.items,
+                        action: rule.action.map(|action| self.add(action)),
+                        guard: rule.guard.map(|guard| self.add(guard)),
+                    })
+                    .collect(),
+            },
+            ShenNode::PrologQuery { goals } => Node::PrologQuery {
+                goals: self.add_all(goals),
+            },
+            ShenNode::Package { name, exports, body } => Node::Package {
+                name,
+                exports,
+                body: self.add_all(body),
+            },
+            ShenNode::Tuple { first, second } => Node::Tuple {
+                first: self.add(*first),
+                second: self.add(*second),
+            },
+            ShenNode::Vector { elements } => Node::Vector {
+                elements: self.add_all(elements),
+            },
+            ShenNode::VectorCons { head, tail } => Node::VectorCons {
+                head: self.add(*head),
+                tail: self.add(*tail),
+            },
+            ShenNode::StringCons { head, tail } => Node::StringCons {
+                head: self.add(*head),
+                tail: self.add(*tail),
+            },
+            ShenNode::TrapError { body, handler } => Node::TrapError {
+                body: self.add(*body),
+                handler: self.add(*handler),
+            },
+            ShenNode::Freeze { body } => Node::Freeze {
+                body: self.add(*body),
+            },
+            ShenNode::BinaryOperation {
+                operator,
+                left,
+                right,
+                ..
+            } => Node::BinaryOperation {
+                operator,
+                left: self.add(*left),
+                right: self.add(*right),
+            },
+            ShenNode::Nil => Node::Nil,
+            ShenNode::Error { message } => Node::Error { message },
+        };
+        self.push(node, ty)
+    }
+
+    fn add_all(&mut self, nodes: Vec<ShenNode>) -> Vec<NodeId> {
+        nodes.into_iter().map(|node| self.add(node)).collect()
+    }
+
+    fn add_rules(&mut self, rules: Vec<super::Rule>) -> Vec<Rule> {
+        rules
+            .into_iter()
+            .map(|rule| Rule {
+                patterns: rule.patterns,
+                body: self.add(rule.body),
+                guard: rule.guard.map(|guard| self.add(guard)),
+            })
+            .collect()
+    }
+
+    /// The owned tree under `id`. A node shared by several parents is copied
+    /// into each of them.
+    pub fn to_node(&self, id: NodeId) -> ShenNode {
+        let node = |id: &NodeId| Box::new(self.to_node(*id));
+        let nodes = |ids: &[NodeId]| ids.iter().map(|id| self.to_node(*id)).collect::<Vec<_>>();
+        let rules = |rules: &[Rule]| {
+            rules
+                .iter()
+                .map(|rule| super::Rule {
+                    patterns: rule.patterns.clone(),
+                    body: self.to_node(rule.body),
+                    guard: rule.guard.map(|guard| self.to_node(guard)),
+                })
+                .collect()
+        };
+        let ty = self.ty(id).clone();
+
+        match self.node(id) {
+            Node::Function { name, args, body } => ShenNode::Function {
+                name: *name,
+                args: args.clone(),
+                return_type: ty,
+                body: node(body),
+            },
+            Node::Application { func, args } => ShenNode::Application {
+                func: node(func),
+                args: nodes(args),
+            },
+            Node::Literal { value } => ShenNode::Literal { value: value.clone() },
+            Node::Symbol { name } => ShenNode::Symbol {
+                name: *name,
+                type_hint: ty,
+            },
+            Node::List { elements, element_type } => ShenNode::List {
+                elements: nodes(elements),
+                element_type: element_type.clone(),
+            },
+            Node::Cons { head, tail } => ShenNode::Cons {
+                head: node(head),
+                tail: node(tail),
+            },
+            Node::Lambda { arg, body } => ShenNode::Lambda {
+                arg: arg.clone(),
+                return_type: ty,
+                body: node(body),
+            },
+            Node::Conditional {
+                condition,
+                true_branch,
+                false_branch,
+            } => ShenNode::Conditional {
+                condition: node(condition),
+                true_branch: node(true_branch),
+                false_branch: false_branch.as_ref().map(node),
+            },
+            Node::Define { name, rules: define } => ShenNode::Define {
+                name: *name,
+                rules: rules(define),
+            },
+            Node::Defmacro { name, rules: defmacro } => ShenNode::Defmacro {
+                name: *name,
+                rules: rules(defmacro),
+            },
+            Node::Defprolog { name, clauses } => ShenNode::Defprolog {
+                name: *name,
+                clauses: clauses
+                    .iter()
+                    .map(|clause| super::PrologClause {
+                        head: clause.head.clone(),
+                        body: nodes(&clause.body),
+                    })
+                    .collect(),
+            },
+            Node::Defcc { name, rules } => ShenNode::Defcc {
+                name: *name,
+                rules: rules
+                    .iter()
+                    .map(|rule| super::YaccRule {
+                        items: rule.items.clone(),
+                        action: rule.action.map(|action| self.to_node(action)),
+                        guard: rule.guard.map(|guard| self.to_node(guard)),
+                    })
+                    .collect(),
+            },
+            Node::PrologQuery { goals } => ShenNode::PrologQuery { goals: nodes(goals) },
+            Node::Package { name, exports, body } => ShenNode::Package {
+                name: *name,
+                exports: exports.clone(),
+                body: nodes(body),
+            },
+            Node::Tuple { first, second } => ShenNode::Tuple {
+                first: node(first),
+                second: node(second),
+            },
+            Node::Vector { elements } => ShenNode::Vector { elements: nodes(elements) },
+            Node::VectorCons { head, tail } => ShenNode::VectorCons {
+                head: node(head),
+                tail: node(tail),
+            },
+            Node::StringCons { head, tail } => ShenNode::StringCons {
+                head: node(head),
+                tail: node(tail),
+            },
+            Node::TrapError { body, handler } => ShenNode::TrapError {
+                body: node(body),
+                handler: node(handler),
+            },
+            Node::Freeze { body } => ShenNode::Freeze { body: node(body) },
+            Node::BinaryOperation { operator, left, right } => ShenNode::BinaryOperation {
+                operator: *operator,
+                left: node(left),
+                right: node(right),
+                result_type: ty,
+            },
+            Node::Nil => ShenNode::Nil,
+            Node::Error { message } => ShenNode::Error { message: message.clone() },
+        }
+    }
+
+    /// [`ShenNode::try_convert`] without copying: a node that already has the
+    /// target type is returned as it is, and only literals and symbols, the
+    /// nodes a conversion can change, are converted into new nodes.
+    pub fn try_convert(&mut self, id: NodeId, target_type: &ShenType) -> Option<NodeId> {
+        if self.ty(id) == target_type {
+            return Some(id);
+        }
+        match self.node(id) {
+            Node::Literal { .. } | Node::Symbol { .. } => {
+                let converted = self.to_node(id).try_convert(target_type)?;
+                Some(self.add(converted))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Rebuilding traversal of the AST
+//!
+//! A [`Fold`] takes a tree apart and puts it back together, replacing each
+//! node, rule, clause, grammar rule and pattern with what the matching method
+//! returns for it. As with [`Visitor`](super::Visitor), the defaults rebuild
+//! the children unchanged, and an implementation calls the matching `walk_`
+//! function to fold below whatever it overrides.
+//!
+//! Folds may fail, with their own [`Fold::Error`]; one that cannot fail uses
+//! [`Infallible`](std::convert::Infallible), so that its results can be
+//! unwrapped with `let Ok(node) = ...`.
+
+use super::{Pattern, PrologClause, Rule, ShenNode, YaccItem, YaccRule};
+
+pub trait Fold {
+    type Error;
+
+    fn fold_node(&mut self, node: ShenNode) -> Result<ShenNode, Self::Error> {
+        walk_node(self, node)
+    }
+
+    fn fold_rule(&mut self, rule: Rule) -> Result<Rule, Self::Error> {
+        walk_rule(self, rule)
+    }
+
+    fn fold_prolog_clause(&mut self, clause: PrologClause) -> Result<PrologClause, Self::Error> {
+        walk_prolog_clause(self, clause)
+    }
+
+    fn fold_yacc_rule(&mut self, rule: YaccRule) -> Result<YaccRule, Self::Error> {
+        walk_yacc_rule(self, rule)
+    }
+
+    /// Grammar items have no children; this only lets a fold replace them.
+    fn fold_yacc_item(&mut self, item: YaccItem) -> Result<YaccItem, Self::Error> {
+        Ok(item)
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Result<Pattern, Self::Error> {
+        walk_pattern(self, pattern)
+    }
+}
+
+/// Fold the node in `node`, keeping the allocation of the box.
+fn fold_box<F: Fold + ?Sized>(folder: &mut F, mut node: Box<ShenNode>) -> Result<Box<ShenNode>, F::Error> {
+    *node = folder.fold_node(std::mem::replace(&mut *node, ShenNode::Nil))?;
+    Ok(node)
+}
+
+fn fold_all<F: Fold + ?Sized>(folder: &mut F, nodes: Vec<ShenNode>) -> Result<Vec<ShenNode>, F::Error> {
+    nodes.into_iter().map(|node| folder.fold_node(node)).collect()
+}
+
+fn fold_optional<F: Fold + ?Sized>(folder: &mut F, node: Option<ShenNode>) -> Result<Option<ShenNode>, F::Error> {
+    node.map(|node| folder.fold_node(node)).transpose()
+}
+
+/// Rebuild `node` with each of its children folded.
+pub fn walk_node<F: Fold + ?Sized>(folder: &mut F, node: ShenNode) -> Result<ShenNode, F::Error> {
+    Ok(match node {
+        ShenNode::Function { name, args, return_type, body } => ShenNode::Function {
+            name,
+            args,
+            return_type,
+            body: fold_box(folder, body)?,
+        },
+        ShenNode::Application { func, args } => ShenNode::Application {
+            func: fold_box(folder, func)?,
+            args: fold_all(folder, args)?,
+        },
+        ShenNode::List { elements, element_type } => ShenNode::List {
+            elements: fold_all(folder, elements)?,
+            element_type,
+        },
+        ShenNode::Cons { head, tail } => ShenNode::Cons {
+            head: fold_box(folder, head)?,
+            tail: fold_box(folder, tail)?,
+        },
+        ShenNode::Lambda { arg, return_type, body } => ShenNode::Lambda {
+            arg,
+            return_type,
+            body: fold_box(folder, body)?,
+        },
+        ShenNode::Conditional { condition, true_branch, false_branch } => ShenNode::Conditional {
+            condition: fold_box(folder, condition)?,
+            true_branch: fold_box(folder, true_branch)?,
+            false_branch: false_branch.map(|branch| fold_box(folder, branch)).transpose()?,
+        },
+        ShenNode::Define { name, rules } => ShenNode::Define {
+            name,
+            rules: rules.into_iter().map(|rule| folder.fold_rule(rule)).collect::<Result<_, _>>()?,
+        },
+        ShenNode::Defmacro { name, rules } => ShenNode::Defmacro {
+            name,
+            rules: rules.into_iter().map(|rule| folder.fold_rule(rule)).collect::<Result<_, _>>()?,
+        },
+        ShenNode::Defprolog { name, clauses } => ShenNode::Defprolog {
+            name,
+            clauses: clauses
+                .into_iter()
+                .map(|clause| folder.fold_prolog_clause(clause))
+                .collect::<Result<_, _>>()?,
+        },
+        ShenNode::Defcc { name, rules } => ShenNode::Defcc {
+            name,
+            rules: rules.into_iter().map(|rule| folder.fold_yacc_rule(rule)).collect::<Result<_, _>>()?,
+        },
+        ShenNode::PrologQuery { goals } => ShenNode::PrologQuery {
+            goals: fold_all(folder, goals)?,
+        },
+        ShenNode::Package { name, exports, body } => ShenNode::Package {
+            name,
+            exports,
+            body: fold_all(folder, body)?,
+        },
+        ShenNode::Tuple { first, second } => ShenNode::Tuple {
+            first: fold_box(folder, first)?,
+            second: fold_box(folder, second)?,
+        },
+        ShenNode::Vector { elements } => ShenNode::Vector {
+            elements: fold_all(folder, elements)?,
+        },
+        ShenNode::VectorCons { head, tail } => ShenNode::VectorCons {
+            head: fold_box(folder, head)?,
+            tail: fold_box(folder, tail)?,
+        },
+        ShenNode::StringCons { head, tail } => ShenNode::StringCons {
+            head: fold_box(folder, head)?,
+            tail: fold_box(folder, tail)?,
+        },
+        ShenNode::TrapError { body, handler } => ShenNode::TrapError {
+            body: fold_box(folder, body)?,
+            handler: fold_box(folder, handler)?,
+        },
+        ShenNode::Freeze { body } => ShenNode::Freeze {
+            body: fold_box(folder, body)?,
+        },
+        ShenNode::BinaryOperation { operator, left, right, result_type } => ShenNode::BinaryOperation {
+            operator,
+            left: fold_box(folder, left)?,
+            right: fold_box(folder, right)?,
+            result_type,
+        },
+        node @ (ShenNode::Symbol { .. } | ShenNode::Literal { .. } | ShenNode::Nil | ShenNode::Error { .. }) => {
+            node
+        }
+    })
+}
+
+/// Rebuild `rule` with its patterns, body and guard folded.
+pub fn walk_rule<F: Fold + ?Sized>(folder: &mut F, rule: Rule) -> Result<Rule, F::Error> {
+    Ok(Rule {
+        patterns: rule
+            .patterns
+            .into_iter()
+            .map(|pattern| folder.fold_pattern(pattern))
+            .collect::<Result<_, _>>()?,
+        body: folder.fold_node(rule.body)?,
+        guard: fold_optional(folder, rule.guard)?,
+    })
+}
+
+/// Rebuild `clause` with its head patterns and goals folded.
+pub fn walk_prolog_clause<F: Fold + ?Sized>(folder: &mut F, clause: PrologClause) -> Result<PrologClause, F::Error> {
+    Ok(PrologClause {
+        head: clause
+            .head
+            .into_iter()
+            .map(|pattern| folder.fold_pattern(pattern))
+            .collect::<Result<_, _>>()?,
+        body: fold_all(folder, clause.body)?,
+    })
+}
+
+/// Rebuild `rule` with its items, action and guard folded.
+pub fn walk_yacc_rule<F: Fold + ?Sized>(folder: &mut F, rule: YaccRule) -> Result<YaccRule, F::Error> {
+    Ok(YaccRule {
+        items: rule
+            .items
+            .into_iter()
+            .map(|item| folder.fold_yacc_item(item))
+            .collect::<Result<_, _>>()?,
+        action: fold_optional(folder, rule.action)?,
+        guard: fold_optional(folder, rule.guard)?,
+    })
+}
+
+/// Rebuild `pattern` with its sub-patterns folded.
+pub fn walk_pattern<F: Fold + ?Sized>(folder: &mut F, pattern: Pattern) -> Result<Pattern, F::Error> {
+    let mut pair = |mut first: Box<Pattern>, mut second: Box<Pattern>| {
+        *first = folder.fold_pattern(std::mem::replace(&mut *first, Pattern::Wildcard))?;
+        *second = folder.fold_pattern(std::mem::replace(&mut *second, Pattern::Wildcard))?;
+        Ok((first, second))
+    };
+    Ok(match pattern {
+        Pattern::Cons(head, tail) => {
+            let (head, tail) = pair(head, tail)?;
+            Pattern::Cons(head, tail)
+        }
+        Pattern::Tuple(first, second) => {
+            let (first, second) = pair(first, second)?;
+            Pattern::Tuple(first, second)
+        }
+        Pattern::VectorCons(head, tail) => {
+            let (head, tail) = pair(head, tail)?;
+            Pattern::VectorCons(head, tail)
+        }
+        Pattern::StringCons(head, tail) => {
+            let (head, tail) = pair(head, tail)?;
+            Pattern::StringCons(head, tail)
+        }
+        pattern @ (Pattern::Variable(_)
+        | Pattern::Wildcard
+        | Pattern::Literal(_)
+        | Pattern::Symbol(_)
+        | Pattern::EmptyList
+        | Pattern::EmptyVector) => pattern,
+    })
+}
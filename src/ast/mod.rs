@@ -1,6 +1,18 @@
 //! Abstract Syntax Tree for Shen language constructs
 
-#[derive(Debug, Clone)]
+use std::fmt;
+
+pub mod arena;
+pub mod fold;
+mod symbol;
+pub mod visit;
+pub mod visit_mut;
+
+pub use fold::Fold;
+pub use symbol::Symbol;
+pub use visit::Visitor;
+pub use visit_mut::VisitorMut;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ShenType {
     Integer,
@@ -10,14 +22,20 @@ pub enum ShenType {
     Symbol,
     List,
     Function,
+    /// `(lazy A)`: a frozen computation producing an `A` when thawed
+    Lazy(Box<ShenType>),
+    /// `(A * B)`: a tuple built by `@p`
+    Tuple(Box<ShenType>, Box<ShenType>),
+    /// `(vector A)`: a standard, 1-based vector
+    Vector(Box<ShenType>),
     Nil,
 }
 
 #[derive(Debug, Clone)]
 pub enum ShenNode {
     Function {
-        name: String,
-        args: Vec<(String, ShenType)>,
+        name: Symbol,
+        args: Vec<(Symbol, ShenType)>,
         return_type: ShenType,
         body: Box<ShenNode>,
     },
@@ -29,15 +47,22 @@ pub enum ShenNode {
         value: ShenValue,
     },
     Symbol {
-        name: String,
+        name: Symbol,
         type_hint: ShenType,
     },
     List {
         elements: Vec<ShenNode>,
         element_type: ShenType,
     },
+    /// `[Head | Tail]`
+    Cons {
+        head: Box<ShenNode>,
+        tail: Box<ShenNode>,
+    },
+    /// A single-argument lambda. Multi-argument forms such as `(/. X Y Body)`
+    /// are curried into nested `Lambda` nodes by [`ShenNode::curried_lambda`].
     Lambda {
-        args: Vec<(String, ShenType)>,
+        arg: (Symbol, ShenType),
         return_type: ShenType,
         body: Box<ShenNode>,
     },
@@ -46,16 +71,153 @@ pub enum ShenNode {
         true_branch: Box<ShenNode>,
         false_branch: Option<Box<ShenNode>>,
     },
+    /// `(define name Rules...)`: a function defined by pattern-matching rules
+    Define {
+        name: Symbol,
+        rules: Vec<Rule>,
+    },
+    /// `(defmacro name Pattern -> Expansion ...)`: a macro applied to every
+    /// form before code generation; each rule has exactly one pattern
+    Defmacro {
+        name: Symbol,
+        rules: Vec<Rule>,
+    },
+    /// `(defprolog name Heads <-- Goals; ...)`: a Shen-Prolog predicate
+    Defprolog {
+        name: Symbol,
+        clauses: Vec<PrologClause>,
+    },
+    /// `(defcc <name> Items := Action; ...)`: a Shen-YACC non-terminal
+    Defcc {
+        name: Symbol,
+        rules: Vec<YaccRule>,
+    },
+    /// `(prolog? Goals...)`: runs a query against the Prolog database
+    PrologQuery {
+        goals: Vec<ShenNode>,
+    },
+    /// `(package name [Exports...] Forms...)`, with the symbols of `body`
+    /// already prefixed; see [`crate::expand::package`]
+    Package {
+        name: Symbol,
+        exports: Vec<Symbol>,
+        body: Vec<ShenNode>,
+    },
+    /// `(@p A B)`; `(@p A B C)` is sugar for `(@p A (@p B C))`
+    Tuple {
+        first: Box<ShenNode>,
+        second: Box<ShenNode>,
+    },
+    /// A vector with known elements: `<>`, or `(@v A B <>)` once its tail is
+    /// known to be empty
+    Vector {
+        elements: Vec<ShenNode>,
+    },
+    /// `(@v X V)`: `X` followed by the elements of the vector `V`
+    VectorCons {
+        head: Box<ShenNode>,
+        tail: Box<ShenNode>,
+    },
+    /// `(@s A B)`: the concatenation of strings `A` and `B`;
+    /// `(@s A B C)` is `(@s A (@s B C))`
+    StringCons {
+        head: Box<ShenNode>,
+        tail: Box<ShenNode>,
+    },
+    /// `(trap-error Expr Handler)`: evaluates `Expr`, passing any error it
+    /// raises to `Handler`, normally a lambda `(/. E ...)`
+    TrapError {
+        body: Box<ShenNode>,
+        handler: Box<ShenNode>,
+    },
+    /// `(freeze Expr)`: a zero-argument continuation forced by `thaw`
+    Freeze {
+        body: Box<ShenNode>,
+    },
     BinaryOperation {
-        operator: String,
+        operator: Symbol,
         left: Box<ShenNode>,
         right: Box<ShenNode>,
         result_type: ShenType,
     },
     Nil,
+    /// A form the parser could not read, standing in for it in a partially
+    /// parsed program; see [`crate::parser::parse_shen_program_partial`]
+    Error {
+        message: String,
+    },
+}
+
+/// One rule of a `define`: `Patterns -> Body` or `Patterns -> Body where Guard`
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub patterns: Vec<Pattern>,
+    pub body: ShenNode,
+    pub guard: Option<ShenNode>,
+}
+
+/// One clause of a `defprolog`: `Heads <-- Goals;`
+///
+/// Heads are matched by unification, so they reuse [`Pattern`]; goals are
+/// ordinary expressions such as `(member X Y)`, `!` or `(is X (+ Y 1))`.
+#[derive(Debug, Clone)]
+pub struct PrologClause {
+    pub head: Vec<Pattern>,
+    pub body: Vec<ShenNode>,
 }
 
+/// One rule of a `defcc`: `Items := Action where Guard;`
+///
+/// Without an action the rule returns the input it consumed.
 #[derive(Debug, Clone)]
+pub struct YaccRule {
+    pub items: Vec<YaccItem>,
+    pub action: Option<ShenNode>,
+    pub guard: Option<ShenNode>,
+}
+
+/// What a single item on the left of `:=` matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum YaccItem {
+    /// `<name>`: another `defcc`, whose result is bound to `<name>`
+    NonTerminal(Symbol),
+    /// `<e>`: the empty sequence
+    Empty,
+    /// `<!>`: the rest of the input, bound to `<!>`
+    Rest,
+    /// An uppercase variable, bound to any one element
+    Variable(Symbol),
+    /// A symbol that must appear literally in the input
+    Symbol(Symbol),
+    /// A number or string that must appear literally in the input
+    Literal(ShenValue),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// An uppercase variable, bound to the matched value
+    Variable(Symbol),
+    /// `_`
+    Wildcard,
+    Literal(ShenValue),
+    /// A lowercase symbol, matching only itself
+    Symbol(Symbol),
+    /// `[]`
+    EmptyList,
+    /// `[X | Y]`; `[X Y]` is `[X | [Y | []]]`
+    Cons(Box<Pattern>, Box<Pattern>),
+    /// `(@p A B)`
+    Tuple(Box<Pattern>, Box<Pattern>),
+    /// `<>`
+    EmptyVector,
+    /// `(@v X Y)`: a non-empty vector, `Y` matching the remaining elements
+    VectorCons(Box<Pattern>, Box<Pattern>),
+    /// `(@s X Y)`: a non-empty string. A string literal `X` matches a prefix;
+    /// any other `X` matches the first unit string.
+    StringCons(Box<Pattern>, Box<Pattern>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ShenValue {
     Integer(i64),
     Float(f64),
@@ -64,70 +226,123 @@ pub enum ShenValue {
     Nil,
 }
 
-impl ShenNode {
-    pub fn get_type(&self) -> ShenType {
+impl ShenType {
+    /// Whether values of this type have a fixed Rust representation, so that
+    /// generated code can use native Rust values instead of runtime `Value`s.
+    pub fn is_concrete(&self) -> bool {
         match self {
-            ShenNode::Literal { value } => match value {
-                ShenValue::Integer(_) => ShenType::Integer,
-                ShenValue::Float(_) => ShenType::Float,
-                ShenValue::String(_) => ShenType::String,
-                ShenValue::Boolean(_) => ShenType::Boolean,
-                ShenValue::Nil => ShenType::Nil,
-            },
-            ShenNode::Symbol { type_hint, .. } => type_hint.clone(),
-            ShenNode::List { element_type, .. } => ShenType::List,
-            ShenNode::Function { return_type, .. } => return_type.clone(),
-            ShenNode::Lambda { return_type, .. } => return_type.clone(),
-            ShenNode::Nil => ShenType::Nil,
-            ShenNode::BinaryOperation { result_type, .. } => result_type.clone(),
-            ShenNode::Conditional { true_branch, .. } => true_branch.get_type(),
-            _ => ShenType::Symbol, // Default fallback
+            ShenType::Integer | ShenType::Float | ShenType::String | ShenType::Boolean => true,
+            ShenType::Tuple(first, second) => first.is_concrete() && second.is_concrete(),
+            _ => false,
+        }
+    }
+}
+
+/// Types in Shen's own notation, such as `number` or `(vector string)`.
+/// Integers and floats are both `number`; types the transpiler does not track
+/// in detail, such as list element types, print as a variable `A`.
+impl fmt::Display for ShenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShenType::Integer | ShenType::Float => write!(f, "number"),
+            ShenType::String => write!(f, "string"),
+            ShenType::Boolean => write!(f, "boolean"),
+            ShenType::Symbol => write!(f, "symbol"),
+            ShenType::List | ShenType::Nil => write!(f, "(list A)"),
+            ShenType::Function => write!(f, "(A --> B)"),
+            ShenType::Lazy(value) => write!(f, "(lazy {})", value),
+            ShenType::Tuple(first, second) => write!(f, "({} * {})", first, second),
+            ShenType::Vector(element) => write!(f, "(vector {})", element),
         }
     }
+}
+
+impl ShenNode {
+    /// Build a curried lambda from a parameter list: `(/. X Y Body)` becomes
+    /// `Lambda(X, Lambda(Y, Body))`. Returns `None` for an empty parameter list.
+    pub fn curried_lambda(args: Vec<(Symbol, ShenType)>, body: ShenNode) -> Option<ShenNode> {
+        if args.is_empty() {
+            return None;
+        }
+
+        let mut return_type = body.get_type();
+        let mut node = body;
+        for arg in args.into_iter().rev() {
+            node = ShenNode::Lambda {
+                arg,
+                return_type,
+                body: Box::new(node),
+            };
+            return_type = ShenType::Function;
+        }
+        Some(node)
+    }
+
+    /// Flatten a chain of nested lambdas back into its parameters and the
+    /// innermost body.
+    pub fn uncurry_lambda(&self) -> Option<(Vec<&(Symbol, ShenType)>, &ShenNode)> {
+        let ShenNode::Lambda { .. } = self else {
+            return None;
+        };
+
+        let mut args = Vec::new();
+        let mut node = self;
+        while let ShenNode::Lambda { arg, body, .. } = node {
+            args.push(arg);
+            node = body;
+        }
+        Some((args, node))
+    }
+
+    /// The node's type, from its literal, hint or declared type, or from the
+    /// children that decide it.
+    pub fn get_type(&self) -> ShenType {
+        TypeOf::hinted().of(self)
+    }
 
     pub fn try_convert(&self, target_type: &ShenType) -> Option<ShenNode> {
         match (self.get_type(), target_type) {
             // Numeric conversions
             (ShenType::Integer, ShenType::Float) => {
-                if let ShenNode::Literal(ShenValue::Integer(val)) = self {
-                    Some(ShenNode::Literal(ShenValue::Float(*val as f64)))
+                if let ShenNode::Literal { value: ShenValue::Integer(val) } = self {
+                    Some(ShenNode::Literal { value: ShenValue::Float(*val as f64) })
                 } else {
                     None
                 }
             },
             (ShenType::Float, ShenType::Integer) => {
-                if let ShenNode::Literal(ShenValue::Float(val)) = self {
-                    Some(ShenNode::Literal(ShenValue::Integer(*val as i64)))
+                if let ShenNode::Literal { value: ShenValue::Float(val) } = self {
+                    Some(ShenNode::Literal { value: ShenValue::Integer(*val as i64) })
                 } else {
                     None
                 }
             },
             // String conversions
             (ShenType::Integer, ShenType::String) => {
-                if let ShenNode::Literal(ShenValue::Integer(val)) = self {
-                    Some(ShenNode::Literal(ShenValue::String(val.to_string())))
+                if let ShenNode::Literal { value: ShenValue::Integer(val) } = self {
+                    Some(ShenNode::Literal { value: ShenValue::String(val.to_string()) })
                 } else {
                     None
                 }
             },
             (ShenType::Float, ShenType::String) => {
-                if let ShenNode::Literal(ShenValue::Float(val)) = self {
-                    Some(ShenNode::Literal(ShenValue::String(val.to_string())))
+                if let ShenNode::Literal { value: ShenValue::Float(val) } = self {
+                    Some(ShenNode::Literal { value: ShenValue::String(val.to_string()) })
                 } else {
                     None
                 }
             },
             // Boolean conversions
             (ShenType::Integer, ShenType::Boolean) => {
-                if let ShenNode::Literal(ShenValue::Integer(val)) = self {
-                    Some(ShenNode::Literal(ShenValue::Boolean(*val != 0)))
+                if let ShenNode::Literal { value: ShenValue::Integer(val) } = self {
+                    Some(ShenNode::Literal { value: ShenValue::Boolean(*val != 0) })
                 } else {
                     None
                 }
             },
             (ShenType::Float, ShenType::Boolean) => {
-                if let ShenNode::Literal(ShenValue::Float(val)) = self {
-                    Some(ShenNode::Literal(ShenValue::Boolean(*val != 0.0)))
+                if let ShenNode::Literal { value: ShenValue::Float(val) } = self {
+                    Some(ShenNode::Literal { value: ShenValue::Boolean(*val != 0.0) })
                 } else {
                     None
                 }
@@ -143,19 +358,19 @@ impl ShenNode {
     fn infer_complex_conversion(&self, target_type: &ShenType) -> Option<ShenNode> {
         match (self, target_type) {
             // Handle list conversions
-            (ShenNode::List { elements, .. }, ShenType::List) => Some(self.clone()),
+            (ShenNode::List { .. }, ShenType::List) => Some(self.clone()),
             
             // Handle symbol to specific type conversions
             (ShenNode::Symbol { name, .. }, target) => {
                 // Try parsing symbol name to target type
                 match target {
                     ShenType::Integer => name.parse::<i64>()
-                        .map(|val| ShenNode::Literal(ShenValue::Integer(val))).ok(),
+                        .map(|val| ShenNode::Literal { value: ShenValue::Integer(val) }).ok(),
                     ShenType::Float => name.parse::<f64>()
-                        .map(|val| ShenNode::Literal(ShenValue::Float(val))).ok(),
+                        .map(|val| ShenNode::Literal { value: ShenValue::Float(val) }).ok(),
                     ShenType::Boolean => match name.to_lowercase().as_str() {
-                        "true" => Some(ShenNode::Literal(ShenValue::Boolean(true))),
-                        "false" => Some(ShenNode::Literal(ShenValue::Boolean(false))),
+                        "true" => Some(ShenNode::Literal { value: ShenValue::Boolean(true) }),
+                        "false" => Some(ShenNode::Literal { value: ShenValue::Boolean(false) }),
                         _ => None
                     },
                     _ => None
@@ -167,9 +382,70 @@ impl ShenNode {
         }
     }
 
-    /// Attempt to find the most appropriate type for a node
+    /// Attempt to find the most appropriate type for a node: as
+    /// [`get_type`](ShenNode::get_type), but also reading numbers and
+    /// booleans from symbol names.
     pub fn infer_type(&self) -> ShenType {
-        match self {
+        TypeOf::inferred().of(self)
+    }
+}
+
+/// Works out the type of a node. Nodes that carry their own type stop
+/// there; the rest are walked, and their type built from those of their
+/// children: the true branch of a conditional, the parts of a tuple, the body
+/// of a `freeze`.
+struct TypeOf {
+    /// Whether to read the types of symbols from their names
+    infer: bool,
+    /// The types of the nodes visited so far, innermost last
+    types: Vec<ShenType>,
+}
+
+impl TypeOf {
+    fn hinted() -> Self {
+        TypeOf { infer: false, types: Vec::new() }
+    }
+
+    fn inferred() -> Self {
+        TypeOf { infer: true, types: Vec::new() }
+    }
+
+    fn of(&mut self, node: &ShenNode) -> ShenType {
+        self.visit_node(node);
+        self.types.pop().unwrap_or(ShenType::Symbol)
+    }
+
+    /// The type of a node whose children, of types `children`, decide it.
+    fn combine(node: &ShenNode, children: Vec<ShenType>) -> ShenType {
+        let mut children = children.into_iter();
+        let mut next = || children.next().unwrap_or(ShenType::Symbol);
+        match node {
+            // The condition comes first
+            ShenNode::Conditional { .. } => {
+                next();
+                next()
+            }
+            ShenNode::Tuple { .. } => ShenType::Tuple(Box::new(next()), Box::new(next())),
+            ShenNode::Vector { .. } => ShenType::Vector(Box::new(next())),
+            ShenNode::VectorCons { .. } => ShenType::Vector(Box::new(next())),
+            ShenNode::Freeze { .. } => ShenType::Lazy(Box::new(next())),
+            ShenNode::TrapError { .. } => next(),
+            // `(thaw F)` has type `A` when `F` has type `(lazy A)`
+            ShenNode::Application { .. } => {
+                next();
+                match next() {
+                    ShenType::Lazy(inner) => *inner,
+                    _ => ShenType::Symbol,
+                }
+            }
+            _ => ShenType::Symbol,
+        }
+    }
+}
+
+impl Visitor for TypeOf {
+    fn visit_node(&mut self, node: &ShenNode) {
+        let ty = match node {
             ShenNode::Literal { value } => match value {
                 ShenValue::Integer(_) => ShenType::Integer,
                 ShenValue::Float(_) => ShenType::Float,
@@ -177,25 +453,45 @@ impl ShenNode {
                 ShenValue::Boolean(_) => ShenType::Boolean,
                 ShenValue::Nil => ShenType::Nil,
             },
-            ShenNode::Symbol { type_hint, name } => {
-                // Enhanced type inference for symbols
-                if let Ok(_) = name.parse::<i64>() {
+            ShenNode::Symbol { name, type_hint } if self.infer => {
+                if name.parse::<i64>().is_ok() {
                     ShenType::Integer
-                } else if let Ok(_) = name.parse::<f64>() {
+                } else if name.parse::<f64>().is_ok() {
                     ShenType::Float
-                } else if name.to_lowercase() == "true" || name.to_lowercase() == "false" {
+                } else if name.eq_ignore_ascii_case("true") || name.eq_ignore_ascii_case("false") {
                     ShenType::Boolean
                 } else {
                     type_hint.clone()
                 }
-            },
-            ShenNode::List { element_type, .. } => ShenType::List,
-            ShenNode::Function { return_type, .. } => return_type.clone(),
-            ShenNode::Lambda { return_type, .. } => return_type.clone(),
-            ShenNode::BinaryOperation { result_type, .. } => result_type.clone(),
-            ShenNode::Conditional { true_branch, .. } => true_branch.get_type(),
+            }
+            ShenNode::Symbol { type_hint, .. } => type_hint.clone(),
+            ShenNode::List { .. } | ShenNode::Cons { .. } => ShenType::List,
+            ShenNode::Function { return_type, .. } | ShenNode::Lambda { return_type, .. } => {
+                return_type.clone()
+            }
             ShenNode::Nil => ShenType::Nil,
-            _ => ShenType::Symbol,
-        }
+            ShenNode::BinaryOperation { result_type, .. } => result_type.clone(),
+            ShenNode::Define { .. }
+            | ShenNode::Defmacro { .. }
+            | ShenNode::Defprolog { .. }
+            | ShenNode::Defcc { .. } => ShenType::Function,
+            ShenNode::StringCons { .. } => ShenType::String,
+            ShenNode::Application { func, args }
+                if !(args.len() == 1
+                    && matches!(&**func, ShenNode::Symbol { name, .. } if name == "thaw")) =>
+            {
+                ShenType::Symbol
+            }
+            ShenNode::Package { .. } | ShenNode::Error { .. } | ShenNode::PrologQuery { .. } => {
+                ShenType::Symbol
+            }
+            _ => {
+                let start = self.types.len();
+                visit::walk_node(self, node);
+                let children = self.types.split_off(start);
+                Self::combine(node, children)
+            }
+        };
+        self.types.push(ty);
     }
 }
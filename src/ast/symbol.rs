@@ -0,0 +1,161 @@
+//! Interned symbol names
+//!
+//! Every name in the AST, of a symbol, a variable or a definition, is a
+//! [`Symbol`], a `u32` naming an entry in one process-wide table, so that
+//! copying or comparing a name costs no more than copying or comparing an
+//! integer. Names are never removed from
+//! the table; a program has few enough distinct ones that they can live for
+//! the rest of the process.
+//!
+//! Each thread keeps its own copy of the names it has looked up, so turning a
+//! symbol back into its name only takes the table's lock the first time a
+//! thread sees a symbol.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::ops::Deref;
+use std::sync::{OnceLock, RwLock};
+
+/// An interned name, such as `append` or `X`.
+///
+/// A `Symbol` dereferences to its name, and compares equal to a `str` with
+/// the same text; two `Symbol`s are equal exactly when their names are, and
+/// order as their names do.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, Symbol, BuildHasherDefault<NameHasher>>,
+    names: Vec<&'static str>,
+}
+
+thread_local! {
+    static NAMES: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The multiply-rotate hash used by rustc, much quicker than the default
+/// hasher on short keys like names. The table's keys are not attacker-chosen
+/// enough for the default's resistance to collisions to matter.
+#[derive(Default)]
+struct NameHasher(u64);
+
+impl Hasher for NameHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0.rotate_left(5) ^ byte as u64).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Symbol {
+    /// The symbol named `name`, adding it to the table the first time.
+    pub fn intern(name: &str) -> Symbol {
+        if let Some(&symbol) = interner().read().unwrap().ids.get(name) {
+            return symbol;
+        }
+        let mut interner = interner().write().unwrap();
+        // Another thread may have added it between the two locks
+        if let Some(&symbol) = interner.ids.get(name) {
+            return symbol;
+        }
+        let name: &'static str = Box::leak(name.into());
+        let symbol = Symbol(interner.names.len() as u32);
+        interner.names.push(name);
+        interner.ids.insert(name, symbol);
+        symbol
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        let index = self.0 as usize;
+        NAMES.with(|names| {
+            if let Some(name) = names.borrow().get(index) {
+                return *name;
+            }
+            let mut names = names.borrow_mut();
+            let interned = &interner().read().unwrap().names;
+            let known = names.len();
+            names.extend_from_slice(&interned[known..]);
+            names[index]
+        })
+    }
+
+    /// The symbol's index in the table.
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::intern(&name)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// By name rather than by id, which only records when a name was first seen.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self.0 == other.0 {
+            Ordering::Equal
+        } else {
+            self.as_str().cmp(other.as_str())
+        }
+    }
+}
+
+/// Symbols debug-print as their names, as the `String`s they replaced did.
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
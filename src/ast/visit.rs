@@ -0,0 +1,131 @@
+//! Read-only traversal of the AST
+//!
+//! A [`Visitor`] is shown every node of a tree, along with the rules, clauses,
+//! grammar rules and patterns inside them. Each method's default walks into
+//! the children of what it is given, so an implementation overrides only the
+//! methods for what it is interested in and calls the matching `walk_`
+//! function wherever it wants to carry on into the children.
+
+use super::{Pattern, PrologClause, Rule, ShenNode, YaccItem, YaccRule};
+
+pub trait Visitor {
+    fn visit_node(&mut self, node: &ShenNode) {
+        walk_node(self, node)
+    }
+
+    fn visit_rule(&mut self, rule: &Rule) {
+        walk_rule(self, rule)
+    }
+
+    fn visit_prolog_clause(&mut self, clause: &PrologClause) {
+        walk_prolog_clause(self, clause)
+    }
+
+    fn visit_yacc_rule(&mut self, rule: &YaccRule) {
+        walk_yacc_rule(self, rule)
+    }
+
+    /// Grammar items have no children; this only lets a visitor see them.
+    fn visit_yacc_item(&mut self, _item: &YaccItem) {}
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern)
+    }
+}
+
+/// Visit each child of `node`.
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &ShenNode) {
+    match node {
+        ShenNode::Function { body, .. }
+        | ShenNode::Lambda { body, .. }
+        | ShenNode::Freeze { body } => visitor.visit_node(body),
+        ShenNode::Application { func, args } => {
+            visitor.visit_node(func);
+            args.iter().for_each(|arg| visitor.visit_node(arg));
+        }
+        ShenNode::List { elements, .. } | ShenNode::Vector { elements } => {
+            elements.iter().for_each(|element| visitor.visit_node(element))
+        }
+        ShenNode::PrologQuery { goals: nodes } | ShenNode::Package { body: nodes, .. } => {
+            nodes.iter().for_each(|node| visitor.visit_node(node))
+        }
+        ShenNode::Cons { head, tail }
+        | ShenNode::VectorCons { head, tail }
+        | ShenNode::StringCons { head, tail } => {
+            visitor.visit_node(head);
+            visitor.visit_node(tail);
+        }
+        ShenNode::Tuple { first, second } => {
+            visitor.visit_node(first);
+            visitor.visit_node(second);
+        }
+        ShenNode::BinaryOperation { left, right, .. } => {
+            visitor.visit_node(left);
+            visitor.visit_node(right);
+        }
+        ShenNode::TrapError { body, handler } => {
+            visitor.visit_node(body);
+            visitor.visit_node(handler);
+        }
+        ShenNode::Conditional { condition, true_branch, false_branch } => {
+            visitor.visit_node(condition);
+            visitor.visit_node(true_branch);
+            if let Some(false_branch) = false_branch {
+                visitor.visit_node(false_branch);
+            }
+        }
+        ShenNode::Define { rules, .. } | ShenNode::Defmacro { rules, .. } => {
+            rules.iter().for_each(|rule| visitor.visit_rule(rule))
+        }
+        ShenNode::Defprolog { clauses, .. } => {
+            clauses.iter().for_each(|clause| visitor.visit_prolog_clause(clause))
+        }
+        ShenNode::Defcc { rules, .. } => rules.iter().for_each(|rule| visitor.visit_yacc_rule(rule)),
+        ShenNode::Symbol { .. } | ShenNode::Literal { .. } | ShenNode::Nil | ShenNode::Error { .. } => {}
+    }
+}
+
+/// Visit the patterns of `rule`, then its body and guard.
+pub fn walk_rule<V: Visitor + ?Sized>(visitor: &mut V, rule: &Rule) {
+    rule.patterns.iter().for_each(|pattern| visitor.visit_pattern(pattern));
+    visitor.visit_node(&rule.body);
+    if let Some(guard) = &rule.guard {
+        visitor.visit_node(guard);
+    }
+}
+
+/// Visit the head patterns of `clause`, then its goals.
+pub fn walk_prolog_clause<V: Visitor + ?Sized>(visitor: &mut V, clause: &PrologClause) {
+    clause.head.iter().for_each(|pattern| visitor.visit_pattern(pattern));
+    clause.body.iter().for_each(|goal| visitor.visit_node(goal));
+}
+
+/// Visit the items of `rule`, then its action and guard.
+pub fn walk_yacc_rule<V: Visitor + ?Sized>(visitor: &mut V, rule: &YaccRule) {
+    rule.items.iter().for_each(|item| visitor.visit_yacc_item(item));
+    if let Some(action) = &rule.action {
+        visitor.visit_node(action);
+    }
+    if let Some(guard) = &rule.guard {
+        visitor.visit_node(guard);
+    }
+}
+
+/// Visit the sub-patterns of `pattern`.
+pub fn walk_pattern<V: Visitor + ?Sized>(visitor: &mut V, pattern: &Pattern) {
+    match pattern {
+        Pattern::Cons(first, second)
+        | Pattern::Tuple(first, second)
+        | Pattern::VectorCons(first, second)
+        | Pattern::StringCons(first, second) => {
+            visitor.visit_pattern(first);
+            visitor.visit_pattern(second);
+        }
+        Pattern::Variable(_)
+        | Pattern::Wildcard
+        | Pattern::Literal(_)
+        | Pattern::Symbol(_)
+        | Pattern::EmptyList
+        | Pattern::EmptyVector => {}
+    }
+}
@@ -0,0 +1,131 @@
+//! In-place traversal of the AST
+//!
+//! [`VisitorMut`] is [`Visitor`](super::Visitor) with mutable references,
+//! for passes that change a tree where it stands instead of rebuilding it
+//! with a [`Fold`](super::Fold).
+
+use super::{Pattern, PrologClause, Rule, ShenNode, YaccItem, YaccRule};
+
+pub trait VisitorMut {
+    fn visit_node_mut(&mut self, node: &mut ShenNode) {
+        walk_node_mut(self, node)
+    }
+
+    fn visit_rule_mut(&mut self, rule: &mut Rule) {
+        walk_rule_mut(self, rule)
+    }
+
+    fn visit_prolog_clause_mut(&mut self, clause: &mut PrologClause) {
+        walk_prolog_clause_mut(self, clause)
+    }
+
+    fn visit_yacc_rule_mut(&mut self, rule: &mut YaccRule) {
+        walk_yacc_rule_mut(self, rule)
+    }
+
+    /// Grammar items have no children; this only lets a visitor see them.
+    fn visit_yacc_item_mut(&mut self, _item: &mut YaccItem) {}
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern)
+    }
+}
+
+/// Visit each child of `node`.
+pub fn walk_node_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut ShenNode) {
+    match node {
+        ShenNode::Function { body, .. }
+        | ShenNode::Lambda { body, .. }
+        | ShenNode::Freeze { body } => visitor.visit_node_mut(body),
+        ShenNode::Application { func, args } => {
+            visitor.visit_node_mut(func);
+            args.iter_mut().for_each(|arg| visitor.visit_node_mut(arg));
+        }
+        ShenNode::List { elements, .. } | ShenNode::Vector { elements } => {
+            elements.iter_mut().for_each(|element| visitor.visit_node_mut(element))
+        }
+        ShenNode::PrologQuery { goals: nodes } | ShenNode::Package { body: nodes, .. } => {
+            nodes.iter_mut().for_each(|node| visitor.visit_node_mut(node))
+        }
+        ShenNode::Cons { head, tail }
+        | ShenNode::VectorCons { head, tail }
+        | ShenNode::StringCons { head, tail } => {
+            visitor.visit_node_mut(head);
+            visitor.visit_node_mut(tail);
+        }
+        ShenNode::Tuple { first, second } => {
+            visitor.visit_node_mut(first);
+            visitor.visit_node_mut(second);
+        }
+        ShenNode::BinaryOperation { left, right, .. } => {
+            visitor.visit_node_mut(left);
+            visitor.visit_node_mut(right);
+        }
+        ShenNode::TrapError { body, handler } => {
+            visitor.visit_node_mut(body);
+            visitor.visit_node_mut(handler);
+        }
+        ShenNode::Conditional { condition, true_branch, false_branch } => {
+            visitor.visit_node_mut(condition);
+            visitor.visit_node_mut(true_branch);
+            if let Some(false_branch) = false_branch {
+                visitor.visit_node_mut(false_branch);
+            }
+        }
+        ShenNode::Define { rules, .. } | ShenNode::Defmacro { rules, .. } => {
+            rules.iter_mut().for_each(|rule| visitor.visit_rule_mut(rule))
+        }
+        ShenNode::Defprolog { clauses, .. } => {
+            clauses.iter_mut().for_each(|clause| visitor.visit_prolog_clause_mut(clause))
+        }
+        ShenNode::Defcc { rules, .. } => {
+            rules.iter_mut().for_each(|rule| visitor.visit_yacc_rule_mut(rule))
+        }
+        ShenNode::Symbol { .. } | ShenNode::Literal { .. } | ShenNode::Nil | ShenNode::Error { .. } => {}
+    }
+}
+
+/// Visit the patterns of `rule`, then its body and guard.
+pub fn walk_rule_mut<V: VisitorMut + ?Sized>(visitor: &mut V, rule: &mut Rule) {
+    rule.patterns.iter_mut().for_each(|pattern| visitor.visit_pattern_mut(pattern));
+    visitor.visit_node_mut(&mut rule.body);
+    if let Some(guard) = &mut rule.guard {
+        visitor.visit_node_mut(guard);
+    }
+}
+
+/// Visit the head patterns of `clause`, then its goals.
+pub fn walk_prolog_clause_mut<V: VisitorMut + ?Sized>(visitor: &mut V, clause: &mut PrologClause) {
+    clause.head.iter_mut().for_each(|pattern| visitor.visit_pattern_mut(pattern));
+    clause.body.iter_mut().for_each(|goal| visitor.visit_node_mut(goal));
+}
+
+/// Visit the items of `rule`, then its action and guard.
+pub fn walk_yacc_rule_mut<V: VisitorMut + ?Sized>(visitor: &mut V, rule: &mut YaccRule) {
+    rule.items.iter_mut().for_each(|item| visitor.visit_yacc_item_mut(item));
+    if let Some(action) = &mut rule.action {
+        visitor.visit_node_mut(action);
+    }
+    if let Some(guard) = &mut rule.guard {
+        visitor.visit_node_mut(guard);
+    }
+}
+
+/// Visit the sub-patterns of `pattern`.
+pub fn walk_pattern_mut<V: VisitorMut + ?Sized>(visitor: &mut V, pattern: &mut Pattern) {
+    match pattern {
+        Pattern::Cons(first, second)
+        | Pattern::Tuple(first, second)
+        | Pattern::VectorCons(first, second)
+        | Pattern::StringCons(first, second) => {
+            visitor.visit_pattern_mut(first);
+            visitor.visit_pattern_mut(second);
+        }
+        Pattern::Variable(_)
+        | Pattern::Wildcard
+        | Pattern::Literal(_)
+        | Pattern::Symbol(_)
+        | Pattern::EmptyList
+        | Pattern::EmptyVector => {}
+    }
+}
@@ -0,0 +1,252 @@
+//! Bootstrapping Shen on Rust
+//!
+//! The Shen kernel is itself written in Shen and shipped as KLambda. This
+//! module transpiles a whole kernel into a standalone Rust crate whose `main`
+//! starts the kernel's REPL, `shen.shen`, and checks a REPL binary against
+//! reference transcripts from the Shen test suite.
+//!
+//! KLambda is untyped, so the kernel is generated over runtime `Value`s with
+//! [`generate_klambda_code`]. Every kernel `defun` becomes a public function of
+//! the crate's `kernel` module; the kernel's other top-level forms, mostly
+//! `set`s of its global variables, run in order from `kernel::init` before the
+//! REPL starts. A kernel without `shen.shen` gets one that reports it missing.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::ast::ShenNode;
+use crate::codegen::{generate_klambda_code, CodegenOptions, ErrorStrategy};
+use crate::error::TranspilerError;
+use crate::parser::load_kernel;
+
+/// Options controlling the generated kernel crate.
+#[derive(Debug, Clone)]
+pub struct BootstrapOptions {
+    /// The package name of the generated crate.
+    pub crate_name: String,
+    /// Where the generated crate finds this crate, for the runtime.
+    pub runtime_path: PathBuf,
+    pub codegen: CodegenOptions,
+}
+
+impl Default for BootstrapOptions {
+    fn default() -> Self {
+        BootstrapOptions {
+            crate_name: "shen".to_string(),
+            runtime_path: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+            codegen: CodegenOptions::default(),
+        }
+    }
+}
+
+/// The files of a generated crate, relative to its root.
+#[derive(Debug, Clone)]
+pub struct GeneratedCrate {
+    pub files: Vec<(PathBuf, String)>,
+}
+
+impl GeneratedCrate {
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|(file, _)| file == path.as_ref())
+            .map(|(_, contents)| contents.as_str())
+    }
+
+    /// Write the crate under `dir`, creating directories as needed.
+    pub fn write_to(&self, dir: impl AsRef<Path>) -> Result<(), TranspilerError> {
+        for (file, contents) in &self.files {
+            let path = dir.as_ref().join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|err| io_error(parent, err))?;
+            }
+            fs::write(&path, contents).map_err(|err| io_error(&path, err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Transpile the kernel in `kernel_dir` into a crate written to `out_dir`.
+pub fn bootstrap(
+    kernel_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    options: &BootstrapOptions,
+) -> Result<GeneratedCrate, TranspilerError> {
+    let generated = generate_kernel_crate(&load_kernel(kernel_dir)?, options)?;
+    generated.write_to(out_dir)?;
+    Ok(generated)
+}
+
+/// Transpile the forms of a kernel into the files of a crate.
+pub fn generate_kernel_crate(
+    kernel: &[ShenNode],
+    options: &BootstrapOptions,
+) -> Result<GeneratedCrate, TranspilerError> {
+    let uses_result = options.codegen.error_strategy == ErrorStrategy::Result;
+
+    let mut functions = Vec::new();
+    let mut init = Vec::new();
+    let mut has_entry_point = false;
+    for node in kernel {
+        let code = generate_klambda_code(node, &options.codegen)?;
+        match node {
+            ShenNode::Function { name, .. } => {
+                has_entry_point |= name == "shen.shen";
+                functions.push(format!("pub {}", code));
+            }
+            _ => init.push(format!("{};", code)),
+        }
+    }
+    if uses_result {
+        init.push("Ok(())".to_string());
+    }
+    if !has_entry_point {
+        // Keep the crate buildable; running it reports what is missing
+        let missing = "simple_error(\"this kernel does not define shen.shen\")";
+        functions.push(if uses_result {
+            format!("pub fn shen_shen() -> ShenResult<Value> {{\n    Err({})\n}}", missing)
+        } else {
+            format!("pub fn shen_shen() -> Value {{\n    raise({})\n}}", missing)
+        });
+    }
+    let init = init
+        .iter()
+        .flat_map(|code| code.lines())
+        .map(|line| format!("    {}", line))
+        .collect::<Vec<_>>();
+
+    let init_signature = if uses_result { "pub fn init() -> ShenResult<()>" } else { "pub fn init()" };
+    let kernel_rs = format!(
+        "//! The Shen kernel, transpiled from KLambda\n\n\
+         #![allow(non_snake_case, unused, unreachable_code)]\n\n\
+         use shen_transpiler::runtime::prelude::*;\n\n\
+         /// Set up the kernel's global state.\n\
+         {} {{\n{}\n}}\n\n{}\n",
+        init_signature,
+        init.join("\n"),
+        functions.join("\n\n")
+    );
+
+    let main_rs = if uses_result {
+        "mod kernel;\n\n\
+         use shen_transpiler::runtime::prelude::*;\n\n\
+         fn main() {\n    \
+             if let Err(err) = kernel::init().and_then(|_| kernel::shen_shen()) {\n        \
+                 eprintln!(\"{}\", error_to_string(&err));\n        \
+                 std::process::exit(1);\n    \
+             }\n\
+         }\n"
+    } else {
+        "mod kernel;\n\n\
+         use shen_transpiler::runtime::prelude::*;\n\n\
+         fn main() {\n    \
+             trap_error(\n        \
+                 || {\n            \
+                     kernel::init();\n            \
+                     kernel::shen_shen();\n        \
+                 },\n        \
+                 |err| {\n            \
+                     eprintln!(\"{}\", error_to_string(&err));\n            \
+                     std::process::exit(1);\n        \
+                 },\n    \
+             )\n\
+         }\n"
+    };
+
+    let cargo_toml = format!(
+        "[package]\nname = {:?}\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [dependencies]\nshen_transpiler = {{ path = {:?} }}\n",
+        options.crate_name,
+        options.runtime_path.display().to_string()
+    );
+
+    Ok(GeneratedCrate {
+        files: vec![
+            (PathBuf::from("Cargo.toml"), cargo_toml),
+            (PathBuf::from("src/main.rs"), main_rs.to_string()),
+            (PathBuf::from("src/kernel.rs"), kernel_rs),
+        ],
+    })
+}
+
+/// A reference transcript the REPL did not reproduce.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixtureFailure {
+    pub name: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Run `program` once per fixture in `fixtures_dir`, feeding it `name.shen`
+/// on stdin and comparing its stdout with `name.expected`. The banner before
+/// the first `(0-)` prompt, which names the port and platform, and trailing
+/// whitespace on each line are ignored. Returns the fixtures whose output
+/// differed.
+pub fn check_fixtures(
+    program: impl AsRef<Path>,
+    fixtures_dir: impl AsRef<Path>,
+) -> Result<Vec<FixtureFailure>, TranspilerError> {
+    let fixtures_dir = fixtures_dir.as_ref();
+    let mut inputs = fs::read_dir(fixtures_dir)
+        .map_err(|err| io_error(fixtures_dir, err))?
+        .map(|entry| entry.map(|entry| entry.path()).map_err(|err| io_error(fixtures_dir, err)))
+        .collect::<Result<Vec<_>, _>>()?;
+    inputs.retain(|path| path.extension().is_some_and(|extension| extension == "shen"));
+    inputs.sort();
+
+    let mut failures = Vec::new();
+    for input in inputs {
+        let expected_path = input.with_extension("expected");
+        let expected = fs::read_to_string(&expected_path).map_err(|err| io_error(&expected_path, err))?;
+        let source = fs::read(&input).map_err(|err| io_error(&input, err))?;
+        let actual = run(program.as_ref(), &source)?;
+
+        if normalize(&actual) != normalize(&expected) {
+            failures.push(FixtureFailure {
+                name: input
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(failures)
+}
+
+fn run(program: &Path, input: &[u8]) -> Result<String, TranspilerError> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| io_error(program, err))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input)
+        .map_err(|err| io_error(program, err))?;
+    let output = child.wait_with_output().map_err(|err| io_error(program, err))?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn normalize(output: &str) -> String {
+    let transcript = output.find("(0-)").map_or(output, |start| &output[start..]);
+    transcript
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> TranspilerError {
+    TranspilerError::IoError {
+        path: path.display().to_string(),
+        message: err.to_string(),
+    }
+}
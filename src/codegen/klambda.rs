@@ -0,0 +1,552 @@
+//! Code generation for KLambda, over runtime values
+//!
+//! KLambda is untyped, so where typed Shen becomes plain Rust values, every
+//! KLambda value is a runtime `Value`. A `defun` becomes a function taking and
+//! returning `Value`s, primitives lower to the runtime functions over
+//! `Value`s, and a symbol that is not a bound variable stands for itself. The
+//! kernel is generated this way, and so are the rules of a `define` and the
+//! expressions of Prolog goals, whose variables are `Value`s too.
+
+use crate::ast::visit::Visitor;
+use crate::ast::{ShenNode, ShenValue, Symbol};
+use crate::error::TranspilerError;
+
+use super::{fallible, indent, rust_identifier, CodegenOptions, ErrorStrategy, Mentions};
+
+/// Generate Rust for a top-level KLambda form: a function for a `defun`, and
+/// an expression of type `Value` for anything else.
+pub fn generate_klambda_code(
+    node: &ShenNode,
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let mut generator = Generator {
+        options,
+        scope: Vec::new(),
+    };
+    match node {
+        ShenNode::Function { name, args, body, .. } => {
+            let params = args.iter().map(|(param, _)| *param).collect::<Vec<_>>();
+            let params_str = params
+                .iter()
+                .map(|param| format!("{}: Value", rust_identifier(param)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let body_str = generator.bound(&params, |generator| generator.expr(body))?;
+            match options.error_strategy {
+                ErrorStrategy::Unwind => Ok(format!(
+                    "fn {}({}) -> Value {{\n{}\n}}",
+                    rust_identifier(name),
+                    params_str,
+                    indent(&body_str)
+                )),
+                ErrorStrategy::Result => Ok(format!(
+                    "fn {}({}) -> ShenResult<Value> {{\n{}\n}}",
+                    rust_identifier(name),
+                    params_str,
+                    indent(&format!("Ok({})", body_str))
+                )),
+            }
+        }
+        node => generator.expr(node),
+    }
+}
+
+/// Generate an expression of type `Value` in which `variables` are bound
+/// `Value`s.
+pub(super) fn generate_value_expr(
+    node: &ShenNode,
+    variables: &[Symbol],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    Generator {
+        options,
+        scope: variables.to_vec(),
+    }
+    .expr(node)
+}
+
+/// Generate a Rust `bool` for a condition in which `variables` are bound
+/// `Value`s.
+pub(super) fn generate_value_condition(
+    node: &ShenNode,
+    variables: &[Symbol],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    Generator {
+        options,
+        scope: variables.to_vec(),
+    }
+    .condition(node)
+}
+
+/// What a runtime primitive returns, and so how its result becomes a `Value`.
+#[derive(Clone, Copy)]
+enum Returns {
+    /// `Value`
+    Value,
+    /// `ShenResult<Value>`
+    CheckedValue,
+    /// `bool`
+    Bool,
+    /// `ShenResult<bool>`
+    CheckedBool,
+    /// `ShenResult<usize>`
+    CheckedSize,
+}
+
+/// Primitives lowered to a runtime function: Shen name, runtime function, how
+/// each argument is passed (`r` by reference, `v` by value) and what the
+/// function returns.
+const PRIMITIVES: &[(&str, &str, &str, Returns)] = &[
+    ("+", "add", "rr", Returns::CheckedValue),
+    ("-", "subtract", "rr", Returns::CheckedValue),
+    ("*", "multiply", "rr", Returns::CheckedValue),
+    ("/", "divide", "rr", Returns::CheckedValue),
+    ("<", "less", "rr", Returns::CheckedValue),
+    (">", "greater", "rr", Returns::CheckedValue),
+    ("<=", "less_or_equal", "rr", Returns::CheckedValue),
+    (">=", "greater_or_equal", "rr", Returns::CheckedValue),
+    ("number?", "is_number", "r", Returns::Bool),
+    ("integer?", "is_integer", "r", Returns::Bool),
+    ("hd", "hd", "r", Returns::CheckedValue),
+    ("tl", "tl", "r", Returns::CheckedValue),
+    ("cons?", "is_cons", "r", Returns::Bool),
+    ("empty?", "is_empty", "r", Returns::Bool),
+    ("element?", "is_element", "rr", Returns::CheckedBool),
+    ("@p", "Value::tuple", "vv", Returns::Value),
+    ("fst", "fst", "r", Returns::CheckedValue),
+    ("snd", "snd", "r", Returns::CheckedValue),
+    ("tuple?", "is_tuple", "r", Returns::Bool),
+    ("thaw", "thaw_value", "r", Returns::CheckedValue),
+    ("error-to-string", "error_message", "r", Returns::CheckedValue),
+    ("pr", "pr", "rr", Returns::CheckedValue),
+    ("pos", "pos", "rv", Returns::CheckedValue),
+    ("tlstr", "tlstr", "r", Returns::CheckedValue),
+    ("cn", "cn", "rr", Returns::CheckedValue),
+    ("str", "str", "r", Returns::CheckedValue),
+    ("string->n", "string_to_n", "r", Returns::CheckedValue),
+    ("n->string", "n_to_string", "v", Returns::CheckedValue),
+    ("string?", "is_string", "r", Returns::Bool),
+    ("shen.app", "app", "rrr", Returns::CheckedValue),
+    ("absvector", "absvector", "v", Returns::CheckedValue),
+    ("<-address", "address_get", "rv", Returns::CheckedValue),
+    ("address->", "address_set", "rvv", Returns::CheckedValue),
+    ("absvector?", "is_absvector", "r", Returns::Bool),
+    ("vector", "vector", "v", Returns::CheckedValue),
+    ("<-vector", "vector_get", "rv", Returns::CheckedValue),
+    ("vector->", "vector_set", "rvv", Returns::CheckedValue),
+    ("vector?", "is_vector", "r", Returns::Bool),
+    ("limit", "limit", "r", Returns::CheckedSize),
+];
+
+struct Generator<'a> {
+    options: &'a CodegenOptions,
+    /// The variables bound around the expression being generated.
+    scope: Vec<Symbol>,
+}
+
+impl Generator<'_> {
+    fn expr(&mut self, node: &ShenNode) -> Result<String, TranspilerError> {
+        match node {
+            ShenNode::Symbol { name, .. } if self.scope.contains(name) => {
+                Ok(format!("{}.clone()", rust_identifier(name)))
+            }
+            ShenNode::Symbol { name, .. } => Ok(match name.as_str() {
+                "true" | "false" => format!("Value::Boolean({})", name),
+                name => format!("Value::symbol({:?})", name),
+            }),
+            ShenNode::Literal { value } => Ok(literal(value)),
+            ShenNode::Nil => Ok("Value::Nil".to_string()),
+            ShenNode::Error { message } => Err(TranspilerError::CodegenError(format!(
+                "cannot generate code for a form that failed to parse: {}",
+                message
+            ))),
+            ShenNode::Application { func, args } => self.application(func, args),
+            ShenNode::BinaryOperation { operator, left, right, .. } if *operator == "=" => {
+                Ok(format!("Value::from({} == {})", self.expr(left)?, self.expr(right)?))
+            }
+            ShenNode::BinaryOperation { operator, left, right, .. } => {
+                let args = [left.as_ref().clone(), right.as_ref().clone()];
+                self.primitive(operator, &args)?.ok_or_else(|| {
+                    TranspilerError::CodegenError(format!("unknown operator {}", operator))
+                })
+            }
+            ShenNode::Conditional { condition, true_branch, false_branch } => {
+                let false_str = match false_branch {
+                    Some(branch) => self.expr(branch)?,
+                    None => "Value::Nil".to_string(),
+                };
+                Ok(format!(
+                    "if {} {{\n{}\n}} else {{\n{}\n}}",
+                    self.condition(condition)?,
+                    indent(&self.expr(true_branch)?),
+                    indent(&false_str)
+                ))
+            }
+            ShenNode::Lambda { arg: (param, _), body, .. } => {
+                let body_str = self.bound(&[*param], |generator| generator.expr(body))?;
+                Ok(self.capturing(
+                    body,
+                    &[*param],
+                    format!(
+                        "Value::Function(Function::new(move |{}| Ok({})))",
+                        rust_identifier(param),
+                        body_str
+                    ),
+                ))
+            }
+            ShenNode::Freeze { body } => {
+                let body_str = self.expr(body)?;
+                let thunk = match self.options.error_strategy {
+                    ErrorStrategy::Unwind => body_str,
+                    // A frozen computation cannot return an error, so it raises it
+                    ErrorStrategy::Result => format!(
+                        "(|| -> ShenResult<Value> {{ Ok({}) }})().or_raise()",
+                        body_str
+                    ),
+                };
+                Ok(self.capturing(body, &[], format!("Value::Lazy(Lazy::new(move || {}))", thunk)))
+            }
+            ShenNode::TrapError { body, handler } => self.trap_error(body, handler),
+            ShenNode::Cons { head, tail } => {
+                Ok(format!("Value::cons({}, {})", self.expr(head)?, self.expr(tail)?))
+            }
+            ShenNode::List { elements, .. } => Ok(format!("Value::list(vec![{}])", self.exprs(elements)?)),
+            ShenNode::Vector { elements } => Ok(format!("Value::vector(vec![{}])", self.exprs(elements)?)),
+            ShenNode::Tuple { first, second } => {
+                Ok(format!("Value::tuple({}, {})", self.expr(first)?, self.expr(second)?))
+            }
+            ShenNode::VectorCons { head, tail } => Ok(fallible(
+                format!("vector_cons({}, {})", self.expr(head)?, self.reference(tail)?),
+                self.options,
+            )),
+            ShenNode::StringCons { head, tail } => Ok(fallible(
+                format!("cn({}, {})", self.reference(head)?, self.reference(tail)?),
+                self.options,
+            )),
+            ShenNode::Function { name, .. } => Err(TranspilerError::CodegenError(format!(
+                "defun {} must be at the top level",
+                name
+            ))),
+            ShenNode::PrologQuery { goals } => super::generate_prolog_query(goals, self.options),
+            ShenNode::Define { .. }
+            | ShenNode::Defmacro { .. }
+            | ShenNode::Defprolog { .. }
+            | ShenNode::Defcc { .. }
+            | ShenNode::Package { .. } => Err(TranspilerError::CodegenError(
+                "definitions must be at the top level".to_string(),
+            )),
+        }
+    }
+
+    fn exprs(&mut self, nodes: &[ShenNode]) -> Result<String, TranspilerError> {
+        Ok(nodes
+            .iter()
+            .map(|node| self.expr(node))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(", "))
+    }
+
+    /// An argument for a runtime function taking `&Value`.
+    fn reference(&mut self, node: &ShenNode) -> Result<String, TranspilerError> {
+        Ok(format!("&{}", self.operand(node)?))
+    }
+
+    /// An expression to borrow or call a method on: a bound variable as it
+    /// is, and blocks in parentheses.
+    fn operand(&mut self, node: &ShenNode) -> Result<String, TranspilerError> {
+        match node {
+            ShenNode::Symbol { name, .. } if self.scope.contains(name) => Ok(rust_identifier(name)),
+            node => Ok(parenthesize(self.expr(node)?)),
+        }
+    }
+
+    /// A Rust `bool` for the condition of `if` and `cond`.
+    fn condition(&mut self, node: &ShenNode) -> Result<String, TranspilerError> {
+        match node {
+            ShenNode::Symbol { name, .. } if !self.scope.contains(name) && *name == "true" => {
+                Ok("true".to_string())
+            }
+            ShenNode::BinaryOperation { operator, left, right, .. } if *operator == "=" => {
+                Ok(format!("{} == {}", self.expr(left)?, self.expr(right)?))
+            }
+            // Predicates that return a `bool` need no conversion
+            ShenNode::Application { func, args } => match func.as_ref() {
+                ShenNode::Symbol { name, .. } if !self.scope.contains(name) => {
+                    match self.primitive_call(name, args)? {
+                        Some((call, Returns::Bool)) => Ok(call),
+                        Some((call, Returns::CheckedBool)) => Ok(fallible(call, self.options)),
+                        _ => self.truth(node),
+                    }
+                }
+                _ => self.truth(node),
+            },
+            node => self.truth(node),
+        }
+    }
+
+    fn truth(&mut self, node: &ShenNode) -> Result<String, TranspilerError> {
+        Ok(fallible(format!("{}.as_bool()", self.operand(node)?), self.options))
+    }
+
+    fn application(&mut self, func: &ShenNode, args: &[ShenNode]) -> Result<String, TranspilerError> {
+        let name = match func {
+            ShenNode::Symbol { name, .. } if !self.scope.contains(name) => *name,
+            ShenNode::Lambda { arg: (param, _), body, .. } if args.len() == 1 => {
+                // ((lambda X Body) V) binds X to V directly
+                let value = self.expr(&args[0])?;
+                let body_str = self.bound(&[*param], |generator| generator.expr(body))?;
+                return Ok(format!(
+                    "{{\n    let {} = {};\n{}\n}}",
+                    rust_identifier(param),
+                    value,
+                    indent(&body_str)
+                ));
+            }
+            func => {
+                // A function computed at run time takes its arguments one at a time
+                let mut function = self.operand(func)?;
+                for arg in args {
+                    let call = format!("apply(&{}, {})", function, self.expr(arg)?);
+                    function = fallible(call, self.options);
+                }
+                return Ok(function);
+            }
+        };
+
+        match (name.as_str(), args) {
+            ("let", [.., _]) if args.len() % 2 == 1 => self.let_form(args),
+            ("do", [.., _]) => {
+                let mut statements = args
+                    .iter()
+                    .map(|arg| self.expr(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let last = statements.pop().unwrap_or_default();
+                let statements = statements
+                    .into_iter()
+                    .map(|statement| format!("{};\n", statement))
+                    .collect::<String>();
+                Ok(format!("{{\n{}\n}}", indent(&(statements + &last))))
+            }
+            ("and" | "or", [left, right]) => Ok(format!(
+                "Value::from({} {} {})",
+                self.condition(left)?,
+                if name == "and" { "&&" } else { "||" },
+                self.condition(right)?
+            )),
+            ("not", [value]) => Ok(format!("Value::from(!{})", self.condition(value)?)),
+            ("set" | "value" | "bound?", _) => self.global_access(name.as_str(), args),
+            ("stoutput" | "stinput" | "sterror", []) => Ok(super::global_access(
+                "value",
+                format!("\"*{}*\"", name),
+                None,
+                self.options,
+            )),
+            ("fail", []) => Ok("Value::fail()".to_string()),
+            ("simple-error", [message]) => {
+                let error = match message {
+                    ShenNode::Literal { value: ShenValue::String(message) } => {
+                        format!("simple_error({:?})", message)
+                    }
+                    message => format!("simple_error_from({})", self.reference(message)?),
+                };
+                match self.options.error_strategy {
+                    ErrorStrategy::Unwind => Ok(format!("raise({})", error)),
+                    ErrorStrategy::Result => Ok(format!("Err::<Value, _>({})?", error)),
+                }
+            }
+            (name, args) => match self.primitive(name, args)? {
+                Some(call) => Ok(call),
+                None => {
+                    let call = format!("{}({})", rust_identifier(name), self.exprs(args)?);
+                    match self.options.error_strategy {
+                        ErrorStrategy::Unwind => Ok(call),
+                        ErrorStrategy::Result => Ok(format!("{}?", call)),
+                    }
+                }
+            },
+        }
+    }
+
+    /// `(let X V Body)`, as a block binding `X` to `V`.
+    fn let_form(&mut self, args: &[ShenNode]) -> Result<String, TranspilerError> {
+        let (body, bindings) = args.split_last().expect("let has a body");
+        let mut statements = String::new();
+        let mut variables = Vec::new();
+        for binding in bindings.chunks(2) {
+            let variable = match &binding[0] {
+                ShenNode::Symbol { name, .. } => *name,
+                _ => {
+                    return Err(TranspilerError::CodegenError(
+                        "let expects a variable to bind".to_string(),
+                    ))
+                }
+            };
+            let value = self.bound(&variables, |generator| generator.expr(&binding[1]))?;
+            statements.push_str(&format!("let {} = {};\n", rust_identifier(&variable), value));
+            variables.push(variable);
+        }
+        let body_str = self.bound(&variables, |generator| generator.expr(body))?;
+        Ok(format!("{{\n{}\n}}", indent(&(statements + &body_str))))
+    }
+
+    /// `(set Name V)`, `(value Name)` and `(bound? Name)`.
+    fn global_access(&mut self, operation: &str, args: &[ShenNode]) -> Result<String, TranspilerError> {
+        let name = match args.first() {
+            Some(ShenNode::Symbol { name, .. }) if !self.scope.contains(name) => format!("{:?}", name),
+            Some(name) => format!("&{}.to_string()", self.expr(name)?),
+            None => {
+                return Err(TranspilerError::CodegenError(format!(
+                    "'{}' expects a global variable name",
+                    operation
+                )))
+            }
+        };
+        let value = match (operation, &args[1..]) {
+            ("set", [value]) => Some(self.expr(value)?),
+            ("value" | "bound?", []) => None,
+            _ => {
+                return Err(TranspilerError::CodegenError(format!(
+                    "Wrong number of arguments to '{}'",
+                    operation
+                )))
+            }
+        };
+        let access = super::global_access(operation, name, value, self.options);
+        match operation {
+            "bound?" => Ok(format!("Value::from({})", access)),
+            _ => Ok(access),
+        }
+    }
+
+    /// Lower a call to a runtime primitive, or `None` if `name` is not one.
+    fn primitive(&mut self, name: &str, args: &[ShenNode]) -> Result<Option<String>, TranspilerError> {
+        let Some((call, returns)) = self.primitive_call(name, args)? else {
+            return Ok(None);
+        };
+        Ok(Some(match returns {
+            Returns::Value => call,
+            Returns::CheckedValue => fallible(call, self.options),
+            Returns::Bool => format!("Value::from({})", call),
+            Returns::CheckedBool => format!("Value::from({})", fallible(call, self.options)),
+            Returns::CheckedSize => format!("Value::from({} as i64)", fallible(call, self.options)),
+        }))
+    }
+
+    /// The call to the runtime function behind a primitive, and what it
+    /// returns.
+    fn primitive_call(
+        &mut self,
+        name: &str,
+        args: &[ShenNode],
+    ) -> Result<Option<(String, Returns)>, TranspilerError> {
+        let Some((_, function, passing, returns)) = PRIMITIVES.iter().find(|(shen, ..)| *shen == name)
+        else {
+            return Ok(None);
+        };
+        if passing.len() != args.len() {
+            return Err(TranspilerError::CodegenError(format!(
+                "{} expects {} argument(s)",
+                name,
+                passing.len()
+            )));
+        }
+
+        let args_str = passing
+            .chars()
+            .zip(args)
+            .map(|(passing, arg)| match passing {
+                'r' => self.reference(arg),
+                _ => self.expr(arg),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .join(", ");
+        Ok(Some((format!("{}({})", function, args_str), *returns)))
+    }
+
+    /// `(trap-error Body Handler)`. A handler written as a lambda runs inline
+    /// with its variable bound to the error.
+    fn trap_error(&mut self, body: &ShenNode, handler: &ShenNode) -> Result<String, TranspilerError> {
+        let body_str = self.expr(body)?;
+        let handler_str = match handler {
+            ShenNode::Lambda { arg: (param, _), body, .. } => {
+                let handler_body = self.bound(&[*param], |generator| generator.expr(body))?;
+                let handler_body = match self.options.error_strategy {
+                    ErrorStrategy::Unwind => handler_body,
+                    ErrorStrategy::Result => format!("Ok({})", handler_body),
+                };
+                format!(
+                    "{{\n    let {} = Value::Error(error);\n{}\n}}",
+                    rust_identifier(param),
+                    indent(&handler_body)
+                )
+            }
+            handler => {
+                let call = format!("apply({}, Value::Error(error))", self.reference(handler)?);
+                match self.options.error_strategy {
+                    ErrorStrategy::Unwind => format!("{}.or_raise()", call),
+                    ErrorStrategy::Result => call,
+                }
+            }
+        };
+        match self.options.error_strategy {
+            ErrorStrategy::Unwind => Ok(format!("trap_error(|| {}, |error| {})", body_str, handler_str)),
+            ErrorStrategy::Result => Ok(format!(
+                "trap_error_result(|| -> ShenResult<Value> {{ Ok({}) }}, |error| -> ShenResult<Value> {})?",
+                body_str,
+                match handler {
+                    ShenNode::Lambda { .. } => handler_str,
+                    _ => format!("{{ {} }}", handler_str),
+                }
+            )),
+        }
+    }
+
+    /// Wrap a `move` closure so that it captures copies of the bound variables
+    /// its body mentions, leaving the originals usable.
+    fn capturing(&self, body: &ShenNode, params: &[Symbol], closure: String) -> String {
+        let mut mentioned = Mentions(Vec::new());
+        mentioned.visit_node(body);
+        let mut captures = Vec::new();
+        for name in mentioned.0 {
+            if self.scope.contains(&name) && !params.contains(&name) && !captures.contains(&name) {
+                captures.push(name);
+            }
+        }
+        if captures.is_empty() {
+            return closure;
+        }
+        let clones = captures
+            .iter()
+            .map(|name| format!("let {0} = {0}.clone();\n", rust_identifier(name)))
+            .collect::<String>();
+        format!("{{\n{}\n}}", indent(&(clones + &closure)))
+    }
+
+    /// Run `generate` with `variables` bound.
+    fn bound<T>(&mut self, variables: &[Symbol], generate: impl FnOnce(&mut Self) -> T) -> T {
+        let depth = self.scope.len();
+        self.scope.extend_from_slice(variables);
+        let generated = generate(self);
+        self.scope.truncate(depth);
+        generated
+    }
+}
+
+pub(super) fn literal(value: &ShenValue) -> String {
+    match value {
+        ShenValue::Integer(value) => format!("Value::Integer({})", value),
+        ShenValue::Float(value) => format!("Value::Float({:?})", value),
+        ShenValue::String(value) => format!("Value::from({:?})", value),
+        ShenValue::Boolean(value) => format!("Value::Boolean({})", value),
+        ShenValue::Nil => "Value::Nil".to_string(),
+    }
+}
+
+fn parenthesize(expr: String) -> String {
+    if expr.starts_with('{') || expr.starts_with("if ") {
+        format!("({})", expr)
+    } else {
+        expr
+    }
+}
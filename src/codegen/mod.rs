@@ -1,93 +1,1137 @@
 //! Code generation from AST to Rust
 
-use quote::quote;
-use syn;
+use std::collections::HashMap;
 
-use crate::ast::ShenNode;
+use crate::ast::visit::{self, Visitor};
+use crate::ast::visit_mut::{self, VisitorMut};
+use crate::ast::{Pattern, PrologClause, Rule, ShenNode, ShenType, ShenValue, Symbol, YaccItem, YaccRule};
 use crate::error::TranspilerError;
 
+mod klambda;
+
+pub use klambda::generate_klambda_code;
+
+/// How generated code raises and catches Shen errors (`simple-error`,
+/// `trap-error`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorStrategy {
+    /// Errors unwind the stack and `trap-error` catches them; function
+    /// signatures are left untouched.
+    #[default]
+    Unwind,
+    /// Every function returns `ShenResult` and calls propagate errors with `?`.
+    Result,
+}
+
+/// Which global environment `set`, `value` and `bound?` operate on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlobalsMode {
+    /// One environment shared by every thread, behind `globals()`.
+    #[default]
+    Shared,
+    /// A separate environment per thread, behind `with_thread_globals`.
+    ThreadLocal,
+}
+
+/// Options controlling the shape of generated Rust code.
+#[derive(Debug, Clone, Default)]
+pub struct CodegenOptions {
+    pub error_strategy: ErrorStrategy,
+    pub globals: GlobalsMode,
+}
+
 pub fn generate_rust_code(node: &ShenNode) -> Result<String, TranspilerError> {
+    generate_rust_code_with_options(node, &CodegenOptions::default())
+}
+
+pub fn generate_rust_code_with_options(
+    node: &ShenNode,
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let mut generator = RustGenerator {
+        options,
+        code: Vec::new(),
+        error: None,
+    };
+    generator.visit_node(node);
+    match generator.error {
+        Some(error) => Err(error),
+        None => Ok(generator.code.pop().expect("every node generates code")),
+    }
+}
+
+/// Generates Rust for a tree. Most nodes are generated from the code of
+/// their children, which [`visit::walk_node`] generates in order; the rest,
+/// whose children need more than their code, are generated whole.
+struct RustGenerator<'o> {
+    options: &'o CodegenOptions,
+    /// The code of the nodes generated so far, innermost last
+    code: Vec<String>,
+    /// The first error, after which nothing more is generated
+    error: Option<TranspilerError>,
+}
+
+impl Visitor for RustGenerator<'_> {
+    fn visit_node(&mut self, node: &ShenNode) {
+        if self.error.is_some() {
+            return;
+        }
+        let code = match self.generate_whole(node) {
+            Some(code) => code,
+            None => {
+                let depth = self.code.len();
+                visit::walk_node(self, node);
+                if self.error.is_some() {
+                    return;
+                }
+                let children = self.code.split_off(depth);
+                self.combine(node, children)
+            }
+        };
+        match code {
+            Ok(code) => self.code.push(code),
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+impl RustGenerator<'_> {
+    /// The code for `node` when it is not made from the code of its
+    /// children: leaves, definitions, and forms whose helpers look at their
+    /// arguments' types or shapes.
+    fn generate_whole(&self, node: &ShenNode) -> Option<Result<String, TranspilerError>> {
+        let options = self.options;
+        Some(match node {
+            ShenNode::Symbol { name, .. } => Ok(rust_identifier(name)),
+            ShenNode::Literal { value } => Ok(generate_literal(value)),
+            ShenNode::Nil => Ok("None".to_string()),
+            ShenNode::Error { message } => Err(TranspilerError::CodegenError(format!(
+                "cannot generate code for a form that failed to parse: {}",
+                message
+            ))),
+            ShenNode::Define { name, rules } => generate_define(name, rules, options),
+            ShenNode::Defprolog { name, clauses } => generate_defprolog(name, clauses, options),
+            ShenNode::Defcc { name, rules } => generate_defcc(name, rules, options),
+            ShenNode::Package { name, exports, body } => generate_package(name, exports, body, options),
+            ShenNode::Defmacro { name, .. } => Err(TranspilerError::CodegenError(format!(
+                "macro {} must be expanded with expand_program before code generation",
+                name
+            ))),
+            ShenNode::PrologQuery { goals } => generate_prolog_query(goals, options),
+            ShenNode::BinaryOperation { operator, left, right, .. } => {
+                generate_binary_operation(operator, left, right, options)
+            },
+            ShenNode::StringCons { head, tail }
+                if head.get_type() != ShenType::String || tail.get_type() != ShenType::String =>
+            {
+                generate_value_ref(head, options)
+                    .and_then(|head| Ok((head, generate_value_ref(tail, options)?)))
+                    .map(|(head, tail)| fallible(format!("cn({}, {})", head, tail), options))
+            },
+            ShenNode::Application { func, args } => {
+                let shen_name = match func.as_ref() {
+                    ShenNode::Symbol { name, .. } => name.as_str(),
+                    _ => return None,
+                };
+                match shen_name {
+                    "set" | "value" | "bound?" => generate_global_access(shen_name, args, options),
+                    "compile" => match args.as_slice() {
+                        [ShenNode::Symbol { name, .. }, input] if !is_variable(name) => {
+                            generate_value_ref(input, options).map(|input| {
+                                fallible(format!("compile({}, {})", rust_identifier(name), input), options)
+                            })
+                        },
+                        _ => Err(TranspilerError::CodegenError(
+                            "compile expects a grammar name and an input list".to_string(),
+                        )),
+                    },
+                    "fst" | "snd" | "tuple?" => generate_tuple_access(shen_name, args, options),
+                    name if VECTOR_PRIMITIVES.iter().any(|(shen, ..)| *shen == name) => {
+                        generate_vector_primitive(name, args, options)
+                    },
+                    name if STRING_PRIMITIVES.iter().any(|(shen, ..)| *shen == name) => {
+                        generate_string_primitive(name, args, options)
+                    },
+                    _ => return None,
+                }
+            },
+            _ => return None,
+        })
+    }
+
+    /// The code for `node` from the code of its children, in the order
+    /// [`visit::walk_node`] visits them.
+    fn combine(&self, node: &ShenNode, children: Vec<String>) -> Result<String, TranspilerError> {
+        let uses_result = self.options.error_strategy == ErrorStrategy::Result;
+        let mut children = children.into_iter();
+        let mut next = || children.next().expect("a child was generated");
+
+        match node {
+            ShenNode::Function { name, args, .. } => {
+                let args_str = args.iter()
+                    .map(|(name, _type)| format!("{}: impl Clone", rust_identifier(name)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let body_str = next();
+                if uses_result {
+                    Ok(format!("fn {}({}) -> ShenResult<impl Clone> {{\n    Ok({})\n}}",
+                        rust_identifier(name), args_str, body_str))
+                } else {
+                    Ok(format!("fn {}({}) -> impl Clone {{\n    {}\n}}",
+                        rust_identifier(name), args_str, body_str))
+                }
+            },
+            ShenNode::Application { func, args } => {
+                let func_str = next();
+                let arg_strs = args.iter().map(|_| next()).collect::<Vec<_>>();
+                let args_str = arg_strs.join(", ");
+                let propagate = if uses_result { "?" } else { "" };
+
+                // Special handling for common Shen functions and special forms
+                let shen_name = match func.as_ref() {
+                    ShenNode::Symbol { name, .. } => name.as_str(),
+                    _ => "",
+                };
+                match shen_name {
+                    "length" => Ok(format!("{}.len()", args_str)),
+                    "first" => Ok(format!("{}.first()", args_str)),
+                    "thaw" => Ok(format!("{}.thaw()", args_str)),
+                    "simple-error" if uses_result => {
+                        Ok(format!("return Err(simple_error({}))", args_str))
+                    },
+                    "simple-error" => Ok(format!("raise(simple_error({}))", args_str)),
+                    "error-to-string" => Ok(format!("error_to_string(&{})", args_str)),
+                    "if" => {
+                        // Handle if as a ternary-like operation
+                        match arg_strs.as_slice() {
+                            [condition, true_branch, false_branch] => Ok(format!(
+                                "if {} {{ {} }} else {{ {} }}",
+                                condition, true_branch, false_branch
+                            )),
+                            _ => Err(TranspilerError::CodegenError("Invalid 'if' expression".to_string())),
+                        }
+                    },
+                    "let" if args.len() % 2 == 1 => {
+                        let (body, bindings) = arg_strs.split_last().expect("let has a body");
+                        let mut statements = String::new();
+                        for binding in bindings.chunks(2) {
+                            statements.push_str(&format!("let {} = {};\n", binding[0], binding[1]));
+                        }
+                        Ok(format!("{{\n{}\n}}", indent(&(statements + body))))
+                    },
+                    "do" if !args.is_empty() => Ok(format!("{{\n{}\n}}", indent(&arg_strs.join(";\n")))),
+                    // A lambda applied in place is called in parentheses
+                    _ if matches!(func.as_ref(), ShenNode::Lambda { .. }) => {
+                        Ok(format!("({})({}){}", func_str, args_str, propagate))
+                    },
+                    _ => Ok(format!("{}({}){}", func_str, args_str, propagate)),
+                }
+            },
+            ShenNode::Conditional { false_branch, .. } => {
+                let condition_str = next();
+                let true_str = next();
+                let false_str = match false_branch {
+                    Some(_) => next(),
+                    None => "None".to_string(),
+                };
+
+                Ok(format!("if {} {{\n        {}\n    }} else {{\n        {}\n    }}",
+                    condition_str,
+                    true_str,
+                    false_str
+                ))
+            },
+            ShenNode::Lambda { arg: (name, _type), body, .. } => {
+                let name = rust_identifier(name);
+                let body_str = next();
+                // Inner lambdas of a curried chain capture the outer arguments
+                let capture = if matches!(**body, ShenNode::Lambda { .. }) { "move " } else { "" };
+                if uses_result {
+                    Ok(format!("|{}| -> ShenResult<_> {{ Ok({}{}) }}", name, capture, body_str))
+                } else {
+                    Ok(format!("|{}| {{ {}{} }}", name, capture, body_str))
+                }
+            },
+            ShenNode::Tuple { .. } => {
+                let first_str = next();
+                let second_str = next();
+                // Tuples of statically known types become native Rust tuples
+                if node.get_type().is_concrete() {
+                    Ok(format!("({}, {})", first_str, second_str))
+                } else {
+                    Ok(format!("Value::tuple({}, {})", first_str, second_str))
+                }
+            },
+            ShenNode::Vector { elements } => {
+                let elements_str = elements.iter()
+                    .map(|_| format!("Value::from({})", next()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Ok(format!("Value::vector(vec![{}])", elements_str))
+            },
+            ShenNode::VectorCons { .. } => {
+                let call = format!("vector_cons({}, &{})", next(), next());
+                Ok(fallible(call, self.options))
+            },
+            // Strings on both sides; otherwise generated whole
+            ShenNode::StringCons { .. } => Ok(format!("format!(\"{{}}{{}}\", {}, {})", next(), next())),
+            ShenNode::TrapError { .. } => {
+                let body_str = next();
+                let handler_str = next();
+                if uses_result {
+                    Ok(format!("trap_error_result(|| -> ShenResult<_> {{ Ok({}) }}, {})?",
+                        body_str, handler_str))
+                } else {
+                    Ok(format!("trap_error(|| {{ {} }}, {})", body_str, handler_str))
+                }
+            },
+            ShenNode::Freeze { .. } => Ok(format!("Lazy::new(move || {{ {} }})", next())),
+            ShenNode::List { elements, .. } => {
+                let elements_str = elements.iter().map(|_| next()).collect::<Vec<_>>().join(", ");
+                Ok(format!("vec![{}]", elements_str))
+            },
+            ShenNode::Cons { .. } => Ok(format!(
+                "Value::cons(Value::from({}), Value::from({}))",
+                next(),
+                next()
+            )),
+            _ => unreachable!("generate_whole generates every other node"),
+        }
+    }
+}
+
+fn generate_binary_operation(
+    operator: &Symbol,
+    left: &ShenNode,
+    right: &ShenNode,
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let left_str = generate_operand(left, right, options)?;
+    let right_str = generate_operand(right, left, options)?;
+
+    // Map Shen operators to Rust equivalents
+    let rust_op = match operator.as_str() {
+        "=" => "==",
+        "<" => "<",
+        ">" => ">",
+        "-" => "-",
+        "+" => "+",
+        operator => operator,
+    };
+
+    Ok(format!("{} {} {}", left_str, rust_op, right_str))
+}
+
+/// Lower `(set Name Value)`, `(value Name)` and `(bound? Name)` to calls on the
+/// runtime global environment selected by `options.globals`.
+fn generate_global_access(
+    operation: &str,
+    args: &[ShenNode],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let name = match args.first() {
+        Some(ShenNode::Symbol { name, .. }) if !is_variable(name) => format!("{:?}", name),
+        Some(name) => format!("&{}.to_string()", generate_rust_code_with_options(name, options)?),
+        None => {
+            return Err(TranspilerError::CodegenError(format!(
+                "'{}' expects a global variable name",
+                operation
+            )))
+        }
+    };
+
+    let value = match (operation, &args[1..]) {
+        ("set", [value]) => Some(generate_rust_code_with_options(value, options)?),
+        ("value" | "bound?", []) => None,
+        _ => {
+            return Err(TranspilerError::CodegenError(format!(
+                "Wrong number of arguments to '{}'",
+                operation
+            )))
+        }
+    };
+    Ok(global_access(operation, name, value, options))
+}
+
+/// The call behind `set`, `value` or `bound?` of the global `name`.
+fn global_access(operation: &str, name: String, value: Option<String>, options: &CodegenOptions) -> String {
+    let call = match (operation, value) {
+        ("set", Some(value)) => format!("set({}, {})", name, value),
+        ("value", _) => format!("value({})", name),
+        _ => format!("is_bound({})", name),
+    };
+
+    let access = match options.globals {
+        GlobalsMode::Shared => format!("globals().{}", call),
+        GlobalsMode::ThreadLocal => format!("with_thread_globals(|globals| globals.{})", call),
+    };
+
+    // Only `value` can fail, on an unbound variable
+    match operation {
+        "value" => fallible(access, options),
+        _ => access,
+    }
+}
+
+/// Lower `fst`, `snd` and `tuple?`, using field access when the argument is
+/// known to be a native Rust tuple.
+fn generate_tuple_access(
+    operation: &str,
+    args: &[ShenNode],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let tuple = match args {
+        [tuple] => tuple,
+        _ => {
+            return Err(TranspilerError::CodegenError(format!(
+                "'{}' expects one argument",
+                operation
+            )))
+        }
+    };
+    let tuple_str = generate_rust_code_with_options(tuple, options)?;
+    let is_native = matches!(tuple.get_type(), ShenType::Tuple(..)) && tuple.get_type().is_concrete();
+
+    match (operation, is_native) {
+        ("fst", true) => Ok(format!("{}.0", tuple_str)),
+        ("snd", true) => Ok(format!("{}.1", tuple_str)),
+        ("tuple?", true) => Ok("true".to_string()),
+        ("tuple?", false) => Ok(format!("is_tuple(&{})", tuple_str)),
+        (_, false) => Ok(fallible(format!("{}(&{})", operation, tuple_str), options)),
+        _ => unreachable!("unexpected tuple operation {}", operation),
+    }
+}
+
+/// Vector and absvector primitives: Shen name, runtime function, and whether
+/// the call can raise an error.
+const VECTOR_PRIMITIVES: &[(&str, &str, bool)] = &[
+    ("vector", "vector", true),
+    ("<-vector", "vector_get", true),
+    ("vector->", "vector_set", true),
+    ("limit", "limit", true),
+    ("vector?", "is_vector", false),
+    ("absvector", "absvector", true),
+    ("<-address", "address_get", true),
+    ("address->", "address_set", true),
+    ("absvector?", "is_absvector", false),
+];
+
+/// Lower a vector primitive to its runtime function. The vector argument is
+/// passed by reference; constructors take only a size.
+fn generate_vector_primitive(
+    name: &str,
+    args: &[ShenNode],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let (_, function, can_fail) = VECTOR_PRIMITIVES
+        .iter()
+        .find(|(shen, ..)| *shen == name)
+        .ok_or_else(|| TranspilerError::InternalError(format!("Unknown vector primitive {}", name)))?;
+
+    let args_str = args
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let arg_str = generate_rust_code_with_options(arg, options)?;
+            if i == 0 && !matches!(name, "vector" | "absvector") {
+                Ok(format!("&{}", arg_str))
+            } else {
+                Ok(arg_str)
+            }
+        })
+        .collect::<Result<Vec<_>, TranspilerError>>()?
+        .join(", ");
+
+    let call = format!("{}({})", function, args_str);
+    if *can_fail {
+        Ok(fallible(call, options))
+    } else {
+        Ok(call)
+    }
+}
+
+/// String primitives: Shen name, runtime function, and whether the call can
+/// raise an error.
+const STRING_PRIMITIVES: &[(&str, &str, bool)] = &[
+    ("pos", "pos", true),
+    ("tlstr", "tlstr", true),
+    ("cn", "cn", true),
+    ("str", "str", true),
+    ("string->n", "string_to_n", true),
+    ("n->string", "n_to_string", true),
+    ("string?", "is_string", false),
+    ("shen.app", "app", true),
+];
+
+/// Lower a string primitive to its runtime function. String arguments are
+/// passed as `&Value`; the numeric arguments of `pos` and `n->string` by value.
+fn generate_string_primitive(
+    name: &str,
+    args: &[ShenNode],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let (_, function, can_fail) = STRING_PRIMITIVES
+        .iter()
+        .find(|(shen, ..)| *shen == name)
+        .ok_or_else(|| TranspilerError::InternalError(format!("Unknown string primitive {}", name)))?;
+
+    let args_str = args
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| match (name, i) {
+            ("n->string", _) | ("pos", 1) => generate_rust_code_with_options(arg, options),
+            _ => generate_value_ref(arg, options),
+        })
+        .collect::<Result<Vec<_>, TranspilerError>>()?
+        .join(", ");
+
+    let call = format!("{}({})", function, args_str);
+    if *can_fail {
+        Ok(fallible(call, options))
+    } else {
+        Ok(call)
+    }
+}
+
+/// Generate an argument for a runtime function taking `&Value`. Variables are
+/// already `Value`s and other symbols stand for themselves; anything else is
+/// converted first.
+fn generate_value_ref(node: &ShenNode, options: &CodegenOptions) -> Result<String, TranspilerError> {
     match node {
-        ShenNode::Function { name, args, body, return_type } => {
-            let args_str = args.iter()
-                .map(|(name, _type)| format!("{}: impl Clone", name))
-                .collect::<Vec<_>>()
-                .join(", ");
-            let body_str = generate_rust_code(body)?;
-            Ok(format!("fn {}({}) -> impl Clone {{\n    {}\n}}", name, args_str, body_str))
-        },
-        ShenNode::Symbol { name, type_hint } => Ok(name.clone()),
-        ShenNode::Nil => Ok("None".to_string()),
-        ShenNode::Application { func, args } => {
-            let func_str = generate_rust_code(func)?;
-            let args_str = args.iter()
-                .map(|arg| generate_rust_code(arg).unwrap_or_default())
-                .collect::<Vec<_>>()
-                .join(", ");
-            
-            // Special handling for common Shen functions and special forms
-            match func_str.as_str() {
-                "length" => Ok(format!("{}.len()", args_str)),
-                "first" => Ok(format!("{}.first()", args_str)),
-                "if" => {
-                    // Handle if as a ternary-like operation
-                    if args.len() == 3 {
-                        let condition = generate_rust_code(&args[0])?;
-                        let true_branch = generate_rust_code(&args[1])?;
-                        let false_branch = generate_rust_code(&args[2])?;
-                        Ok(format!("if {} {{ {} }} else {{ {} }}", condition, true_branch, false_branch))
-                    } else {
-                        Err(TranspilerError::CodegenError("Invalid 'if' expression".to_string()))
-                    }
-                },
-                _ => Ok(format!("{}({})", func_str, args_str)),
+        ShenNode::Symbol { name, .. } if is_variable(name) => Ok(format!("&{}", rust_identifier(name))),
+        ShenNode::Symbol { name, .. } => Ok(format!("&Value::symbol({:?})", name)),
+        _ => Ok(format!(
+            "&Value::from({})",
+            generate_rust_code_with_options(node, options)?
+        )),
+    }
+}
+
+/// Append the error handling for a runtime call returning `ShenResult`.
+fn fallible(call: String, options: &CodegenOptions) -> String {
+    match options.error_strategy {
+        ErrorStrategy::Result => format!("{}?", call),
+        ErrorStrategy::Unwind => format!("{}.or_raise()", call),
+    }
+}
+
+/// Lower a `define` to a function over runtime `Value`s that tries each rule
+/// in order and raises an error when none matches. Rule bodies and guards are
+/// generated over `Value`s too, with the pattern variables bound.
+fn generate_define(
+    name: &str,
+    rules: &[Rule],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let uses_result = options.error_strategy == ErrorStrategy::Result;
+    let arity = rules.first().map(|rule| rule.patterns.len()).unwrap_or(0);
+    let params = (1..=arity).map(|i| format!("V{}", i)).collect::<Vec<_>>();
+
+    let mut body = String::new();
+    for rule in rules {
+        let mut variables = PatternVariables(Vec::new());
+        rule.patterns.iter().for_each(|pattern| variables.visit_pattern(pattern));
+        let variables = variables.0;
+
+        let result = klambda::generate_value_expr(&rule.body, &variables, options)?;
+        let mut code = if uses_result {
+            format!("return Ok({});", result)
+        } else {
+            format!("return {};", result)
+        };
+        if let Some(guard) = &rule.guard {
+            let guard_str = klambda::generate_value_condition(guard, &variables, options)?;
+            code = format!("if {} {{\n{}\n}}", guard_str, indent(&code));
+        }
+        for (pattern, param) in rule.patterns.iter().zip(&params).rev() {
+            code = generate_pattern(pattern, param, code);
+        }
+        body.push_str(&indent(&code));
+        body.push('\n');
+    }
+
+    let failure = format!("simple_error({:?})", format!("partial function {}", name));
+    let (return_type, fallthrough) = if uses_result {
+        ("ShenResult<Value>", format!("Err({})", failure))
+    } else {
+        ("Value", format!("raise({})", failure))
+    };
+    let params_str = params
+        .iter()
+        .map(|param| format!("{}: Value", param))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(format!(
+        "fn {}({}) -> {} {{\n{}    {}\n}}",
+        rust_identifier(name),
+        params_str,
+        return_type,
+        body,
+        fallthrough
+    ))
+}
+
+/// Lower a `package` to a Rust module. Exported functions are `pub` and
+/// re-exported from the enclosing module, as exported Shen symbols are
+/// global; everything else stays private to the module. The package `null`
+/// has no module of its own.
+fn generate_package(
+    name: &str,
+    exports: &[Symbol],
+    body: &[ShenNode],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    if name == "null" {
+        return Ok(body
+            .iter()
+            .map(|node| generate_rust_code_with_options(node, options))
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n\n"));
+    }
+
+    // Inside the module, the package's own functions drop the prefix
+    let prefix = format!("{}.", name);
+    let internal = body
+        .iter()
+        .filter_map(definition_name)
+        .filter(|item| item.starts_with(&prefix))
+        .collect::<Vec<_>>();
+
+    let mut items = Vec::new();
+    let mut public = Vec::new();
+    for node in body {
+        let mut node = node.clone();
+        StripPrefix { prefix: &prefix, internal: &internal }.visit_node_mut(&mut node);
+        let code = generate_rust_code_with_options(&node, options)?;
+        match definition_name(&node) {
+            Some(item) if exports.contains(&item) => {
+                public.push(rust_identifier(&item));
+                items.push(format!("pub {}", code));
             }
-        },
-        ShenNode::BinaryOperation { operator, left, right, result_type } => {
-            let left_str = generate_rust_code(left)?;
-            let right_str = generate_rust_code(right)?;
-            
-            // Map Shen operators to Rust equivalents
-            let rust_op = match operator.as_str() {
-                "=" => "==",
-                "<" => "<",
-                ">" => ">",
-                "-" => "-",
-                "+" => "+",
-                _ => operator,
+            _ => items.push(code),
+        }
+    }
+
+    let module = rust_identifier(name);
+    let items = items.iter().map(|item| indent(item)).collect::<Vec<_>>();
+    let mut code = format!("mod {} {{\n    use super::*;\n\n{}\n}}", module, items.join("\n\n"));
+    if !public.is_empty() {
+        code.push_str(&format!("\n\npub use {}::{{{}}};", module, public.join(", ")));
+    }
+    Ok(code)
+}
+
+/// The name of the Rust function a top-level form defines, if any.
+fn definition_name(node: &ShenNode) -> Option<Symbol> {
+    match node {
+        ShenNode::Define { name, .. } | ShenNode::Function { name, .. } | ShenNode::Defcc { name, .. } => {
+            Some(*name)
+        }
+        _ => None,
+    }
+}
+
+/// Renames the `internal` functions of a package, and references to them,
+/// to their names without `prefix`.
+struct StripPrefix<'a> {
+    prefix: &'a str,
+    internal: &'a [Symbol],
+}
+
+impl StripPrefix<'_> {
+    fn local(&self, name: &mut Symbol) {
+        if self.internal.contains(name) {
+            *name = Symbol::intern(&name[self.prefix.len()..]);
+        }
+    }
+}
+
+impl VisitorMut for StripPrefix<'_> {
+    fn visit_node_mut(&mut self, node: &mut ShenNode) {
+        match node {
+            ShenNode::Symbol { name, .. }
+            | ShenNode::Define { name, .. }
+            | ShenNode::Function { name, .. }
+            | ShenNode::Defcc { name, .. } => self.local(name),
+            _ => {}
+        }
+        visit_mut::walk_node_mut(self, node)
+    }
+
+    fn visit_yacc_item_mut(&mut self, item: &mut YaccItem) {
+        if let YaccItem::NonTerminal(name) = item {
+            self.local(name)
+        }
+    }
+}
+
+/// Lower a `defcc` to a recursive-descent function over the runtime list
+/// value; see [`crate::runtime::yacc`].
+fn generate_defcc(
+    name: &str,
+    rules: &[YaccRule],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let mut body = String::new();
+    for rule in rules {
+        let streams = (0..=rule.items.len())
+            .map(|i| if i == 0 { "input".to_string() } else { format!("input_{}", i) })
+            .collect::<Vec<_>>();
+        let rest = &streams[rule.items.len()];
+
+        let result = match &rule.action {
+            Some(action) => format!(
+                "Value::from({})",
+                generate_rust_code_with_options(action, options)?
+            ),
+            None => format!("yacc_consumed(input, &{})", rest),
+        };
+        let rest = if rule.items.is_empty() { format!("{}.clone()", rest) } else { rest.clone() };
+        let mut code = format!("return Ok(Some(({}, {})));", rest, result);
+        if let Some(guard) = &rule.guard {
+            let guard_str = generate_rust_code_with_options(guard, options)?;
+            code = format!("if {} {{\n{}\n}}", guard_str, indent(&code));
+        }
+
+        for (i, item) in rule.items.iter().enumerate().rev() {
+            let (input, output) = (&streams[i], &streams[i + 1]);
+            code = match item {
+                YaccItem::NonTerminal(name) => format!(
+                    "if let Some(({}, {})) = {}(&{})? {{\n{}\n}}",
+                    output,
+                    rust_identifier(name),
+                    rust_identifier(name),
+                    input,
+                    indent(&code)
+                ),
+                YaccItem::Empty => format!("let {} = {}.clone();\n{}", output, input, code),
+                YaccItem::Rest => format!(
+                    "let ({}, {}) = ({}.clone(), Value::Nil);\n{}",
+                    rust_identifier("<!>"),
+                    output,
+                    input,
+                    code
+                ),
+                YaccItem::Variable(name) => format!(
+                    "if let Some(({}, {})) = {}.as_cons() {{\n{}\n}}",
+                    rust_identifier(name),
+                    output,
+                    input,
+                    indent(&code)
+                ),
+                YaccItem::Symbol(symbol) => format!(
+                    "if let Some({}) = yacc_terminal(&{}, &Value::symbol({:?})) {{\n{}\n}}",
+                    output,
+                    input,
+                    symbol,
+                    indent(&code)
+                ),
+                YaccItem::Literal(value) => format!(
+                    "if let Some({}) = yacc_terminal(&{}, &Value::from({})) {{\n{}\n}}",
+                    output,
+                    input,
+                    generate_literal(value),
+                    indent(&code)
+                ),
             };
-            
-            Ok(format!("{} {} {}", left_str, rust_op, right_str))
-        },
-        ShenNode::Conditional { condition, true_branch, false_branch } => {
-            let condition_str = generate_rust_code(condition)?;
-            let true_str = generate_rust_code(true_branch)?;
-            let false_str = false_branch
-                .as_ref()
-                .map(|branch| generate_rust_code(branch).unwrap_or_default())
-                .unwrap_or_else(|| "None".to_string());
-            
-            Ok(format!("if {} {{\n        {}\n    }} else {{\n        {}\n    }}", 
-                condition_str, 
-                true_str, 
-                false_str
+        }
+        body.push_str(&indent(&code));
+        body.push('\n');
+    }
+
+    Ok(format!(
+        "fn {}(input: &Value) -> ShenResult<Option<(Value, Value)>> {{\n{}    Ok(None)\n}}",
+        rust_identifier(name),
+        body
+    ))
+}
+
+/// Lower a `defprolog` to a function `register_<name>` that adds its clauses
+/// to the runtime Prolog database. Whoever assembles the generated items into
+/// a program calls it before any query that uses the predicate runs.
+fn generate_defprolog(
+    name: &str,
+    clauses: &[PrologClause],
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let mut clauses_str = String::new();
+    for clause in clauses {
+        let mut variables = PrologVariables::default();
+        let head = clause
+            .head
+            .iter()
+            .map(|pattern| generate_pattern_term(pattern, &mut variables))
+            .collect::<Result<Vec<_>, _>>()?;
+        let body = clause
+            .body
+            .iter()
+            .map(|goal| generate_goal(goal, &mut variables, options))
+            .collect::<Result<Vec<_>, _>>()?;
+        clauses_str.push_str(&format!(
+            "    Clause::new({}, vec![{}], vec![{}]),\n",
+            variables.count,
+            head.join(", "),
+            body.join(", ")
+        ));
+    }
+
+    Ok(format!(
+        "pub fn register_{}() {{\n{}\n}}",
+        rust_identifier(name),
+        indent(&format!("define_prolog({:?}, vec![\n{}]);", name, clauses_str))
+    ))
+}
+
+/// Lower a `prolog?` query to a call running it against the runtime Prolog
+/// database.
+fn generate_prolog_query(goals: &[ShenNode], options: &CodegenOptions) -> Result<String, TranspilerError> {
+    let mut variables = PrologVariables::default();
+    let goals_str = goals
+        .iter()
+        .map(|goal| generate_goal(goal, &mut variables, options))
+        .collect::<Result<Vec<_>, _>>()?
+        .join(", ");
+    Ok(fallible(
+        format!("prolog_query({}, vec![{}])", variables.count, goals_str),
+        options,
+    ))
+}
+
+/// Numbers the variables of a Prolog clause or query in order of appearance;
+/// every `_` is a fresh variable.
+#[derive(Default)]
+struct PrologVariables {
+    indices: HashMap<String, usize>,
+    count: usize,
+}
+
+impl PrologVariables {
+    fn index(&mut self, name: &str) -> usize {
+        if name != "_" {
+            if let Some(&index) = self.indices.get(name) {
+                return index;
+            }
+            self.indices.insert(name.to_string(), self.count);
+        }
+        self.count += 1;
+        self.count - 1
+    }
+}
+
+fn generate_pattern_term(
+    pattern: &Pattern,
+    variables: &mut PrologVariables,
+) -> Result<String, TranspilerError> {
+    match pattern {
+        Pattern::Variable(name) => Ok(format!("Term::var({})", variables.index(name))),
+        Pattern::Wildcard => Ok(format!("Term::var({})", variables.index("_"))),
+        Pattern::Literal(value) => Ok(format!("Term::value({})", generate_literal(value))),
+        Pattern::Symbol(name) => Ok(format!("Term::value(Value::symbol({:?}))", name)),
+        Pattern::EmptyList => Ok("Term::value(Value::Nil)".to_string()),
+        Pattern::Cons(head, tail) => Ok(format!(
+            "Term::cons({}, {})",
+            generate_pattern_term(head, variables)?,
+            generate_pattern_term(tail, variables)?
+        )),
+        _ => Err(TranspilerError::UnsupportedConstruct(format!(
+            "{:?} in a Prolog clause head",
+            pattern
+        ))),
+    }
+}
+
+/// A Prolog argument: unlike ordinary expressions, terms are not evaluated.
+fn generate_term(node: &ShenNode, variables: &mut PrologVariables) -> Result<String, TranspilerError> {
+    match node {
+        ShenNode::Symbol { name, .. } if name == "_" || is_variable(name) => {
+            Ok(format!("Term::var({})", variables.index(name)))
+        }
+        ShenNode::Symbol { name, .. } => Ok(format!("Term::value(Value::symbol({:?}))", name)),
+        ShenNode::Literal { value } => Ok(format!("Term::value({})", generate_literal(value))),
+        ShenNode::Nil => Ok("Term::value(Value::Nil)".to_string()),
+        ShenNode::Cons { head, tail } => Ok(format!(
+            "Term::cons({}, {})",
+            generate_term(head, variables)?,
+            generate_term(tail, variables)?
+        )),
+        ShenNode::List { elements, .. } => {
+            let elements = elements
+                .iter()
+                .map(|element| generate_term(element, variables))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(elements.into_iter().rev().fold(
+                "Term::value(Value::Nil)".to_string(),
+                |tail, head| format!("Term::cons({}, {})", head, tail),
             ))
+        }
+        _ => Err(TranspilerError::UnsupportedConstruct(format!(
+            "{:?} as a Prolog term",
+            node
+        ))),
+    }
+}
+
+fn generate_goal(
+    node: &ShenNode,
+    variables: &mut PrologVariables,
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let (name, args) = match node {
+        ShenNode::Symbol { name, .. } if name == "!" => return Ok("Goal::Cut".to_string()),
+        ShenNode::Symbol { name, .. } if !is_variable(name) => (name, &[][..]),
+        ShenNode::Application { func, args } => match func.as_ref() {
+            ShenNode::Symbol { name, .. } => (name, &args[..]),
+            _ => {
+                return Err(TranspilerError::UnsupportedConstruct(format!(
+                    "{:?} as a Prolog goal",
+                    node
+                )))
+            }
         },
-        ShenNode::Lambda { args, body, return_type } => {
-            let args_str = args.iter()
-                .map(|(name, _type)| name.clone())
-                .collect::<Vec<_>>()
-                .join(", ");
-            let body_str = generate_rust_code(body)?;
-            Ok(format!("|{}| {{ {} }}", args_str, body_str))
-        },
-        ShenNode::List { elements, element_type } => {
-            let elements_str = elements.iter()
-                .map(|elem| generate_rust_code(elem).unwrap_or_default())
-                .collect::<Vec<_>>()
-                .join(", ");
-            Ok(format!("vec![{}]", elements_str))
-        },
-        _ => Err(TranspilerError::CodegenError("Unsupported node type".to_string())),
+        _ => {
+            return Err(TranspilerError::UnsupportedConstruct(format!(
+                "{:?} as a Prolog goal",
+                node
+            )))
+        }
+    };
+
+    match (name.as_str(), args) {
+        ("is", [term, expr]) => Ok(format!(
+            "Goal::Is({}, {})",
+            generate_term(term, variables)?,
+            generate_prolog_expr(expr, variables, options)?
+        )),
+        ("bind", [term, expr]) => Ok(format!(
+            "Goal::Bind({}, {})",
+            generate_term(term, variables)?,
+            generate_prolog_expr(expr, variables, options)?
+        )),
+        ("when", [expr]) => Ok(format!(
+            "Goal::When({})",
+            generate_prolog_expr(expr, variables, options)?
+        )),
+        ("call", [term]) => Ok(format!("Goal::CallTerm({})", generate_term(term, variables)?)),
+        ("return", [term]) => Ok(format!("Goal::Return({})", generate_term(term, variables)?)),
+        _ => {
+            let args = args
+                .iter()
+                .map(|arg| generate_term(arg, variables))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("Goal::Call({:?}.to_string(), vec![{}])", name, args.join(", ")))
+        }
+    }
+}
+
+/// A Shen expression inside `is`, `bind` or `when`, generated over runtime
+/// `Value`s with the clause variables it mentions bound to their current
+/// values.
+fn generate_prolog_expr(
+    expr: &ShenNode,
+    variables: &mut PrologVariables,
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    let mut mentioned = Mentions(Vec::new());
+    mentioned.visit_node(expr);
+    let mut bound = Vec::new();
+    let mut bindings = String::new();
+    for name in mentioned.0.into_iter().filter(|name| is_variable(name)) {
+        if !bound.contains(&name) {
+            bindings.push_str(&format!("let {} = env.value({}); ", rust_identifier(&name), variables.index(&name)));
+            bound.push(name);
+        }
+    }
+    // The expression runs over Values and returns its errors through the closure
+    let options = CodegenOptions {
+        error_strategy: ErrorStrategy::Result,
+        ..options.clone()
+    };
+    let expr_str = klambda::generate_value_expr(expr, &bound, &options)?;
+    Ok(format!("Expr::new(|env| {{ {}Ok({}) }})", bindings, expr_str))
+}
+
+/// Collects every symbol appearing in the expressions it visits, outside
+/// nested definitions of macros, predicates and grammars.
+struct Mentions(Vec<Symbol>);
+
+impl Visitor for Mentions {
+    fn visit_node(&mut self, node: &ShenNode) {
+        match node {
+            ShenNode::Symbol { name, .. } => self.0.push(*name),
+            ShenNode::Defmacro { .. } | ShenNode::Defprolog { .. } | ShenNode::Defcc { .. } => {}
+            node => visit::walk_node(self, node),
+        }
+    }
+}
+
+/// Collects the variables the patterns it visits bind.
+struct PatternVariables(Vec<Symbol>);
+
+impl Visitor for PatternVariables {
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Variable(name) if !self.0.contains(name) => self.0.push(*name),
+            pattern => visit::walk_pattern(self, pattern),
+        }
+    }
+}
+
+/// Wrap `code` so that it only runs when `scrutinee` matches `pattern`, with
+/// the pattern's variables bound.
+fn generate_pattern(pattern: &Pattern, scrutinee: &str, code: String) -> String {
+    match pattern {
+        Pattern::Wildcard => code,
+        Pattern::Variable(name) => {
+            format!("let {} = {}.clone();\n{}", rust_identifier(name), scrutinee, code)
+        }
+        Pattern::Literal(value) => format!(
+            "if {} == {} {{\n{}\n}}",
+            scrutinee,
+            klambda::literal(value),
+            indent(&code)
+        ),
+        Pattern::Symbol(name) => format!(
+            "if {} == Value::symbol({:?}) {{\n{}\n}}",
+            scrutinee,
+            name,
+            indent(&code)
+        ),
+        Pattern::EmptyList => format!(
+            "if {} == Value::Nil {{\n{}\n}}",
+            scrutinee,
+            indent(&code)
+        ),
+        Pattern::Cons(head, tail) => {
+            let (head_name, tail_name) = (format!("{}_1", scrutinee), format!("{}_2", scrutinee));
+            let inner = generate_pattern(head, &head_name, generate_pattern(tail, &tail_name, code));
+            format!(
+                "if let Some(({}, {})) = {}.as_cons() {{\n{}\n}}",
+                head_name,
+                tail_name,
+                scrutinee,
+                indent(&inner)
+            )
+        }
+        Pattern::EmptyVector => format!(
+            "if {}.is_empty_vector() {{\n{}\n}}",
+            scrutinee,
+            indent(&code)
+        ),
+        Pattern::VectorCons(head, tail) => {
+            let (head_name, tail_name) = (format!("{}_1", scrutinee), format!("{}_2", scrutinee));
+            let inner = generate_pattern(head, &head_name, generate_pattern(tail, &tail_name, code));
+            format!(
+                "if let Some(({}, {})) = {}.as_vector_cons() {{\n{}\n}}",
+                head_name,
+                tail_name,
+                scrutinee,
+                indent(&inner)
+            )
+        }
+        Pattern::StringCons(head, tail) => {
+            let tail_name = format!("{}_2", scrutinee);
+            match head.as_ref() {
+                Pattern::Literal(ShenValue::String(prefix)) => format!(
+                    "if let Some({}) = {}.strip_string_prefix({:?}) {{\n{}\n}}",
+                    tail_name,
+                    scrutinee,
+                    prefix,
+                    indent(&generate_pattern(tail, &tail_name, code))
+                ),
+                _ => {
+                    let head_name = format!("{}_1", scrutinee);
+                    let inner =
+                        generate_pattern(head, &head_name, generate_pattern(tail, &tail_name, code));
+                    format!(
+                        "if let Some(({}, {})) = {}.as_string_cons() {{\n{}\n}}",
+                        head_name,
+                        tail_name,
+                        scrutinee,
+                        indent(&inner)
+                    )
+                }
+            }
+        }
+        Pattern::Tuple(first, second) => {
+            let (first_name, second_name) = (format!("{}_1", scrutinee), format!("{}_2", scrutinee));
+            let inner = generate_pattern(first, &first_name, generate_pattern(second, &second_name, code));
+            format!(
+                "if let Some(({}, {})) = {}.as_tuple() {{\n{}\n}}",
+                first_name,
+                second_name,
+                scrutinee,
+                indent(&inner)
+            )
+        }
+    }
+}
+
+fn indent(code: &str) -> String {
+    code.lines()
+        .map(|line| format!("    {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Shen variables start with an uppercase letter; every other symbol
+/// evaluates to itself.
+fn is_variable(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_uppercase())
+}
+
+/// Rust keywords, which Shen symbols may spell. `true` and `false` are left
+/// out: as symbols they are the booleans.
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "do", "dyn", "else", "enum",
+    "extern", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+    "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "try", "type",
+    "unsafe", "use", "where", "while", "yield",
+];
+
+/// Turn a Shen symbol such as `error-to-string` or `cons?` into a valid Rust
+/// identifier. Keywords get a trailing underscore.
+fn rust_identifier(name: &str) -> String {
+    let identifier: String = name
+        .chars()
+        .map(|c| match c {
+            '?' => "_p".to_string(),
+            '!' => "_x".to_string(),
+            c if c.is_alphanumeric() || c == '_' => c.to_string(),
+            _ => "_".to_string(),
+        })
+        .collect();
+    if RUST_KEYWORDS.contains(&identifier.as_str()) {
+        identifier + "_"
+    } else {
+        identifier
+    }
+}
+
+fn generate_literal(value: &ShenValue) -> String {
+    match value {
+        ShenValue::Integer(val) => val.to_string(),
+        ShenValue::Float(val) => format!("{:?}", val),
+        ShenValue::String(val) => format!("{:?}", val),
+        ShenValue::Boolean(val) => val.to_string(),
+        ShenValue::Nil => "None".to_string(),
+    }
+}
+
+/// Generate one side of a binary operation. Integral literals next to an
+/// operand of unknown type are emitted without a fractional part, so that
+/// rustc can infer the numeric type from the other side.
+fn generate_operand(
+    operand: &ShenNode,
+    other: &ShenNode,
+    options: &CodegenOptions,
+) -> Result<String, TranspilerError> {
+    match (operand, other.get_type()) {
+        (ShenNode::Literal { value: ShenValue::Float(val) }, ShenType::Symbol)
+            if val.fract() == 0.0 =>
+        {
+            Ok(format!("{}", *val as i64))
+        }
+        _ => generate_rust_code_with_options(operand, options),
     }
 }
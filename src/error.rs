@@ -2,9 +2,11 @@
 
 use thiserror::Error;
 
+use crate::runtime::ShenError;
+
 #[derive(Error, Debug)]
 pub enum TranspilerError {
-    #[error("Parsing error at token {token}: {message}")]
+    #[error("Parsing error at {line}:{column}, token {token}: {message}")]
     ParseError {
         token: String,
         message: String,
@@ -24,9 +26,22 @@ pub enum TranspilerError {
         to: String,
     },
 
+    #[error("Macro expansion error: {0}")]
+    MacroError(String),
+
+    #[error("Cannot read {path}: {message}")]
+    IoError {
+        path: String,
+        message: String,
+    },
+
     #[error("Syntax error: {0}")]
     SyntaxError(String),
 
+    /// An error raised while evaluating Shen code with the interpreter
+    #[error("{0}")]
+    RuntimeError(#[from] ShenError),
+
     #[error("Internal transpiler error: {0}")]
     InternalError(String),
 }
@@ -0,0 +1,524 @@
+//! A tree-walking interpreter for Shen
+//!
+//! [`Evaluator`] runs the AST directly on runtime [`Value`]s, without going
+//! through rustc. Macro expansion uses it to run macro bodies at transpile
+//! time, and it doubles as a reference implementation to test generated Rust
+//! against and as the engine behind a REPL.
+//!
+//! Errors are Shen errors: anything that goes wrong while evaluating, from
+//! `simple-error` to taking the head of an empty list, can be caught with
+//! `trap-error`. Prolog, Shen-YACC and macros are transpile-time constructs
+//! and are not evaluated.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::ast::{Pattern, Rule, ShenNode, ShenValue, Symbol};
+use crate::runtime::error::{error_message, raise, simple_error, simple_error_from, trap_error};
+use crate::runtime::{list, number, stream, string, tuple, vector};
+use crate::runtime::{Function, Globals, Lazy, ShenResult, Value};
+
+/// Local variable bindings.
+pub type Env = HashMap<Symbol, Value>;
+
+/// An interpreter session: the functions defined so far and the global
+/// variables. Cloning is cheap and shares the session.
+#[derive(Debug, Clone)]
+pub struct Evaluator {
+    session: Arc<Session>,
+}
+
+#[derive(Debug)]
+struct Session {
+    functions: RwLock<HashMap<Symbol, Definition>>,
+    globals: Globals,
+}
+
+#[derive(Debug, Clone)]
+enum Definition {
+    /// `(define name Rules...)`
+    Rules(Arc<Vec<Rule>>),
+    /// `(defun name Params Body)`
+    Defun(Arc<(Vec<Symbol>, ShenNode)>),
+}
+
+impl Definition {
+    fn arity(&self) -> usize {
+        match self {
+            Definition::Rules(rules) => rules.first().map_or(0, |rule| rule.patterns.len()),
+            Definition::Defun(defun) => defun.0.len(),
+        }
+    }
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Evaluator {
+            session: Arc::new(Session {
+                functions: RwLock::new(HashMap::new()),
+                globals: Globals::with_defaults(),
+            }),
+        }
+    }
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        Evaluator::default()
+    }
+
+    /// The global variables of this session.
+    pub fn globals(&self) -> &Globals {
+        &self.session.globals
+    }
+
+    /// Make a `define`d function callable from evaluated code.
+    pub fn define(&mut self, name: Symbol, rules: Vec<Rule>) {
+        self.insert(name, Definition::Rules(Arc::new(rules)));
+    }
+
+    fn insert(&self, name: Symbol, definition: Definition) {
+        self.session
+            .functions
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(name, definition);
+    }
+
+    fn definition(&self, name: Symbol) -> Option<Definition> {
+        self.session
+            .functions
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&name)
+            .cloned()
+    }
+
+    /// Evaluate every form of a program in turn, returning the value of the
+    /// last one.
+    pub fn eval_program(&self, program: &[ShenNode]) -> ShenResult<Value> {
+        program
+            .iter()
+            .try_fold(Value::Nil, |_, node| self.eval(node, &Env::new()))
+    }
+
+    /// Apply the first rule whose patterns match `args`, or return `None` if
+    /// no rule does.
+    pub fn apply_rules(&self, rules: &[Rule], args: &[Value]) -> ShenResult<Option<Value>> {
+        for rule in rules {
+            if rule.patterns.len() != args.len() {
+                continue;
+            }
+            let mut env = Env::new();
+            if !rule
+                .patterns
+                .iter()
+                .zip(args)
+                .all(|(pattern, arg)| match_pattern(pattern, arg, &mut env))
+            {
+                continue;
+            }
+            if let Some(guard) = &rule.guard {
+                if !self.eval_condition(guard, &env)? {
+                    continue;
+                }
+            }
+            return self.eval(&rule.body, &env).map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Call a defined function or a primitive. Shen functions are curried, so
+    /// too few arguments give a function waiting for the rest and too many
+    /// apply the result to the ones left over.
+    pub fn call(&self, name: Symbol, args: Vec<Value>) -> ShenResult<Value> {
+        let definition = match self.definition(name) {
+            Some(definition) => definition,
+            None => return self.primitive(&name, args),
+        };
+
+        let arity = definition.arity();
+        if args.len() < arity && !args.is_empty() {
+            return Ok(self.partial(name, args, arity));
+        }
+        let mut args = args;
+        let rest = args.split_off(arity.min(args.len()));
+
+        let result = match &definition {
+            Definition::Rules(rules) => self
+                .apply_rules(rules, &args)?
+                .ok_or_else(|| simple_error(format!("partial function {}", name)))?,
+            Definition::Defun(defun) => {
+                let (params, body) = defun.as_ref();
+                let env = params.iter().cloned().zip(args).collect();
+                self.eval(body, &env)?
+            }
+        };
+        rest.into_iter().try_fold(result, |function, arg| self.apply(&function, arg))
+    }
+
+    /// A function that calls `name` once it has `arity` arguments.
+    fn partial(&self, name: Symbol, args: Vec<Value>, arity: usize) -> Value {
+        let evaluator = self.clone();
+        Value::Function(Function::new(move |arg| {
+            let mut args = args.clone();
+            args.push(arg);
+            if args.len() < arity {
+                Ok(evaluator.partial(name, args, arity))
+            } else {
+                evaluator.call(name, args)
+            }
+        }))
+    }
+
+    /// Apply a function value, a lambda or the name of a function, to one
+    /// argument.
+    pub fn apply(&self, function: &Value, arg: Value) -> ShenResult<Value> {
+        match function {
+            Value::Function(function) => function.call(arg),
+            Value::Symbol(name) => self.call(Symbol::intern(name), vec![arg]),
+            _ => Err(simple_error(format!("{} is not a function", function))),
+        }
+    }
+
+    pub fn eval(&self, node: &ShenNode, env: &Env) -> ShenResult<Value> {
+        match node {
+            ShenNode::Literal { value } => Ok(literal(value)),
+            ShenNode::Nil => Ok(Value::Nil),
+            ShenNode::Error { message } => Err(simple_error(message.clone())),
+            ShenNode::Symbol { name, .. } => match env.get(name) {
+                Some(value) => Ok(value.clone()),
+                None if is_variable(name) => Err(simple_error(format!("variable {} is unbound", name))),
+                None => Ok(symbol(name)),
+            },
+            ShenNode::List { elements, .. } => Ok(Value::list(self.eval_all(elements, env)?)),
+            ShenNode::Cons { head, tail } => {
+                Ok(Value::cons(self.eval(head, env)?, self.eval(tail, env)?))
+            }
+            ShenNode::Tuple { first, second } => {
+                Ok(Value::tuple(self.eval(first, env)?, self.eval(second, env)?))
+            }
+            ShenNode::Vector { elements } => Ok(Value::vector(self.eval_all(elements, env)?)),
+            ShenNode::VectorCons { head, tail } => {
+                vector::vector_cons(self.eval(head, env)?, &self.eval(tail, env)?)
+            }
+            ShenNode::StringCons { head, tail } => {
+                string::cn(&self.eval(head, env)?, &self.eval(tail, env)?)
+            }
+            ShenNode::Conditional {
+                condition,
+                true_branch,
+                false_branch,
+            } => {
+                if self.eval_condition(condition, env)? {
+                    self.eval(true_branch, env)
+                } else {
+                    match false_branch {
+                        Some(false_branch) => self.eval(false_branch, env),
+                        None => Ok(Value::Nil),
+                    }
+                }
+            }
+            ShenNode::BinaryOperation {
+                operator,
+                left,
+                right,
+                ..
+            } => self.call(*operator, vec![self.eval(left, env)?, self.eval(right, env)?]),
+            ShenNode::Lambda { arg: (param, _), body, .. } => {
+                let (evaluator, env, param, body) =
+                    (self.clone(), env.clone(), *param, body.clone());
+                Ok(Value::Function(Function::new(move |arg| {
+                    let mut env = env.clone();
+                    env.insert(param, arg);
+                    evaluator.eval(&body, &env)
+                })))
+            }
+            ShenNode::Freeze { body } => {
+                let (evaluator, env, body) = (self.clone(), env.clone(), body.clone());
+                Ok(Value::Lazy(Lazy::new(move || {
+                    evaluator
+                        .eval(&body, &env)
+                        .unwrap_or_else(|error| raise(error))
+                })))
+            }
+            ShenNode::TrapError { body, handler } => match self.eval(body, env) {
+                Ok(value) => Ok(value),
+                Err(error) => {
+                    let handler = self.eval(handler, env)?;
+                    self.apply(&handler, Value::Error(error))
+                }
+            },
+            ShenNode::Define { name, rules } => {
+                self.insert(*name, Definition::Rules(Arc::new(rules.clone())));
+                Ok(Value::symbol(name.as_str()))
+            }
+            ShenNode::Function { name, args, body, .. } => {
+                let params = args.iter().map(|(arg, _)| *arg).collect();
+                self.insert(*name, Definition::Defun(Arc::new((params, body.as_ref().clone()))));
+                Ok(Value::symbol(name.as_str()))
+            }
+            ShenNode::Package { name, body, .. } => {
+                self.eval_program(body)?;
+                Ok(Value::symbol(name.as_str()))
+            }
+            ShenNode::Application { func, args } => self.eval_application(func, args, env),
+            ShenNode::Defmacro { .. }
+            | ShenNode::Defprolog { .. }
+            | ShenNode::Defcc { .. }
+            | ShenNode::PrologQuery { .. } => Err(simple_error(format!(
+                "{} cannot be evaluated by the interpreter",
+                crate::expand::to_source(node)
+            ))),
+        }
+    }
+
+    fn eval_application(&self, func: &ShenNode, args: &[ShenNode], env: &Env) -> ShenResult<Value> {
+        let name = match func {
+            ShenNode::Symbol { name, .. } if !env.contains_key(name) && !is_variable(name) => name,
+            // A lambda or a variable bound to a function
+            _ => {
+                let function = self.eval(func, env)?;
+                return self
+                    .eval_all(args, env)?
+                    .into_iter()
+                    .try_fold(function, |function, arg| self.apply(&function, arg));
+            }
+        };
+
+        match (name.as_str(), args) {
+            ("and", [left, right]) => Ok(Value::Boolean(
+                self.eval_condition(left, env)? && self.eval_condition(right, env)?,
+            )),
+            ("or", [left, right]) => Ok(Value::Boolean(
+                self.eval_condition(left, env)? || self.eval_condition(right, env)?,
+            )),
+            // (cond (C1 E1) (C2 E2) ...) fails when no condition holds
+            ("cond", clauses) => {
+                for clause in clauses {
+                    let ShenNode::Application { func: condition, args } = clause else {
+                        return Err(simple_error("cond expects (Condition Result) clauses"));
+                    };
+                    let [result] = args.as_slice() else {
+                        return Err(simple_error("cond expects (Condition Result) clauses"));
+                    };
+                    if self.eval_condition(condition, env)? {
+                        return self.eval(result, env);
+                    }
+                }
+                Err(simple_error("cond failure"))
+            }
+            ("let", [bindings @ .., body]) if bindings.len() % 2 == 0 => {
+                let mut env = env.clone();
+                for binding in bindings.chunks(2) {
+                    let value = self.eval(&binding[1], &env)?;
+                    match &binding[0] {
+                        ShenNode::Symbol { name, .. } => env.insert(*name, value),
+                        _ => return Err(simple_error("let expects a variable")),
+                    };
+                }
+                self.eval(body, &env)
+            }
+            _ => self.call(*name, self.eval_all(args, env)?),
+        }
+    }
+
+    fn eval_all(&self, nodes: &[ShenNode], env: &Env) -> ShenResult<Vec<Value>> {
+        nodes.iter().map(|node| self.eval(node, env)).collect()
+    }
+
+    fn eval_condition(&self, node: &ShenNode, env: &Env) -> ShenResult<bool> {
+        self.eval(node, env)?.as_bool()
+    }
+
+    /// Primitives that need the session: globals, higher-order functions and
+    /// output. Everything else is a pure [`primitive`].
+    fn primitive(&self, name: &str, args: Vec<Value>) -> ShenResult<Value> {
+        let globals = self.globals();
+        match (name, args.as_slice()) {
+            ("set", [Value::Symbol(variable), value]) => Ok(globals.set(variable, value.clone())),
+            ("value", [Value::Symbol(variable)]) => globals.value(variable),
+            ("bound?", [Value::Symbol(variable)]) => Ok(Value::Boolean(globals.is_bound(variable))),
+            ("stoutput", []) => globals.value("*stoutput*"),
+            ("stinput", []) => globals.value("*stinput*"),
+            ("thaw", [Value::Lazy(lazy)]) => trap_error(|| Ok(lazy.thaw()), Err),
+            ("map", [function, list]) => Ok(Value::list(
+                list::list_items(list)?
+                    .into_iter()
+                    .map(|item| self.apply(function, item))
+                    .collect::<ShenResult<Vec<_>>>()?,
+            )),
+            ("pr" | "prhush", [text, stream]) => stream::pr(text, stream),
+            ("print", [value]) => {
+                stream::write_stream(&globals.value("*stoutput*")?, &value.to_string())?;
+                Ok(value.clone())
+            }
+            ("nl", [count]) => {
+                let count = integer(count)?;
+                stream::write_stream(&globals.value("*stoutput*")?, &"\n".repeat(count.max(0) as usize))?;
+                Ok(Value::Integer(0))
+            }
+            _ => primitive(name, args),
+        }
+    }
+}
+
+/// Match `value` against `pattern`, binding its variables in `env`. A
+/// variable that occurs twice must match equal values.
+pub fn match_pattern(pattern: &Pattern, value: &Value, env: &mut Env) -> bool {
+    match pattern {
+        Pattern::Wildcard => true,
+        Pattern::Variable(name) => match env.get(name) {
+            Some(bound) => bound == value,
+            None => {
+                env.insert(*name, value.clone());
+                true
+            }
+        },
+        Pattern::Literal(literal_value) => *value == literal(literal_value),
+        Pattern::Symbol(name) => *value == symbol(name),
+        Pattern::EmptyList => *value == Value::Nil,
+        Pattern::Cons(head, tail) => match value.as_cons() {
+            Some((value_head, value_tail)) => {
+                match_pattern(head, &value_head, env) && match_pattern(tail, &value_tail, env)
+            }
+            None => false,
+        },
+        Pattern::Tuple(first, second) => match value.as_tuple() {
+            Some((value_first, value_second)) => {
+                match_pattern(first, &value_first, env) && match_pattern(second, &value_second, env)
+            }
+            None => false,
+        },
+        Pattern::EmptyVector => value.is_empty_vector(),
+        Pattern::VectorCons(head, tail) => match value.as_vector_cons() {
+            Some((value_head, value_tail)) => {
+                match_pattern(head, &value_head, env) && match_pattern(tail, &value_tail, env)
+            }
+            None => false,
+        },
+        Pattern::StringCons(head, tail) => match (head.as_ref(), value) {
+            // A literal prefix matches as many characters as it has
+            (Pattern::Literal(ShenValue::String(prefix)), _) => match value.strip_string_prefix(prefix) {
+                Some(rest) => match_pattern(tail, &rest, env),
+                None => false,
+            },
+            _ => match value.as_string_cons() {
+                Some((value_head, value_tail)) => {
+                    match_pattern(head, &value_head, env) && match_pattern(tail, &value_tail, env)
+                }
+                None => false,
+            },
+        },
+    }
+}
+
+/// The primitives that only depend on their arguments.
+fn primitive(name: &str, args: Vec<Value>) -> ShenResult<Value> {
+    match (name, args.as_slice()) {
+        ("cons", [head, tail]) => Ok(Value::cons(head.clone(), tail.clone())),
+        ("hd" | "head", [list]) => list::hd(list),
+        ("tl" | "tail", [list]) => list::tl(list),
+        ("cons?", [value]) => Ok(Value::Boolean(list::is_cons(value))),
+        ("empty?", [value]) => Ok(Value::Boolean(list::is_empty(value))),
+        ("=", [left, right]) => Ok(Value::Boolean(left == right)),
+        ("not", [value]) => Ok(Value::Boolean(!value.as_bool()?)),
+        ("symbol?", [value]) => Ok(Value::Boolean(matches!(value, Value::Symbol(_)))),
+        ("variable?", [value]) => {
+            Ok(Value::Boolean(matches!(value, Value::Symbol(name) if is_variable(name))))
+        }
+        ("number?", [value]) => Ok(Value::Boolean(number::is_number(value))),
+        ("integer?", [value]) => Ok(Value::Boolean(number::is_integer(value))),
+        ("string?", [value]) => Ok(Value::Boolean(string::is_string(value))),
+        ("boolean?", [value]) => Ok(Value::Boolean(matches!(value, Value::Boolean(_)))),
+        ("tuple?", [value]) => Ok(Value::Boolean(tuple::is_tuple(value))),
+        ("vector?", [value]) => Ok(Value::Boolean(vector::is_vector(value))),
+        ("absvector?", [value]) => Ok(Value::Boolean(vector::is_absvector(value))),
+        ("@p", [first, second]) => Ok(Value::tuple(first.clone(), second.clone())),
+        ("fst", [tuple]) => tuple::fst(tuple),
+        ("snd", [tuple]) => tuple::snd(tuple),
+        ("length", [list]) => Ok(Value::Integer(list::list_items(list)?.len() as i64)),
+        ("reverse", [list]) => Ok(Value::list(list::list_items(list)?.into_iter().rev())),
+        ("append", [left, right]) => Ok(list::list_items(left)?
+            .into_iter()
+            .rev()
+            .fold(right.clone(), |tail, head| Value::cons(head, tail))),
+        ("element?", [value, list]) => Ok(Value::Boolean(list::is_element(value, list)?)),
+        ("nth", [index, list]) => {
+            let index = integer(index)?;
+            list::list_items(list)?
+                .into_iter()
+                .nth((index - 1).max(0) as usize)
+                .filter(|_| index >= 1)
+                .ok_or_else(|| simple_error(format!("nth: no element {} in {}", index, list)))
+        }
+        ("concat", [Value::Symbol(left), right]) => Ok(Value::symbol(format!("{}{}", left, right))),
+        ("intern", [Value::String(name)]) => Ok(symbol(name)),
+        ("str", [value]) => string::str(value),
+        ("cn", [left, right]) => string::cn(left, right),
+        ("pos", [text, index]) => string::pos(text, index.clone()),
+        ("tlstr", [text]) => string::tlstr(text),
+        ("string->n", [text]) => string::string_to_n(text),
+        ("n->string", [code]) => string::n_to_string(code.clone()),
+        ("shen.app", [value, suffix, mode]) => string::app(value, suffix, mode),
+        ("absvector", [size]) => vector::absvector(size.clone()),
+        ("<-address", [vector, index]) => vector::address_get(vector, index.clone()),
+        ("address->", [vector, index, value]) => {
+            vector::address_set(vector, index.clone(), value.clone())
+        }
+        ("vector", [size]) => vector::vector(size.clone()),
+        ("<-vector", [vector, index]) => vector::vector_get(vector, index.clone()),
+        ("vector->", [vector, index, value]) => {
+            vector::vector_set(vector, index.clone(), value.clone())
+        }
+        ("limit", [vector]) => vector::limit(vector).map(|limit| Value::Integer(limit as i64)),
+        ("fail", []) => Ok(Value::fail()),
+        ("do", [.., last]) => Ok(last.clone()),
+        ("simple-error", [message]) => Err(simple_error_from(message)),
+        ("error-to-string", [error]) => error_message(error),
+        ("+", [left, right]) => number::add(left, right),
+        ("-", [left, right]) => number::subtract(left, right),
+        ("*", [left, right]) => number::multiply(left, right),
+        ("/", [left, right]) => number::divide(left, right),
+        ("<", [left, right]) => number::less(left, right),
+        (">", [left, right]) => number::greater(left, right),
+        ("<=", [left, right]) => number::less_or_equal(left, right),
+        (">=", [left, right]) => number::greater_or_equal(left, right),
+        _ => Err(simple_error(format!(
+            "{} is not a function of {} argument(s)",
+            name,
+            args.len()
+        ))),
+    }
+}
+
+fn literal(value: &ShenValue) -> Value {
+    match value {
+        ShenValue::Integer(value) => Value::Integer(*value),
+        ShenValue::Float(value) => Value::Float(*value),
+        ShenValue::String(value) => Value::String(value.clone()),
+        ShenValue::Boolean(value) => Value::Boolean(*value),
+        ShenValue::Nil => Value::Nil,
+    }
+}
+
+/// `true` and `false` are booleans; every other symbol stands for itself.
+pub(crate) fn symbol(name: &str) -> Value {
+    match name {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => Value::symbol(name),
+    }
+}
+
+fn is_variable(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_uppercase())
+}
+
+fn integer(value: &Value) -> ShenResult<i64> {
+    match value {
+        Value::Integer(value) => Ok(*value),
+        Value::Float(value) if value.fract() == 0.0 => Ok(*value as i64),
+        _ => Err(simple_error(format!("{} is not an integer", value))),
+    }
+}
@@ -0,0 +1,428 @@
+//! Macro expansion between parsing and code generation
+//!
+//! Shen macros receive a form as data, a list of symbols, numbers, strings
+//! and nested lists, and return the form to use instead. Expansion quotes each
+//! top-level form into that data representation once, runs every `defmacro`
+//! over it and its subforms with the [`Evaluator`], and parses the result back
+//! into an AST when a macro changed it. A form is re-expanded until no macro
+//! changes it, then its subforms are expanded in turn.
+
+pub mod package;
+pub mod standard;
+
+use crate::ast::{Pattern, Rule, ShenNode, ShenValue, YaccItem};
+use crate::error::TranspilerError;
+use crate::eval::{symbol, Evaluator};
+use crate::parser::{parse_built_form, read, SExpr, Span, Token};
+use crate::printer::{print_node, DEFAULT_WIDTH};
+use crate::runtime::list::list_items;
+use crate::runtime::Value;
+
+/// How many times a single form may be rewritten before expansion is assumed
+/// not to terminate.
+const EXPANSION_LIMIT: usize = 100;
+
+/// Options controlling macro expansion.
+#[derive(Debug, Clone, Default)]
+pub struct ExpandOptions {
+    /// Print every expanded top-level form to stderr, for debugging macros.
+    pub dump_expanded: bool,
+}
+
+/// Expand the macros defined in `program` throughout it. The `defmacro`s
+/// themselves are removed, as they only exist at transpile time.
+pub fn expand_program(program: Vec<ShenNode>) -> Result<Vec<ShenNode>, TranspilerError> {
+    expand_program_with_options(program, &ExpandOptions::default())
+}
+
+pub fn expand_program_with_options(
+    program: Vec<ShenNode>,
+    options: &ExpandOptions,
+) -> Result<Vec<ShenNode>, TranspilerError> {
+    let mut expander = Expander {
+        interpreter: Evaluator::new(),
+        macros: Vec::new(),
+    };
+    let forms = expander.collect_definitions(program);
+
+    let expanded = forms
+        .into_iter()
+        .map(|node| expander.expand(node))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if options.dump_expanded {
+        for node in &expanded {
+            eprintln!("{}", print_node(node, DEFAULT_WIDTH));
+        }
+    }
+    Ok(expanded)
+}
+
+/// Shen source text for `node`, as parsed back by
+/// [`parse_shen_source`](crate::parser::parse_shen_source) unless it holds a
+/// string containing `"`, which source cannot spell.
+pub fn to_source(node: &ShenNode) -> String {
+    value_to_source(&quote(node))
+}
+
+struct Expander {
+    interpreter: Evaluator,
+    macros: Vec<Vec<Rule>>,
+}
+
+impl Expander {
+    /// Register the macros and functions defined in `forms`, including those
+    /// inside packages, and return the forms without the macros.
+    fn collect_definitions(&mut self, forms: Vec<ShenNode>) -> Vec<ShenNode> {
+        let mut kept = Vec::new();
+        for node in forms {
+            match node {
+                ShenNode::Defmacro { rules, .. } => self.macros.push(rules),
+                ShenNode::Define { ref name, ref rules } => {
+                    // Macros may call helper functions defined alongside them
+                    self.interpreter.define(*name, rules.clone());
+                    kept.push(node);
+                }
+                ShenNode::Package {
+                    name,
+                    exports,
+                    body,
+                } => {
+                    let body = self.collect_definitions(body);
+                    kept.push(ShenNode::Package {
+                        name,
+                        exports,
+                        body,
+                    });
+                }
+                node => kept.push(node),
+            }
+        }
+        kept
+    }
+
+    /// Expand the macros in a top-level form, which is quoted only once and
+    /// only parsed back if a macro changed it.
+    fn expand(&self, node: ShenNode) -> Result<ShenNode, TranspilerError> {
+        if self.macros.is_empty() {
+            return Ok(node);
+        }
+        match self.expand_form(&quote(&node))? {
+            Some(expansion) => unquote(&expansion),
+            None => Ok(node),
+        }
+    }
+
+    /// Expand `form` until no macro changes it, then its subforms in turn,
+    /// or return `None` if nothing changed. Macro definitions are left as
+    /// they are.
+    fn expand_form(&self, form: &Value) -> Result<Option<Value>, TranspilerError> {
+        if let Some((Value::Symbol(head), _)) = form.as_cons() {
+            if head == "defmacro" {
+                return Ok(None);
+            }
+        }
+
+        let mut expanded = None;
+        let mut rewrites = 0;
+        while let Some(expansion) = self.expand_once(expanded.as_ref().unwrap_or(form))? {
+            rewrites += 1;
+            if rewrites > EXPANSION_LIMIT {
+                return Err(TranspilerError::MacroError(format!(
+                    "expansion of {} does not terminate",
+                    value_to_source(form)
+                )));
+            }
+            expanded = Some(expansion);
+        }
+
+        // Only proper lists have subforms; an improper one is data
+        let current = expanded.as_ref().unwrap_or(form);
+        let Ok(items) = list_items(current) else {
+            return Ok(expanded);
+        };
+        let mut changed = false;
+        let items = items
+            .into_iter()
+            .map(|item| match self.expand_form(&item)? {
+                Some(expansion) => {
+                    changed = true;
+                    Ok(expansion)
+                }
+                None => Ok(item),
+            })
+            .collect::<Result<Vec<_>, TranspilerError>>()?;
+        Ok(if changed { Some(Value::list(items)) } else { expanded })
+    }
+
+    /// Run every macro over `form` in turn, or return `None` if none of them
+    /// changed it.
+    fn expand_once(&self, form: &Value) -> Result<Option<Value>, TranspilerError> {
+        let mut expansion = form.clone();
+        for rules in &self.macros {
+            if let Some(result) = self
+                .interpreter
+                .apply_rules(rules, &[expansion.clone()])
+                .map_err(|err| TranspilerError::MacroError(err.to_string()))? {
+                expansion = result;
+            }
+        }
+        Ok((expansion != *form).then_some(expansion))
+    }
+}
+
+/// The form `node` was read from, as the data a macro sees.
+fn quote(node: &ShenNode) -> Value {
+    let form = |head: &str, rest: Vec<Value>| {
+        Value::list(std::iter::once(Value::symbol(head)).chain(rest))
+    };
+
+    match node {
+        ShenNode::Symbol { name, .. } => symbol(name),
+        ShenNode::Literal { value } => match value {
+            ShenValue::Integer(value) => Value::Integer(*value),
+            ShenValue::Float(value) => Value::Float(*value),
+            ShenValue::String(value) => Value::String(value.clone()),
+            ShenValue::Boolean(value) => Value::Boolean(*value),
+            ShenValue::Nil => Value::Nil,
+        },
+        ShenNode::Nil => Value::Nil,
+        // What was read is lost; stand in a form that fails the same way
+        ShenNode::Error { message } => form("simple-error", vec![Value::String(message.clone())]),
+        ShenNode::List { elements, .. } => elements
+            .iter()
+            .rev()
+            .fold(Value::Nil, |tail, head| form("cons", vec![quote(head), tail])),
+        ShenNode::Cons { head, tail } => form("cons", vec![quote(head), quote(tail)]),
+        ShenNode::Application { func, args } => {
+            Value::list(std::iter::once(quote(func)).chain(args.iter().map(quote)))
+        }
+        ShenNode::BinaryOperation {
+            operator,
+            left,
+            right,
+            ..
+        } => form(operator, vec![quote(left), quote(right)]),
+        ShenNode::Function { name, args, body, .. } => form(
+            "defun",
+            vec![
+                Value::symbol(name.as_str()),
+                Value::list(args.iter().map(|(arg, _)| Value::symbol(arg.as_str()))),
+                quote(body),
+            ],
+        ),
+        ShenNode::Lambda { arg, body, .. } => {
+            form("/.", vec![Value::symbol(arg.0.as_str()), quote(body)])
+        }
+        ShenNode::Conditional {
+            condition,
+            true_branch,
+            false_branch,
+        } => form(
+            "if",
+            [Some(condition), Some(true_branch), false_branch.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(|branch| quote(branch))
+                .collect(),
+        ),
+        ShenNode::Define { name, rules } => form("define", quote_rules(name, rules)),
+        ShenNode::Defmacro { name, rules } => form("defmacro", quote_rules(name, rules)),
+        ShenNode::Defprolog { name, clauses } => {
+            let mut items = vec![Value::symbol(name.as_str())];
+            for clause in clauses {
+                items.extend(clause.head.iter().map(quote_pattern));
+                items.push(Value::symbol("<--"));
+                items.extend(clause.body.iter().map(quote));
+                items.push(Value::symbol(";"));
+            }
+            form("defprolog", items)
+        }
+        ShenNode::Defcc { name, rules } => {
+            let mut items = vec![Value::symbol(name.as_str())];
+            for rule in rules {
+                items.extend(rule.items.iter().map(quote_yacc_item));
+                if let Some(action) = &rule.action {
+                    items.push(Value::symbol(":="));
+                    items.push(quote(action));
+                }
+                if let Some(guard) = &rule.guard {
+                    items.push(Value::symbol("where"));
+                    items.push(quote(guard));
+                }
+                items.push(Value::symbol(";"));
+            }
+            form("defcc", items)
+        }
+        ShenNode::PrologQuery { goals } => form("prolog?", goals.iter().map(quote).collect()),
+        ShenNode::Package {
+            name,
+            exports,
+            body,
+        } => form(
+            "package",
+            [
+                Value::symbol(name.as_str()),
+                Value::list(exports.iter().map(|export| Value::symbol(export.as_str()))),
+            ]
+            .into_iter()
+            .chain(body.iter().map(quote))
+            .collect(),
+        ),
+        ShenNode::Tuple { first, second } => form("@p", vec![quote(first), quote(second)]),
+        ShenNode::Vector { elements } => elements
+            .iter()
+            .rev()
+            .fold(Value::symbol("<>"), |tail, head| form("@v", vec![quote(head), tail])),
+        ShenNode::VectorCons { head, tail } => form("@v", vec![quote(head), quote(tail)]),
+        ShenNode::StringCons { head, tail } => form("@s", vec![quote(head), quote(tail)]),
+        ShenNode::TrapError { body, handler } => {
+            form("trap-error", vec![quote(body), quote(handler)])
+        }
+        ShenNode::Freeze { body } => form("freeze", vec![quote(body)]),
+    }
+}
+
+fn quote_rules(name: &str, rules: &[Rule]) -> Vec<Value> {
+    let mut items = vec![Value::symbol(name)];
+    for rule in rules {
+        items.extend(rule.patterns.iter().map(quote_pattern));
+        items.push(Value::symbol("->"));
+        items.push(quote(&rule.body));
+        if let Some(guard) = &rule.guard {
+            items.push(Value::symbol("where"));
+            items.push(quote(guard));
+        }
+    }
+    items
+}
+
+fn quote_pattern(pattern: &Pattern) -> Value {
+    let form = |head: &str, first: &Pattern, second: &Pattern| {
+        Value::list([Value::symbol(head), quote_pattern(first), quote_pattern(second)])
+    };
+
+    match pattern {
+        Pattern::Variable(name) | Pattern::Symbol(name) => symbol(name),
+        Pattern::Wildcard => Value::symbol("_"),
+        Pattern::Literal(value) => quote(&ShenNode::Literal {
+            value: value.clone(),
+        }),
+        Pattern::EmptyList => Value::Nil,
+        Pattern::Cons(head, tail) => form("cons", head, tail),
+        Pattern::Tuple(first, second) => form("@p", first, second),
+        Pattern::EmptyVector => Value::symbol("<>"),
+        Pattern::VectorCons(head, tail) => form("@v", head, tail),
+        Pattern::StringCons(head, tail) => form("@s", head, tail),
+    }
+}
+
+fn quote_yacc_item(item: &YaccItem) -> Value {
+    match item {
+        YaccItem::NonTerminal(name) | YaccItem::Variable(name) | YaccItem::Symbol(name) => {
+            Value::symbol(name.as_str())
+        }
+        YaccItem::Empty => Value::symbol("<e>"),
+        YaccItem::Rest => Value::symbol("<!>"),
+        YaccItem::Literal(value) => quote(&ShenNode::Literal {
+            value: value.clone(),
+        }),
+    }
+}
+
+/// Parse a macro's result back into an AST. The result is turned into
+/// reader forms directly, so that strings need no escaping.
+fn unquote(form: &Value) -> Result<ShenNode, TranspilerError> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        TranspilerError::MacroError(format!(
+            "macro produced invalid code {}: {}",
+            value_to_source(form),
+            err
+        ))
+    };
+    let datum = Datum::from_value(form).map_err(|err| invalid(&err))?;
+    let sexpr = datum.to_sexpr().map_err(|err| invalid(&err))?;
+    parse_built_form(&sexpr).map_err(|err| invalid(&err))
+}
+
+/// Code held as data, owning the names and strings that the reader forms
+/// built from it borrow.
+enum Datum {
+    Symbol(String),
+    String(String),
+    Number(f64),
+    /// `[]`
+    Nil,
+    List(Vec<Datum>),
+}
+
+impl Datum {
+    fn from_value(value: &Value) -> Result<Datum, String> {
+        let mut items = Vec::new();
+        let mut rest = value.clone();
+        while let Some((head, tail)) = rest.as_cons() {
+            items.push(Datum::from_value(&head)?);
+            rest = tail;
+        }
+
+        Ok(match (items.is_empty(), rest) {
+            (true, Value::Nil) => Datum::Nil,
+            (true, Value::Symbol(name)) => Datum::Symbol(name),
+            (true, Value::Boolean(value)) => Datum::Symbol(value.to_string()),
+            (true, Value::String(value)) => Datum::String(value),
+            (true, Value::Integer(value)) => Datum::Number(value as f64),
+            (true, Value::Float(value)) => Datum::Number(value),
+            (true, value) => return Err(format!("{} is not code", value)),
+            (false, Value::Nil) => Datum::List(items),
+            // An improper list [A B | T] is (cons A (cons B T))
+            (false, tail) => items.into_iter().rev().try_fold(Datum::from_value(&tail)?, |tail, head| {
+                Ok::<_, String>(Datum::List(vec![Datum::Symbol("cons".to_string()), head, tail]))
+            })?,
+        })
+    }
+
+    fn to_sexpr(&self) -> Result<SExpr<'_>, String> {
+        let span = Span::default();
+        Ok(match self {
+            // Symbols are read, to be told apart from keywords and operators
+            // as they are in source
+            Datum::Symbol(name) => match read(name).as_deref() {
+                Ok([atom @ SExpr::Atom(..)]) => atom.clone(),
+                _ => return Err(format!("the symbol {:?} cannot be read", name)),
+            },
+            Datum::String(value) => SExpr::Atom(Token::Literal(value), span),
+            Datum::Number(value) => SExpr::Atom(Token::Number(*value), span),
+            Datum::Nil => SExpr::Bracket(Vec::new(), span),
+            Datum::List(items) => SExpr::List(
+                items.iter().map(Datum::to_sexpr).collect::<Result<Vec<_>, _>>()?,
+                span,
+            ),
+        })
+    }
+}
+
+/// Print code held as data: proper lists are forms in parentheses.
+fn value_to_source(value: &Value) -> String {
+    let mut items = Vec::new();
+    let mut rest = value.clone();
+    while let Some((head, tail)) = rest.as_cons() {
+        items.push(head);
+        rest = tail;
+    }
+
+    match (items.is_empty(), rest) {
+        (true, Value::Nil) => "[]".to_string(),
+        (true, Value::Symbol(name)) => name,
+        (true, Value::Boolean(value)) => value.to_string(),
+        (true, Value::String(value)) => format!("\"{}\"", value),
+        (true, value) => value.to_string(),
+        (false, Value::Nil) => format!(
+            "({})",
+            items.iter().map(value_to_source).collect::<Vec<_>>().join(" ")
+        ),
+        // An improper list [A B | T] is (cons A (cons B T))
+        (false, tail) => items.iter().rev().fold(value_to_source(&tail), |tail, head| {
+            format!("(cons {} {})", value_to_source(head), tail)
+        }),
+    }
+}
@@ -0,0 +1,148 @@
+//! Packages
+//!
+//! `(package name [Exports...] Forms...)` gives every symbol in `Forms` a
+//! `name.` prefix so that separately written code cannot clash. A symbol
+//! keeps its name when it is
+//!
+//! - a variable,
+//! - listed in the exports,
+//! - already in the `shen.` namespace, or
+//! - one of Shen's own functions or keywords.
+//!
+//! The package `null` prefixes nothing.
+
+use std::convert::Infallible;
+
+use crate::ast::fold::{self, Fold};
+use crate::ast::{Pattern, ShenNode, Symbol, YaccItem};
+
+/// Symbols that belong to the language and are never prefixed.
+const SYSTEM_SYMBOLS: &[&str] = &[
+    // Special forms and keywords
+    "and", "or", "if", "cond", "let", "lambda", "/.", "freeze", "thaw", "trap-error",
+    "define", "defun", "defmacro", "defprolog", "defcc", "prolog?", "package", "cases",
+    "true", "false", "->", "<-", "<--", "where", ":=", ";", "_", "<e>", "<!>", "-->",
+    "!", "is", "bind", "when", "call", "return", "fail", "fail-if", "findall",
+    // Types
+    "number", "string", "symbol", "boolean", "list", "vector", "lazy", "stream",
+    "in", "out",
+    // Primitives
+    "cons", "hd", "tl", "cons?", "=", "+", "-", "*", "/", ">", "<", ">=", "<=",
+    "intern", "set", "value", "bound?", "simple-error", "error-to-string",
+    "absvector", "absvector?", "address->", "<-address", "string?", "number?",
+    "symbol?", "pos", "tlstr", "cn", "str", "string->n", "n->string", "eval-kl",
+    "get-time", "type", "open", "close", "read-byte", "write-byte",
+    // The standard library
+    "@p", "@s", "@v", "fst", "snd", "tuple?", "<>", "vector?", "<-vector", "vector->",
+    "limit", "head", "tail", "append", "reverse", "length", "element?", "empty?",
+    "map", "filter", "nth", "concat", "gensym", "variable?", "boolean?", "integer?",
+    "not", "print", "output", "error", "make-string", "put", "get", "unput", "input",
+    "input+", "read", "read-from-string", "lineread", "stinput", "stoutput", "prhush",
+    "pr", "nl", "tc", "load", "eval", "compile", "external", "explode", "occurrences",
+    "difference", "union", "intersection", "subst", "remove", "arity", "function",
+    "apply", "assoc", "hdv", "hdstr", "fn", "abs", "sum", "adjoin", "do", "destroy",
+    "declare", "first", "hash", "fix", "specialise", "systemf",
+];
+
+/// Prefix the symbols of a package's `body` with `name.`.
+pub fn qualify_package(name: &str, exports: &[Symbol], body: Vec<ShenNode>) -> Vec<ShenNode> {
+    if name == "null" {
+        return body;
+    }
+    let mut qualifier = Qualifier {
+        prefix: format!("{}.", name),
+        exports,
+    };
+    body.into_iter()
+        .map(|node| {
+            let Ok(node) = qualifier.fold_node(node);
+            node
+        })
+        .collect()
+}
+
+struct Qualifier<'a> {
+    prefix: String,
+    exports: &'a [Symbol],
+}
+
+impl Qualifier<'_> {
+    /// Whether `name` is left as it is: a variable, a system or exported
+    /// symbol, or one already in a package.
+    fn keeps(&self, name: Symbol) -> bool {
+        name.starts_with(|c: char| c.is_uppercase())
+            || name.starts_with("shen.")
+            || name.starts_with(&self.prefix)
+            || self.exports.contains(&name)
+            || SYSTEM_SYMBOLS.contains(&name.as_str())
+    }
+
+    fn symbol(&self, name: Symbol) -> Symbol {
+        match self.keeps(name) {
+            true => name,
+            false => Symbol::intern(&format!("{}{}", self.prefix, name)),
+        }
+    }
+}
+
+/// Qualifies symbols, the names of definitions and the symbols in their
+/// patterns and grammar rules.
+impl Fold for Qualifier<'_> {
+    type Error = Infallible;
+
+    fn fold_node(&mut self, node: ShenNode) -> Result<ShenNode, Infallible> {
+        // Nested packages keep their own names
+        if let ShenNode::Package { .. } = node {
+            return Ok(node);
+        }
+        Ok(match fold::walk_node(self, node)? {
+            ShenNode::Symbol { name, type_hint } => ShenNode::Symbol {
+                name: self.symbol(name),
+                type_hint,
+            },
+            ShenNode::Function {
+                name,
+                args,
+                return_type,
+                body,
+            } => ShenNode::Function {
+                name: self.symbol(name),
+                args,
+                return_type,
+                body,
+            },
+            ShenNode::Define { name, rules } => ShenNode::Define {
+                name: self.symbol(name),
+                rules,
+            },
+            ShenNode::Defmacro { name, rules } => ShenNode::Defmacro {
+                name: self.symbol(name),
+                rules,
+            },
+            ShenNode::Defprolog { name, clauses } => ShenNode::Defprolog {
+                name: self.symbol(name),
+                clauses,
+            },
+            ShenNode::Defcc { name, rules } => ShenNode::Defcc {
+                name: self.symbol(name),
+                rules,
+            },
+            node => node,
+        })
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Result<Pattern, Infallible> {
+        Ok(match fold::walk_pattern(self, pattern)? {
+            Pattern::Symbol(name) => Pattern::Symbol(self.symbol(name)),
+            pattern => pattern,
+        })
+    }
+
+    fn fold_yacc_item(&mut self, item: YaccItem) -> Result<YaccItem, Infallible> {
+        Ok(match item {
+            YaccItem::NonTerminal(name) => YaccItem::NonTerminal(self.symbol(name)),
+            YaccItem::Symbol(name) => YaccItem::Symbol(self.symbol(name)),
+            item => item,
+        })
+    }
+}
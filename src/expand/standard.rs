@@ -0,0 +1,214 @@
+//! The standard Shen macros
+//!
+//! These rewrite the sugar the Shen kernel defines as built-in macros into
+//! the core forms code generation understands:
+//!
+//! - `(@p A B C)`, `(@s A B C)` and `(@v A B V)` nest into pairs
+//! - `(/. X Y Body)` curries into single-argument lambdas
+//! - `(let X 1 Y 2 Body)` nests into single-binding `let`s
+//! - `(input+ Type)` reads from `(stinput)`
+//! - `(output Format Args...)` and `(error Format Args...)` build their
+//!   message with `make-string`, which becomes `cn` and `shen.app` calls
+//! - `(put X P V)` and `(get X P)` use the global `*property-vector*`
+//! - `(cases C1 R1 C2 R2 ...)` becomes a chain of `if`s
+//!
+//! `defcc` and `defprolog`, which the kernel also implements as macros, have
+//! their own AST nodes and code generators instead.
+
+use crate::ast::fold::{self, Fold};
+use crate::ast::{ShenNode, ShenType, ShenValue, Symbol};
+use crate::error::TranspilerError;
+
+/// Expand the standard macros throughout `node`.
+pub fn expand_standard(node: ShenNode) -> Result<ShenNode, TranspilerError> {
+    Standard.fold_node(node)
+}
+
+/// Rewrites a node until no standard macro applies, then its children.
+struct Standard;
+
+impl Fold for Standard {
+    type Error = TranspilerError;
+
+    fn fold_node(&mut self, mut node: ShenNode) -> Result<ShenNode, TranspilerError> {
+        while let Some(expansion) = rewrite(&node)? {
+            node = expansion;
+        }
+        let node = match node {
+            node @ ShenNode::Defmacro { .. } => return Ok(node),
+            node => fold::walk_node(self, node)?,
+        };
+        // (@v A (@v B <>)) only folds into a vector literal once its tail has
+        // become one, so look again now the children are expanded
+        match rewrite(&node)? {
+            Some(expansion) => self.fold_node(expansion),
+            None => Ok(node),
+        }
+    }
+}
+
+/// Apply one standard macro to `node` itself, or return `None` if none
+/// applies.
+fn rewrite(node: &ShenNode) -> Result<Option<ShenNode>, TranspilerError> {
+    let (name, args) = match node {
+        ShenNode::Application { func, args } => match func.as_ref() {
+            ShenNode::Symbol { name, .. } => (name.as_str(), args.as_slice()),
+            _ => return Ok(None),
+        },
+        // (@v A <>) is the vector literal <A>
+        ShenNode::VectorCons { head, tail } => {
+            return Ok(match tail.as_ref() {
+                ShenNode::Vector { elements } => Some(ShenNode::Vector {
+                    elements: std::iter::once(head.as_ref().clone())
+                        .chain(elements.iter().cloned())
+                        .collect(),
+                }),
+                _ => None,
+            })
+        }
+        _ => return Ok(None),
+    };
+
+    let expansion = match (name, args) {
+        ("@p", [_, _, _, ..]) => nest(args, |first, second| ShenNode::Tuple {
+            first: Box::new(first),
+            second: Box::new(second),
+        }),
+        ("@s", [_, _, _, ..]) => nest(args, |head, tail| ShenNode::StringCons {
+            head: Box::new(head),
+            tail: Box::new(tail),
+        }),
+        ("@v", [_, _, _, ..]) => nest(args, |head, tail| ShenNode::VectorCons {
+            head: Box::new(head),
+            tail: Box::new(tail),
+        }),
+        ("/.", [params @ .., body]) if !params.is_empty() => {
+            let params = params
+                .iter()
+                .map(|param| match param {
+                    ShenNode::Symbol { name, type_hint } => Ok((*name, type_hint.clone())),
+                    _ => Err(macro_error("/. expects variables before its body")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            ShenNode::curried_lambda(params, body.clone())
+                .ok_or_else(|| macro_error("/. expects at least one variable"))?
+        }
+        ("let", [_, _, _]) => return Ok(None),
+        ("let", [variable, value, rest @ ..]) => {
+            if rest.len() % 2 == 0 {
+                return Err(macro_error("let expects variable and value pairs and a body"));
+            }
+            application("let", vec![variable.clone(), value.clone(), application("let", rest.to_vec())])
+        }
+        ("input+", [type_]) => application("input+", vec![type_.clone(), application("stinput", vec![])]),
+        ("output", [_, ..]) => application(
+            "prhush",
+            vec![application("make-string", args.to_vec()), application("stoutput", vec![])],
+        ),
+        ("error", [_, ..]) => {
+            application("simple-error", vec![application("make-string", args.to_vec())])
+        }
+        ("make-string", [ShenNode::Literal { value: ShenValue::String(format) }, args @ ..]) => {
+            make_string(format, args)?
+        }
+        ("put", [_, _, _]) | ("get", [_, _]) => {
+            let mut args = args.to_vec();
+            args.push(application("value", vec![symbol("*property-vector*")]));
+            application(name, args)
+        }
+        ("cases", _) => {
+            if args.len() % 2 != 0 {
+                return Err(macro_error("cases expects condition and result pairs"));
+            }
+            let failure = application("simple-error", vec![string("error: cases exhausted")]);
+            args.chunks(2).rev().fold(failure, |rest, case| ShenNode::Conditional {
+                condition: Box::new(case[0].clone()),
+                true_branch: Box::new(case[1].clone()),
+                false_branch: Some(Box::new(rest)),
+            })
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(expansion))
+}
+
+/// `(make-string "~A and ~S~%" X Y)`: `~A`, `~S` and `~R` print the next
+/// argument with `shen.app` followed by the rest of the string; `~%` is a
+/// newline.
+fn make_string(format: &str, args: &[ShenNode]) -> Result<ShenNode, TranspilerError> {
+    enum Piece<'a> {
+        Text(String),
+        Arg(&'a ShenNode, &'static str),
+    }
+
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut args = args.iter();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        let mode = match (c, chars.clone().next()) {
+            ('~', Some('A')) => "shen.a",
+            ('~', Some('S')) => "shen.s",
+            ('~', Some('R')) => "shen.r",
+            ('~', Some('%')) => {
+                chars.next();
+                text.push('\n');
+                continue;
+            }
+            _ => {
+                text.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        let arg = args
+            .next()
+            .ok_or_else(|| macro_error(format!("too few arguments for {:?}", format)))?;
+        pieces.push(Piece::Text(std::mem::take(&mut text)));
+        pieces.push(Piece::Arg(arg, mode));
+    }
+    if args.next().is_some() {
+        return Err(macro_error(format!("too many arguments for {:?}", format)));
+    }
+
+    Ok(pieces.into_iter().rev().fold(string(&text), |rest, piece| match piece {
+        Piece::Text(text) if text.is_empty() => rest,
+        Piece::Text(text) => match rest {
+            ShenNode::Literal { value: ShenValue::String(rest) } => string(&(text + &rest)),
+            rest => application("cn", vec![string(&text), rest]),
+        },
+        Piece::Arg(arg, mode) => application("shen.app", vec![arg.clone(), rest, symbol(mode)]),
+    }))
+}
+
+fn nest(args: &[ShenNode], pair: impl Fn(ShenNode, ShenNode) -> ShenNode) -> ShenNode {
+    args.iter()
+        .cloned()
+        .rev()
+        .reduce(|second, first| pair(first, second))
+        .unwrap_or(ShenNode::Nil)
+}
+
+fn application(name: &str, args: Vec<ShenNode>) -> ShenNode {
+    ShenNode::Application {
+        func: Box::new(symbol(name)),
+        args,
+    }
+}
+
+fn symbol(name: &str) -> ShenNode {
+    ShenNode::Symbol {
+        name: Symbol::intern(name),
+        type_hint: ShenType::Symbol,
+    }
+}
+
+fn string(value: &str) -> ShenNode {
+    ShenNode::Literal {
+        value: ShenValue::String(value.to_string()),
+    }
+}
+
+fn macro_error(message: impl Into<String>) -> TranspilerError {
+    TranspilerError::MacroError(message.into())
+}
@@ -0,0 +1,581 @@
+//! Formatting Shen source
+//!
+//! Rewrites source in one canonical layout without changing what it reads
+//! as. Formatting works on the read forms rather than the parsed tree, so
+//! type signatures, `[X | Y]` written either way and the text of each atom
+//! are kept as written, and the comments the lexer finds are put back where
+//! they were: before the form they precede, or after it on the same line.
+//!
+//! The layout follows the [printer](crate::printer):
+//!
+//! - each rule of a `define` on its own line, with the `->`s aligned when
+//!   the patterns are short enough
+//! - each clause of a `defprolog` or `defcc` on its own line
+//! - the bindings of a `let` aligned and its body indented under them
+//! - one `cond` case per line
+//! - long applications and lists with their items aligned under the first
+//!
+//! Blank lines between forms, and between the rules of a definition, are
+//! kept, but never more than one in a row.
+
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use pretty::{Arena, DocAllocator};
+
+use crate::error::TranspilerError;
+use crate::parser::{comments, read, tokenize, Comment, SExpr, Token};
+use crate::printer::Doc;
+
+/// `source` in the canonical layout, fitting in `width` columns where it
+/// can. Fails if the source cannot be read.
+pub fn format_source(source: &str, width: usize) -> Result<String, TranspilerError> {
+    let forms = read(source).map_err(|error| error.into_transpiler_error(source))?;
+    let arena = Arena::new();
+    let mut formatter = Formatter {
+        a: &arena,
+        source,
+        comments: comments(source),
+        next: 0,
+        pos: 0,
+        width,
+    };
+    let doc = formatter.program(&forms);
+
+    let mut formatted = String::new();
+    doc.1
+        .render_fmt(width, &mut formatted)
+        .expect("writing to a string cannot fail");
+    // Blank lines are indented like their neighbours; other lines may end
+    // in a string or comment, whose spaces are kept
+    let mut formatted = formatted
+        .lines()
+        .map(|line| if line.trim().is_empty() { "" } else { line })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+
+    if !reads_the_same(source, &formatted) {
+        return Err(TranspilerError::InternalError(
+            "formatting would change the tokens or comments of the source".to_string(),
+        ));
+    }
+    Ok(formatted)
+}
+
+/// Format the file at `path` in place, or with `check` only see whether it
+/// needs it. Returns whether the file was not already formatted.
+pub fn format_file(path: impl AsRef<Path>, width: usize, check: bool) -> Result<bool, TranspilerError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|err| io_error(path, err))?;
+    let formatted = format_source(&source, width)?;
+    let changed = formatted != source;
+    if changed && !check {
+        fs::write(path, formatted).map_err(|err| io_error(path, err))?;
+    }
+    Ok(changed)
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> TranspilerError {
+    TranspilerError::IoError {
+        path: path.display().to_string(),
+        message: err.to_string(),
+    }
+}
+
+/// Whether `formatted` has the tokens and comments of `source`, ignoring
+/// the spaces that end the lines of comments.
+fn reads_the_same(source: &str, formatted: &str) -> bool {
+    let tokens = |input| tokenize(input).0.into_iter().map(|(token, _)| token).collect::<Vec<_>>();
+    let comments = |input| {
+        comments(input)
+            .into_iter()
+            .map(|(text, _)| text.lines().map(str::trim_end).collect::<Vec<_>>().join("\n"))
+            .collect::<Vec<_>>()
+    };
+    tokens(source) == tokens(formatted) && comments(source) == comments(formatted)
+}
+
+/// A formatted form with the comments around it.
+struct Item<'a> {
+    /// The comments before it, each followed by a line break
+    comments: Doc<'a>,
+    /// The form and the comments after it on its line
+    doc: Doc<'a>,
+    /// Whether it ends in a line comment, so that what follows it has to
+    /// start a new line
+    breaks: bool,
+    /// Whether a blank line comes before it, or before its first comment
+    blank: bool,
+}
+
+impl<'a> Item<'a> {
+    fn whole(self) -> Doc<'a> {
+        self.comments.append(self.doc)
+    }
+
+    /// The item as a line of a definition, and whether a blank line comes
+    /// before it.
+    fn line(self) -> (Doc<'a>, bool) {
+        let blank = self.blank;
+        (self.whole(), blank)
+    }
+}
+
+struct Formatter<'a> {
+    a: &'a Arena<'a>,
+    source: &'a str,
+    comments: Vec<Comment<'a>>,
+    /// The first comment not yet placed
+    next: usize,
+    /// How far into the source has been formatted
+    pos: usize,
+    width: usize,
+}
+
+impl<'a> Formatter<'a> {
+    /// The forms of a program, one or two lines apart as in the source, and
+    /// the comments after the last.
+    fn program(&mut self, forms: &[SExpr<'a>]) -> Doc<'a> {
+        let mut lines: Vec<_> = self.items(forms).into_iter().map(Item::line).collect();
+        while let Some((text, span)) = self.comment_before(self.source.len()) {
+            lines.push((self.a.text(text), self.blank_before(span.start)));
+            self.pos = span.end;
+        }
+        let a = self.a;
+        a.concat(lines.into_iter().enumerate().map(|(index, (line, blank))| match index {
+            0 => line,
+            _ => a.hardline().append(if blank { a.hardline() } else { a.nil() }).append(line),
+        }))
+    }
+
+    fn items(&mut self, exprs: &[SExpr<'a>]) -> Vec<Item<'a>> {
+        exprs.iter().map(|expr| self.item(expr)).collect()
+    }
+
+    /// `expr` with the comments before it, and those after it on its line.
+    fn item(&mut self, expr: &SExpr<'a>) -> Item<'a> {
+        let start = expr.span().start;
+        let blank = self.blank_before(self.upcoming(start));
+        let mut comments = self.a.nil();
+        while let Some((text, span)) = self.comment_before(start) {
+            self.pos = span.end;
+            comments = comments.append(text).append(self.a.hardline());
+            if self.blank_before(self.upcoming(start)) {
+                comments = comments.append(self.a.hardline());
+            }
+        }
+        let mut doc = self.expr(expr);
+
+        let mut breaks = false;
+        while let Some(&(text, span)) = self.comments.get(self.next) {
+            let between = &self.source[self.pos..span.start];
+            if breaks || between.contains('\n') || !between.trim().is_empty() {
+                break;
+            }
+            self.next += 1;
+            self.pos = span.end;
+            doc = doc.append(" ").append(text);
+            breaks = text.starts_with("\\\\");
+        }
+        Item {
+            comments,
+            doc,
+            breaks,
+            blank,
+        }
+    }
+
+    /// The next comment, if it starts before `before`.
+    fn comment_before(&mut self, before: usize) -> Option<Comment<'a>> {
+        let comment = self.comments.get(self.next).filter(|(_, span)| span.start < before).copied()?;
+        self.next += 1;
+        Some(comment)
+    }
+
+    /// Where the next comment starts, if before `before`, or else `before`.
+    fn upcoming(&self, before: usize) -> usize {
+        self.comments.get(self.next).map_or(before, |(_, span)| span.start.min(before))
+    }
+
+    fn blank_before(&self, start: usize) -> bool {
+        self.source[self.pos..start].matches('\n').count() > 1
+    }
+
+    fn text(&self, expr: &SExpr<'a>) -> Option<&'a str> {
+        match expr {
+            SExpr::Atom(_, span) => Some(&self.source[span.start..span.end]),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self, expr: &SExpr<'a>) -> Doc<'a> {
+        let span = expr.span();
+        let doc = match expr {
+            SExpr::Atom(..) => self.a.text(&self.source[span.start..span.end]),
+            SExpr::List(exprs, _) | SExpr::Bracket(exprs, _) | SExpr::Brace(exprs, _) => {
+                self.pos = span.start + 1;
+                let items = self.items(exprs);
+                let end = self.end(span.end - 1, items.last());
+                match expr {
+                    SExpr::List(..) => self.list(exprs, items, end),
+                    SExpr::Bracket(..) => self.sequence("[", items, end, "]"),
+                    _ => self.sequence("{", items, end, "}"),
+                }
+            }
+        };
+        self.pos = span.end;
+        doc
+    }
+
+    /// The comments left in a group before its closer at `end`, each on its
+    /// own line, and a line break if the closer has to go on the next.
+    fn end(&mut self, end: usize, last: Option<&Item<'a>>) -> Doc<'a> {
+        let mut doc = self.a.nil();
+        let mut breaks = last.is_some_and(|item| item.breaks);
+        while let Some((text, span)) = self.comment_before(end) {
+            if self.blank_before(span.start) {
+                doc = doc.append(self.a.hardline());
+            }
+            doc = doc.append(self.a.hardline()).append(text);
+            self.pos = span.end;
+            breaks = text.starts_with("\\\\");
+        }
+        match breaks {
+            true => doc.append(self.a.hardline()),
+            false => doc,
+        }
+    }
+
+    /// `separator`, or a new line after an item that ends in a line comment.
+    fn after(&self, item: &Item<'a>, separator: Doc<'a>) -> Doc<'a> {
+        match item.breaks {
+            true => self.a.hardline(),
+            false => separator,
+        }
+    }
+
+    fn join(&self, items: Vec<Item<'a>>, separator: impl Fn() -> Doc<'a>) -> Doc<'a> {
+        let mut doc = self.a.nil();
+        let mut breaks = None;
+        for item in items {
+            match breaks.replace(item.breaks) {
+                Some(true) => doc = doc.append(self.a.hardline()),
+                Some(false) => doc = doc.append(separator()),
+                None => {}
+            }
+            doc = doc.append(item.whole());
+        }
+        doc
+    }
+
+    /// `[...]` or `{...}`, the items aligned under the first when they break.
+    fn sequence(&self, open: &'a str, items: Vec<Item<'a>>, end: Doc<'a>, close: &'a str) -> Doc<'a> {
+        let a = self.a;
+        let items = self.join(items, || a.line());
+        a.text(open).append(items.append(end).align()).append(close).group()
+    }
+
+    fn list(&self, exprs: &[SExpr<'a>], items: Vec<Item<'a>>, end: Doc<'a>) -> Doc<'a> {
+        let head = exprs.first().and_then(|head| match head {
+            SExpr::Atom(Token::Lambda, _) => Some("/."),
+            _ => self.text(head),
+        });
+        let formatted = match head {
+            Some("define" | "defmacro") => self.define(exprs, items, end),
+            Some("defprolog") => self.clauses(exprs, items, end, "<--"),
+            Some("defcc") => self.clauses(exprs, items, end, ":="),
+            Some("package") if exprs.len() >= 3 => Ok(self.definition(items, 3, end)),
+            Some("let") if exprs.len() >= 4 && exprs.len().is_multiple_of(2) => Ok(self.let_form(items, end)),
+            Some("cond") if exprs.len() >= 2 => Ok(self.cond(items, end)),
+            Some("defun") if exprs.len() == 4 => Ok(self.function(items, end)),
+            Some("/." | "lambda") if exprs.len() >= 3 => Ok(self.function(items, end)),
+            _ => Err((items, end)),
+        };
+        formatted.unwrap_or_else(|(items, end)| self.form(items, end))
+    }
+
+    /// `(head Items...)`, the items aligned under the first when they break.
+    fn form(&self, items: Vec<Item<'a>>, end: Doc<'a>) -> Doc<'a> {
+        let a = self.a;
+        let mut items = items.into_iter();
+        let Some(head) = items.next() else {
+            return a.text("(").append(end).append(")");
+        };
+        let rest: Vec<_> = items.collect();
+        let rest = match rest.is_empty() {
+            true => end,
+            false => self.after(&head, a.space()).append(self.join(rest, || a.line()).append(end).align()),
+        };
+        a.text("(").append(head.whole()).append(rest).append(")").group()
+    }
+
+    /// `(keyword Name ...` of its first `words` items, followed by the rest,
+    /// one per line and indented, with blank lines kept.
+    fn definition(&self, items: Vec<Item<'a>>, words: usize, end: Doc<'a>) -> Doc<'a> {
+        let a = self.a;
+        let mut items = items.into_iter();
+        let head = self.join(items.by_ref().take(words).collect(), || a.space());
+        let lines = items.map(Item::line).collect();
+        self.lines(head, lines, end)
+    }
+
+    /// `(head` followed by `lines`, each indented on its own line, after a
+    /// blank one where the source had one.
+    fn lines(&self, head: Doc<'a>, lines: Vec<(Doc<'a>, bool)>, end: Doc<'a>) -> Doc<'a> {
+        let a = self.a;
+        let lines = a.concat(lines.into_iter().map(|(line, blank)| {
+            let blank = if blank { a.hardline() } else { a.nil() };
+            blank.append(a.hardline()).append(line)
+        }));
+        a.text("(").append(head).append(lines.append(end).nest(2)).append(")")
+    }
+
+    /// `define` and `defmacro`: the signature and each rule on its own line,
+    /// with the `->`s of the rules aligned when every rule has patterns and
+    /// they are no wider than half the line.
+    fn define(&self, exprs: &[SExpr<'a>], items: Vec<Item<'a>>, end: Doc<'a>) -> Formatted<'a> {
+        let start = match exprs.get(2) {
+            Some(SExpr::Brace(..)) => 3,
+            _ => 2,
+        };
+        let Some(rules) = exprs.get(1).and_then(SExpr::identifier).and_then(|_| split_rules(&exprs[start..])) else {
+            return Err((items, end));
+        };
+        let a = self.a;
+
+        let widths = rules
+            .iter()
+            .map(|rule| {
+                let patterns = &exprs[start + rule.patterns.start..start + rule.patterns.end];
+                let (first, arrow) = (patterns.first()?, &exprs[start + rule.patterns.end]);
+                let commented = self.comments.iter().any(|(_, span)| {
+                    span.start > first.span().start && span.start < arrow.span().start
+                });
+                let width = patterns.iter().map(|pattern| self.flat(pattern).chars().count() + 1).sum::<usize>() - 1;
+                (!commented && width <= self.width / 2).then_some(width)
+            })
+            .collect::<Option<Vec<_>>>();
+        let widest = widths.as_ref().and_then(|widths| widths.iter().max().copied());
+
+        let mut items = items.into_iter();
+        let head = self.join(items.by_ref().take(2).collect(), || a.space());
+        let mut lines: Vec<_> = items.by_ref().take(start - 2).map(Item::line).collect();
+        for (index, rule) in rules.iter().enumerate() {
+            let mut patterns: Vec<_> = items.by_ref().take(rule.patterns.len()).collect();
+            let mut arrow = items.next().expect("a rule has an arrow");
+            let (comments, blank) = take_comments(a, patterns.first_mut().unwrap_or(&mut arrow));
+            let mut lhs = a.nil();
+            if let Some(last) = patterns.last() {
+                let padding = match (&widths, widest) {
+                    (Some(widths), Some(widest)) => " ".repeat(widest - widths[index]),
+                    _ => String::new(),
+                };
+                let separator = self.after(last, a.space());
+                lhs = self.join(patterns, || a.space()).append(padding).append(separator);
+            }
+
+            let mut rhs = vec![items.next().expect("a rule has a body")];
+            if rule.guard {
+                let keyword = items.next().expect("a guard follows where");
+                let guard = items.next().expect("a guard follows where");
+                rhs.push(self.guard(keyword, guard));
+            }
+            let rhs = self.after(&arrow, a.line()).append(self.join(rhs, || a.line()));
+            lines.push((comments.append(lhs.append(arrow.whole()).append(rhs.nest(2)).group()), blank));
+        }
+        Ok(self.lines(head, lines, end))
+    }
+
+    /// `defprolog` and `defcc`: each clause, up to its `;`, on its own line,
+    /// breaking after `arrow` when it is too long.
+    fn clauses(&self, exprs: &[SExpr<'a>], items: Vec<Item<'a>>, end: Doc<'a>, arrow: &str) -> Formatted<'a> {
+        if exprs.get(1).and_then(SExpr::identifier).is_none() {
+            return Err((items, end));
+        }
+        let a = self.a;
+        let mut items = items.into_iter();
+        let head = self.join(items.by_ref().take(2).collect(), || a.space());
+
+        let exprs = &exprs[2..];
+        let mut lines = Vec::new();
+        let mut start = 0;
+        while start < exprs.len() {
+            let stop = exprs[start..]
+                .iter()
+                .position(|expr| expr.is(&Token::Semicolon))
+                .map_or(exprs.len(), |stop| start + stop);
+            let split = exprs[start..stop].iter().position(|expr| self.text(expr) == Some(arrow));
+            let mut lhs: Vec<_> = items.by_ref().take(stop - start).collect();
+            let mut semicolon = (stop < exprs.len()).then(|| items.next().expect("a clause ends in a semicolon"));
+
+            let (comments, blank) = match (lhs.first_mut(), semicolon.as_mut()) {
+                (Some(first), _) | (None, Some(first)) => take_comments(a, first),
+                (None, None) => (a.nil(), false),
+            };
+            let breaks = lhs.last().is_some_and(|item| item.breaks);
+            let mut doc = match split {
+                Some(split) => {
+                    let rhs = self.guarded(&exprs[start + split + 1..stop], lhs.split_off(split + 1));
+                    let arrow = lhs.pop().expect("the arrow");
+                    let lhs = match lhs.last() {
+                        Some(last) => {
+                            let separator = self.after(last, a.space());
+                            self.join(lhs, || a.space()).append(separator)
+                        }
+                        None => a.nil(),
+                    };
+                    let rhs = match rhs.is_empty() {
+                        true => a.nil(),
+                        false => self.after(&arrow, a.line()).append(self.join(rhs, || a.line())),
+                    };
+                    lhs.append(arrow.whole()).append(rhs.nest(2))
+                }
+                None => self.join(lhs, || a.space()),
+            };
+            if let Some(semicolon) = semicolon {
+                doc = doc.append(if breaks { a.hardline() } else { a.nil() }).append(semicolon.whole());
+            }
+            lines.push((comments.append(doc.group()), blank));
+            start = stop + 1;
+        }
+        Ok(self.lines(head, lines, end))
+    }
+
+    /// `items`, with each `where` joined to the guard after it.
+    fn guarded(&self, exprs: &[SExpr<'a>], items: Vec<Item<'a>>) -> Vec<Item<'a>> {
+        let mut guarded = Vec::new();
+        let mut items = exprs.iter().zip(items).peekable();
+        while let Some((expr, item)) = items.next() {
+            match items.next_if(|_| expr.identifier() == Some("where")) {
+                Some((_, guard)) => guarded.push(self.guard(item, guard)),
+                None => guarded.push(item),
+            }
+        }
+        guarded
+    }
+
+    /// `where Guard` as one item.
+    fn guard(&self, keyword: Item<'a>, guard: Item<'a>) -> Item<'a> {
+        let separator = self.after(&keyword, self.a.space());
+        Item {
+            comments: self.a.nil(),
+            breaks: guard.breaks,
+            blank: keyword.blank,
+            doc: keyword.whole().append(separator).append(guard.whole()),
+        }
+    }
+
+    /// `(let X 1 Y 2 Body)`, the bindings aligned and the body indented.
+    fn let_form(&self, items: Vec<Item<'a>>, end: Doc<'a>) -> Doc<'a> {
+        let a = self.a;
+        let mut items = items.into_iter();
+        let keyword = items.next().expect("let");
+        let body = items.next_back().expect("a body");
+        let mut bindings = Vec::new();
+        while let (Some(variable), Some(value)) = (items.next(), items.next()) {
+            bindings.push(Item {
+                comments: a.nil(),
+                breaks: value.breaks,
+                blank: variable.blank,
+                doc: {
+                    let separator = self.after(&variable, a.space());
+                    variable.whole().append(separator).append(value.whole().align())
+                },
+            });
+        }
+        let last = bindings.last().is_some_and(|binding| binding.breaks);
+        let body = match last {
+            true => a.hardline(),
+            false => a.line(),
+        }
+        .append(body.whole())
+        .append(end);
+        let separator = self.after(&keyword, a.space());
+        a.text("(")
+            .append(keyword.whole())
+            .append(separator)
+            .append(self.join(bindings, || a.line()).align())
+            .append(body.nest(2))
+            .append(")")
+            .group()
+    }
+
+    /// `(cond ...)`, one case per line.
+    fn cond(&self, items: Vec<Item<'a>>, end: Doc<'a>) -> Doc<'a> {
+        let a = self.a;
+        let mut items = items.into_iter();
+        let keyword = items.next().expect("cond");
+        let separator = self.after(&keyword, a.space());
+        let cases = self.join(items.collect(), || a.hardline());
+        a.text("(").append(keyword.whole()).append(separator).append(cases.append(end).align()).append(")")
+    }
+
+    /// `defun`, `lambda` and `/.`: everything but the body on the first line
+    /// and the body indented under it when it does not fit.
+    fn function(&self, mut items: Vec<Item<'a>>, end: Doc<'a>) -> Doc<'a> {
+        let a = self.a;
+        let body = items.pop().expect("a body");
+        let separator = self.after(items.last().expect("a keyword"), a.line());
+        let head = self.join(items, || a.space());
+        a.text("(")
+            .append(head)
+            .append(separator.append(body.whole()).append(end).nest(2))
+            .append(")")
+            .group()
+    }
+
+    /// `expr` on one line, as it is formatted when it fits.
+    fn flat(&self, expr: &SExpr<'a>) -> String {
+        let items = |exprs: &[SExpr<'a>]| exprs.iter().map(|expr| self.flat(expr)).collect::<Vec<_>>().join(" ");
+        match expr {
+            SExpr::Atom(..) => self.text(expr).unwrap_or_default().to_string(),
+            SExpr::List(exprs, _) => format!("({})", items(exprs)),
+            SExpr::Bracket(exprs, _) => format!("[{}]", items(exprs)),
+            SExpr::Brace(exprs, _) => format!("{{{}}}", items(exprs)),
+        }
+    }
+}
+
+/// The comments before `item`, taken out to go before the line it starts
+/// rather than inside it, and whether a blank line comes before them.
+fn take_comments<'a>(a: &'a Arena<'a>, item: &mut Item<'a>) -> (Doc<'a>, bool) {
+    (std::mem::replace(&mut item.comments, a.nil()), item.blank)
+}
+
+/// A form laid out for its head, or its items and end back if it is not
+/// the shape the layout expects.
+type Formatted<'a> = Result<Doc<'a>, (Vec<Item<'a>>, Doc<'a>)>;
+
+/// Where a rule of a `define` is among the items after the name and
+/// signature: its patterns, which the arrow follows, then its body and
+/// whether a `where` and guard come after.
+struct RuleParts {
+    patterns: Range<usize>,
+    guard: bool,
+}
+
+/// The rules of a `define`, or `None` if `exprs` are not a sequence of
+/// `Patterns... -> Body` or `<-`, each with an optional `where Guard`.
+fn split_rules(exprs: &[SExpr]) -> Option<Vec<RuleParts>> {
+    let mut rules = Vec::new();
+    let mut start = 0;
+    while start < exprs.len() {
+        let arrow = start
+            + exprs[start..]
+                .iter()
+                .position(|expr| expr.is_operator("->") || expr.is_operator("<-"))?;
+        exprs.get(arrow + 1)?;
+        let guard = exprs.get(arrow + 2).is_some_and(|expr| expr.identifier() == Some("where"))
+            && exprs.get(arrow + 3).is_some();
+        rules.push(RuleParts {
+            patterns: start..arrow,
+            guard,
+        });
+        start = arrow + if guard { 4 } else { 2 };
+    }
+    Some(rules)
+}
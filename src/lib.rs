@@ -2,7 +2,14 @@
 //! 
 //! This library provides functionality for transpiling Shen language to Rust
 
+pub mod bootstrap;
 pub mod parser;
+pub mod repl;
 pub mod ast;
 pub mod codegen;
+pub mod expand;
+pub mod eval;
 pub mod error;
+pub mod formatter;
+pub mod printer;
+pub mod runtime;
@@ -2,11 +2,138 @@
 //! 
 //! This project aims to transpile Shen language code to Rust
 
-mod parser;
-mod ast;
-mod codegen;
-mod error;
+use std::path::PathBuf;
+use std::process;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use shen_transpiler::bootstrap::{bootstrap, check_fixtures, BootstrapOptions};
+use shen_transpiler::error::TranspilerError;
+use shen_transpiler::formatter::format_file;
+use shen_transpiler::printer::DEFAULT_WIDTH;
+use shen_transpiler::repl::{is_complete, Repl, ReplOutput};
+
+const USAGE: &str = "usage:
+    shen-transpile repl
+        evaluate Shen interactively; :help lists the commands
+    shen-transpile bootstrap <kernel-dir> <out-dir>
+        transpile the KLambda kernel in <kernel-dir> into a Rust crate
+    shen-transpile check <repl> <fixtures-dir>
+        run <repl> on each <name>.shen fixture and compare with <name>.expected
+    shen-transpile fmt [--check] [--width <n>] <file>...
+        rewrite Shen source files in the canonical layout; with --check, list
+        the files that would change and fail if there are any";
 
 fn main() {
-    println!("Shen Transpiler - Initial Setup");
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let result = match args.as_slice() {
+        ["repl"] => {
+            run_repl();
+            Ok(())
+        }
+        ["bootstrap", kernel_dir, out_dir] => {
+            bootstrap(kernel_dir, out_dir, &BootstrapOptions::default()).map(|generated| {
+                println!("wrote {} files to {}", generated.files.len(), out_dir);
+            })
+        }
+        ["check", repl, fixtures_dir] => check_fixtures(repl, fixtures_dir).map(|failures| {
+            for failure in &failures {
+                println!("FAIL {}\n--- expected\n{}\n--- actual\n{}", failure.name, failure.expected, failure.actual);
+            }
+            if !failures.is_empty() {
+                process::exit(1);
+            }
+        }),
+        ["fmt", args @ ..] => run_fmt(args),
+        _ => usage(),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn run_fmt(args: &[&str]) -> Result<(), TranspilerError> {
+    let mut check = false;
+    let mut width = DEFAULT_WIDTH;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--check" => check = true,
+            "--width" => width = args.next().and_then(|width| width.parse().ok()).unwrap_or_else(|| usage()),
+            file => files.push(file),
+        }
+    }
+    if files.is_empty() {
+        usage();
+    }
+
+    let mut unformatted = false;
+    for file in files {
+        if format_file(file, width, check)? && check {
+            println!("would reformat {}", file);
+            unformatted = true;
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_repl() {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("error: cannot start the line editor: {}", err);
+            process::exit(1);
+        }
+    };
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".shen-transpile-history"));
+    if let Some(history) = &history {
+        // No history yet is fine
+        let _ = editor.load_history(history);
+    }
+
+    let mut repl = Repl::new();
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { repl.prompt() } else { String::new() };
+        match editor.readline(&prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+                if !is_complete(&input) {
+                    continue;
+                }
+                let _ = editor.add_history_entry(input.trim());
+                match repl.handle(&std::mem::take(&mut input)) {
+                    Ok(ReplOutput::Text(text)) if text.is_empty() => {}
+                    Ok(ReplOutput::Text(text)) => println!("{}\n", text),
+                    Ok(ReplOutput::Quit) => break,
+                    Err(err) => println!("{}\n", err),
+                }
+            }
+            // Ctrl-C abandons the current input
+            Err(ReadlineError::Interrupted) => input.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                break;
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
 }
@@ -0,0 +1,137 @@
+//! Syntax errors
+//!
+//! A [`ParseError`] says what kind of mistake was found, where, what was
+//! there and what would have been accepted instead. It becomes a
+//! [`TranspilerError::ParseError`] once the source it points into is known
+//! to turn its span into a line and column.
+
+use std::fmt;
+
+use crate::error::TranspilerError;
+
+use super::token::Span;
+
+/// The kinds of syntax error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A `(`, `[` or `{` never closed, or a closer with nothing to close
+    UnbalancedParen,
+    /// A closer that does not match the innermost open group
+    MismatchedDelimiter,
+    /// Characters that do not make a token
+    InvalidToken,
+    /// No forms at all
+    EmptyInput,
+    /// A token or form where it is not allowed
+    UnexpectedToken,
+    /// A form that ends before all of its parts
+    UnexpectedEnd,
+    /// A rule, branch or definition without its body
+    MissingBody,
+    /// Something in pattern position that cannot be matched against
+    BadPattern,
+    /// Rules or clauses of one definition with different numbers of patterns
+    ArityMismatch,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseErrorKind::UnbalancedParen => "unbalanced parentheses",
+            ParseErrorKind::MismatchedDelimiter => "mismatched delimiter",
+            ParseErrorKind::InvalidToken => "invalid token",
+            ParseErrorKind::EmptyInput => "empty input",
+            ParseErrorKind::UnexpectedToken => "unexpected token",
+            ParseErrorKind::UnexpectedEnd => "unexpected end of form",
+            ParseErrorKind::MissingBody => "missing body",
+            ParseErrorKind::BadPattern => "bad pattern",
+            ParseErrorKind::ArityMismatch => "arity mismatch",
+        })
+    }
+}
+
+/// A syntax error in Shen source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// What would have been accepted: tokens in backticks, as `` `->` ``, or
+    /// descriptions of forms, as `a symbol`
+    pub expected: Vec<String>,
+    /// What was there instead; `None` at the end of the input
+    pub found: Option<String>,
+    pub span: Span,
+    /// Further explanation, such as the definition the error is in
+    pub notes: Vec<String>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> Self {
+        ParseError {
+            kind,
+            expected: Vec::new(),
+            found: None,
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn expected<S: Into<String>>(mut self, expected: impl IntoIterator<Item = S>) -> Self {
+        self.expected.extend(expected.into_iter().map(Into::into));
+        self
+    }
+
+    /// Expect tokens, written as in the source: `expected_tokens(["->"])`.
+    pub fn expected_tokens<'t>(self, tokens: impl IntoIterator<Item = &'t str>) -> Self {
+        self.expected(tokens.into_iter().map(|token| format!("`{}`", token)))
+    }
+
+    pub fn found(mut self, found: impl ToString) -> Self {
+        self.found = Some(found.to_string());
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// The error without what was found: the kind, what was expected and
+    /// the notes.
+    pub fn message(&self) -> String {
+        let mut message = self.kind.to_string();
+        match self.expected.as_slice() {
+            [] => {}
+            [expected] => message.push_str(&format!("; expected {}", expected)),
+            [init @ .., last] => {
+                message.push_str(&format!("; expected {} or {}", init.join(", "), last))
+            }
+        }
+        for note in &self.notes {
+            message.push_str(&format!("; {}", note));
+        }
+        message
+    }
+
+    /// The error as a [`TranspilerError::ParseError`], with the line and
+    /// column of its span in `input`.
+    pub fn into_transpiler_error(self, input: &str) -> TranspilerError {
+        let (line, column) = self.span.line_column(input);
+        TranspilerError::ParseError {
+            message: self.message(),
+            token: self.found.unwrap_or_else(|| "end of input".to_string()),
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.found {
+            Some(found) => write!(f, "{}, found {}", self.message(), found),
+            None => write!(f, "{}, found end of input", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -0,0 +1,219 @@
+//! Reader for KLambda, the language Shen's kernel is distributed in
+//!
+//! KLambda is plain s-expressions: no `[...]` lists, patterns or macros, just
+//! parenthesised forms, symbols, numbers and strings. Symbols may contain any
+//! character other than whitespace, parentheses and `"`, so `|`, `;` and `:=`,
+//! which the Shen reader treats specially, are ordinary symbols here. Strings
+//! have no escapes; the kernel builds special characters with `n->string`.
+//!
+//! The official kernel files have not yet been loaded with this reader. It is
+//! tested on the small hand-written files in `tests/fixtures/kernel`, which
+//! follow their shape, so loading the whole official kernel without errors is
+//! still to be shown.
+
+use std::fs;
+use std::path::Path;
+
+use super::reader::{read_klambda, SExpr};
+use super::token::{Span, Token};
+use crate::ast::{ShenNode, ShenType, ShenValue, Symbol};
+use crate::error::TranspilerError;
+
+/// Read every form of a KLambda file.
+pub fn parse_klambda(input: &str) -> Result<Vec<ShenNode>, TranspilerError> {
+    let forms = read_klambda(input).map_err(|error| error.into_transpiler_error(input))?;
+    let reader = Reader { input };
+    forms.iter().map(|form| reader.to_node(form)).collect()
+}
+
+/// Read the KLambda files of a Shen kernel, every `*.kl` file in `dir` in
+/// name order. The string at the head of each kernel file is its licence and
+/// is left out.
+pub fn load_kernel(dir: impl AsRef<Path>) -> Result<Vec<ShenNode>, TranspilerError> {
+    let dir = dir.as_ref();
+    let mut paths = fs::read_dir(dir)
+        .map_err(|err| io_error(dir, err))?
+        .map(|entry| entry.map(|entry| entry.path()).map_err(|err| io_error(dir, err)))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "kl"));
+    paths.sort();
+
+    let mut forms = Vec::new();
+    for path in paths {
+        let source = fs::read_to_string(&path).map_err(|err| io_error(&path, err))?;
+        let nodes = parse_klambda(&source).map_err(|err| match err {
+            TranspilerError::ParseError {
+                token,
+                message,
+                line,
+                column,
+            } => TranspilerError::ParseError {
+                token,
+                message: format!("{} in {}", message, path.display()),
+                line,
+                column,
+            },
+            err => err,
+        })?;
+        forms.extend(
+            nodes
+                .into_iter()
+                .filter(|node| !matches!(node, ShenNode::Literal { value: ShenValue::String(_) })),
+        );
+    }
+    Ok(forms)
+}
+
+/// Numbers are symbols that parse as one; `-` or `1+` stay symbols.
+fn number(text: &str) -> Option<ShenValue> {
+    if let Ok(value) = text.parse::<i64>() {
+        Some(ShenValue::Integer(value))
+    } else if text.contains(|c: char| c.is_ascii_digit()) && !text.contains(|c: char| c.is_alphabetic()) {
+        text.parse::<f64>().ok().map(ShenValue::Float)
+    } else {
+        None
+    }
+}
+
+/// The name of a symbol atom.
+fn symbol_name<'a>(sexpr: &SExpr<'a>) -> Option<&'a str> {
+    sexpr.identifier().filter(|text| number(text).is_none())
+}
+
+/// Gives read forms their meaning, with the source to place errors in.
+struct Reader<'a> {
+    input: &'a str,
+}
+
+impl Reader<'_> {
+    /// Give a read form its meaning as an AST node.
+    fn to_node(&self, sexpr: &SExpr) -> Result<ShenNode, TranspilerError> {
+        let (items, span) = match sexpr {
+            SExpr::Atom(Token::Identifier(text), _) => {
+                return Ok(number(text).map_or_else(|| symbol(text), literal))
+            }
+            SExpr::Atom(Token::Literal(text), _) => return Ok(literal(ShenValue::String(text.to_string()))),
+            SExpr::List(items, span) => (items.as_slice(), *span),
+            // The KLambda lexer makes nothing else
+            other => return Err(self.parse_error(&other.to_string(), "unexpected form", other.span())),
+        };
+        let head = match items.first() {
+            Some(head) => head,
+            None => return Ok(ShenNode::Nil),
+        };
+        let malformed = |form: &str| {
+            self.parse_error(form, &format!("malformed {}", form), span)
+        };
+        let boxed = |sexpr: &SExpr| self.to_node(sexpr).map(Box::new);
+
+        match (symbol_name(head), &items[1..]) {
+            (Some("defun"), [name, SExpr::List(params, _), body]) => {
+                let name = symbol_name(name).ok_or_else(|| malformed("defun"))?;
+                let args = params
+                    .iter()
+                    .map(|param| match symbol_name(param) {
+                        Some(param) => Ok((Symbol::intern(param), ShenType::Symbol)),
+                        None => Err(self.parse_error(
+                            name,
+                            "parameters must be symbols",
+                            param.span(),
+                        )),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let body = self.to_node(body)?;
+                Ok(ShenNode::Function {
+                    name: Symbol::intern(name),
+                    args,
+                    return_type: body.get_type(),
+                    body: Box::new(body),
+                })
+            }
+            (Some("lambda"), [param, body]) => {
+                let param = symbol_name(param).ok_or_else(|| malformed("lambda"))?;
+                let body = self.to_node(body)?;
+                Ok(ShenNode::Lambda {
+                    arg: (Symbol::intern(param), ShenType::Symbol),
+                    return_type: body.get_type(),
+                    body: Box::new(body),
+                })
+            }
+            (Some("let"), [variable, _, _]) if symbol_name(variable).is_some() => self.application(items),
+            (Some("if"), [condition, true_branch, false_branch]) => Ok(ShenNode::Conditional {
+                condition: boxed(condition)?,
+                true_branch: boxed(true_branch)?,
+                false_branch: Some(boxed(false_branch)?),
+            }),
+            (Some("cond"), clauses) => {
+                // (cond (C1 E1) (C2 E2) ...) fails when no condition holds
+                let failure = ShenNode::Application {
+                    func: Box::new(symbol("simple-error")),
+                    args: vec![literal(ShenValue::String("cond failure".to_string()))],
+                };
+                clauses.iter().rev().try_fold(failure, |rest, clause| match clause {
+                    SExpr::List(clause, _) if clause.len() == 2 => Ok(ShenNode::Conditional {
+                        condition: boxed(&clause[0])?,
+                        true_branch: boxed(&clause[1])?,
+                        false_branch: Some(Box::new(rest)),
+                    }),
+                    _ => Err(self.parse_error("cond", "malformed cond clause", clause.span())),
+                })
+            }
+            (Some("trap-error"), [body, handler]) => Ok(ShenNode::TrapError {
+                body: boxed(body)?,
+                handler: boxed(handler)?,
+            }),
+            (Some("freeze"), [body]) => Ok(ShenNode::Freeze { body: boxed(body)? }),
+            (Some("cons"), [head, tail]) => Ok(ShenNode::Cons {
+                head: boxed(head)?,
+                tail: boxed(tail)?,
+            }),
+            // (type X T) only annotates X
+            (Some("type"), [value, _]) => self.to_node(value),
+            (Some(operator @ ("+" | "-" | "*" | "/" | "=" | "<" | ">" | "<=" | ">=")), [left, right]) => {
+                Ok(ShenNode::BinaryOperation {
+                    operator: Symbol::intern(operator),
+                    left: boxed(left)?,
+                    right: boxed(right)?,
+                    result_type: match operator {
+                        "=" | "<" | ">" | "<=" | ">=" => ShenType::Boolean,
+                        _ => ShenType::Float,
+                    },
+                })
+            }
+            (Some(form @ ("defun" | "lambda" | "let" | "if" | "trap-error" | "freeze")), _) => {
+                Err(malformed(form))
+            }
+            _ => self.application(items),
+        }
+    }
+
+    fn application(&self, items: &[SExpr]) -> Result<ShenNode, TranspilerError> {
+        Ok(ShenNode::Application {
+            func: Box::new(self.to_node(&items[0])?),
+            args: items[1..].iter().map(|item| self.to_node(item)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn parse_error(&self, token: &str, message: &str, span: Span) -> TranspilerError {
+        let (line, column) = span.line_column(self.input);
+        TranspilerError::new_parse_error(token, message, line, column)
+    }
+}
+
+fn symbol(name: &str) -> ShenNode {
+    ShenNode::Symbol {
+        name: Symbol::intern(name),
+        type_hint: ShenType::Symbol,
+    }
+}
+
+fn literal(value: ShenValue) -> ShenNode {
+    ShenNode::Literal { value }
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> TranspilerError {
+    TranspilerError::IoError {
+        path: path.display().to_string(),
+        message: err.to_string(),
+    }
+}
@@ -1,189 +1,310 @@
 //! Parser for Shen language
-
+//!
+//! Parsing runs in two stages. The [`reader`] groups tokens into a tree of
+//! s-expressions with source spans; the functions here then give that tree
+//! its meaning, matching each special form on its shape.
+
+mod error;
+mod klambda;
+mod reader;
+mod spans;
 mod token;
 
-use crate::ast::{ShenNode, ShenType, ShenValue};
+use crate::ast::arena::{Ast, NodeId};
+use crate::ast::{Pattern, PrologClause, Rule, ShenNode, ShenType, ShenValue, Symbol, YaccItem, YaccRule};
 use crate::error::TranspilerError;
-use token::{tokenize, Token};
+use crate::expand::package::qualify_package;
+use crate::expand::standard::expand_standard;
+use reader::read_recovering;
+pub(crate) use reader::{read, SExpr};
+
+pub use error::{ParseError, ParseErrorKind};
+pub use klambda::{load_kernel, parse_klambda};
+pub use reader::is_unfinished;
+pub use token::{comments, tokenize, tokenize_partial, Comment, Span, Token};
 
+/// Parse a single Shen expression, with the standard macros expanded.
 pub fn parse_shen_source(input: &str) -> Result<ShenNode, TranspilerError> {
-    let tokens = tokenize(input).map_err(|e| TranspilerError::SyntaxError(e.to_string()))?;
+    parse_form(input).and_then(expand_standard)
+}
 
-    // Ensure tokens are not empty
-    if tokens.is_empty() {
-        return Err(TranspilerError::SyntaxError("Empty input".to_string()));
-    }
+fn parse_form(input: &str) -> Result<ShenNode, TranspilerError> {
+    read(input)
+        .and_then(|forms| match forms.as_slice() {
+            [] => Err(ParseError::new(ParseErrorKind::EmptyInput, Span::default())
+                .expected(["an expression"])),
+            // defun and lambda may be written without enclosing parentheses
+            [first @ SExpr::Atom(Token::Defun, _), .., last] => {
+                parse_function_definition(&forms, first.span().to(last.span()))
+            }
+            [first @ SExpr::Atom(Token::Lambda, _), .., last] => {
+                parse_lambda(&forms, first.span().to(last.span()))
+            }
+            [form] => parse_expression(form),
+            [_, extra, ..] => Err(unexpected(extra, "the end of input")
+                .note("only a single expression is read")),
+        })
+        .map_err(|error| error.into_transpiler_error(input))
+}
 
-    // Handle top-level parsing scenarios
-    match tokens[0] {
-        Token::OpenParen => {
-            // Handle complex expressions like function definitions, conditionals, etc.
-            parse_complex_expression(&tokens).map_err(|e| match e {
-                ParseError::Syntax(msg) => TranspilerError::SyntaxError(msg),
-                ParseError::Token(token, msg) => {
-                    TranspilerError::SyntaxError(format!("{}: {}", token, msg))
-                }
-            })
-        }
-        Token::Defun => {
-            // Handle function definitions
-            parse_function_definition(&tokens).map_err(|e| match e {
-                ParseError::Syntax(msg) => TranspilerError::SyntaxError(msg),
-                ParseError::Token(token, msg) => {
-                    TranspilerError::SyntaxError(format!("{}: {}", token, msg))
-                }
-            })
-        }
-        Token::Lambda => {
-            // Handle lambda expressions
-            parse_lambda(&tokens).map_err(|e| match e {
-                ParseError::Syntax(msg) => TranspilerError::SyntaxError(msg),
-                ParseError::Token(token, msg) => {
-                    TranspilerError::SyntaxError(format!("{}: {}", token, msg))
-                }
-            })
-        }
-        Token::Identifier(_) | Token::Number(_) | Token::Literal(_) => {
-            // Handle simple symbols, literals
-            parse_symbol_or_application(&tokens).map_err(|e| match e {
-                ParseError::Syntax(msg) => TranspilerError::SyntaxError(msg),
-                ParseError::Token(token, msg) => {
-                    TranspilerError::SyntaxError(format!("{}: {}", token, msg))
-                }
-            })
-        }
-        _ => Err(TranspilerError::SyntaxError(format!(
-            "Unsupported top-level token: {:?}",
-            tokens[0]
-        ))),
-    }
+/// Parse a form built as data rather than read from source, such as a
+/// macro's result, with the standard macros expanded.
+pub(crate) fn parse_built_form(sexpr: &SExpr) -> Result<ShenNode, TranspilerError> {
+    parse_expression(sexpr)
+        .map_err(|error| TranspilerError::SyntaxError(error.to_string()))
+        .and_then(expand_standard)
 }
 
-// New error enum for more granular parsing errors
-#[derive(Debug)]
-pub enum ParseError {
-    Syntax(String),
-    Token(String, String),
+/// Parse a whole program: a sequence of top-level forms, with the standard
+/// macros expanded.
+pub fn parse_shen_program(input: &str) -> Result<Vec<ShenNode>, TranspilerError> {
+    read(input)
+        .and_then(|forms| forms.iter().map(parse_expression).collect::<Result<Vec<_>, _>>())
+        .map_err(|error| error.into_transpiler_error(input))?
+        .into_iter()
+        .map(expand_standard)
+        .collect()
 }
 
-fn parse_expression(tokens: &[Token]) -> Result<ShenNode, ParseError> {
-    if tokens.is_empty() {
-        return Err(ParseError::Syntax("Empty input".to_string()));
+/// Parse a whole program into an [`Ast`], with the standard macros expanded.
+/// Returns the arena and the root of each top-level form. Every node has the
+/// span of the form it was parsed from; the nodes a standard macro builds
+/// have the span of the macro's form.
+pub fn parse_shen_program_arena(input: &str) -> Result<(Ast, Vec<NodeId>), TranspilerError> {
+    let sexprs = read(input).map_err(|error| error.into_transpiler_error(input))?;
+    let mut ast = Ast::new();
+    let mut forms = Vec::new();
+    for sexpr in &sexprs {
+        let node = parse_expression(sexpr).map_err(|error| error.into_transpiler_error(input))?;
+        let form = ast.add(expand_standard(node)?);
+        spans::record_spans(&mut ast, form, sexpr);
+        forms.push(form);
     }
+    Ok((ast, forms))
+}
 
-    match &tokens[0] {
-        Token::OpenParen => parse_complex_expression(tokens),
-        Token::Defun => parse_function_definition(tokens),
-        Token::Lambda => parse_lambda(tokens),
-        Token::Identifier(_) => parse_symbol_or_application(tokens),
-        _ => Err(ParseError::Syntax("Unexpected token".to_string())),
-    }
+/// A program parsed as far as possible; see [`parse_shen_program_partial`].
+#[derive(Debug)]
+pub struct PartialProgram {
+    /// One node per top-level form read, with [`ShenNode::Error`] standing in
+    /// for each form that could not be parsed
+    pub forms: Vec<ShenNode>,
+    /// Every syntax error found, in source order, each a
+    /// [`TranspilerError::ParseError`] with its line and column
+    pub errors: Vec<TranspilerError>,
 }
 
-fn parse_complex_expression(tokens: &[Token]) -> Result<ShenNode, ParseError> {
-    if tokens.len() < 2 {
-        return Err(ParseError::Syntax("Invalid complex expression".to_string()));
+/// Parse a whole program, carrying on past syntax errors so that all of them
+/// are reported at once. Forms that fail to parse become [`ShenNode::Error`]
+/// nodes. A form missing a `)` is taken to end where the next one starts,
+/// at the next `(` in the first column.
+pub fn parse_shen_program_partial(input: &str) -> PartialProgram {
+    let (sexprs, read_errors) = read_recovering(input);
+
+    let mut errors = Vec::new();
+    let mut forms = Vec::new();
+    for sexpr in &sexprs {
+        let span = sexpr.span();
+        // A form the reader had to repair is already reported
+        let repaired = read_errors
+            .iter()
+            .any(|error| span.start <= error.span.start && error.span.start < span.end);
+
+        let node = parse_expression(sexpr)
+            .map_err(|error| (error.span, error.into_transpiler_error(input)))
+            .and_then(|node| {
+                expand_standard(node).map_err(|error| {
+                    let (line, column) = span.line_column(input);
+                    let message = error.to_string();
+                    (span, TranspilerError::new_parse_error(&form_name(sexpr), &message, line, column))
+                })
+            });
+        match node {
+            Ok(node) => forms.push(node),
+            Err((span, error)) => {
+                forms.push(ShenNode::Error {
+                    message: match &error {
+                        TranspilerError::ParseError { message, .. } => message.clone(),
+                        error => error.to_string(),
+                    },
+                });
+                if !repaired {
+                    errors.push((span, error));
+                }
+            }
+        }
     }
-
-    match &tokens[1] {
-        Token::Identifier(op) if op == "if" => parse_conditional(tokens),
-        Token::Identifier(_) => parse_application(tokens),
-        _ => parse_list(tokens),
+    errors.extend(
+        read_errors
+            .into_iter()
+            .map(|error| (error.span, error.into_transpiler_error(input))),
+    );
+    errors.sort_by_key(|(span, _)| span.start);
+
+    PartialProgram {
+        forms,
+        errors: errors.into_iter().map(|(_, error)| error).collect(),
     }
 }
 
-fn parse_conditional(tokens: &[Token]) -> Result<ShenNode, ParseError> {
-    // More robust conditional parsing
-    if tokens.len() < 4 {
-        return Err(ParseError::Syntax("Invalid conditional".to_string()));
+/// How a form is named in errors: the head of a list, or the form itself.
+fn form_name(sexpr: &SExpr) -> String {
+    match sexpr {
+        SExpr::List(items, _) if !items.is_empty() => items[0].to_string(),
+        sexpr => sexpr.to_string(),
     }
+}
 
-    // Find the end of the condition
-    let mut condition_end = 2;
-    let mut paren_count = 0;
-    for (i, token) in tokens[2..].iter().enumerate() {
-        match token {
-            Token::OpenParen => paren_count += 1,
-            Token::CloseParen => {
-                if paren_count == 0 {
-                    condition_end = i + 2;
-                    break;
-                }
-                paren_count -= 1;
-            }
-            _ => {}
-        }
-    }
+/// `found` where `expected` should be.
+fn unexpected(found: &SExpr, expected: &str) -> ParseError {
+    ParseError::new(ParseErrorKind::UnexpectedToken, found.span())
+        .expected([expected])
+        .found(found)
+}
 
-    let condition_tokens = &tokens[2..condition_end];
-    let true_branch_tokens = &tokens[condition_end..];
+/// The form at `span` ends where `expected` should be.
+fn unexpected_end(span: Span, expected: &str) -> ParseError {
+    missing(ParseErrorKind::UnexpectedEnd, span, expected)
+}
 
-    let condition = parse_expression(condition_tokens)?;
+/// The form at `span` ends without its `body`.
+fn missing_body(span: Span, body: &str) -> ParseError {
+    missing(ParseErrorKind::MissingBody, span, body)
+}
 
-    // Handle multiple possible true branch scenarios
-    let true_branch = if !true_branch_tokens.is_empty() {
-        parse_expression(true_branch_tokens)?
-    } else {
-        return Err(ParseError::Syntax(
-            "Missing true branch in conditional".to_string(),
-        ));
+fn missing(kind: ParseErrorKind, span: Span, expected: &str) -> ParseError {
+    let closer = Span {
+        start: span.end.saturating_sub(1),
+        end: span.end,
     };
+    ParseError::new(kind, closer).expected([expected]).found(")")
+}
 
-    // Check for false branch
-    let false_branch = if true_branch_tokens.len() > 1 {
-        Some(Box::new(parse_expression(&true_branch_tokens[1..])?))
-    } else {
-        None
-    };
+/// The error for a form of fixed length that has too few or too many
+/// elements; `parts` describes each element after the head.
+fn wrong_length(elements: &[SExpr], span: Span, parts: &[&str]) -> ParseError {
+    match elements.get(parts.len() + 1) {
+        Some(extra) => unexpected(extra, "`)`"),
+        None => unexpected_end(span, parts[(elements.len() - 1).min(parts.len() - 1)]),
+    }
+}
 
-    Ok(ShenNode::Conditional {
-        condition: Box::new(condition),
-        true_branch: Box::new(true_branch),
-        false_branch,
-    })
+/// The name after the head of a definition, and the elements after it.
+fn definition_name<'e>(
+    elements: &'e [SExpr],
+    span: Span,
+    what: &str,
+) -> Result<(&'e str, &'e [SExpr<'e>]), ParseError> {
+    match elements {
+        [_, SExpr::Atom(Token::Identifier(name), _), rest @ ..] => Ok((name, rest)),
+        [_, name, ..] => Err(unexpected(name, what)),
+        _ => Err(unexpected_end(span, what)),
+    }
 }
 
-fn parse_application(tokens: &[Token]) -> Result<ShenNode, ParseError> {
-    // More robust application parsing
-    if tokens.len() < 2 {
-        return Err(ParseError::Syntax("Invalid application".to_string()));
+/// The items of a bracketed list and its optional `| Tail`.
+type ListParts<'e> = (&'e [SExpr<'e>], Option<&'e SExpr<'e>>);
+
+/// Split the elements of `[A B | T]` into the items and the optional tail.
+fn split_list_tail<'e>(elements: &'e [SExpr<'e>], span: Span) -> Result<ListParts<'e>, ParseError> {
+    match elements.iter().position(|element| element.is(&Token::Bar)) {
+        None => Ok((elements, None)),
+        Some(0) => Err(unexpected(&elements[0], "an item before `|`")),
+        Some(bar) => match &elements[bar + 1..] {
+            [tail] => Ok((&elements[..bar], Some(tail))),
+            [] => Err(unexpected_end(span, "a tail after `|`").found("]")),
+            [_, extra, ..] => Err(unexpected(extra, "`]`").note("`|` is followed by exactly one tail")),
+        },
     }
+}
 
-    let func_token = &tokens[1];
-    let args_tokens = &tokens[2..];
+fn parse_expression(sexpr: &SExpr) -> Result<ShenNode, ParseError> {
+    match sexpr {
+        SExpr::List(elements, span) => parse_complex_expression(elements, *span),
+        SExpr::Bracket(elements, span) => parse_bracket_list(elements, *span),
+        SExpr::Brace(..) => Err(unexpected(sexpr, "an expression")
+            .note("type signatures go after the name of a `define`")),
+        SExpr::Atom(token, _) => parse_atom(token).ok_or_else(|| unexpected(sexpr, "an expression")),
+    }
+}
 
-    // Parse function (can be a symbol, lambda, or nested application)
-    let func = match func_token {
-        Token::Identifier(name) => ShenNode::Symbol {
-            name: name.clone(),
-            type_hint: ShenType::Symbol,
-        },
-        Token::OpenParen => {
-            // Handle nested function or lambda
-            parse_complex_expression(&tokens[1..]).map_err(|_| {
-                ParseError::Syntax("Invalid nested function in application".to_string())
-            })?
-        }
-        Token::Lambda => {
-            // Handle lambda directly in application
-            parse_lambda(&tokens[1..])
-                .map_err(|_| ParseError::Syntax("Invalid lambda in application".to_string()))?
-        }
-        _ => {
-            return Err(ParseError::Syntax(
-                "Invalid function in application".to_string(),
-            ))
-        }
+fn parse_complex_expression(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    let name = match elements.first() {
+        None => return Ok(ShenNode::Nil),
+        Some(SExpr::Atom(Token::If, _)) => return parse_conditional(elements, span),
+        Some(SExpr::Atom(Token::Defun, _)) => return parse_function_definition(elements, span),
+        Some(SExpr::Atom(Token::Lambda, _)) => return parse_lambda(elements, span),
+        Some(SExpr::Atom(Token::List, _)) => return parse_list(elements),
+        Some(SExpr::Atom(Token::Identifier(name), _)) => *name,
+        Some(_) => return parse_application(elements),
     };
 
-    // Parse arguments with more flexibility
-    let mut args = Vec::new();
-    let mut current_pos = 0;
+    match (name, elements) {
+        ("define", _) => parse_define(elements, span),
+        ("defprolog", _) => parse_defprolog(elements, span),
+        ("prolog?", _) => parse_prolog_query(elements),
+        ("defcc", _) => parse_defcc(elements, span),
+        ("defmacro", _) => parse_defmacro(elements, span),
+        ("package", _) => parse_package(elements, span),
+        ("cons", [_, head, tail]) => Ok(ShenNode::Cons {
+            head: Box::new(parse_expression(head)?),
+            tail: Box::new(parse_expression(tail)?),
+        }),
+        ("@p", _) => parse_tuple(elements, span),
+        ("@v", _) => parse_vector_cons(elements, span),
+        ("@s", _) => parse_string_cons(elements, span),
+        ("freeze", _) => parse_freeze(elements, span),
+        ("trap-error", _) => parse_trap_error(elements, span),
+        _ => parse_application(elements),
+    }
+}
 
-    while current_pos < args_tokens.len() {
-        let (arg, consumed) = parse_application_argument(&args_tokens[current_pos..])?;
-        args.push(arg);
-        current_pos += consumed;
+fn parse_conditional(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (if condition true-branch [false-branch])
+    match elements {
+        [_, condition, true_branch, rest @ ..] if rest.len() <= 1 => {
+            let false_branch = match rest {
+                [false_branch] => Some(Box::new(parse_expression(false_branch)?)),
+                _ => None,
+            };
+
+            Ok(ShenNode::Conditional {
+                condition: Box::new(parse_expression(condition)?),
+                true_branch: Box::new(parse_expression(true_branch)?),
+                false_branch,
+            })
+        }
+        [_, _] => Err(missing_body(span, "a true branch")),
+        _ => Err(wrong_length(
+            elements,
+            span,
+            &["a condition", "a true branch", "a false branch"],
+        )),
+    }
+}
+
+fn parse_application(elements: &[SExpr]) -> Result<ShenNode, ParseError> {
+    let (func_sexpr, arg_sexprs) = elements
+        .split_first()
+        .expect("applications have at least a function");
+
+    let func = parse_expression(func_sexpr)?;
+
+    let args = arg_sexprs
+        .iter()
+        .map(parse_expression)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Binary operators get their own node so codegen can emit infix Rust
+    if let (SExpr::Atom(Token::Operator(operator), _), 2) = (func_sexpr, args.len()) {
+        let mut operands = args.into_iter();
+        let (left, right) = (operands.next().unwrap(), operands.next().unwrap());
+        return Ok(ShenNode::BinaryOperation {
+            operator: Symbol::intern(operator),
+            left: Box::new(left),
+            right: Box::new(right),
+            result_type: binary_result_type(operator),
+        });
     }
 
     Ok(ShenNode::Application {
@@ -192,368 +313,544 @@ fn parse_application(tokens: &[Token]) -> Result<ShenNode, ParseError> {
     })
 }
 
-// Helper function to parse individual application arguments
-fn parse_application_argument(tokens: &[Token]) -> Result<(ShenNode, usize), ParseError> {
-    if tokens.is_empty() {
-        return Err(ParseError::Syntax(
-            "Unexpected end of arguments".to_string(),
-        ));
+fn binary_result_type(operator: &str) -> ShenType {
+    match operator {
+        "=" | "<" | ">" | "<=" | ">=" => ShenType::Boolean,
+        _ => ShenType::Float,
     }
+}
 
-    match &tokens[0] {
-        Token::Identifier(name) => Ok((
-            ShenNode::Symbol {
-                name: name.clone(),
-                type_hint: ShenType::Symbol,
-            },
-            1,
-        )),
-        Token::Number(value) => Ok((
-            ShenNode::Literal {
-                value: ShenValue::Float(*value),
-            },
-            1,
-        )),
-        Token::Literal(value) => Ok((
-            ShenNode::Literal {
-                value: ShenValue::String(value.clone()),
-            },
-            1,
-        )),
-        Token::OpenParen => {
-            // Handle nested expressions (lists, applications, etc.)
-            let mut paren_count = 0;
-            let mut end_index = 0;
-
-            for (i, token) in tokens.iter().enumerate() {
-                match token {
-                    Token::OpenParen => paren_count += 1,
-                    Token::CloseParen => {
-                        paren_count -= 1;
-                        if paren_count == 0 {
-                            end_index = i + 1;
-                            break;
-                        }
-                    }
-                    _ => {}
-                }
-            }
-
-            if end_index == 0 {
-                return Err(ParseError::Syntax(
-                    "Unbalanced parentheses in argument".to_string(),
-                ));
-            }
-
-            // Recursively parse nested structure
-            let nested_tokens = &tokens[..end_index];
-            let result = match nested_tokens[1] {
-                Token::Lambda => parse_lambda(nested_tokens),
-                Token::Defun => parse_function_definition(nested_tokens),
-                Token::Identifier(_) => parse_application(nested_tokens),
-                _ => parse_list(nested_tokens),
-            }?;
-
-            Ok((result, end_index))
-        }
-        _ => Err(ParseError::Syntax(format!(
-            "Unsupported argument type: {:?}",
-            tokens[0]
-        ))),
-    }
-}
-
-fn parse_function_definition(tokens: &[Token]) -> Result<ShenNode, ParseError> {
-    // More robust function definition parsing
-    if tokens.len() < 4 {
-        return Err(ParseError::Syntax(
-            "Invalid function definition".to_string(),
-        ));
-    }
-
-    match &tokens[1..] {
-        [Token::Identifier(name), Token::OpenParen, rest @ ..] => {
-            let mut args = Vec::new();
-            let mut body_tokens = Vec::new();
-            let mut in_args = true;
-            let mut paren_count = 1;
-
-            for token in rest {
-                match token {
-                    Token::OpenParen => {
-                        if in_args {
-                            return Err(ParseError::Syntax(
-                                "Unexpected parenthesis in argument list".to_string(),
-                            ));
-                        }
-                        paren_count += 1;
-                        body_tokens.push(token.clone());
-                    }
-                    Token::CloseParen => {
-                        paren_count -= 1;
-                        if paren_count == 0 {
-                            in_args = false;
-                            continue;
-                        }
-                        body_tokens.push(token.clone());
-                    }
-                    Token::Identifier(arg) if in_args => {
-                        args.push((arg.clone(), ShenType::Symbol));
-                    }
-                    _ if !in_args => {
-                        body_tokens.push(token.clone());
-                    }
-                    _ => {
-                        return Err(ParseError::Syntax(
-                            "Invalid function definition".to_string(),
-                        ))
-                    }
-                }
-            }
-
-            if paren_count != 0 {
-                return Err(ParseError::Syntax(
-                    "Unbalanced parentheses in function definition".to_string(),
-                ));
-            }
-
-            let body = parse_expression(&body_tokens)?;
+fn parse_function_definition(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (defun name (arg1 arg2 ...) body)
+    let (name, rest) = definition_name(elements, span, "a function name")?;
+    match rest {
+        [SExpr::List(args, _), body] => {
+            let args = args
+                .iter()
+                .map(|arg| match arg.identifier() {
+                    Some(arg) => Ok((Symbol::intern(arg), ShenType::Symbol)),
+                    None => Err(unexpected(arg, "a parameter name")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let body = parse_expression(body)?;
 
             // Infer return type from body
             let return_type = body.get_type();
 
             Ok(ShenNode::Function {
-                name: name.clone(),
+                name: Symbol::intern(name),
                 args,
                 return_type,
                 body: Box::new(body),
             })
         }
-        _ => Err(ParseError::Syntax(
-            "Invalid function definition".to_string(),
-        )),
+        [] => Err(unexpected_end(span, "a parameter list")),
+        [SExpr::List(..)] => Err(missing_body(span, "a function body").note(format!("in defun {}", name))),
+        [SExpr::List(..), _, extra, ..] => Err(unexpected(extra, "`)`")),
+        [args, ..] => Err(unexpected(args, "a parameter list in parentheses")),
     }
 }
 
-fn parse_lambda(tokens: &[Token]) -> Result<ShenNode, ParseError> {
-    // Lambda syntax: (lambda (arg1 arg2 ...) body)
-    if tokens.len() < 4 {
-        return Err(ParseError::Syntax("Invalid lambda expression".to_string()));
+fn parse_define(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (define name {Signature} P1 P2 -> Body where Guard ...); the type
+    // signature is optional and not checked
+    let (name, rest) = definition_name(elements, span, "a function name")?;
+    let rest = match rest {
+        [SExpr::Brace(..), rest @ ..] => rest,
+        rest => rest,
+    };
+    if rest.is_empty() {
+        return Err(unexpected_end(span, "a rule").note(format!("in the definition of {}", name)));
     }
 
-    // Find the arguments list
-    let args_start = match tokens.get(1) {
-        Some(Token::OpenParen) => 2,
-        _ => {
-            return Err(ParseError::Syntax(
-                "Lambda arguments must be enclosed in parentheses".to_string(),
-            ))
-        }
-    };
+    Ok(ShenNode::Define {
+        name: Symbol::intern(name),
+        rules: parse_rules(name, rest, span)?,
+    })
+}
 
-    // Collect lambda arguments
-    let mut args = Vec::new();
-    let mut body_start = args_start;
-    for (i, token) in tokens[args_start..].iter().enumerate() {
-        match token {
-            Token::Identifier(arg) => {
-                args.push((arg.clone(), ShenType::Symbol));
-            }
-            Token::CloseParen => {
-                body_start = args_start + i + 1;
-                break;
-            }
-            _ => return Err(ParseError::Syntax("Invalid lambda argument".to_string())),
-        }
+fn parse_defmacro(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (defmacro name Pattern -> Expansion ...), matched against whole forms
+    let (name, rest) = definition_name(elements, span, "a macro name")?;
+    if rest.is_empty() {
+        return Err(unexpected_end(span, "a rule").note(format!("in macro {}", name)));
     }
 
-    // Parse lambda body
-    let body_tokens = &tokens[body_start..];
-    let body = parse_expression(body_tokens)?;
+    let rules = parse_rules(name, rest, span)?;
+    if rules[0].patterns.len() != 1 {
+        return Err(ParseError::new(ParseErrorKind::ArityMismatch, rest[0].span())
+            .expected(["one pattern"])
+            .found(format!("{} patterns", rules[0].patterns.len()))
+            .note(format!("rules of macro {} match a whole form", name)));
+    }
 
-    // Infer return type from body
-    let return_type = body.get_type();
+    Ok(ShenNode::Defmacro {
+        name: Symbol::intern(name),
+        rules,
+    })
+}
 
-    Ok(ShenNode::Lambda {
-        args,
-        return_type,
-        body: Box::new(body),
+fn parse_package(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (package name [Exports...] Forms...)
+    let (name, rest) = definition_name(elements, span, "a package name")?;
+    let (exports, body) = match rest {
+        [SExpr::Bracket(exports, _), body @ ..] => (exports, body),
+        [exports, ..] => return Err(unexpected(exports, "a list of exports")),
+        [] => return Err(unexpected_end(span, "a list of exports")),
+    };
+
+    let exports = exports
+        .iter()
+        .map(|export| match export.identifier() {
+            Some(export) => Ok(Symbol::intern(export)),
+            None => Err(unexpected(export, "a symbol").note(format!("in the exports of package {}", name))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let body = body
+        .iter()
+        .map(parse_expression)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ShenNode::Package {
+        name: Symbol::intern(name),
+        body: qualify_package(name, &exports, body),
+        exports,
     })
 }
 
-fn parse_list(tokens: &[Token]) -> Result<ShenNode, ParseError> {
-    // Ensure the list starts and ends with parentheses
-    if tokens.first() != Some(&Token::OpenParen) || tokens.last() != Some(&Token::CloseParen) {
-        return Err(ParseError::Syntax(
-            "Invalid list: must be enclosed in parentheses".to_string(),
-        ));
-    }
+/// The `Patterns -> Body where Guard` rules shared by `define` and `defmacro`.
+fn parse_rules(name: &str, rest: &[SExpr], span: Span) -> Result<Vec<Rule>, ParseError> {
+    let mut rules = Vec::new();
+    for forms in rule_forms(name, rest, span) {
+        let (patterns, arrow, body, guard) = forms?;
+        let first = patterns.first().unwrap_or(arrow);
+        let patterns = patterns
+            .iter()
+            .map(parse_pattern)
+            .collect::<Result<Vec<_>, _>>()?;
+        let body = parse_expression(body)?;
+
+        if rules.first().is_some_and(|rule: &Rule| rule.patterns.len() != patterns.len()) {
+            return Err(ParseError::new(ParseErrorKind::ArityMismatch, first.span().to(arrow.span()))
+                .expected([format!("{} patterns", rules[0].patterns.len())])
+                .found(format!("{} patterns", patterns.len()))
+                .note(format!("rules of {} take different numbers of arguments", name)));
+        }
 
-    // Check for explicit list creation
-    if tokens.get(1) == Some(&Token::List) {
-        // Remove outer parentheses and 'list' token
-        let inner_tokens = &tokens[2..tokens.len() - 1];
+        rules.push(Rule {
+            patterns,
+            body,
+            guard: guard.map(parse_expression).transpose()?,
+        });
+    }
 
-        // Parse list elements
-        let mut elements = Vec::new();
-        let mut current_pos = 0;
+    Ok(rules)
+}
 
-        while current_pos < inner_tokens.len() {
-            // Handle nested structures (nested lists, applications, etc.)
-            let (element, consumed) = parse_list_element(&inner_tokens[current_pos..])?;
-            elements.push(element);
-            current_pos += consumed;
+/// A rule as read: its patterns, its `->`, its body and its guard.
+type RuleForms<'e> = (&'e [SExpr<'e>], &'e SExpr<'e>, &'e SExpr<'e>, Option<&'e SExpr<'e>>);
+
+/// Split the rules of a `define` or `defmacro` one at a time, so that an
+/// error in one rule is found before the rules after it are split.
+fn rule_forms<'e>(
+    name: &'e str,
+    mut rest: &'e [SExpr<'e>],
+    span: Span,
+) -> impl Iterator<Item = Result<RuleForms<'e>, ParseError>> {
+    let in_rule = move || format!("in a rule of {}", name);
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
         }
+        let Some(arrow) = rest.iter().position(|element| element.is_operator("->")) else {
+            rest = &[];
+            return Some(Err(unexpected_end(span, "`->`").note(in_rule())));
+        };
+        let Some(body) = rest.get(arrow + 1) else {
+            rest = &[];
+            return Some(Err(missing_body(span, "a rule body").note(in_rule())));
+        };
+        let forms = (&rest[..arrow], &rest[arrow], body);
+        rest = &rest[arrow + 2..];
+
+        let guard = match rest {
+            [keyword, guard, remaining @ ..] if keyword.identifier() == Some("where") => {
+                rest = remaining;
+                Some(guard)
+            }
+            _ => None,
+        };
+        Some(Ok((forms.0, forms.1, forms.2, guard)))
+    })
+}
 
-        // Infer element type from first element if possible
-        let element_type = elements
-            .first()
-            .map(|elem| elem.get_type())
-            .unwrap_or(ShenType::Symbol);
+/// A clause of a `defprolog` or `defcc` and the `;` that ends it.
+type Clause<'e> = (&'e [SExpr<'e>], &'e SExpr<'e>);
 
-        Ok(ShenNode::List {
-            elements,
-            element_type,
+/// Split the elements of a `defprolog` or `defcc` into its clauses.
+fn split_clauses<'e>(rest: &'e [SExpr], span: Span, name: &str) -> Result<Vec<Clause<'e>>, ParseError> {
+    rest.split_inclusive(|element| element.is(&Token::Semicolon))
+        .map(|clause| match clause.split_last() {
+            Some((semicolon, clause)) if semicolon.is(&Token::Semicolon) => Ok((clause, semicolon)),
+            _ => Err(unexpected_end(span, "`;`").note(format!("after a clause of {}", name))),
         })
-    } else {
-        // Fallback to parsing as a generic list or application
-        parse_complex_expression(tokens)
-    }
-}
-
-// Helper function to parse individual list elements
-fn parse_list_element(tokens: &[Token]) -> Result<(ShenNode, usize), ParseError> {
-    if tokens.is_empty() {
-        return Err(ParseError::Syntax("Unexpected end of list".to_string()));
-    }
-
-    match tokens[0] {
-        Token::OpenParen => {
-            // Find the matching closing parenthesis
-            let mut paren_count = 0;
-            let mut end_index = 0;
-
-            for (i, token) in tokens.iter().enumerate() {
-                match token {
-                    Token::OpenParen => paren_count += 1,
-                    Token::CloseParen => {
-                        paren_count -= 1;
-                        if paren_count == 0 {
-                            end_index = i + 1;
-                            break;
-                        }
-                    }
-                    _ => {}
-                }
-            }
+        .collect()
+}
+
+fn parse_defprolog(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (defprolog name Head... <-- Goal...; ...)
+    let (name, rest) = definition_name(elements, span, "a predicate name")?;
+    if rest.is_empty() {
+        return Err(unexpected_end(span, "a clause").note(format!("in predicate {}", name)));
+    }
+
+    let mut clauses = Vec::new();
+    for (clause, semicolon) in split_clauses(rest, span, name)? {
+        let arrow = clause
+            .iter()
+            .position(|element| element.is_operator("<--"))
+            .ok_or_else(|| unexpected(semicolon, "`<--`").note(format!("in a clause of {}", name)))?;
+
+        let head = clause[..arrow]
+            .iter()
+            .map(parse_pattern)
+            .collect::<Result<Vec<_>, _>>()?;
+        let body = clause[arrow + 1..]
+            .iter()
+            .map(parse_expression)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if clauses.first().is_some_and(|first: &PrologClause| first.head.len() != head.len()) {
+            return Err(ParseError::new(ParseErrorKind::ArityMismatch, clause[0].span().to(clause[arrow].span()))
+                .expected([format!("{} patterns", clauses[0].head.len())])
+                .found(format!("{} patterns", head.len()))
+                .note(format!("clauses of {} take different numbers of arguments", name)));
+        }
+
+        clauses.push(PrologClause { head, body });
+    }
+
+    Ok(ShenNode::Defprolog {
+        name: Symbol::intern(name),
+        clauses,
+    })
+}
 
-            if end_index == 0 {
-                return Err(ParseError::Syntax(
-                    "Unbalanced parentheses in list".to_string(),
-                ));
+fn parse_prolog_query(elements: &[SExpr]) -> Result<ShenNode, ParseError> {
+    // (prolog? Goal...)
+    let goals = elements[1..]
+        .iter()
+        .map(parse_expression)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ShenNode::PrologQuery { goals })
+}
+
+fn parse_defcc(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (defcc <name> Item... := Action where Guard; ...)
+    let non_terminal = "a non-terminal such as `<digits>`";
+    let (name, rest) = definition_name(elements, span, non_terminal)?;
+    if !is_non_terminal(name) {
+        return Err(unexpected(&elements[1], non_terminal));
+    }
+    if rest.is_empty() {
+        return Err(unexpected_end(span, "a rule").note(format!("in grammar {}", name)));
+    }
+
+    let mut rules = Vec::new();
+    for (rule, semicolon) in split_clauses(rest, span, name)? {
+        let arrow = rule
+            .iter()
+            .position(|element| element.is_operator(":="));
+
+        let items = rule[..arrow.unwrap_or(rule.len())]
+            .iter()
+            .map(parse_yacc_item)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (action, guard) = match arrow.map(|arrow| &rule[arrow + 1..]) {
+            None => (None, None),
+            Some([]) => {
+                return Err(ParseError::new(ParseErrorKind::MissingBody, semicolon.span())
+                    .expected(["a semantic action"])
+                    .found(semicolon))
             }
+            Some([action]) => (Some(parse_expression(action)?), None),
+            Some([action, keyword, guard]) if keyword.identifier() == Some("where") => {
+                (Some(parse_expression(action)?), Some(parse_expression(guard)?))
+            }
+            Some([_, keyword]) if keyword.identifier() == Some("where") => {
+                return Err(unexpected(semicolon, "a guard"))
+            }
+            Some([_, extra, ..]) => {
+                return Err(unexpected(extra, "`where` or `;`")
+                    .note(format!("a rule of {} has one semantic action", name)))
+            }
+        };
 
-            // Recursively parse nested structure
-            let nested_tokens = &tokens[..end_index];
-            let result = if nested_tokens.len() > 2 {
-                match nested_tokens[1] {
-                    Token::Lambda => parse_lambda(nested_tokens),
-                    Token::Defun => parse_function_definition(nested_tokens),
-                    Token::Identifier(_) | Token::Operator(_) => parse_application(nested_tokens),
-                    Token::If => parse_conditional(nested_tokens),
-                    _ => parse_list(nested_tokens),
-                }
-            } else {
-                parse_list(nested_tokens)
-            }?;
+        rules.push(YaccRule {
+            items,
+            action,
+            guard,
+        });
+    }
 
-            Ok((result, end_index))
-        }
-        Token::Identifier(ref name) => {
-            // Simple symbol
-            Ok((
-                ShenNode::Symbol {
-                    name: name.clone(),
-                    type_hint: ShenType::Symbol,
-                },
-                1,
-            ))
-        }
-        Token::Literal(ref value) => {
-            // String literal
-            Ok((
-                ShenNode::Literal {
-                    value: ShenValue::String(value.clone()),
-                },
-                1,
-            ))
-        }
-        Token::Number(value) => {
-            // Numeric literal
-            Ok((
-                ShenNode::Literal {
-                    value: ShenValue::Float(value),
-                },
-                1,
-            ))
+    Ok(ShenNode::Defcc {
+        name: Symbol::intern(name),
+        rules,
+    })
+}
+
+/// `<digits>`, `<e>` and `<!>` name non-terminals in a `defcc`.
+fn is_non_terminal(name: &str) -> bool {
+    name.len() > 2 && name.starts_with('<') && name.ends_with('>')
+}
+
+fn parse_yacc_item(sexpr: &SExpr) -> Result<YaccItem, ParseError> {
+    match sexpr {
+        SExpr::Atom(Token::Identifier(name), _) => Ok(match *name {
+            "<e>" => YaccItem::Empty,
+            "<!>" => YaccItem::Rest,
+            name if is_non_terminal(name) => YaccItem::NonTerminal(Symbol::intern(name)),
+            name if name == "_" || name.starts_with(|c: char| c.is_uppercase()) => {
+                YaccItem::Variable(Symbol::intern(name))
+            }
+            name => YaccItem::Symbol(Symbol::intern(name)),
+        }),
+        SExpr::Atom(Token::Number(value), _) => Ok(YaccItem::Literal(ShenValue::Float(*value))),
+        SExpr::Atom(Token::Literal(value), _) => Ok(YaccItem::Literal(ShenValue::String(value.to_string()))),
+        _ => Err(unexpected(sexpr, "a grammar item")),
+    }
+}
+
+fn parse_pattern(sexpr: &SExpr) -> Result<Pattern, ParseError> {
+    let bad_pattern = || ParseError::new(ParseErrorKind::BadPattern, sexpr.span()).found(sexpr);
+    match sexpr {
+        SExpr::Atom(Token::Identifier("_"), _) => Ok(Pattern::Wildcard),
+        SExpr::Atom(Token::Identifier(name), _) if name.starts_with(|c: char| c.is_uppercase()) => {
+            Ok(Pattern::Variable(Symbol::intern(name)))
         }
-        Token::Operator(ref op) => {
-            // Handle operators as symbols
-            Ok((
-                ShenNode::Symbol {
-                    name: op.clone(),
-                    type_hint: ShenType::Symbol,
-                },
-                1,
-            ))
+        SExpr::Atom(Token::Identifier(name), _) => Ok(Pattern::Symbol(Symbol::intern(name))),
+        SExpr::Atom(Token::Number(value), _) => Ok(Pattern::Literal(ShenValue::Float(*value))),
+        SExpr::Atom(Token::Literal(value), _) => Ok(Pattern::Literal(ShenValue::String(value.to_string()))),
+        SExpr::Atom(Token::Operator("<>"), _) => Ok(Pattern::EmptyVector),
+        SExpr::Bracket(elements, span) => {
+            let (items, tail) = split_list_tail(elements, *span)?;
+            let tail = match tail {
+                Some(tail) => parse_pattern(tail)?,
+                None => Pattern::EmptyList,
+            };
+            items.iter().rev().try_fold(tail, |tail, item| {
+                Ok(Pattern::Cons(Box::new(parse_pattern(item)?), Box::new(tail)))
+            })
         }
-        Token::If | Token::Defun | Token::Lambda => {
-            // Handle special tokens as symbols
-            Ok((
-                ShenNode::Symbol {
-                    name: match tokens[0] {
-                        Token::If => "if".to_string(),
-                        Token::Defun => "defun".to_string(),
-                        Token::Lambda => "lambda".to_string(),
-                        _ => unreachable!(),
-                    },
-                    type_hint: ShenType::Symbol,
-                },
-                1,
-            ))
+        SExpr::List(elements, _) => {
+            let constructors = || bad_pattern().note("only `cons`, `@p`, `@v` and `@s` forms are patterns");
+            let (name, components) = match elements.split_first() {
+                Some((head, components)) => (head.identifier().ok_or_else(constructors)?, components),
+                None => return Err(constructors()),
+            };
+            let pair: fn(Box<Pattern>, Box<Pattern>) -> Pattern = match (name, components) {
+                ("cons", [_, _]) => Pattern::Cons,
+                ("@p", _) => Pattern::Tuple,
+                ("@v", _) => Pattern::VectorCons,
+                ("@s", _) => Pattern::StringCons,
+                _ => return Err(constructors()),
+            };
+            let components = components
+                .iter()
+                .map(parse_pattern)
+                .collect::<Result<Vec<_>, _>>()?;
+            nest_pairs(components, |first, second| pair(Box::new(first), Box::new(second)))
+                .ok_or_else(|| {
+                    bad_pattern().note(match name {
+                        "@v" => "@v expects elements and a vector".to_string(),
+                        name => format!("{} expects at least two elements", name),
+                    })
+                })
         }
-        Token::CloseParen => {
-            // Ignore closing parenthesis in list parsing
-            Err(ParseError::Syntax("Unexpected closing parenthesis".to_string()))
+        _ => Err(bad_pattern()),
+    }
+}
+
+fn parse_tuple(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (@p A B); longer forms are left to the standard macros
+    match elements {
+        [_, first, second] => Ok(ShenNode::Tuple {
+            first: Box::new(parse_expression(first)?),
+            second: Box::new(parse_expression(second)?),
+        }),
+        [_, _, _, _, ..] => parse_application(elements),
+        _ => Err(wrong_length(elements, span, &["a first element", "a second element"])),
+    }
+}
+
+fn parse_vector_cons(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (@v A V); longer forms are left to the standard macros
+    match elements {
+        [_, head, tail] => Ok(ShenNode::VectorCons {
+            head: Box::new(parse_expression(head)?),
+            tail: Box::new(parse_expression(tail)?),
+        }),
+        [_, _, _, _, ..] => parse_application(elements),
+        _ => Err(wrong_length(elements, span, &["an element", "a vector"])),
+    }
+}
+
+fn parse_string_cons(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (@s A B); longer forms are left to the standard macros
+    match elements {
+        [_, head, tail] => Ok(ShenNode::StringCons {
+            head: Box::new(parse_expression(head)?),
+            tail: Box::new(parse_expression(tail)?),
+        }),
+        [_, _, _, _, ..] => parse_application(elements),
+        _ => Err(wrong_length(elements, span, &["a string", "a string"])),
+    }
+}
+
+/// Nest `[A, B, C]` into `pair(A, pair(B, C))`; `None` for fewer than two items.
+fn nest_pairs<T>(items: Vec<T>, pair: impl Fn(T, T) -> T) -> Option<T> {
+    if items.len() < 2 {
+        return None;
+    }
+    items.into_iter().rev().reduce(|second, first| pair(first, second))
+}
+
+fn parse_lambda(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // Accepted forms; several arguments are curried by the standard macros:
+    //   (/. X Y Body)            Shen
+    //   (lambda X Body)          KLambda
+    //   (lambda (x y ...) Body)  parenthesized argument list
+    let (body, params) = match elements {
+        [_, params @ .., body] if !params.is_empty() => (body, params),
+        [_, _] => return Err(missing_body(span, "a lambda body")),
+        _ => return Err(unexpected_end(span, "a parameter")),
+    };
+
+    let params = match params {
+        [SExpr::List(list, _)] if list.is_empty() => return Err(unexpected(&params[0], "a parameter")),
+        [SExpr::List(list, _)] => list.as_slice(),
+        _ => params,
+    };
+
+    let args = params
+        .iter()
+        .map(|arg| match arg.identifier() {
+            Some(arg) => Ok((Symbol::intern(arg), ShenType::Symbol)),
+            None => Err(unexpected(arg, "a parameter name")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Parse lambda body
+    let body = parse_expression(body)?;
+
+    match args.len() {
+        1 => Ok(ShenNode::curried_lambda(args, body).expect("one argument")),
+        _ => Ok(ShenNode::Application {
+            func: Box::new(ShenNode::Symbol {
+                name: Symbol::intern("/."),
+                type_hint: ShenType::Symbol,
+            }),
+            args: args
+                .into_iter()
+                .map(|(name, type_hint)| ShenNode::Symbol { name, type_hint })
+                .chain(std::iter::once(body))
+                .collect(),
+        }),
+    }
+}
+
+fn parse_bracket_list(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // [A B C] is a list; [A B | T] conses A and B onto T
+    let (items, tail) = split_list_tail(elements, span)?;
+    let items = items
+        .iter()
+        .map(parse_expression)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match tail {
+        None if items.is_empty() => Ok(ShenNode::Nil),
+        None => {
+            let element_type = items[0].get_type();
+            Ok(ShenNode::List {
+                elements: items,
+                element_type,
+            })
         }
-        _ => Err(ParseError::Syntax(format!(
-            "Unsupported list element: {:?}",
-            tokens[0]
-        ))),
+        Some(tail) => Ok(items.into_iter().rev().fold(parse_expression(tail)?, |tail, head| {
+            ShenNode::Cons {
+                head: Box::new(head),
+                tail: Box::new(tail),
+            }
+        })),
     }
 }
 
-fn parse_symbol_or_application(tokens: &[Token]) -> Result<ShenNode, ParseError> {
-    if tokens.is_empty() {
-        return Err(ParseError::Syntax(
-            "Empty input for symbol or application".to_string(),
-        ));
+fn parse_freeze(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (freeze Expr)
+    match elements {
+        [_, body] => Ok(ShenNode::Freeze {
+            body: Box::new(parse_expression(body)?),
+        }),
+        _ => Err(wrong_length(elements, span, &["an expression"])),
     }
+}
 
-    match &tokens[0] {
-        Token::Identifier(name) => Ok(ShenNode::Symbol {
-            name: name.clone(),
-            type_hint: ShenType::Symbol,
+fn parse_trap_error(elements: &[SExpr], span: Span) -> Result<ShenNode, ParseError> {
+    // (trap-error Expr Handler)
+    match elements {
+        [_, body, handler] => Ok(ShenNode::TrapError {
+            body: Box::new(parse_expression(body)?),
+            handler: Box::new(parse_expression(handler)?),
         }),
-        Token::Number(value) => Ok(ShenNode::Literal {
+        _ => Err(wrong_length(elements, span, &["an expression", "a handler"])),
+    }
+}
+
+fn parse_list(elements: &[SExpr]) -> Result<ShenNode, ParseError> {
+    // (list element1 element2 ...)
+    let elements = elements[1..]
+        .iter()
+        .map(parse_expression)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Infer element type from first element if possible
+    let element_type = elements
+        .first()
+        .map(|elem| elem.get_type())
+        .unwrap_or(ShenType::Symbol);
+
+    Ok(ShenNode::List {
+        elements,
+        element_type,
+    })
+}
+
+/// The node for an atom; `None` for `|` and `;`, which only mean something
+/// inside lists and definitions.
+fn parse_atom(token: &Token) -> Option<ShenNode> {
+    let symbol = |name: &str| ShenNode::Symbol {
+        name: Symbol::intern(name),
+        type_hint: ShenType::Symbol,
+    };
+
+    match token {
+        Token::Operator("<>") => Some(ShenNode::Vector { elements: Vec::new() }),
+        Token::Identifier(name) | Token::Operator(name) => Some(symbol(name)),
+        Token::Number(value) => Some(ShenNode::Literal {
             value: ShenValue::Float(*value),
         }),
-        Token::Literal(value) => Ok(ShenNode::Literal {
-            value: ShenValue::String(value.clone()),
+        Token::Literal(value) => Some(ShenNode::Literal {
+            value: ShenValue::String(value.to_string()),
         }),
-        _ => Err(ParseError::Syntax(format!(
-            "Unsupported token for symbol: {:?}",
-            tokens[0]
-        ))),
+        // Keywords in argument position are plain symbols
+        Token::Defun => Some(symbol("defun")),
+        Token::Lambda => Some(symbol("lambda")),
+        Token::If => Some(symbol("if")),
+        Token::Let => Some(symbol("let")),
+        Token::List => Some(symbol("list")),
+        _ => None,
     }
 }
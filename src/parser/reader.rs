@@ -0,0 +1,261 @@
+//! The first stage of parsing: tokens to s-expressions
+//!
+//! The reader knows only about delimiters. It groups tokens into [`SExpr`]s,
+//! atoms and `(...)`, `[...]` and `{...}` groups, each with the [`Span`] of
+//! source it covers. What the groups mean, which forms are special and what
+//! a `|` or `->` does inside them, is left to the second stage in the parent
+//! module, which matches on the tree instead of counting tokens.
+
+use std::fmt;
+
+use super::token::{lexemes, tokenize_partial, Span, Syntax, Token};
+use super::error::{ParseError, ParseErrorKind};
+
+/// A read form, before it is given meaning.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SExpr<'a> {
+    Atom(Token<'a>, Span),
+    /// `(...)`
+    List(Vec<SExpr<'a>>, Span),
+    /// `[...]`
+    Bracket(Vec<SExpr<'a>>, Span),
+    /// `{...}`, as in type signatures
+    Brace(Vec<SExpr<'a>>, Span),
+}
+
+impl<'a> SExpr<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            SExpr::Atom(_, span)
+            | SExpr::List(_, span)
+            | SExpr::Bracket(_, span)
+            | SExpr::Brace(_, span) => *span,
+        }
+    }
+
+    /// The name of a symbol atom.
+    pub fn identifier(&self) -> Option<&'a str> {
+        match self {
+            SExpr::Atom(Token::Identifier(name), _) => Some(*name),
+            _ => None,
+        }
+    }
+
+    /// Whether this is the atom `token`.
+    pub fn is(&self, token: &Token) -> bool {
+        matches!(self, SExpr::Atom(atom, _) if atom == token)
+    }
+
+    /// Whether this is the operator `op`, such as `->` or `:=`.
+    pub fn is_operator(&self, op: &str) -> bool {
+        matches!(self, SExpr::Atom(Token::Operator(atom), _) if *atom == op)
+    }
+}
+
+/// Read every form of `input`, stopping at the first error.
+pub fn read(input: &str) -> Result<Vec<SExpr<'_>>, ParseError> {
+    first_error(read_forms(input, Syntax::Shen, false))
+}
+
+/// Read every form of KLambda `input`, stopping at the first error. Its
+/// forms are all `(...)` lists, and its atoms identifiers or strings.
+pub(crate) fn read_klambda(input: &str) -> Result<Vec<SExpr<'_>>, ParseError> {
+    first_error(read_forms(input, Syntax::KLambda, false))
+}
+
+fn first_error<'a>((forms, errors): (Vec<SExpr<'a>>, Vec<ParseError>)) -> Result<Vec<SExpr<'a>>, ParseError> {
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(forms),
+    }
+}
+
+/// Read every form of `input`, carrying on past errors. Unreadable
+/// characters and stray closers are skipped and unclosed groups are closed,
+/// so every form comes back along with each error and where it was found.
+///
+/// A `(` in the first column is taken to start a new top-level form, as it
+/// does in conventionally indented Shen; a form still open at that point is
+/// closed there, so one missing `)` costs only the form it is missing from.
+pub fn read_recovering(input: &str) -> (Vec<SExpr<'_>>, Vec<ParseError>) {
+    read_forms(input, Syntax::Shen, true)
+}
+
+/// Whether `input` stops partway through a form, inside a string or with a
+/// group still open, so that reading the rest of it may complete it. Stray
+/// closers and bad tokens are not the reader's to wait for; they are errors
+/// once the input is read.
+pub fn is_unfinished(input: &str) -> bool {
+    let Ok((tokens, _)) = tokenize_partial(input) else {
+        return true;
+    };
+    let depth = tokens.iter().fold(0i32, |depth, (token, _)| match token {
+        Token::OpenParen | Token::OpenBracket | Token::OpenBrace => depth + 1,
+        Token::CloseParen | Token::CloseBracket | Token::CloseBrace => depth - 1,
+        _ => depth,
+    });
+    depth > 0
+}
+
+/// A group being read: its closing token, where it opened and its items.
+type OpenGroup<'a> = (Token<'a>, Span, Vec<SExpr<'a>>);
+
+fn read_forms(input: &str, syntax: Syntax, recover: bool) -> (Vec<SExpr<'_>>, Vec<ParseError>) {
+    let mut errors = Vec::new();
+    let mut open: Vec<OpenGroup> = Vec::new();
+    let mut forms = Vec::new();
+
+    for (lexeme, span) in lexemes(input, syntax) {
+        let token = match lexeme {
+            Ok(token) => token,
+            Err(message) => {
+                errors.push(
+                    ParseError::new(ParseErrorKind::InvalidToken, span)
+                        .found(&input[span.start..span.end])
+                        .note(message),
+                );
+                continue;
+            }
+        };
+        if recover && token == Token::OpenParen && !open.is_empty() && starts_line(input, span) {
+            errors.push(
+                ParseError::new(ParseErrorKind::UnbalancedParen, open[0].1)
+                    .expected_tokens(open.iter().rev().map(|(closer, ..)| delimiter(closer)))
+                    .found("(")
+                    .note("the form is still open where the next top-level form starts"),
+            );
+            close_all(&mut open, &mut forms);
+        }
+
+        let form = match token {
+            Token::OpenParen => {
+                open.push((Token::CloseParen, span, Vec::new()));
+                continue;
+            }
+            Token::OpenBracket => {
+                open.push((Token::CloseBracket, span, Vec::new()));
+                continue;
+            }
+            Token::OpenBrace => {
+                open.push((Token::CloseBrace, span, Vec::new()));
+                continue;
+            }
+            Token::CloseParen | Token::CloseBracket | Token::CloseBrace => {
+                let depth = match open.iter().rposition(|(closer, ..)| *closer == token) {
+                    Some(depth) => depth,
+                    None => {
+                        let error = match open.last() {
+                            Some((closer, ..)) => {
+                                ParseError::new(ParseErrorKind::MismatchedDelimiter, span)
+                                    .expected_tokens([delimiter(closer)])
+                            }
+                            None => ParseError::new(ParseErrorKind::UnbalancedParen, span)
+                                .note("there is nothing to close"),
+                        };
+                        errors.push(error.found(delimiter(&token)));
+                        continue;
+                    }
+                };
+                if depth + 1 < open.len() {
+                    // Groups opened inside this one are missing their closers
+                    errors.push(
+                        ParseError::new(ParseErrorKind::MismatchedDelimiter, span)
+                            .expected_tokens(open[depth + 1..].iter().rev().map(|(closer, ..)| delimiter(closer)))
+                            .found(delimiter(&token)),
+                    );
+                    while open.len() > depth + 1 {
+                        close_innermost(&mut open, &mut forms, None);
+                    }
+                }
+                close_innermost(&mut open, &mut forms, Some(span));
+                continue;
+            }
+            token => SExpr::Atom(token, span),
+        };
+        add_form(&mut open, &mut forms, form);
+    }
+
+    if let Some((_, start, _)) = open.first() {
+        errors.push(
+            ParseError::new(ParseErrorKind::UnbalancedParen, *start)
+                .expected_tokens(open.iter().rev().map(|(closer, ..)| delimiter(closer))),
+        );
+        close_all(&mut open, &mut forms);
+    }
+    errors.sort_by_key(|error| error.span.start);
+    (forms, errors)
+}
+
+/// How a delimiter token is written.
+fn delimiter(token: &Token) -> &'static str {
+    match token {
+        Token::OpenParen => "(",
+        Token::CloseParen => ")",
+        Token::OpenBracket => "[",
+        Token::CloseBracket => "]",
+        Token::OpenBrace => "{",
+        _ => "}",
+    }
+}
+
+/// Whether `span` starts at the beginning of a line.
+fn starts_line(input: &str, span: Span) -> bool {
+    span.start == 0 || input[..span.start].ends_with('\n')
+}
+
+fn add_form<'a>(open: &mut [OpenGroup<'a>], forms: &mut Vec<SExpr<'a>>, form: SExpr<'a>) {
+    match open.last_mut() {
+        Some((_, _, items)) => items.push(form),
+        None => forms.push(form),
+    }
+}
+
+/// Close the innermost open group at `end`, or after its last item when its
+/// closer is missing.
+fn close_innermost<'a>(open: &mut Vec<OpenGroup<'a>>, forms: &mut Vec<SExpr<'a>>, end: Option<Span>) {
+    let Some((closer, start, items)) = open.pop() else {
+        return;
+    };
+    let end = end.or_else(|| items.last().map(SExpr::span)).unwrap_or(start);
+    let span = start.to(end);
+    let group = match closer {
+        Token::CloseParen => SExpr::List(items, span),
+        Token::CloseBracket => SExpr::Bracket(items, span),
+        _ => SExpr::Brace(items, span),
+    };
+    add_form(open, forms, group);
+}
+
+fn close_all<'a>(open: &mut Vec<OpenGroup<'a>>, forms: &mut Vec<SExpr<'a>>) {
+    while !open.is_empty() {
+        close_innermost(open, forms, None);
+    }
+}
+
+/// Forms print as source, for error messages.
+impl fmt::Display for SExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let group = |f: &mut fmt::Formatter<'_>, open, items: &[SExpr], close| {
+            let items = items.iter().map(SExpr::to_string).collect::<Vec<_>>();
+            write!(f, "{}{}{}", open, items.join(" "), close)
+        };
+        match self {
+            SExpr::Atom(token, _) => match token {
+                Token::Identifier(text) | Token::Operator(text) => write!(f, "{}", text),
+                Token::Literal(text) => write!(f, "{:?}", text),
+                Token::Number(value) => write!(f, "{}", value),
+                Token::Bar => write!(f, "|"),
+                Token::Semicolon => write!(f, ";"),
+                Token::Defun => write!(f, "defun"),
+                Token::Lambda => write!(f, "lambda"),
+                Token::If => write!(f, "if"),
+                Token::Let => write!(f, "let"),
+                Token::List => write!(f, "list"),
+                delimiter => write!(f, "{:?}", delimiter),
+            },
+            SExpr::List(items, _) => group(f, "(", items, ")"),
+            SExpr::Bracket(items, _) => group(f, "[", items, "]"),
+            SExpr::Brace(items, _) => group(f, "{", items, "}"),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! The source of each node of a parsed form
+//!
+//! A form is parsed into a [`ShenNode`](crate::ast::ShenNode) tree and moved
+//! into an [`Ast`]; [`record_spans`] then walks the arena nodes alongside the
+//! s-expression they were read from and gives each node the [`Span`] of its
+//! sub-form. The nodes a standard macro builds from a form, such as the
+//! nested tuples of `(@p A B C)`, have no sub-form of their own and get the
+//! span of the whole form, as do their children.
+
+use super::reader::SExpr;
+use super::token::{Span, Token};
+use super::{rule_forms, split_clauses, split_list_tail};
+use crate::ast::arena::{Ast, Node, NodeId};
+
+/// Record the span of `sexpr`, the form `id` was parsed from, on `id`, and
+/// the span of each sub-form on the node parsed from it.
+pub(super) fn record_spans(ast: &mut Ast, id: NodeId, sexpr: &SExpr) {
+    let recorded = match sexpr {
+        SExpr::Bracket(elements, span) => match split_list_tail(elements, *span) {
+            Ok((items, tail)) => record_list(ast, id, items, tail, *span),
+            Err(_) => false,
+        },
+        sexpr => match parts(ast, id, sexpr) {
+            Some(parts) => {
+                ast.set_span(id, sexpr.span());
+                for (child, sexpr) in parts {
+                    record_spans(ast, child, sexpr);
+                }
+                true
+            }
+            None => false,
+        },
+    };
+    if !recorded {
+        record_expansion(ast, id, sexpr.span());
+    }
+}
+
+/// Give `id` and everything under it the span of the form it was built from.
+fn record_expansion(ast: &mut Ast, id: NodeId, span: Span) {
+    ast.set_span(id, span);
+    for child in ast.children(id) {
+        record_expansion(ast, child, span);
+    }
+}
+
+/// Record the spans of `[Items | Tail]`, read as a list or as a chain of
+/// conses onto the tail; `false` if `id` is neither.
+fn record_list(ast: &mut Ast, id: NodeId, items: &[SExpr], tail: Option<&SExpr>, span: Span) -> bool {
+    let parts = match (ast.node(id), items, tail) {
+        (Node::Nil, [], None) => Vec::new(),
+        (Node::List { elements, .. }, items, None) if elements.len() == items.len() => {
+            elements.iter().copied().zip(items).collect()
+        }
+        (Node::Cons { head, tail: rest }, [first, others @ ..], Some(tail)) => {
+            let (head, rest) = (*head, *rest);
+            match others {
+                [] => vec![(head, first), (rest, tail)],
+                // The conses after the first stand for `Others | Tail`, which
+                // is not a form of its own
+                [second, ..] => {
+                    if !record_list(ast, rest, others, Some(tail), second.span().to(tail.span())) {
+                        return false;
+                    }
+                    vec![(head, first)]
+                }
+            }
+        }
+        _ => return false,
+    };
+    ast.set_span(id, span);
+    for (child, sexpr) in parts {
+        record_spans(ast, child, sexpr);
+    }
+    true
+}
+
+/// Each child of `id` with the sub-form of the list `sexpr` it was parsed
+/// from, or `None` when `id` is not the node the parser makes of `sexpr`.
+fn parts<'s>(ast: &Ast, id: NodeId, sexpr: &'s SExpr<'s>) -> Option<Vec<(NodeId, &'s SExpr<'s>)>> {
+    let elements = match sexpr {
+        SExpr::Atom(..) => return Some(Vec::new()),
+        SExpr::List(elements, _) => elements.as_slice(),
+        _ => return None,
+    };
+    let head_is = |name: &str| elements.first().and_then(SExpr::identifier) == Some(name);
+    let pairs = |ids: &[NodeId], sexprs: &'s [SExpr<'s>]| {
+        (ids.len() == sexprs.len()).then(|| ids.iter().copied().zip(sexprs).collect::<Vec<_>>())
+    };
+
+    match (ast.node(id), elements) {
+        (Node::Nil, []) => Some(Vec::new()),
+        (Node::Application { func, args }, [head, ..]) if names(ast, *func, head) => {
+            let ids = std::iter::once(*func).chain(args.iter().copied()).collect::<Vec<_>>();
+            pairs(&ids, elements)
+        }
+        (Node::BinaryOperation { left, right, .. }, [_, left_form, right_form]) => {
+            Some(vec![(*left, left_form), (*right, right_form)])
+        }
+        (Node::Conditional { condition, true_branch, false_branch }, [head, rest @ ..]) if head.is(&Token::If) => {
+            let ids = [*condition, *true_branch].into_iter().chain(*false_branch).collect::<Vec<_>>();
+            pairs(&ids, rest)
+        }
+        (Node::Cons { head, tail }, [_, head_form, tail_form]) if head_is("cons") => {
+            Some(vec![(*head, head_form), (*tail, tail_form)])
+        }
+        (Node::Tuple { first, second }, [_, first_form, second_form]) if head_is("@p") => {
+            Some(vec![(*first, first_form), (*second, second_form)])
+        }
+        (Node::VectorCons { head, tail }, [_, head_form, tail_form]) if head_is("@v") => {
+            Some(vec![(*head, head_form), (*tail, tail_form)])
+        }
+        (Node::StringCons { head, tail }, [_, head_form, tail_form]) if head_is("@s") => {
+            Some(vec![(*head, head_form), (*tail, tail_form)])
+        }
+        (Node::TrapError { body, handler }, [_, body_form, handler_form]) if head_is("trap-error") => {
+            Some(vec![(*body, body_form), (*handler, handler_form)])
+        }
+        (Node::Freeze { body }, [_, body_form]) if head_is("freeze") => Some(vec![(*body, body_form)]),
+        (Node::Lambda { body, .. }, [head, _, body_form]) if head.is(&Token::Lambda) => {
+            Some(vec![(*body, body_form)])
+        }
+        (Node::Function { body, .. }, [head, _, _, body_form]) if head.is(&Token::Defun) => {
+            Some(vec![(*body, body_form)])
+        }
+        (Node::List { elements: ids, .. }, [head, rest @ ..]) if head.is(&Token::List) => pairs(ids, rest),
+        (Node::PrologQuery { goals }, [_, rest @ ..]) if head_is("prolog?") => pairs(goals, rest),
+        (Node::Package { body, .. }, [_, _, _, rest @ ..]) if head_is("package") => pairs(body, rest),
+        (Node::Define { name, rules } | Node::Defmacro { name, rules }, [_, _, rest @ ..]) => {
+            let rest = match rest {
+                [SExpr::Brace(..), rest @ ..] => rest,
+                rest => rest,
+            };
+            let forms = rule_forms(name.as_str(), rest, sexpr.span()).collect::<Result<Vec<_>, _>>().ok()?;
+            if forms.len() != rules.len() {
+                return None;
+            }
+            let mut parts = Vec::new();
+            for (rule, (_, _, body, guard)) in rules.iter().zip(forms) {
+                parts.push((rule.body, body));
+                parts.extend(rule.guard.zip(guard));
+            }
+            Some(parts)
+        }
+        (Node::Defprolog { name, clauses }, [_, _, rest @ ..]) => {
+            let forms = split_clauses(rest, sexpr.span(), name.as_str()).ok()?;
+            if forms.len() != clauses.len() {
+                return None;
+            }
+            let mut parts = Vec::new();
+            for (clause, (form, _)) in clauses.iter().zip(forms) {
+                let arrow = form.iter().position(|element| element.is_operator("<--"))?;
+                parts.extend(pairs(&clause.body, &form[arrow + 1..])?);
+            }
+            Some(parts)
+        }
+        (Node::Defcc { name, rules }, [_, _, rest @ ..]) => {
+            let forms = split_clauses(rest, sexpr.span(), name.as_str()).ok()?;
+            if forms.len() != rules.len() {
+                return None;
+            }
+            let mut parts = Vec::new();
+            // (defcc <name> Item... := Action where Guard; ...)
+            for (rule, (form, _)) in rules.iter().zip(forms) {
+                if let Some(arrow) = form.iter().position(|element| element.is_operator(":=")) {
+                    parts.extend(rule.action.zip(form.get(arrow + 1)));
+                    parts.extend(rule.guard.zip(form.get(arrow + 3)));
+                }
+            }
+            Some(parts)
+        }
+        _ => None,
+    }
+}
+
+/// Whether the function `id` is the symbol written as `head`, perhaps with a
+/// package prefix; a function that is not a symbol is taken as written.
+fn names(ast: &Ast, id: NodeId, head: &SExpr) -> bool {
+    let Node::Symbol { name } = ast.node(id) else {
+        return true;
+    };
+    let text = head.to_string();
+    let name = name.as_str();
+    name == text || name.strip_suffix(text.as_str()).is_some_and(|prefix| prefix.ends_with('.'))
+}
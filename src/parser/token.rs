@@ -1,109 +1,310 @@
-#[derive(Debug, PartialEq, Clone)]
-pub enum Token {
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_until, take_while, take_while1};
+use nom::character::complete::{anychar, char, not_line_ending, one_of};
+use nom::combinator::{map, opt, recognize, rest, value, verify};
+use nom::sequence::{pair, tuple};
+use nom::{IResult, Needed};
+use nom_locate::LocatedSpan;
+
+/// A token, borrowing its text from the source it was read from.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Token<'a> {
     OpenParen,
     CloseParen,
+    OpenBracket,
+    CloseBracket,
+    OpenBrace,
+    CloseBrace,
+    Bar,
+    Semicolon,
     Defun,
     Lambda,
     If,
     Let,
     List,  // Add List token
-    Identifier(String),
-    Literal(String),
-    Symbol(String),
-    Operator(String),
+    Identifier(&'a str),
+    Literal(&'a str),
+    Operator(&'a str),
     Number(f64),
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+/// The source a token or expression was read from, as byte offsets.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// The 1-based line and column where the span starts in `input`.
+    pub fn line_column(&self, input: &str) -> (usize, usize) {
+        let before = &input[..self.start.min(input.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map_or(0, |line| line.chars().count()) + 1;
+        (line, column)
+    }
+}
+
+/// The language being lexed. KLambda, the language of Shen's kernel, has
+/// only parentheses, strings and atoms: any other run of characters up to
+/// whitespace, a parenthesis or `"` is one atom, so `|`, `;` and `:=` are
+/// symbols there, and it has no comments.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Syntax {
+    Shen,
+    KLambda,
+}
+
+/// A message about a span of the input.
+pub type SpanError = (String, Span);
+
+/// Lexed tokens with their spans, and the errors for what was skipped.
+pub type Tokens<'a> = (Vec<(Token<'a>, Span)>, Vec<SpanError>);
+
+/// A comment, `\\ ...` to the end of its line or `\* ... *\`, with its
+/// markers, and where it was read.
+pub type Comment<'a> = (&'a str, Span);
+
+/// Split `input` into tokens, each with the span it was read from. What
+/// cannot be read is skipped, with an error for each skipped span.
+pub fn tokenize(input: &str) -> Tokens<'_> {
     let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
-
-    while let Some(&c) = chars.peek() {
-        match c {
-            '(' => {
-                tokens.push(Token::OpenParen);
-                chars.next();
-            },
-            ')' => {
-                tokens.push(Token::CloseParen);
-                chars.next();
-            },
-            c if c.is_whitespace() => {
-                chars.next();
-            },
-            '\\' | 'λ' => {
-                tokens.push(Token::Lambda);
-                chars.next();
-            },
-            '+' | '-' | '*' | '/' | '=' | '<' | '>' => {
-                let mut op = String::new();
-                while let Some(&next_ch) = chars.peek() {
-                    if "+-*/=<>".contains(next_ch) {
-                        op.push(next_ch);
-                        chars.next();
-                    } else {
-                        break;
-                    }
-                }
-                tokens.push(Token::Operator(op));
-            },
-            c if c.is_alphabetic() || c == '_' => {
-                let mut identifier = String::new();
-                while let Some(&next_ch) = chars.peek() {
-                    if next_ch.is_alphanumeric() || next_ch == '_' || next_ch == '?' || next_ch == '!' {
-                        identifier.push(next_ch);
-                        chars.next();
-                    } else {
-                        break;
-                    }
-                }
-                
-                tokens.push(match identifier.as_str() {
-                    "defun" => Token::Defun,
-                    "lambda" | "fn" => Token::Lambda,
-                    "if" => Token::If,
-                    "let" => Token::Let,
-                    "list" => Token::List,  // Add list token recognition
-                    _ => Token::Identifier(identifier),
-                });
-            },
-            c if c.is_numeric() => {
-                let mut number = String::new();
-                let mut is_float = false;
-                while let Some(&next_ch) = chars.peek() {
-                    if next_ch.is_numeric() || next_ch == '.' {
-                        if next_ch == '.' {
-                            is_float = true;
-                        }
-                        number.push(next_ch);
-                        chars.next();
-                    } else {
-                        break;
-                    }
-                }
-                let parsed_number = if is_float {
-                    number.parse::<f64>().map_err(|_| format!("Invalid number: {}", number))?
-                } else {
-                    number.parse::<f64>().map_err(|_| format!("Invalid number: {}", number))?
-                };
-                tokens.push(Token::Number(parsed_number));
-            },
-            '"' => {
-                chars.next(); // consume opening quote
-                let mut literal = String::new();
-                while let Some(&next_ch) = chars.peek() {
-                    if next_ch == '"' {
-                        chars.next(); // consume closing quote
-                        break;
-                    }
-                    literal.push(next_ch);
-                    chars.next();
+    let mut errors = Vec::new();
+    for (lexeme, span) in lexemes(input, Syntax::Shen) {
+        match lexeme {
+            Ok(token) => tokens.push((token, span)),
+            Err(error) => errors.push((error, span)),
+        }
+    }
+    (tokens, errors)
+}
+
+/// Split `input`, which may stop partway through, into tokens. `Err` means
+/// the input ends inside a token, such as a string that is not yet closed,
+/// and more is needed before it can be read.
+pub fn tokenize_partial(input: &str) -> Result<Tokens<'_>, Needed> {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut rest = Input::new(input);
+    while let Some((lexeme, span)) = next_lexeme(&mut rest, Syntax::Shen, true, None)? {
+        match lexeme {
+            Ok(token) => tokens.push((token, span)),
+            Err(error) => errors.push((error, span)),
+        }
+    }
+    Ok((tokens, errors))
+}
+
+/// The comments of `input`, which the lexer otherwise skips like whitespace.
+pub fn comments(input: &str) -> Vec<Comment<'_>> {
+    let mut comments = Vec::new();
+    let mut rest = Input::new(input);
+    while next_lexeme(&mut rest, Syntax::Shen, false, Some(&mut comments))
+        .expect("complete input is never incomplete")
+        .is_some()
+    {}
+    comments
+}
+
+/// The tokens of `input` one at a time, or the errors for what cannot be
+/// read, each with its span. Tokens are read as they are asked for, so the
+/// reader never holds every token of a large file at once.
+pub(crate) fn lexemes(input: &str, syntax: Syntax) -> impl Iterator<Item = (Lexeme<'_>, Span)> {
+    let mut rest = Input::new(input);
+    std::iter::from_fn(move || {
+        next_lexeme(&mut rest, syntax, false, None).expect("complete input is never incomplete")
+    })
+}
+
+/// Input to the lexer: the source, tracking where in it the lexer is.
+type Input<'a> = LocatedSpan<&'a str>;
+
+/// A token, or why the input at that point is not one.
+pub(crate) type Lexeme<'a> = Result<Token<'a>, String>;
+
+/// Read the lexeme at the start of `rest`, past any whitespace and comments,
+/// and move `rest` past it; `None` at the end of the input. The comments
+/// passed over are added to `comments` when it is given.
+fn next_lexeme<'a>(
+    rest: &mut Input<'a>,
+    syntax: Syntax,
+    streaming: bool,
+    mut comments: Option<&mut Vec<Comment<'a>>>,
+) -> Result<Option<(Lexeme<'a>, Span)>, Needed> {
+    let mut start = *rest;
+    loop {
+        (start, _) = whitespace(start).expect("whitespace always matches");
+        if syntax == Syntax::KLambda {
+            break;
+        }
+        match comment(start) {
+            Ok((after, text)) => {
+                if let Some(comments) = comments.as_deref_mut() {
+                    let span = Span {
+                        start: start.location_offset(),
+                        end: after.location_offset(),
+                    };
+                    comments.push((text.into_fragment(), span));
                 }
-                tokens.push(Token::Literal(literal));
-            },
-            _ => return Err(format!("Unexpected character: {}", c)),
+                start = after;
+            }
+            Err(_) if start.fragment().starts_with("\\*") && streaming => {
+                return Err(Needed::new(2))
+            }
+            Err(_) => break,
         }
     }
+    if start.fragment().is_empty() {
+        *rest = start;
+        return Ok(None);
+    }
+    let lexed = match syntax {
+        Syntax::Shen => lexeme(start, streaming),
+        Syntax::KLambda => klambda_lexeme(start, streaming),
+    };
+    let (after, lexeme) = match lexed {
+        Ok(lexed) => lexed,
+        Err(nom::Err::Incomplete(needed)) => return Err(needed),
+        Err(_) => unreachable!("any character is at least an unexpected one"),
+    };
+    let span = Span {
+        start: start.location_offset(),
+        end: after.location_offset(),
+    };
+    *rest = after;
+    Ok(Some((lexeme, span)))
+}
+
+fn whitespace(input: Input) -> IResult<Input, Input> {
+    take_while(char::is_whitespace)(input)
+}
+
+/// `\\` to the end of the line, or a closed `\* ... *\`, which does not nest.
+fn comment(input: Input) -> IResult<Input, Input> {
+    alt((
+        recognize(pair(tag("\\\\"), not_line_ending)),
+        recognize(tuple((tag("\\*"), take_until("*\\"), tag("*\\")))),
+    ))(input)
+}
+
+fn lexeme<'a>(input: Input<'a>, streaming: bool) -> IResult<Input<'a>, Lexeme<'a>> {
+    alt((
+        delimiter,
+        map(pair(tag("\\*"), rest), |_| Err("Unterminated comment".to_string())),
+        value(Ok(Token::Lambda), alt((tag("\\"), tag("λ"), tag("/.")))),
+        // The Prolog cut and Shen-YACC's rest-of-input non-terminal
+        map(alt((tag("!"), tag("<!>"))), |name: Input| {
+            Ok(Token::Identifier(name.into_fragment()))
+        }),
+        map(alt((tag(":="), tag(":"))), |op: Input| Ok(Token::Operator(op.into_fragment()))),
+        operator,
+        symbol,
+        number,
+        |input| string(input, streaming),
+        map(anychar, |c| Err(format!("Unexpected character: {}", c))),
+    ))(input)
+}
+
+/// A KLambda parenthesis, string or atom. Atoms are all identifiers; which
+/// of them are numbers is up to the KLambda reader.
+fn klambda_lexeme<'a>(input: Input<'a>, streaming: bool) -> IResult<Input<'a>, Lexeme<'a>> {
+    alt((
+        value(Ok(Token::OpenParen), char('(')),
+        value(Ok(Token::CloseParen), char(')')),
+        |input| string(input, streaming),
+        map(take_while1(|c: char| !c.is_whitespace() && !"()\"".contains(c)), |atom: Input| {
+            Ok(Token::Identifier(atom.into_fragment()))
+        }),
+    ))(input)
+}
+
+fn delimiter<'a>(input: Input<'a>) -> IResult<Input<'a>, Lexeme<'a>> {
+    map(one_of("()[]{}|;"), |c| {
+        Ok(match c {
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '{' => Token::OpenBrace,
+            '}' => Token::CloseBrace,
+            '|' => Token::Bar,
+            _ => Token::Semicolon,
+        })
+    })(input)
+}
+
+/// `+`, `->` or `<=`; symbols such as `*counter*` or `<-vector` start with
+/// operator characters and carry on as symbols. A sign followed by a digit
+/// starts a number instead, such as `-1`.
+fn operator<'a>(input: Input<'a>) -> IResult<Input<'a>, Lexeme<'a>> {
+    let (rest, op) = take_while1(|c| "+-*/=<>".contains(c))(input)?;
+    let signs_number = matches!(*op.fragment(), "-" | "+") && rest.fragment().starts_with(char::is_numeric);
+    if signs_number {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+    }
+    if rest.fragment().starts_with(char::is_alphanumeric) {
+        // Operator characters are all symbol characters
+        let (rest, name) = take_while1(is_symbol_char)(input)?;
+        return Ok((rest, Ok(Token::Identifier(name.into_fragment()))));
+    }
+    Ok((rest, Ok(Token::Operator(op.into_fragment()))))
+}
+
+fn symbol<'a>(input: Input<'a>) -> IResult<Input<'a>, Lexeme<'a>> {
+    let (rest, name) = verify(take_while1(is_symbol_char), |name: &Input| {
+        name.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '@')
+    })(input)?;
+    let token = match *name.fragment() {
+        "defun" => Token::Defun,
+        "lambda" | "fn" => Token::Lambda,
+        "if" => Token::If,
+        "let" => Token::Let,
+        "list" => Token::List,
+        name => Token::Identifier(name),
+    };
+    Ok((rest, Ok(token)))
+}
+
+fn number<'a>(input: Input<'a>) -> IResult<Input<'a>, Lexeme<'a>> {
+    let digits = verify(take_while1(|c: char| c.is_numeric() || c == '.'), |number: &Input| {
+        number.starts_with(char::is_numeric)
+    });
+    let (rest, number) = recognize(pair(opt(one_of("+-")), digits))(input)?;
+    let number = number.fragment();
+    let lexeme = number
+        .parse::<f64>()
+        .map(Token::Number)
+        .map_err(|_| format!("Invalid number: {}", number));
+    Ok((rest, lexeme))
+}
+
+/// A string literal; strings have no escapes. An unclosed string needs more
+/// input when streaming and is an error otherwise.
+fn string<'a>(input: Input<'a>, streaming: bool) -> IResult<Input<'a>, Lexeme<'a>> {
+    let (input, _) = char('"')(input)?;
+    match take_until::<_, _, nom::error::Error<Input>>("\"")(input) {
+        Ok((rest, text)) => {
+            let (rest, _) = char('"')(rest)?;
+            Ok((rest, Ok(Token::Literal(text.into_fragment()))))
+        }
+        Err(_) if streaming => Err(nom::Err::Incomplete(Needed::new(1))),
+        Err(_) => {
+            let (rest, _) = rest(input)?;
+            Ok((rest, Err("Unterminated string".to_string())))
+        }
+    }
+}
 
-    Ok(tokens)
+/// Characters allowed after the first character of a symbol, so that names
+/// like `error-to-string`, `cons?` and `string->n` lex as one identifier.
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || "_?!-*+<>=./@$%&~^#'".contains(c)
 }
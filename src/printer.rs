@@ -0,0 +1,317 @@
+//! Printing the AST back as Shen source
+//!
+//! A Wadler-style pretty printer, built on the `pretty` crate: each node
+//! becomes a document of text and possible line breaks, and a group of breaks
+//! is only taken when the group does not fit in the width. Forms are laid out
+//! the way Shen is usually written:
+//!
+//! - each rule of a `define` or clause of a `defprolog` or `defcc` on its own
+//!   line, breaking after `->` when a rule is too long
+//! - the bindings of a `let` aligned and its body indented under them
+//! - one `cond` case per line
+//! - long applications and lists with their items aligned under the first
+//!
+//! Reading printed source gives back the same tree. Sugar the standard
+//! macros expand is printed short, as `(@p A B C)`, `(/. X Y Body)` and
+//! `(let X 1 Y 2 Body)`, since it expands to the nested forms again.
+
+use pretty::{Arena, DocAllocator, DocBuilder};
+
+use crate::ast::{Pattern, PrologClause, Rule, ShenNode, ShenValue, YaccItem, YaccRule};
+
+/// The width [`print_node`] and [`print_program`] are usually given.
+pub const DEFAULT_WIDTH: usize = 80;
+
+pub(crate) type Doc<'a> = DocBuilder<'a, Arena<'a>>;
+
+/// `node` as Shen source, fitting in `width` columns where it can.
+pub fn print_node(node: &ShenNode, width: usize) -> String {
+    let arena = Arena::new();
+    render(node_doc(&arena, node), width)
+}
+
+/// The forms of a program as Shen source, separated by blank lines.
+pub fn print_program(forms: &[ShenNode], width: usize) -> String {
+    let arena = Arena::new();
+    let forms = forms.iter().map(|form| node_doc(&arena, form));
+    let mut source = render(arena.intersperse(forms, arena.hardline().append(arena.hardline())), width);
+    source.push('\n');
+    source
+}
+
+fn render(doc: Doc<'_>, width: usize) -> String {
+    let mut source = String::new();
+    doc.1
+        .render_fmt(width, &mut source)
+        .expect("writing to a string cannot fail");
+    // Blank lines inside nested forms are indented like their neighbours
+    source.lines().map(str::trim_end).collect::<Vec<_>>().join("\n")
+}
+
+/// `(head Items...)`, the items aligned under the first when they break.
+fn form<'a>(a: &'a Arena<'a>, head: Doc<'a>, items: Vec<Doc<'a>>) -> Doc<'a> {
+    let items = match items.is_empty() {
+        true => a.nil(),
+        false => a.space().append(a.intersperse(items, a.line()).align()),
+    };
+    a.text("(").append(head).append(items).append(")").group()
+}
+
+/// `(keyword Name...` followed by `items`, one per line and indented.
+fn definition<'a>(a: &'a Arena<'a>, head: Doc<'a>, items: Vec<Doc<'a>>) -> Doc<'a> {
+    let items = a.concat(items.into_iter().map(|item| a.hardline().append(item)));
+    a.text("(").append(head).append(items.nest(2)).append(")")
+}
+
+fn words<'a>(a: &'a Arena<'a>, words: Vec<Doc<'a>>) -> Doc<'a> {
+    a.intersperse(words, a.space())
+}
+
+fn node_doc<'a>(a: &'a Arena<'a>, node: &'a ShenNode) -> Doc<'a> {
+    let node_docs = |nodes: &'a [ShenNode]| nodes.iter().map(|node| node_doc(a, node)).collect();
+
+    match node {
+        ShenNode::Literal { value } => literal(a, value),
+        ShenNode::Symbol { name, .. } => a.text(name.as_str()),
+        ShenNode::Nil => a.text("[]"),
+        // What was read is lost; stand in a form that fails the same way
+        ShenNode::Error { message } => form(a, a.text("simple-error"), vec![string(a, message)]),
+        ShenNode::List { elements, .. } => {
+            a.text("[").append(a.intersperse(node_docs(elements), a.line()).align()).append("]").group()
+        }
+        ShenNode::Cons { .. } => {
+            let mut heads = Vec::new();
+            let mut rest = node;
+            while let ShenNode::Cons { head, tail } = rest {
+                heads.push(node_doc(a, head));
+                rest = tail;
+            }
+            let items = a
+                .intersperse(heads, a.line())
+                .append(a.line())
+                .append("| ")
+                .append(node_doc(a, rest));
+            a.text("[").append(items.align()).append("]").group()
+        }
+        ShenNode::Application { func, args } => match (func.as_ref(), args.as_slice()) {
+            (ShenNode::Symbol { name, .. }, [_, _, _]) if *name == "let" => let_doc(a, node),
+            (ShenNode::Symbol { name, .. }, [_, _, ..]) if *name == "cond" => {
+                let cases = a.intersperse(node_docs(args), a.hardline());
+                a.text("(cond ").append(cases.align()).append(")")
+            }
+            _ => form(a, node_doc(a, func), node_docs(args)),
+        },
+        ShenNode::BinaryOperation { operator, left, right, .. } => {
+            form(a, a.text(operator.as_str()), vec![node_doc(a, left), node_doc(a, right)])
+        }
+        ShenNode::Function { name, args, body, .. } => {
+            let args = args.iter().map(|(arg, _)| a.text(arg.as_str())).collect();
+            let head = a.text("(defun ").append(name.as_str()).append(" (").append(words(a, args)).append(")");
+            head.append(a.line().append(node_doc(a, body)).nest(2)).append(")").group()
+        }
+        ShenNode::Lambda { .. } => {
+            let (args, body) = node.uncurry_lambda().expect("a lambda");
+            let args = args.into_iter().map(|(arg, _)| a.text(arg.as_str())).collect();
+            let head = a.text("(/. ").append(words(a, args));
+            head.append(a.line().append(node_doc(a, body)).nest(2)).append(")").group()
+        }
+        ShenNode::Conditional { condition, true_branch, false_branch } => {
+            let branches = [Some(condition), Some(true_branch), false_branch.as_ref()];
+            form(a, a.text("if"), branches.into_iter().flatten().map(|branch| node_doc(a, branch)).collect())
+        }
+        ShenNode::Define { name, rules } => {
+            definition(a, a.text("define ").append(name.as_str()), rules_doc(a, rules))
+        }
+        ShenNode::Defmacro { name, rules } => {
+            definition(a, a.text("defmacro ").append(name.as_str()), rules_doc(a, rules))
+        }
+        ShenNode::Defprolog { name, clauses } => definition(
+            a,
+            a.text("defprolog ").append(name.as_str()),
+            clauses.iter().map(|clause| clause_doc(a, clause)).collect(),
+        ),
+        ShenNode::Defcc { name, rules } => definition(
+            a,
+            a.text("defcc ").append(name.as_str()),
+            rules.iter().map(|rule| yacc_rule_doc(a, rule)).collect(),
+        ),
+        ShenNode::PrologQuery { goals } => form(a, a.text("prolog?"), node_docs(goals)),
+        ShenNode::Package { name, exports, body } => {
+            let exports = exports.iter().map(|export| a.text(export.as_str())).collect();
+            let head = a.text("package ").append(name.as_str()).append(" [").append(words(a, exports)).append("]");
+            let forms = node_docs(body).into_iter().enumerate().map(|(index, form)| match index {
+                0 => form,
+                _ => a.hardline().append(form),
+            });
+            definition(a, head, forms.collect())
+        }
+        ShenNode::Tuple { .. } => {
+            let (items, rest) = chain(a, node, |node| match node {
+                ShenNode::Tuple { first, second } => Some((first, second)),
+                _ => None,
+            });
+            form(a, a.text("@p"), items.into_iter().chain([rest]).collect())
+        }
+        ShenNode::Vector { elements } if elements.is_empty() => a.text("<>"),
+        ShenNode::Vector { elements } => {
+            let mut items: Vec<_> = node_docs(elements);
+            items.push(a.text("<>"));
+            form(a, a.text("@v"), items)
+        }
+        ShenNode::VectorCons { .. } => {
+            let (items, rest) = chain(a, node, |node| match node {
+                ShenNode::VectorCons { head, tail } => Some((head, tail)),
+                _ => None,
+            });
+            form(a, a.text("@v"), items.into_iter().chain([rest]).collect())
+        }
+        ShenNode::StringCons { .. } => {
+            let (items, rest) = chain(a, node, |node| match node {
+                ShenNode::StringCons { head, tail } => Some((head, tail)),
+                _ => None,
+            });
+            form(a, a.text("@s"), items.into_iter().chain([rest]).collect())
+        }
+        ShenNode::TrapError { body, handler } => {
+            form(a, a.text("trap-error"), vec![node_doc(a, body), node_doc(a, handler)])
+        }
+        ShenNode::Freeze { body } => form(a, a.text("freeze"), vec![node_doc(a, body)]),
+    }
+}
+
+/// The heads of a right-nested chain of pairs such as `(@v A (@v B V))`,
+/// and the tail it ends in.
+fn chain<'a>(
+    a: &'a Arena<'a>,
+    mut node: &'a ShenNode,
+    pair: impl Fn(&'a ShenNode) -> Option<(&'a ShenNode, &'a ShenNode)>,
+) -> (Vec<Doc<'a>>, Doc<'a>) {
+    let mut heads = Vec::new();
+    while let Some((head, tail)) = pair(node) {
+        heads.push(node_doc(a, head));
+        node = tail;
+    }
+    (heads, node_doc(a, node))
+}
+
+/// `(let X 1 Y 2 Body)`, with the nested `let`s of its body merged in.
+fn let_doc<'a>(a: &'a Arena<'a>, mut node: &'a ShenNode) -> Doc<'a> {
+    let mut bindings = Vec::new();
+    while let ShenNode::Application { func, args } = node {
+        match (func.as_ref(), args.as_slice()) {
+            (ShenNode::Symbol { name, .. }, [variable, value, body]) if *name == "let" => {
+                bindings.push(node_doc(a, variable).append(" ").append(node_doc(a, value).align()));
+                node = body;
+            }
+            _ => break,
+        }
+    }
+    a.text("(let ")
+        .append(a.intersperse(bindings, a.line()).align())
+        .append(a.line().append(node_doc(a, node)).nest(2))
+        .append(")")
+        .group()
+}
+
+fn rules_doc<'a>(a: &'a Arena<'a>, rules: &'a [Rule]) -> Vec<Doc<'a>> {
+    rules
+        .iter()
+        .map(|rule| {
+            let patterns = rule.patterns.iter().map(|pattern| pattern_doc(a, pattern));
+            let arrow = a.concat(patterns.map(|pattern| pattern.append(" "))).append("->");
+            let guard = match &rule.guard {
+                Some(guard) => a.line().append("where ").append(node_doc(a, guard)),
+                None => a.nil(),
+            };
+            arrow.append(a.line().append(node_doc(a, &rule.body)).append(guard).nest(2)).group()
+        })
+        .collect()
+}
+
+fn clause_doc<'a>(a: &'a Arena<'a>, clause: &'a PrologClause) -> Doc<'a> {
+    let head = clause.head.iter().map(|pattern| pattern_doc(a, pattern));
+    let arrow = a.concat(head.map(|pattern| pattern.append(" "))).append("<--");
+    let goals = a.concat(clause.body.iter().map(|goal| a.line().append(node_doc(a, goal))));
+    arrow.append(goals.nest(2)).append(";").group()
+}
+
+fn yacc_rule_doc<'a>(a: &'a Arena<'a>, rule: &'a YaccRule) -> Doc<'a> {
+    let items = words(a, rule.items.iter().map(|item| yacc_item(a, item)).collect());
+    let action = match &rule.action {
+        Some(action) => {
+            let guard = match &rule.guard {
+                Some(guard) => a.line().append("where ").append(node_doc(a, guard)),
+                None => a.nil(),
+            };
+            a.text(" :=").append(a.line().append(node_doc(a, action)).append(guard).nest(2))
+        }
+        None => a.nil(),
+    };
+    items.append(action).append(";").group()
+}
+
+fn yacc_item<'a>(a: &'a Arena<'a>, item: &'a YaccItem) -> Doc<'a> {
+    match item {
+        YaccItem::NonTerminal(name) | YaccItem::Variable(name) | YaccItem::Symbol(name) => {
+            a.text(name.as_str())
+        }
+        YaccItem::Empty => a.text("<e>"),
+        YaccItem::Rest => a.text("<!>"),
+        YaccItem::Literal(value) => literal(a, value),
+    }
+}
+
+fn pattern_doc<'a>(a: &'a Arena<'a>, pattern: &'a Pattern) -> Doc<'a> {
+    // The components of a right-nested (@p A (@p B C)) and its like
+    let pairs = |pattern: &'a Pattern| {
+        let mut items = Vec::new();
+        let mut rest = pattern;
+        while let (Pattern::Tuple(..), Pattern::Tuple(first, second))
+        | (Pattern::VectorCons(..), Pattern::VectorCons(first, second))
+        | (Pattern::StringCons(..), Pattern::StringCons(first, second)) = (pattern, rest)
+        {
+            items.push(pattern_doc(a, first));
+            rest = second;
+        }
+        items.push(pattern_doc(a, rest));
+        words(a, items)
+    };
+
+    match pattern {
+        Pattern::Variable(name) | Pattern::Symbol(name) => a.text(name.as_str()),
+        Pattern::Wildcard => a.text("_"),
+        Pattern::Literal(value) => literal(a, value),
+        Pattern::EmptyList => a.text("[]"),
+        Pattern::EmptyVector => a.text("<>"),
+        Pattern::Cons(..) => {
+            let mut heads = Vec::new();
+            let mut rest = pattern;
+            while let Pattern::Cons(head, tail) = rest {
+                heads.push(pattern_doc(a, head));
+                rest = tail;
+            }
+            let tail = match rest {
+                Pattern::EmptyList => a.nil(),
+                tail => a.text(" | ").append(pattern_doc(a, tail)),
+            };
+            a.text("[").append(words(a, heads)).append(tail).append("]")
+        }
+        Pattern::Tuple(..) => a.text("(@p ").append(pairs(pattern)).append(")"),
+        Pattern::VectorCons(..) => a.text("(@v ").append(pairs(pattern)).append(")"),
+        Pattern::StringCons(..) => a.text("(@s ").append(pairs(pattern)).append(")"),
+    }
+}
+
+fn literal<'a>(a: &'a Arena<'a>, value: &'a ShenValue) -> Doc<'a> {
+    match value {
+        ShenValue::Integer(value) => a.as_string(value),
+        ShenValue::Float(value) => a.as_string(value),
+        ShenValue::String(value) => string(a, value),
+        ShenValue::Boolean(value) => a.as_string(value),
+        ShenValue::Nil => a.text("[]"),
+    }
+}
+
+fn string<'a>(a: &'a Arena<'a>, value: &'a str) -> Doc<'a> {
+    a.text("\"").append(value).append("\"")
+}
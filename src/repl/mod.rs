@@ -0,0 +1,149 @@
+//! The interactive loop behind `shen-transpile repl`
+//!
+//! [`Repl`] evaluates Shen forms with the [`Evaluator`] and prints results as
+//! the Shen REPL does. Lines starting with `:` are commands for looking at
+//! what the transpiler makes of a form:
+//!
+//! - `:rust <expr>` prints the generated Rust
+//! - `:ast <expr>` prints the parsed [`ShenNode`]
+//! - `:expand <expr>` prints the expression as Shen, with macros expanded
+//! - `:type <expr>` prints the inferred type
+//! - `:load <file>` evaluates every form of a Shen file
+//!
+//! Reading lines, continuing unbalanced input and keeping history are left
+//! to the front end in `main.rs`.
+
+use std::fs;
+
+use crate::ast::ShenNode;
+use crate::codegen::{generate_rust_code_with_options, CodegenOptions};
+use crate::error::TranspilerError;
+use crate::eval::{Env, Evaluator};
+use crate::expand::expand_program;
+use crate::parser::{is_unfinished, parse_shen_program, parse_shen_source};
+use crate::printer::{print_node, DEFAULT_WIDTH};
+
+pub const HELP: &str = "\
+:rust <expr>   show the Rust generated for <expr>
+:ast <expr>    show the parsed AST of <expr>
+:expand <expr> show <expr> with its macros expanded
+:type <expr>   show the inferred type of <expr>
+:load <file>   evaluate every form in a Shen file
+:help          show this message
+:quit          leave the REPL";
+
+/// What the front end should do after a line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplOutput {
+    /// Print this, which may be empty.
+    Text(String),
+    Quit,
+}
+
+/// A REPL session.
+#[derive(Debug, Default)]
+pub struct Repl {
+    evaluator: Evaluator,
+    /// The `defmacro`s entered so far, applied to every later input
+    macros: Vec<ShenNode>,
+    options: CodegenOptions,
+    inputs: usize,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl::default()
+    }
+
+    pub fn with_options(options: CodegenOptions) -> Self {
+        Repl {
+            options,
+            ..Repl::default()
+        }
+    }
+
+    /// The Shen-style prompt, numbered by input: `(0-) `.
+    pub fn prompt(&self) -> String {
+        format!("({}-) ", self.inputs)
+    }
+
+    /// Handle one complete input: a command or one or more Shen forms.
+    pub fn handle(&mut self, input: &str) -> Result<ReplOutput, TranspilerError> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(ReplOutput::Text(String::new()));
+        }
+        self.inputs += 1;
+
+        let (command, argument) = match input.strip_prefix(':') {
+            Some(command) => command.split_once(char::is_whitespace).unwrap_or((command, "")),
+            None => return self.eval_source(input).map(ReplOutput::Text),
+        };
+        let argument = argument.trim();
+        let text = match command {
+            "rust" => generate_rust_code_with_options(&self.parse(argument)?, &self.options)?,
+            "ast" => format!("{:#?}", self.parse(argument)?),
+            "expand" => print_node(&self.parse(argument)?, DEFAULT_WIDTH),
+            "type" => self.parse(argument)?.infer_type().to_string(),
+            "load" => {
+                let source = fs::read_to_string(argument).map_err(|err| TranspilerError::IoError {
+                    path: argument.to_string(),
+                    message: err.to_string(),
+                })?;
+                self.eval_source(&source)?
+            }
+            "help" => HELP.to_string(),
+            "quit" | "q" => return Ok(ReplOutput::Quit),
+            _ => {
+                return Err(TranspilerError::SyntaxError(format!(
+                    "unknown command :{}; try :help",
+                    command
+                )))
+            }
+        };
+        Ok(ReplOutput::Text(text))
+    }
+
+    /// Evaluate the forms of `source` in order, printing each result.
+    fn eval_source(&mut self, source: &str) -> Result<String, TranspilerError> {
+        let forms = parse_shen_program(source)?;
+        let (macros, forms): (Vec<_>, Vec<_>) = forms
+            .into_iter()
+            .partition(|form| matches!(form, ShenNode::Defmacro { .. }));
+        let names = macros
+            .iter()
+            .filter_map(|form| match form {
+                ShenNode::Defmacro { name, .. } => Some(name.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.macros.extend(macros);
+
+        let mut printed = names;
+        for form in self.expand(forms)? {
+            printed.push(self.evaluator.eval(&form, &Env::new())?.to_string());
+        }
+        Ok(printed.join("\n"))
+    }
+
+    /// Parse a single expression and apply the session's macros.
+    fn parse(&self, source: &str) -> Result<ShenNode, TranspilerError> {
+        let node = parse_shen_source(source)?;
+        self.expand(vec![node])?
+            .pop()
+            .ok_or_else(|| TranspilerError::SyntaxError("expected an expression".to_string()))
+    }
+
+    fn expand(&self, forms: Vec<ShenNode>) -> Result<Vec<ShenNode>, TranspilerError> {
+        if self.macros.is_empty() {
+            return Ok(forms);
+        }
+        expand_program(self.macros.iter().cloned().chain(forms).collect())
+    }
+}
+
+/// Whether `input` is whole forms, or a form not yet closed that the front
+/// end should keep reading lines into.
+pub fn is_complete(input: &str) -> bool {
+    !is_unfinished(input)
+}
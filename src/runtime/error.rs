@@ -0,0 +1,105 @@
+//! Shen exceptions: `simple-error`, `trap-error` and `error-to-string`
+//!
+//! Generated code uses one of two schemes, picked by
+//! [`ErrorStrategy`](crate::codegen::ErrorStrategy). Under `Unwind`, [`raise`]
+//! unwinds to the nearest [`trap_error`]. Under `Result`, errors travel as
+//! `Err` values through `?` and are caught by [`trap_error_result`].
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use thiserror::Error;
+
+use super::value::Value;
+
+/// An error raised by Shen code.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ShenError {
+    /// Raised by `(simple-error Message)`
+    #[error("{0}")]
+    Simple(String),
+}
+
+pub type ShenResult<T> = Result<T, ShenError>;
+
+/// Turns a `ShenResult` into a plain value under the unwinding scheme.
+pub trait OrRaise<T> {
+    /// Return the value, or [`raise`] the error.
+    fn or_raise(self) -> T;
+}
+
+impl<T> OrRaise<T> for ShenResult<T> {
+    fn or_raise(self) -> T {
+        self.unwrap_or_else(|error| raise(error))
+    }
+}
+
+/// `(simple-error Message)`: build the error value.
+pub fn simple_error(message: impl Into<String>) -> ShenError {
+    ShenError::Simple(message.into())
+}
+
+/// `(error-to-string E)`: recover the message of a caught error.
+pub fn error_to_string(error: &ShenError) -> String {
+    error.to_string()
+}
+
+/// `(simple-error Message)` with a message only known at run time. A message
+/// that is not a string is itself the error.
+pub fn simple_error_from(message: &Value) -> ShenError {
+    match message {
+        Value::String(message) => simple_error(message.clone()),
+        _ => simple_error(format!("{} is not a string", message)),
+    }
+}
+
+/// `(error-to-string E)` with an error caught as a [`Value`].
+pub fn error_message(error: &Value) -> ShenResult<Value> {
+    match error {
+        Value::Error(error) => Ok(Value::String(error_to_string(error))),
+        _ => Err(simple_error(format!("{} is not an exception", error))),
+    }
+}
+
+/// Raise `error` by unwinding. The panic hook is bypassed, so nothing is
+/// printed when a surrounding [`trap_error`] catches it.
+pub fn raise(error: ShenError) -> ! {
+    panic::resume_unwind(Box::new(error))
+}
+
+/// `(trap-error Body Handler)` under the unwinding scheme.
+///
+/// Ordinary Rust panics with a string payload are caught as well and reach the
+/// handler as a `simple-error` carrying the panic message.
+pub fn trap_error<T>(body: impl FnOnce() -> T, handler: impl FnOnce(ShenError) -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(payload) => match error_from_panic(payload) {
+            Ok(error) => handler(error),
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    }
+}
+
+/// `(trap-error Body Handler)` under the `Result` scheme.
+pub fn trap_error_result<T>(
+    body: impl FnOnce() -> ShenResult<T>,
+    handler: impl FnOnce(ShenError) -> ShenResult<T>,
+) -> ShenResult<T> {
+    body().or_else(handler)
+}
+
+fn error_from_panic(payload: Box<dyn Any + Send>) -> Result<ShenError, Box<dyn Any + Send>> {
+    let payload = match payload.downcast::<ShenError>() {
+        Ok(error) => return Ok(*error),
+        Err(payload) => payload,
+    };
+    let payload = match payload.downcast::<String>() {
+        Ok(message) => return Ok(ShenError::Simple(*message)),
+        Err(payload) => payload,
+    };
+    match payload.downcast::<&'static str>() {
+        Ok(message) => Ok(ShenError::Simple(message.to_string())),
+        Err(payload) => Err(payload),
+    }
+}
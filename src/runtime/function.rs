@@ -0,0 +1,52 @@
+//! Functions as values
+//!
+//! Shen functions are curried: a lambda takes one argument, and a function
+//! applied to too few arguments returns a function waiting for the rest.
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::error::{simple_error, ShenResult};
+use super::value::Value;
+
+type FunctionBody = dyn Fn(Value) -> ShenResult<Value> + Send + Sync;
+
+/// A one-argument Shen function, such as `(/. X (+ X 1))`.
+///
+/// Cloning is cheap and shares the underlying closure.
+#[derive(Clone)]
+pub struct Function {
+    body: Arc<FunctionBody>,
+}
+
+impl Function {
+    pub fn new(body: impl Fn(Value) -> ShenResult<Value> + Send + Sync + 'static) -> Self {
+        Function {
+            body: Arc::new(body),
+        }
+    }
+
+    /// Apply the function to `arg`.
+    pub fn call(&self, arg: Value) -> ShenResult<Value> {
+        (self.body)(arg)
+    }
+
+    /// Whether `self` and `other` are the same closure.
+    pub fn ptr_eq(&self, other: &Function) -> bool {
+        Arc::ptr_eq(&self.body, &other.body)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("#<function>")
+    }
+}
+
+/// Apply a function held as a [`Value`] to `arg`.
+pub fn apply(function: &Value, arg: Value) -> ShenResult<Value> {
+    match function {
+        Value::Function(function) => function.call(arg),
+        _ => Err(simple_error(format!("{} is not a function", function))),
+    }
+}
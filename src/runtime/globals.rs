@@ -0,0 +1,91 @@
+//! The global environment behind `set`, `value` and `bound?`
+//!
+//! Generated code reaches the environment either through [`globals`], shared
+//! by every thread, or through [`with_thread_globals`], private to the calling
+//! thread, depending on [`GlobalsMode`](crate::codegen::GlobalsMode). Host code
+//! uses the same functions to pre-seed or read Shen globals.
+
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use super::error::{simple_error, ShenResult};
+use super::value::{Stream, Value};
+
+/// A thread-safe table of Shen global variables.
+#[derive(Debug, Default)]
+pub struct Globals {
+    values: RwLock<HashMap<String, Value>>,
+}
+
+impl Globals {
+    /// An empty environment.
+    pub fn new() -> Self {
+        Globals::default()
+    }
+
+    /// An environment holding the globals the Shen standard library expects.
+    pub fn with_defaults() -> Self {
+        let globals = Globals::new();
+        let home_directory = std::env::current_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+
+        globals.set("*stinput*", Value::Stream(Stream::Stdin));
+        globals.set("*stoutput*", Value::Stream(Stream::Stdout));
+        globals.set("*sterror*", Value::Stream(Stream::Stderr));
+        globals.set("*home-directory*", home_directory);
+        globals.set("*language*", "Rust");
+        globals.set("*implementation*", "shen-rust");
+        globals.set("*port*", env!("CARGO_PKG_VERSION"));
+        globals.set("*os*", std::env::consts::OS);
+        globals
+    }
+
+    /// `(set Name Value)`: returns the value assigned.
+    pub fn set(&self, name: &str, value: impl Into<Value>) -> Value {
+        let value = value.into();
+        self.values
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(name.to_string(), value.clone());
+        value
+    }
+
+    /// `(value Name)`: fails with a Shen error if `Name` was never set.
+    pub fn value(&self, name: &str) -> ShenResult<Value> {
+        self.get(name)
+            .ok_or_else(|| simple_error(format!("variable {} has no value", name)))
+    }
+
+    /// `(bound? Name)`
+    pub fn is_bound(&self, name: &str) -> bool {
+        self.values
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .contains_key(name)
+    }
+
+    /// Read a global from host code without raising an error.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.values
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(name)
+            .cloned()
+    }
+}
+
+/// The process-wide environment, seeded with [`Globals::with_defaults`].
+pub fn globals() -> &'static Globals {
+    static GLOBALS: OnceLock<Globals> = OnceLock::new();
+    GLOBALS.get_or_init(Globals::with_defaults)
+}
+
+thread_local! {
+    static THREAD_GLOBALS: Globals = Globals::with_defaults();
+}
+
+/// Run `f` against the calling thread's own environment.
+pub fn with_thread_globals<R>(f: impl FnOnce(&Globals) -> R) -> R {
+    THREAD_GLOBALS.with(f)
+}
@@ -0,0 +1,55 @@
+//! Frozen computations: `freeze`, `thaw` and the `(lazy A)` type
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::error::{simple_error, ShenResult};
+use super::value::Value;
+
+/// A value of Shen type `(lazy A)`, built by `(freeze Expr)`.
+///
+/// Thawing re-runs the frozen expression every time, as in Shen; nothing is
+/// memoized. Cloning is cheap and shares the underlying thunk.
+pub struct Lazy<T> {
+    thunk: Arc<dyn Fn() -> T + Send + Sync>,
+}
+
+impl<T> Lazy<T> {
+    pub fn new(thunk: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        Lazy {
+            thunk: Arc::new(thunk),
+        }
+    }
+
+    /// Force the frozen computation: `(thaw F)`
+    pub fn thaw(&self) -> T {
+        (self.thunk)()
+    }
+}
+
+impl<T> Clone for Lazy<T> {
+    fn clone(&self) -> Self {
+        Lazy {
+            thunk: Arc::clone(&self.thunk),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Lazy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("#<freeze>")
+    }
+}
+
+/// Free-function form of [`Lazy::thaw`], for passing `thaw` as a value.
+pub fn thaw<T>(lazy: &Lazy<T>) -> T {
+    lazy.thaw()
+}
+
+/// `(thaw F)` with the frozen computation held as a [`Value`].
+pub fn thaw_value(value: &Value) -> ShenResult<Value> {
+    match value {
+        Value::Lazy(lazy) => Ok(lazy.thaw()),
+        _ => Err(simple_error(format!("{} is not a frozen computation", value))),
+    }
+}
@@ -0,0 +1,48 @@
+//! List primitives over [`Value`]s: `hd`, `tl`, `cons?`, `empty?` and
+//! `element?`
+
+use super::error::{simple_error, ShenResult};
+use super::value::Value;
+
+/// `(hd L)`: fails on anything but a non-empty list.
+pub fn hd(list: &Value) -> ShenResult<Value> {
+    list.as_cons()
+        .map(|(head, _)| head)
+        .ok_or_else(|| simple_error(format!("{} has no head", list)))
+}
+
+/// `(tl L)`: fails on anything but a non-empty list.
+pub fn tl(list: &Value) -> ShenResult<Value> {
+    list.as_cons()
+        .map(|(_, tail)| tail)
+        .ok_or_else(|| simple_error(format!("{} has no tail", list)))
+}
+
+/// `(cons? X)`
+pub fn is_cons(value: &Value) -> bool {
+    matches!(value, Value::Cons(_))
+}
+
+/// `(empty? X)`
+pub fn is_empty(value: &Value) -> bool {
+    *value == Value::Nil
+}
+
+/// `(element? X L)`
+pub fn is_element(value: &Value, list: &Value) -> ShenResult<bool> {
+    Ok(list_items(list)?.contains(value))
+}
+
+/// The elements of a proper list; fails on anything else.
+pub fn list_items(list: &Value) -> ShenResult<Vec<Value>> {
+    let mut items = Vec::new();
+    let mut rest = list.clone();
+    while let Some((head, tail)) = rest.as_cons() {
+        items.push(head);
+        rest = tail;
+    }
+    match rest {
+        Value::Nil => Ok(items),
+        _ => Err(simple_error(format!("{} is not a list", list))),
+    }
+}
@@ -0,0 +1,51 @@
+//! Runtime support for transpiled Shen code
+//!
+//! Generated Rust refers to these items through [`prelude`].
+
+pub mod error;
+pub mod function;
+pub mod globals;
+pub mod lazy;
+pub mod list;
+pub mod number;
+pub mod prolog;
+pub mod stream;
+pub mod string;
+pub mod tuple;
+pub mod value;
+pub mod vector;
+pub mod yacc;
+
+pub use error::{ShenError, ShenResult};
+pub use function::Function;
+pub use globals::{globals, with_thread_globals, Globals};
+pub use lazy::{thaw, Lazy};
+pub use value::Value;
+
+/// Items every generated module imports with `use shen_transpiler::runtime::prelude::*;`
+pub mod prelude {
+    pub use super::error::{
+        error_message, error_to_string, raise, simple_error, simple_error_from, trap_error,
+        trap_error_result, OrRaise, ShenError, ShenResult,
+    };
+    pub use super::function::{apply, Function};
+    pub use super::globals::{globals, with_thread_globals, Globals};
+    pub use super::lazy::{thaw, thaw_value, Lazy};
+    pub use super::list::{hd, is_cons, is_element, is_empty, tl};
+    pub use super::number::{
+        add, divide, greater, greater_or_equal, is_integer, is_number, less, less_or_equal,
+        multiply, subtract,
+    };
+    pub use super::prolog::{
+        define_prolog, prolog_query, Clause, Env, Expr, Goal, PrologDatabase, Term,
+    };
+    pub use super::stream::pr;
+    pub use super::string::{app, cn, is_string, n_to_string, pos, str, string_to_n, tlstr};
+    pub use super::tuple::{fst, is_tuple, snd};
+    pub use super::value::Value;
+    pub use super::vector::{
+        absvector, address_get, address_set, is_absvector, is_vector, limit, vector, vector_cons,
+        vector_get, vector_set,
+    };
+    pub use super::yacc::{compile, yacc_consumed, yacc_terminal, YaccParser};
+}
@@ -0,0 +1,96 @@
+//! Arithmetic and comparisons over [`Value`]s
+//!
+//! Typed generated code uses Rust's own operators. These are for code where
+//! numbers are only known at run time: untyped KLambda and the expressions of
+//! Prolog goals. Integer arithmetic stays integral where the result is exact.
+
+use super::error::{simple_error, ShenResult};
+use super::value::Value;
+
+/// `(+ X Y)`
+pub fn add(left: &Value, right: &Value) -> ShenResult<Value> {
+    arithmetic("+", left, right)
+}
+
+/// `(- X Y)`
+pub fn subtract(left: &Value, right: &Value) -> ShenResult<Value> {
+    arithmetic("-", left, right)
+}
+
+/// `(* X Y)`
+pub fn multiply(left: &Value, right: &Value) -> ShenResult<Value> {
+    arithmetic("*", left, right)
+}
+
+/// `(/ X Y)`: fails on division by zero.
+pub fn divide(left: &Value, right: &Value) -> ShenResult<Value> {
+    arithmetic("/", left, right)
+}
+
+/// `(< X Y)`
+pub fn less(left: &Value, right: &Value) -> ShenResult<Value> {
+    Ok(Value::Boolean(as_number(left)? < as_number(right)?))
+}
+
+/// `(> X Y)`
+pub fn greater(left: &Value, right: &Value) -> ShenResult<Value> {
+    Ok(Value::Boolean(as_number(left)? > as_number(right)?))
+}
+
+/// `(<= X Y)`
+pub fn less_or_equal(left: &Value, right: &Value) -> ShenResult<Value> {
+    Ok(Value::Boolean(as_number(left)? <= as_number(right)?))
+}
+
+/// `(>= X Y)`
+pub fn greater_or_equal(left: &Value, right: &Value) -> ShenResult<Value> {
+    Ok(Value::Boolean(as_number(left)? >= as_number(right)?))
+}
+
+/// `(number? X)`
+pub fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Integer(_) | Value::Float(_))
+}
+
+/// `(integer? X)`: floats without a fractional part count.
+pub fn is_integer(value: &Value) -> bool {
+    match value {
+        Value::Integer(_) => true,
+        Value::Float(value) => value.fract() == 0.0,
+        _ => false,
+    }
+}
+
+fn arithmetic(operator: &str, left: &Value, right: &Value) -> ShenResult<Value> {
+    if let (Value::Integer(left), Value::Integer(right)) = (left, right) {
+        let exact = match operator {
+            "+" => left.checked_add(*right),
+            "-" => left.checked_sub(*right),
+            "*" => left.checked_mul(*right),
+            _ if *right != 0 && left % right == 0 => Some(left / right),
+            _ => None,
+        };
+        if let Some(result) = exact {
+            return Ok(Value::Integer(result));
+        }
+    }
+
+    let (left, right) = (as_number(left)?, as_number(right)?);
+    if operator == "/" && right == 0.0 {
+        return Err(simple_error("division by zero"));
+    }
+    Ok(Value::Float(match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        _ => left / right,
+    }))
+}
+
+fn as_number(value: &Value) -> ShenResult<f64> {
+    match value {
+        Value::Integer(value) => Ok(*value as f64),
+        Value::Float(value) => Ok(*value),
+        _ => Err(simple_error(format!("{} is not a number", value))),
+    }
+}
@@ -0,0 +1,489 @@
+//! Shen-Prolog: the engine behind `defprolog` and `prolog?`
+//!
+//! Each `defprolog` becomes a list of [`Clause`]s stored in a
+//! [`PrologDatabase`]. Clause variables are numbered from zero within their
+//! clause; when a clause is tried its variables are given fresh slots in the
+//! solver's binding table, so the same clause can be active many times at once.
+//! Solving is depth-first with chronological backtracking, and `!` discards the
+//! remaining alternatives of the predicate call that introduced it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::error::{simple_error, ShenResult};
+use super::value::Value;
+
+/// A Prolog term: a logic variable, a Shen value, or a cons whose parts may
+/// contain variables.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Var(usize),
+    Value(Value),
+    Cons(Box<Term>, Box<Term>),
+}
+
+impl Term {
+    /// Variable `index` of the enclosing clause or query.
+    pub fn var(index: usize) -> Self {
+        Term::Var(index)
+    }
+
+    pub fn value(value: impl Into<Value>) -> Self {
+        Term::Value(value.into())
+    }
+
+    /// `[Head | Tail]`
+    pub fn cons(head: Term, tail: Term) -> Self {
+        Term::Cons(Box::new(head), Box::new(tail))
+    }
+
+    /// The same term with its clause-relative variables moved to `base`.
+    fn offset(&self, base: usize) -> Term {
+        match self {
+            Term::Var(index) => Term::Var(base + index),
+            Term::Value(value) => Term::Value(value.clone()),
+            Term::Cons(head, tail) => Term::cons(head.offset(base), tail.offset(base)),
+        }
+    }
+}
+
+/// A Shen expression evaluated by `is`, `bind` and `when`, with access to the
+/// current bindings of its clause's variables.
+#[derive(Clone)]
+pub struct Expr(Arc<ExprFn>);
+
+type ExprFn = dyn Fn(&Env) -> ShenResult<Value> + Send + Sync;
+
+impl Expr {
+    pub fn new(eval: impl Fn(&Env) -> ShenResult<Value> + Send + Sync + 'static) -> Self {
+        Expr(Arc::new(eval))
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<expr>")
+    }
+}
+
+/// The variables of one clause or query as seen from an [`Expr`].
+pub struct Env<'s> {
+    bindings: &'s [Option<Term>],
+    base: usize,
+}
+
+impl Env<'_> {
+    /// The value of variable `index`, with any bound variables inside it
+    /// replaced by their values.
+    pub fn value(&self, index: usize) -> Value {
+        resolve(self.bindings, &Term::Var(self.base + index))
+    }
+}
+
+/// One goal in the body of a clause or query.
+#[derive(Debug, Clone)]
+pub enum Goal {
+    /// `(name Args...)`: call a predicate
+    Call(String, Vec<Term>),
+    /// `!`
+    Cut,
+    /// `(is Term Expr)`: unify `Term` with the value of `Expr`
+    Is(Term, Expr),
+    /// `(bind Var Expr)`: bind an unbound variable to the value of `Expr`
+    Bind(Term, Expr),
+    /// `(when Expr)`: succeed if `Expr` is true
+    When(Expr),
+    /// `(call Goal)`: call a goal built at run time as a list `[name Args...]`
+    CallTerm(Term),
+    /// `(return Term)`: stop the query and make it return `Term`
+    Return(Term),
+}
+
+/// `Head <-- Body;` with the number of distinct variables it uses.
+#[derive(Debug, Clone)]
+pub struct Clause {
+    var_count: usize,
+    head: Vec<Term>,
+    body: Vec<Goal>,
+}
+
+impl Clause {
+    pub fn new(var_count: usize, head: Vec<Term>, body: Vec<Goal>) -> Self {
+        Clause {
+            var_count,
+            head,
+            body,
+        }
+    }
+}
+
+/// The predicates known to the Prolog engine.
+#[derive(Debug, Default)]
+pub struct PrologDatabase {
+    predicates: HashMap<String, Vec<Clause>>,
+}
+
+impl PrologDatabase {
+    pub fn new() -> Self {
+        PrologDatabase::default()
+    }
+
+    /// Append a clause to predicate `name`.
+    pub fn add_clause(&mut self, name: &str, clause: Clause) {
+        self.predicates
+            .entry(name.to_string())
+            .or_default()
+            .push(clause);
+    }
+
+    /// Define predicate `name`, replacing any earlier definition as
+    /// re-evaluating a `defprolog` does.
+    pub fn define(&mut self, name: &str, clauses: Vec<Clause>) {
+        self.predicates.insert(name.to_string(), clauses);
+    }
+
+    /// Solve `goals`, which use variables `0..var_count`, calling
+    /// `on_solution` for each solution until it returns `false`.
+    pub fn solve(
+        &self,
+        var_count: usize,
+        goals: &[Goal],
+        mut on_solution: impl FnMut(&Env) -> ShenResult<bool>,
+    ) -> ShenResult<()> {
+        let mut solver = Solver::new(self, &mut on_solution);
+        solver.bindings.resize(var_count, None);
+        let continuation = solver.push_goals(goals, 0, 0, None);
+        solver.solve(continuation)?;
+        Ok(())
+    }
+
+    /// `(prolog? Goals...)`: the value of the first `return` reached, or
+    /// whether the goals have a solution at all.
+    pub fn query(&self, var_count: usize, goals: &[Goal]) -> ShenResult<Value> {
+        let mut on_solution = |_: &Env| Ok(false);
+        let mut solver = Solver::new(self, &mut on_solution);
+        solver.bindings.resize(var_count, None);
+        let continuation = solver.push_goals(goals, 0, 0, None);
+        let flow = solver.solve(continuation)?;
+        Ok(match solver.returned {
+            Some(value) => value,
+            None => Value::Boolean(matches!(flow, Flow::Stop)),
+        })
+    }
+}
+
+/// The database `defprolog` adds to and `prolog?` queries.
+pub fn prolog_database() -> &'static RwLock<PrologDatabase> {
+    static DATABASE: OnceLock<RwLock<PrologDatabase>> = OnceLock::new();
+    DATABASE.get_or_init(|| RwLock::new(PrologDatabase::new()))
+}
+
+/// `(defprolog name ...)`: returns the predicate name.
+pub fn define_prolog(name: &str, clauses: Vec<Clause>) -> Value {
+    prolog_database()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .define(name, clauses);
+    Value::symbol(name)
+}
+
+/// `(prolog? Goals...)` against [`prolog_database`].
+pub fn prolog_query(var_count: usize, goals: Vec<Goal>) -> ShenResult<Value> {
+    prolog_database()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .query(var_count, &goals)
+}
+
+/// How a branch of the search ended.
+enum Flow {
+    /// No (more) solutions; try the next alternative.
+    Fail,
+    /// A solution asked the search to stop.
+    Stop,
+    /// A cut was backtracked over; unwind to the call with this barrier.
+    Cut(usize),
+}
+
+/// A goal waiting to run, with the frame its variables live in and the
+/// barrier a cut inside it unwinds to.
+struct GoalNode<'a> {
+    goal: Rc<GoalRef<'a>>,
+    base: usize,
+    barrier: usize,
+    next: Option<Rc<GoalNode<'a>>>,
+}
+
+enum GoalRef<'a> {
+    Borrowed(&'a Goal),
+    Owned(Goal),
+}
+
+impl GoalRef<'_> {
+    fn get(&self) -> &Goal {
+        match self {
+            GoalRef::Borrowed(goal) => goal,
+            GoalRef::Owned(goal) => goal,
+        }
+    }
+}
+
+struct Solver<'a, 'f> {
+    database: &'a PrologDatabase,
+    bindings: Vec<Option<Term>>,
+    trail: Vec<usize>,
+    next_barrier: usize,
+    returned: Option<Value>,
+    on_solution: &'f mut dyn FnMut(&Env) -> ShenResult<bool>,
+}
+
+impl<'a, 'f> Solver<'a, 'f> {
+    fn new(
+        database: &'a PrologDatabase,
+        on_solution: &'f mut dyn FnMut(&Env) -> ShenResult<bool>,
+    ) -> Self {
+        Solver {
+            database,
+            bindings: Vec::new(),
+            trail: Vec::new(),
+            next_barrier: 1,
+            returned: None,
+            on_solution,
+        }
+    }
+
+    fn push_goals(
+        &self,
+        goals: &'a [Goal],
+        base: usize,
+        barrier: usize,
+        next: Option<Rc<GoalNode<'a>>>,
+    ) -> Option<Rc<GoalNode<'a>>> {
+        goals.iter().rev().fold(next, |next, goal| {
+            Some(Rc::new(GoalNode {
+                goal: Rc::new(GoalRef::Borrowed(goal)),
+                base,
+                barrier,
+                next,
+            }))
+        })
+    }
+
+    fn solve(&mut self, goals: Option<Rc<GoalNode<'a>>>) -> ShenResult<Flow> {
+        let node = match goals {
+            None => {
+                let env = Env {
+                    bindings: &self.bindings,
+                    base: 0,
+                };
+                return Ok(if (self.on_solution)(&env)? {
+                    Flow::Fail
+                } else {
+                    Flow::Stop
+                });
+            }
+            Some(node) => node,
+        };
+        let (base, next) = (node.base, node.next.clone());
+
+        match node.goal.get() {
+            Goal::Call(name, args) => {
+                let args = args.iter().map(|arg| arg.offset(base)).collect::<Vec<_>>();
+                self.call(name, &args, next)
+            }
+            Goal::Cut => match self.solve(next)? {
+                Flow::Fail => Ok(Flow::Cut(node.barrier)),
+                flow => Ok(flow),
+            },
+            Goal::Is(term, expr) => {
+                let value = self.eval(expr, base)?;
+                self.unify_then(&term.offset(base), &Term::Value(value), next)
+            }
+            Goal::Bind(term, expr) => {
+                let value = self.eval(expr, base)?;
+                match self.deref(&term.offset(base)) {
+                    Term::Var(_) => self.unify_then(&term.offset(base), &Term::Value(value), next),
+                    _ => Err(simple_error("bind expects an unbound variable")),
+                }
+            }
+            Goal::When(expr) => match self.eval(expr, base)? {
+                Value::Boolean(true) => self.solve(next),
+                Value::Boolean(false) => Ok(Flow::Fail),
+                value => Err(simple_error(format!("{} is not a boolean", value))),
+            },
+            Goal::CallTerm(term) => {
+                let (name, args) = self.goal_from_term(&term.offset(base))?;
+                let goal = Rc::new(GoalNode {
+                    goal: Rc::new(GoalRef::Owned(Goal::Call(name, args))),
+                    base: 0,
+                    barrier: node.barrier,
+                    next,
+                });
+                self.solve(Some(goal))
+            }
+            Goal::Return(term) => {
+                self.returned = Some(resolve(&self.bindings, &term.offset(base)));
+                Ok(Flow::Stop)
+            }
+        }
+    }
+
+    /// Try each clause of `name` against `args`, which are already in absolute
+    /// variable numbering.
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[Term],
+        next: Option<Rc<GoalNode<'a>>>,
+    ) -> ShenResult<Flow> {
+        let database = self.database;
+        let clauses = database
+            .predicates
+            .get(name)
+            .ok_or_else(|| simple_error(format!("{} is not a Prolog predicate", name)))?;
+
+        let barrier = self.next_barrier;
+        self.next_barrier += 1;
+
+        for clause in clauses {
+            if clause.head.len() != args.len() {
+                continue;
+            }
+
+            let mark = self.trail.len();
+            let base = self.bindings.len();
+            self.bindings.resize(base + clause.var_count, None);
+
+            let unified = clause
+                .head
+                .iter()
+                .zip(args)
+                .all(|(head, arg)| self.unify(&head.offset(base), arg));
+            let flow = if unified {
+                let body = self.push_goals(&clause.body, base, barrier, next.clone());
+                self.solve(body)?
+            } else {
+                Flow::Fail
+            };
+
+            self.undo(mark);
+            self.bindings.truncate(base);
+
+            match flow {
+                Flow::Fail => continue,
+                Flow::Cut(cut) if cut == barrier => return Ok(Flow::Fail),
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Fail)
+    }
+
+    fn unify_then(
+        &mut self,
+        left: &Term,
+        right: &Term,
+        next: Option<Rc<GoalNode<'a>>>,
+    ) -> ShenResult<Flow> {
+        let mark = self.trail.len();
+        let flow = if self.unify(left, right) {
+            self.solve(next)?
+        } else {
+            Flow::Fail
+        };
+        self.undo(mark);
+        Ok(flow)
+    }
+
+    fn eval(&self, expr: &Expr, base: usize) -> ShenResult<Value> {
+        (expr.0)(&Env {
+            bindings: &self.bindings,
+            base,
+        })
+    }
+
+    /// Follow variable bindings until reaching an unbound variable or a
+    /// non-variable term.
+    fn deref(&self, term: &Term) -> Term {
+        let mut term = term.clone();
+        while let Term::Var(index) = term {
+            match &self.bindings[index] {
+                Some(bound) => term = bound.clone(),
+                None => break,
+            }
+        }
+        term
+    }
+
+    fn unify(&mut self, left: &Term, right: &Term) -> bool {
+        match (self.deref(left), self.deref(right)) {
+            (Term::Var(left), Term::Var(right)) if left == right => true,
+            (Term::Var(index), term) | (term, Term::Var(index)) => {
+                self.bindings[index] = Some(term);
+                self.trail.push(index);
+                true
+            }
+            (Term::Cons(left_head, left_tail), Term::Cons(right_head, right_tail)) => {
+                self.unify(&left_head, &right_head) && self.unify(&left_tail, &right_tail)
+            }
+            (Term::Cons(head, tail), Term::Value(value))
+            | (Term::Value(value), Term::Cons(head, tail)) => match value.as_cons() {
+                Some((value_head, value_tail)) => {
+                    self.unify(&head, &Term::Value(value_head))
+                        && self.unify(&tail, &Term::Value(value_tail))
+                }
+                None => false,
+            },
+            (Term::Value(left), Term::Value(right)) => left == right,
+        }
+    }
+
+    fn undo(&mut self, mark: usize) {
+        for index in self.trail.drain(mark..) {
+            self.bindings[index] = None;
+        }
+    }
+
+    /// Split a goal list `[name Args...]` into a predicate name and arguments.
+    fn goal_from_term(&self, term: &Term) -> ShenResult<(String, Vec<Term>)> {
+        let not_a_goal = || simple_error(format!("{} is not a goal", resolve(&self.bindings, term)));
+        let mut items = Vec::new();
+        let mut rest = self.deref(term);
+        loop {
+            rest = match rest {
+                Term::Cons(head, tail) => {
+                    items.push(*head);
+                    self.deref(&tail)
+                }
+                Term::Value(Value::Nil) => break,
+                Term::Value(value) => {
+                    let (head, tail) = value.as_cons().ok_or_else(not_a_goal)?;
+                    items.push(Term::Value(head));
+                    Term::Value(tail)
+                }
+                Term::Var(_) => return Err(not_a_goal()),
+            };
+        }
+
+        let mut items = items.into_iter();
+        match items.next().map(|name| self.deref(&name)) {
+            Some(Term::Value(Value::Symbol(name))) => Ok((name, items.collect())),
+            _ => Err(not_a_goal()),
+        }
+    }
+}
+
+/// The value of `term` under `bindings`. Unbound variables become symbols
+/// named after their slot.
+fn resolve(bindings: &[Option<Term>], term: &Term) -> Value {
+    match term {
+        Term::Var(index) => match &bindings[*index] {
+            Some(bound) => resolve(bindings, bound),
+            None => Value::symbol(format!("_{}", index)),
+        },
+        Term::Value(value) => value.clone(),
+        Term::Cons(head, tail) => Value::cons(resolve(bindings, head), resolve(bindings, tail)),
+    }
+}
@@ -0,0 +1,30 @@
+//! Writing to the standard streams: `pr`
+
+use std::io::Write;
+
+use super::error::{simple_error, ShenResult};
+use super::value::{Stream, Value};
+
+/// `(pr S Stream)`: write the string `S` and return it.
+pub fn pr(text: &Value, stream: &Value) -> ShenResult<Value> {
+    match text {
+        Value::String(string) => {
+            write_stream(stream, string)?;
+            Ok(text.clone())
+        }
+        _ => Err(simple_error(format!("{} is not a string", text))),
+    }
+}
+
+/// Write `text` to an output stream and flush it.
+pub fn write_stream(stream: &Value, text: &str) -> ShenResult<()> {
+    let written = match stream {
+        Value::Stream(Stream::Stdout) => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(text.as_bytes()).and_then(|_| stdout.flush())
+        }
+        Value::Stream(Stream::Stderr) => std::io::stderr().write_all(text.as_bytes()),
+        _ => return Err(simple_error(format!("{} is not an output stream", stream))),
+    };
+    written.map_err(|err| simple_error(err.to_string()))
+}
@@ -0,0 +1,125 @@
+//! String primitives and `(@s ...)` patterns
+//!
+//! Shen strings are sequences of unit strings. Here a unit string is an
+//! extended grapheme cluster, so `pos`, `tlstr` and `@s` never split a
+//! character made of several code points, such as `e` + U+0301 or a flag
+//! emoji. `string->n` and `n->string` convert between a unit string and a
+//! Unicode code point; for a multi-code-point grapheme, `string->n` returns
+//! the code point of its first scalar value.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::error::{simple_error, ShenResult};
+use super::value::Value;
+
+impl Value {
+    /// Split a non-empty string into its first unit string and the rest, as
+    /// the pattern `(@s X Y)` does.
+    pub fn as_string_cons(&self) -> Option<(Value, Value)> {
+        let Value::String(string) = self else {
+            return None;
+        };
+        let first = string.graphemes(true).next()?;
+        Some((Value::from(first), Value::from(&string[first.len()..])))
+    }
+
+    /// The rest of the string after `prefix`, as the pattern `(@s "ab" Y)`
+    /// does. The prefix must end on a unit string boundary.
+    pub fn strip_string_prefix(&self, prefix: &str) -> Option<Value> {
+        let Value::String(string) = self else {
+            return None;
+        };
+        let rest = string.strip_prefix(prefix)?;
+        let on_boundary = rest.is_empty()
+            || string
+                .grapheme_indices(true)
+                .any(|(offset, _)| offset == prefix.len());
+        on_boundary.then(|| Value::from(rest))
+    }
+}
+
+/// `(pos S N)`: the unit string at 0-based position `N`.
+pub fn pos(string: &Value, index: impl Into<Value>) -> ShenResult<Value> {
+    let string = as_str(string)?;
+    let index = index.into();
+    let position = match index {
+        Value::Integer(n) if n >= 0 => n as usize,
+        Value::Float(n) if n >= 0.0 && n.fract() == 0.0 => n as usize,
+        _ => return Err(simple_error(format!("{} is not a valid string index", index))),
+    };
+    string
+        .graphemes(true)
+        .nth(position)
+        .map(Value::from)
+        .ok_or_else(|| simple_error(format!("string index {} is out of range", position)))
+}
+
+/// `(tlstr S)`: the string without its first unit string.
+pub fn tlstr(string: &Value) -> ShenResult<Value> {
+    string
+        .as_string_cons()
+        .map(|(_, rest)| rest)
+        .ok_or_else(|| simple_error(format!("tlstr: {} is not a non-empty string", string)))
+}
+
+/// `(cn S1 S2)`: concatenation.
+pub fn cn(first: &Value, second: &Value) -> ShenResult<Value> {
+    Ok(Value::String(format!("{}{}", as_str(first)?, as_str(second)?)))
+}
+
+/// `(str X)`: the string form of an atom. Strings are returned quoted, as in
+/// Shen.
+pub fn str(value: &Value) -> ShenResult<Value> {
+    match value {
+        Value::Cons(_) | Value::Tuple(_) | Value::Vector(_) => {
+            Err(simple_error(format!("{} is not an atom; str cannot convert it", value)))
+        }
+        _ => Ok(Value::String(value.to_string())),
+    }
+}
+
+/// `(shen.app X Suffix Mode)`: `X` printed for `make-string`, followed by
+/// `Suffix`. Mode `shen.a` (`~A`) prints strings without quotes; `shen.s` and
+/// `shen.r` (`~S`, `~R`) print them quoted.
+pub fn app(value: &Value, suffix: &Value, mode: &Value) -> ShenResult<Value> {
+    let printed = match (value, mode) {
+        (Value::String(string), Value::Symbol(mode)) if mode == "shen.a" => string.clone(),
+        _ => value.to_string(),
+    };
+    Ok(Value::String(printed + as_str(suffix)?))
+}
+
+/// `(string->n S)`: the code point of the first character of `S`.
+pub fn string_to_n(string: &Value) -> ShenResult<Value> {
+    as_str(string)?
+        .chars()
+        .next()
+        .map(|c| Value::Integer(c as i64))
+        .ok_or_else(|| simple_error("string->n: empty string"))
+}
+
+/// `(n->string N)`: the unit string for code point `N`.
+pub fn n_to_string(code: impl Into<Value>) -> ShenResult<Value> {
+    let code = code.into();
+    let scalar = match code {
+        Value::Integer(n) => u32::try_from(n).ok(),
+        Value::Float(n) if n.fract() == 0.0 && n >= 0.0 => Some(n as u32),
+        _ => None,
+    };
+    scalar
+        .and_then(char::from_u32)
+        .map(|c| Value::String(c.to_string()))
+        .ok_or_else(|| simple_error(format!("{} is not a valid code point", code)))
+}
+
+/// `(string? X)`
+pub fn is_string(value: &Value) -> bool {
+    matches!(value, Value::String(_))
+}
+
+fn as_str(value: &Value) -> ShenResult<&str> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(simple_error(format!("{} is not a string", value))),
+    }
+}
@@ -0,0 +1,32 @@
+//! Runtime tuple primitives: `fst`, `snd` and `tuple?`
+//!
+//! Tuples whose component types are known are generated as native Rust tuples
+//! and never reach these functions.
+
+use super::error::{simple_error, ShenError, ShenResult};
+use super::value::Value;
+
+/// `(fst (@p A B))` is `A`
+pub fn fst(tuple: &Value) -> ShenResult<Value> {
+    tuple
+        .as_tuple()
+        .map(|(first, _)| first)
+        .ok_or_else(|| not_a_tuple(tuple))
+}
+
+/// `(snd (@p A B))` is `B`
+pub fn snd(tuple: &Value) -> ShenResult<Value> {
+    tuple
+        .as_tuple()
+        .map(|(_, second)| second)
+        .ok_or_else(|| not_a_tuple(tuple))
+}
+
+/// `(tuple? X)`
+pub fn is_tuple(value: &Value) -> bool {
+    matches!(value, Value::Tuple(_))
+}
+
+fn not_a_tuple(value: &Value) -> ShenError {
+    simple_error(format!("{} is not a tuple", value))
+}
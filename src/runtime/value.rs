@@ -0,0 +1,230 @@
+//! Dynamically typed Shen values
+//!
+//! Typed generated code works with plain Rust values. `Value` is used wherever
+//! a value's type is not known statically, such as the global environment.
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use super::error::{simple_error, ShenError, ShenResult};
+use super::function::Function;
+use super::lazy::Lazy;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Symbol(String),
+    Boolean(bool),
+    /// The empty list `[]`
+    Nil,
+    Cons(Arc<(Value, Value)>),
+    Tuple(Arc<(Value, Value)>),
+    /// An absvector; see [`vector`](super::vector) for the standard layout
+    Vector(Arc<RwLock<Vec<Value>>>),
+    Lazy(Lazy<Value>),
+    Function(Function),
+    Error(ShenError),
+    Stream(Stream),
+}
+
+/// The standard streams bound to `*stinput*` and `*stoutput*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+impl Value {
+    pub fn symbol(name: impl Into<String>) -> Self {
+        Value::Symbol(name.into())
+    }
+
+    /// The `fail` sentinel returned by `(fail)` and held by unset vector slots
+    pub fn fail() -> Self {
+        Value::Symbol("shen.fail!".to_string())
+    }
+
+    pub fn is_fail(&self) -> bool {
+        matches!(self, Value::Symbol(name) if name == "shen.fail!")
+    }
+
+    /// A boolean as a Rust `bool`, for conditions; anything else is an error.
+    pub fn as_bool(&self) -> ShenResult<bool> {
+        match self {
+            Value::Boolean(value) => Ok(*value),
+            value => Err(simple_error(format!("{} is not a boolean", value))),
+        }
+    }
+
+    pub fn cons(head: Value, tail: Value) -> Self {
+        Value::Cons(Arc::new((head, tail)))
+    }
+
+    /// The head and tail of a non-empty list, or `None` for any other value.
+    pub fn as_cons(&self) -> Option<(Value, Value)> {
+        match self {
+            Value::Cons(pair) => Some((pair.0.clone(), pair.1.clone())),
+            _ => None,
+        }
+    }
+
+    /// `(@p First Second)`
+    pub fn tuple(first: impl Into<Value>, second: impl Into<Value>) -> Self {
+        Value::Tuple(Arc::new((first.into(), second.into())))
+    }
+
+    /// The components of a tuple, or `None` for any other value.
+    pub fn as_tuple(&self) -> Option<(Value, Value)> {
+        match self {
+            Value::Tuple(pair) => Some((pair.0.clone(), pair.1.clone())),
+            _ => None,
+        }
+    }
+
+    /// Build a proper list `[A B C]` from its elements.
+    pub fn list(elements: impl IntoIterator<Item = Value>) -> Self {
+        let elements = elements.into_iter().collect::<Vec<_>>();
+        elements
+            .into_iter()
+            .rev()
+            .fold(Value::Nil, |tail, head| Value::cons(head, tail))
+    }
+}
+
+/// Equality as Shen's `=`: numbers compare by value, frozen computations,
+/// functions and streams only by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Integer(a), Value::Float(b)) | (Value::Float(b), Value::Integer(a)) => {
+                *a as f64 == *b
+            }
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Cons(a), Value::Cons(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
+                Arc::ptr_eq(a, b) || a == b
+            }
+            (Value::Vector(a), Value::Vector(b)) => {
+                Arc::ptr_eq(a, b)
+                    || *a.read().unwrap_or_else(|e| e.into_inner())
+                        == *b.read().unwrap_or_else(|e| e.into_inner())
+            }
+            (Value::Function(a), Value::Function(b)) => a.ptr_eq(b),
+            (Value::Error(a), Value::Error(b)) => a == b,
+            (Value::Stream(a), Value::Stream(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl<A: Into<Value>, B: Into<Value>> From<(A, B)> for Value {
+    fn from((first, second): (A, B)) -> Self {
+        Value::tuple(first, second)
+    }
+}
+
+/// A vector of values becomes a Shen list.
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(elements: Vec<T>) -> Self {
+        Value::list(elements.into_iter().map(Into::into))
+    }
+}
+
+impl From<ShenError> for Value {
+    fn from(value: ShenError) -> Self {
+        Value::Error(value)
+    }
+}
+
+/// Prints values the way the Shen REPL does: strings quoted, lists in square
+/// brackets.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Float(value) if value.fract() == 0.0 && value.is_finite() => {
+                write!(f, "{}", *value as i64)
+            }
+            Value::Float(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{:?}", value),
+            Value::Symbol(name) => write!(f, "{}", name),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Nil => write!(f, "[]"),
+            Value::Cons(_) => {
+                write!(f, "[")?;
+                let mut node = self;
+                let mut first = true;
+                while let Value::Cons(cell) = node {
+                    if !first {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", cell.0)?;
+                    first = false;
+                    node = &cell.1;
+                }
+                match node {
+                    Value::Nil => write!(f, "]"),
+                    tail => write!(f, " | {}]", tail),
+                }
+            }
+            Value::Tuple(pair) => write!(f, "(@p {} {})", pair.0, pair.1),
+            Value::Vector(slots) => match super::vector::standard_elements(self) {
+                Some(elements) => {
+                    let elements = elements
+                        .iter()
+                        .map(|element| match element.is_fail() {
+                            true => "...".to_string(),
+                            false => element.to_string(),
+                        })
+                        .collect::<Vec<_>>();
+                    write!(f, "<{}>", elements.join(" "))
+                }
+                None => {
+                    let size = slots.read().unwrap_or_else(|e| e.into_inner()).len();
+                    write!(f, "#<absvector {}>", size)
+                }
+            },
+            Value::Lazy(_) => write!(f, "#<freeze>"),
+            Value::Function(_) => write!(f, "#<function>"),
+            Value::Error(error) => write!(f, "#<error {}>", error),
+            Value::Stream(stream) => write!(f, "#<stream {:?}>", stream),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Vectors and absvectors
+//!
+//! An absvector is a fixed-size, mutable, 0-based array. A standard Shen
+//! vector of limit `N` is an absvector of size `N + 1`: slot 0 holds `N` and
+//! slots `1..=N` hold the elements, 1-based. A slot that has never been
+//! assigned holds the `fail` sentinel, and reading it is an error.
+
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::error::{simple_error, ShenError, ShenResult};
+use super::value::Value;
+
+impl Value {
+    /// A standard vector holding `elements` at indices `1..`.
+    pub fn vector(elements: impl IntoIterator<Item = Value>) -> Self {
+        let mut slots = vec![Value::Nil];
+        slots.extend(elements);
+        slots[0] = Value::Integer(slots.len() as i64 - 1);
+        Value::Vector(Arc::new(RwLock::new(slots)))
+    }
+
+    /// Split a non-empty standard vector into its first element and a new
+    /// vector of the rest, as the pattern `(@v X Y)` does.
+    pub fn as_vector_cons(&self) -> Option<(Value, Value)> {
+        let elements = standard_elements(self)?;
+        let (first, rest) = elements.split_first()?;
+        Some((first.clone(), Value::vector(rest.to_vec())))
+    }
+
+    /// Whether this is the empty standard vector `<>`.
+    pub fn is_empty_vector(&self) -> bool {
+        standard_elements(self).is_some_and(|elements| elements.is_empty())
+    }
+}
+
+/// `(absvector N)`: `N` slots, all holding `fail`.
+pub fn absvector(size: impl Into<Value>) -> ShenResult<Value> {
+    let size = to_index(&size.into())?;
+    Ok(Value::Vector(Arc::new(RwLock::new(vec![Value::fail(); size]))))
+}
+
+/// `(<-address V I)`: read slot `I` of an absvector, 0-based.
+pub fn address_get(vector: &Value, index: impl Into<Value>) -> ShenResult<Value> {
+    let index = to_index(&index.into())?;
+    let slots = read(vector)?;
+    slots
+        .get(index)
+        .cloned()
+        .ok_or_else(|| out_of_bounds(index, slots.len()))
+}
+
+/// `(address-> V I X)`: write slot `I` of an absvector and return the vector.
+pub fn address_set(
+    vector: &Value,
+    index: impl Into<Value>,
+    value: impl Into<Value>,
+) -> ShenResult<Value> {
+    let index = to_index(&index.into())?;
+    let mut slots = write(vector)?;
+    let size = slots.len();
+    let slot = slots.get_mut(index).ok_or_else(|| out_of_bounds(index, size))?;
+    *slot = value.into();
+    Ok(vector.clone())
+}
+
+/// `(absvector? X)`
+pub fn is_absvector(value: &Value) -> bool {
+    matches!(value, Value::Vector(_))
+}
+
+/// `(vector N)`: a standard vector of limit `N` with every element unset.
+pub fn vector(limit: impl Into<Value>) -> ShenResult<Value> {
+    let limit = to_index(&limit.into())?;
+    let mut slots = vec![Value::fail(); limit + 1];
+    slots[0] = Value::Integer(limit as i64);
+    Ok(Value::Vector(Arc::new(RwLock::new(slots))))
+}
+
+/// `(<-vector V I)`: read element `I`, 1-based. Unset elements are an error.
+pub fn vector_get(vector: &Value, index: impl Into<Value>) -> ShenResult<Value> {
+    let index = to_index(&index.into())?;
+    let limit = limit(vector)?;
+    if index == 0 || index > limit {
+        return Err(out_of_bounds(index, limit));
+    }
+
+    let element = read(vector)?[index].clone();
+    if element.is_fail() {
+        return Err(simple_error(format!("vector element {} not found", index)));
+    }
+    Ok(element)
+}
+
+/// `(vector-> V I X)`: write element `I`, 1-based, and return the vector.
+pub fn vector_set(
+    vector: &Value,
+    index: impl Into<Value>,
+    value: impl Into<Value>,
+) -> ShenResult<Value> {
+    let index = to_index(&index.into())?;
+    let limit = limit(vector)?;
+    if index == 0 || index > limit {
+        return Err(out_of_bounds(index, limit));
+    }
+    address_set(vector, index as i64, value)
+}
+
+/// `(limit V)`: the number of elements of a standard vector.
+pub fn limit(vector: &Value) -> ShenResult<usize> {
+    match read(vector)?.first() {
+        Some(Value::Integer(limit)) => Ok(*limit as usize),
+        _ => Err(simple_error(format!("{} is not a standard vector", vector))),
+    }
+}
+
+/// `(vector? X)`
+pub fn is_vector(value: &Value) -> bool {
+    standard_elements(value).is_some()
+}
+
+/// `(@v X V)`: a new vector with `X` followed by the elements of `V`.
+pub fn vector_cons(element: impl Into<Value>, vector: &Value) -> ShenResult<Value> {
+    let rest = standard_elements(vector)
+        .ok_or_else(|| simple_error(format!("{} is not a standard vector", vector)))?;
+    Ok(Value::vector(std::iter::once(element.into()).chain(rest)))
+}
+
+/// The elements of a standard vector, or `None` for any other value.
+pub(crate) fn standard_elements(value: &Value) -> Option<Vec<Value>> {
+    let Value::Vector(slots) = value else {
+        return None;
+    };
+    let slots = slots.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match slots.first() {
+        Some(Value::Integer(limit)) if *limit as usize == slots.len() - 1 => {
+            Some(slots[1..].to_vec())
+        }
+        _ => None,
+    }
+}
+
+fn read(vector: &Value) -> ShenResult<RwLockReadGuard<'_, Vec<Value>>> {
+    match vector {
+        Value::Vector(slots) => Ok(slots.read().unwrap_or_else(|poisoned| poisoned.into_inner())),
+        _ => Err(simple_error(format!("{} is not a vector", vector))),
+    }
+}
+
+fn write(vector: &Value) -> ShenResult<RwLockWriteGuard<'_, Vec<Value>>> {
+    match vector {
+        Value::Vector(slots) => Ok(slots.write().unwrap_or_else(|poisoned| poisoned.into_inner())),
+        _ => Err(simple_error(format!("{} is not a vector", vector))),
+    }
+}
+
+fn to_index(value: &Value) -> ShenResult<usize> {
+    match value {
+        Value::Integer(index) if *index >= 0 => Ok(*index as usize),
+        Value::Float(index) if *index >= 0.0 && index.fract() == 0.0 => Ok(*index as usize),
+        _ => Err(simple_error(format!("{} is not a valid index", value))),
+    }
+}
+
+fn out_of_bounds(index: usize, limit: usize) -> ShenError {
+    simple_error(format!("index {} is out of bounds for a vector of limit {}", index, limit))
+}
@@ -0,0 +1,55 @@
+//! Runtime support for Shen-YACC grammars
+//!
+//! Each `defcc` is generated as a function from the remaining input, a Shen
+//! list, to `Some((rest, result))` on success or `None` when none of its rules
+//! match. Rules are tried in order and the first that matches wins.
+
+use super::error::{simple_error, ShenResult};
+use super::value::Value;
+
+/// The signature of a generated `defcc` function.
+pub type YaccParser = fn(&Value) -> ShenResult<Option<(Value, Value)>>;
+
+/// The input after `expected`, if `input` starts with it.
+pub fn yacc_terminal(input: &Value, expected: &Value) -> Option<Value> {
+    match input.as_cons() {
+        Some((head, tail)) if head == *expected => Some(tail),
+        _ => None,
+    }
+}
+
+/// The elements of `input` that precede its suffix `rest`: the default result
+/// of a rule without a semantic action.
+pub fn yacc_consumed(input: &Value, rest: &Value) -> Value {
+    let mut consumed = Vec::new();
+    let mut remaining = input.clone();
+    while list_length(&remaining) > list_length(rest) {
+        match remaining.as_cons() {
+            Some((head, tail)) => {
+                consumed.push(head);
+                remaining = tail;
+            }
+            None => break,
+        }
+    }
+    Value::list(consumed)
+}
+
+/// `(compile <name> Input)`: parse all of `Input` with a grammar.
+pub fn compile(parser: YaccParser, input: &Value) -> ShenResult<Value> {
+    match parser(input)? {
+        Some((Value::Nil, result)) => Ok(result),
+        Some((rest, _)) => Err(simple_error(format!("parse error here: {}", rest))),
+        None => Err(simple_error("parse error")),
+    }
+}
+
+fn list_length(list: &Value) -> usize {
+    let mut length = 0;
+    let mut rest = list.clone();
+    while let Some((_, tail)) = rest.as_cons() {
+        length += 1;
+        rest = tail;
+    }
+    length
+}
@@ -0,0 +1,275 @@
+//! Tests for the arena AST and the visitor and fold traits
+
+use pretty_assertions::assert_eq;
+use std::convert::Infallible;
+
+use shen_transpiler::ast::arena::{Ast, Node};
+use shen_transpiler::ast::{fold, visit, visit_mut, Fold, Visitor, VisitorMut};
+use shen_transpiler::ast::{Pattern, ShenNode, ShenType, ShenValue, Symbol, YaccItem};
+use shen_transpiler::parser::{parse_shen_program, parse_shen_program_arena, Span};
+
+const PROGRAM: &str = "\
+(define swap (@p A B) -> (@p B A) _ -> (simple-error \"not a pair\"))
+(define vsum <> -> 0 (@v X Y) -> (+ X (vsum Y)) where (number? X))
+(defun twice (f x) (f (f x)))
+(defprolog member X [X | _] <--; X [_ | Y] <-- ! (member X Y);)
+(defcc <as> a X <as> := [X | <as>]; <e>;)
+(package stack [push] (define push X S -> [X | S]))
+(let X 1 Y [2 3] (if (> X 0) (cons X Y) (@s \"a\" \"b\")))
+(trap-error (thaw (freeze (/. E F (E F)))) (/. E (error-to-string E)))
+(@v 1 2 <>)";
+
+#[test]
+fn test_arena_round_trips_to_owned_nodes() {
+    let program = parse_shen_program(PROGRAM).expect("Parsing should succeed");
+    let expected = format!("{:?}", program);
+
+    let mut ast = Ast::new();
+    let forms = program.into_iter().map(|form| ast.add(form)).collect::<Vec<_>>();
+    let round_tripped = forms.iter().map(|form| ast.to_node(*form)).collect::<Vec<_>>();
+    assert_eq!(format!("{:?}", round_tripped), expected);
+
+    // Children come before their parents, and each form is the last node
+    // added for it
+    for (id, _) in ast.iter() {
+        assert!(ast.children(id).iter().all(|child| child < &id));
+    }
+    assert_eq!(forms.last().map(|form| form.index()), Some(ast.len() - 1));
+}
+
+#[test]
+fn test_arena_records_form_spans() {
+    let input = "(define f X -> X)\n  (f 1)";
+    let (ast, forms) = parse_shen_program_arena(input).expect("Parsing should succeed");
+
+    assert_eq!(forms.len(), 2);
+    assert_eq!(ast.span(forms[0]), Some(Span { start: 0, end: 17 }));
+    assert_eq!(ast.span(forms[1]), Some(Span { start: 20, end: 25 }));
+
+    let Node::Application { func, args } = ast.node(forms[1]) else {
+        panic!("Expected an application, got {:?}", ast.node(forms[1]));
+    };
+    assert!(matches!(ast.node(*func), Node::Symbol { name } if name == "f"));
+    assert_eq!(ast.span(*func), Some(Span { start: 21, end: 22 }));
+    assert_eq!(ast.span(args[0]), Some(Span { start: 23, end: 24 }));
+    assert_eq!(ast.ty(args[0]), &ShenType::Float);
+}
+
+#[test]
+fn test_arena_records_the_span_of_every_node() {
+    let (ast, _) = parse_shen_program_arena(PROGRAM).expect("Parsing should succeed");
+    let source = |id| ast.span(id).map(|span| &PROGRAM[span.start..span.end]);
+    assert!(ast.iter().all(|(id, _)| ast.span(id).is_some()));
+
+    // Rule bodies and guards, and the operands inside them
+    let define = ast
+        .iter()
+        .find_map(|(_, node)| match node {
+            Node::Define { name, rules } if name == "vsum" => Some(rules.clone()),
+            _ => None,
+        })
+        .expect("vsum is defined");
+    assert_eq!(source(define[1].body), Some("(+ X (vsum Y))"));
+    assert_eq!(source(define[1].guard.unwrap()), Some("(number? X)"));
+    let Node::BinaryOperation { right, .. } = ast.node(define[1].body) else {
+        panic!("Expected an operation");
+    };
+    assert_eq!(source(*right), Some("(vsum Y)"));
+
+    // The item of [X | <as>] and the tail it is consed onto
+    let cons = ast
+        .iter()
+        .find_map(|(id, node)| matches!(node, Node::Cons { .. }).then_some(id))
+        .expect("a cons");
+    assert_eq!(source(cons), Some("[X | <as>]"));
+    let Node::Cons { head, tail } = ast.node(cons) else { unreachable!() };
+    assert_eq!((source(*head), source(*tail)), (Some("X"), Some("<as>")));
+
+    // What a standard macro builds has the span of the macro's form
+    let vectors = ast
+        .iter()
+        .filter(|(_, node)| matches!(node, Node::Vector { .. }))
+        .map(|(id, _)| source(id))
+        .collect::<Vec<_>>();
+    assert_eq!(vectors, vec![Some("(@v 1 2 <>)")]);
+}
+
+#[test]
+fn test_arena_shares_nodes_and_types() {
+    let mut ast = Ast::new();
+    let x = ast.push(Node::Symbol { name: Symbol::intern("X") }, ShenType::Symbol);
+    let sum = ast.push(
+        Node::BinaryOperation {
+            operator: Symbol::intern("+"),
+            left: x,
+            right: x,
+        },
+        ShenType::Float,
+    );
+    assert_eq!(ast.children(sum), vec![x, x]);
+
+    // A pass's findings end up on the owned nodes
+    ast.set_type(x, ShenType::Integer);
+    match ast.to_node(sum) {
+        ShenNode::BinaryOperation { left, right, result_type, .. } => {
+            assert_eq!(result_type, ShenType::Float);
+            for operand in [left, right] {
+                assert!(matches!(*operand, ShenNode::Symbol { type_hint: ShenType::Integer, .. }));
+            }
+        }
+        node => panic!("Expected a binary operation, got {:?}", node),
+    }
+
+    // Converting to the type a node already has shares it
+    assert_eq!(ast.try_convert(sum, &ShenType::Float), Some(sum));
+    assert_eq!(ast.try_convert(sum, &ShenType::String), None);
+
+    let one = ast.push(Node::Literal { value: ShenValue::Integer(1) }, ShenType::Integer);
+    let converted = ast.try_convert(one, &ShenType::String).expect("integers convert to strings");
+    assert_ne!(converted, one);
+    assert!(matches!(ast.node(converted), Node::Literal { value: ShenValue::String(s) } if s == "1"));
+}
+
+/// Counts symbols in expressions and in patterns, and grammar items.
+#[derive(Default)]
+struct Counter {
+    symbols: Vec<Symbol>,
+    pattern_symbols: Vec<Symbol>,
+    items: usize,
+}
+
+impl Visitor for Counter {
+    fn visit_node(&mut self, node: &ShenNode) {
+        if let ShenNode::Symbol { name, .. } = node {
+            self.symbols.push(*name);
+        }
+        visit::walk_node(self, node)
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let Pattern::Symbol(name) | Pattern::Variable(name) = pattern {
+            self.pattern_symbols.push(*name);
+        }
+        visit::walk_pattern(self, pattern)
+    }
+
+    fn visit_yacc_item(&mut self, _item: &YaccItem) {
+        self.items += 1;
+    }
+}
+
+#[test]
+fn test_visitor_walks_every_child() {
+    let program = parse_shen_program(PROGRAM).expect("Parsing should succeed");
+    let mut counter = Counter::default();
+    program.iter().for_each(|form| counter.visit_node(form));
+
+    let names = |symbols: &[Symbol]| symbols.iter().map(|symbol| symbol.as_str()).collect::<Vec<_>>();
+    let symbols = names(&counter.symbols);
+    // Rule bodies, guards, clause goals, grammar actions and package bodies
+    for name in ["B", "vsum", "number?", "member", "<as>", "S", "error-to-string"] {
+        assert!(symbols.contains(&name), "{} was not visited", name);
+    }
+    // Patterns nested in tuples, vectors and conses
+    assert_eq!(names(&counter.pattern_symbols), ["A", "B", "X", "Y", "X", "X", "X", "Y", "X", "S"]);
+    assert_eq!(counter.items, 4);
+}
+
+/// Renames every `X` to `Z`, in expressions and patterns alike.
+struct Rename;
+
+impl VisitorMut for Rename {
+    fn visit_node_mut(&mut self, node: &mut ShenNode) {
+        if let ShenNode::Symbol { name, .. } = node {
+            if *name == "X" {
+                *name = "Z".into();
+            }
+        }
+        visit_mut::walk_node_mut(self, node)
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        if let Pattern::Variable(name) = pattern {
+            if *name == "X" {
+                *name = "Z".into();
+            }
+        }
+        visit_mut::walk_pattern_mut(self, pattern)
+    }
+}
+
+#[test]
+fn test_visitor_mut_changes_nodes_in_place() {
+    let mut program = parse_shen_program("(define f [X | Y] -> (+ X 1) where (number? X))")
+        .expect("Parsing should succeed");
+    program.iter_mut().for_each(|form| Rename.visit_node_mut(form));
+
+    let expected = parse_shen_program("(define f [Z | Y] -> (+ Z 1) where (number? Z))")
+        .expect("Parsing should succeed");
+    assert_eq!(format!("{:?}", program), format!("{:?}", expected));
+}
+
+/// Replaces number literals with their doubles, failing on zero.
+struct Double;
+
+impl Fold for Double {
+    type Error = f64;
+
+    fn fold_node(&mut self, node: ShenNode) -> Result<ShenNode, f64> {
+        match node {
+            ShenNode::Literal { value: ShenValue::Float(n) } if n == 0.0 => Err(n),
+            ShenNode::Literal { value: ShenValue::Float(n) } => {
+                Ok(ShenNode::Literal { value: ShenValue::Float(n * 2.0) })
+            }
+            node => fold::walk_node(self, node),
+        }
+    }
+}
+
+#[test]
+fn test_fold_rebuilds_and_stops_at_errors() {
+    let parse = |input| parse_shen_program(input).expect("Parsing should succeed").remove(0);
+
+    let doubled = Double.fold_node(parse("(@p 1 [2 (freeze 3)])")).expect("Folding should succeed");
+    assert_eq!(format!("{:?}", doubled), format!("{:?}", parse("(@p 2 [4 (freeze 6)])")));
+
+    assert_eq!(Double.fold_node(parse("(if true 1 (+ 2 0))")).err(), Some(0.0));
+}
+
+/// The default fold rebuilds the tree unchanged.
+struct Identity;
+
+impl Fold for Identity {
+    type Error = Infallible;
+}
+
+#[test]
+fn test_default_fold_is_identity() {
+    let program = parse_shen_program(PROGRAM).expect("Parsing should succeed");
+    let expected = format!("{:?}", program);
+    let folded = program
+        .into_iter()
+        .map(|form| {
+            let Ok(form) = Identity.fold_node(form);
+            form
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(format!("{:?}", folded), expected);
+}
+
+#[test]
+fn test_infer_type_reads_symbol_names() {
+    let symbol = |name: &str| ShenNode::Symbol { name: name.into(), type_hint: ShenType::Symbol };
+    let pair = ShenNode::Tuple {
+        first: Box::new(symbol("42")),
+        second: Box::new(ShenNode::Freeze { body: Box::new(symbol("true")) }),
+    };
+    assert_eq!(
+        pair.get_type(),
+        ShenType::Tuple(Box::new(ShenType::Symbol), Box::new(ShenType::Lazy(Box::new(ShenType::Symbol))))
+    );
+    assert_eq!(
+        pair.infer_type(),
+        ShenType::Tuple(Box::new(ShenType::Integer), Box::new(ShenType::Lazy(Box::new(ShenType::Boolean))))
+    );
+}
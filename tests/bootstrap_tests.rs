@@ -0,0 +1,149 @@
+//! Tests for transpiling the Shen kernel into a crate
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use shen_transpiler::bootstrap::{bootstrap, check_fixtures, BootstrapOptions};
+use shen_transpiler::codegen::{CodegenOptions, ErrorStrategy};
+
+fn fixtures(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("shen-bootstrap-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn test_bootstrap_writes_kernel_crate() {
+    let out_dir = scratch_dir("crate");
+    let generated = bootstrap(fixtures("kernel"), &out_dir, &BootstrapOptions::default())
+        .expect("Bootstrapping should succeed");
+
+    let cargo_toml = fs::read_to_string(out_dir.join("Cargo.toml")).unwrap();
+    assert!(cargo_toml.contains("name = \"shen\""));
+    assert!(cargo_toml.contains(&format!("path = {:?}", env!("CARGO_MANIFEST_DIR"))));
+
+    let main_rs = generated.file("src/main.rs").unwrap();
+    assert!(main_rs.contains("kernel::init();"));
+    assert!(main_rs.contains("kernel::shen_shen();"));
+
+    let kernel_rs = fs::read_to_string(out_dir.join("src/kernel.rs")).unwrap();
+    assert!(kernel_rs.contains("use shen_transpiler::runtime::prelude::*;"));
+    assert!(kernel_rs.contains(
+        "pub fn init() {\n    globals().set(\"*shen-fixture-version*\", Value::from(\"0.1\"));\n}"
+    ));
+    assert!(kernel_rs.contains("pub fn shen_sum(V1: Value) -> Value {"));
+    assert!(kernel_rs.contains("pub fn shen___vector(V1: Value, V2: Value) -> Value {"));
+    // let binds a local, data symbols are quoted and () is the empty list
+    assert!(kernel_rs.contains("let Total = Value::Integer(0);"));
+    assert!(kernel_rs.contains("Value::cons(Value::symbol(\"->\"), Value::cons(Value::symbol(\"<-\")"));
+    assert!(kernel_rs.contains("pub fn shen_nothing() -> Value {\n    Value::Nil\n}"));
+    // The fixture has no REPL of its own
+    assert!(kernel_rs.contains("pub fn shen_shen() -> Value {"));
+    // The licence strings are not code
+    assert!(!kernel_rs.contains("kernel-style fixture"));
+
+    fs::remove_dir_all(&out_dir).unwrap();
+}
+
+#[test]
+fn test_bootstrap_with_result_errors() {
+    let options = BootstrapOptions {
+        crate_name: "shen-result".to_string(),
+        codegen: CodegenOptions {
+            error_strategy: ErrorStrategy::Result,
+            ..CodegenOptions::default()
+        },
+        ..BootstrapOptions::default()
+    };
+    let out_dir = scratch_dir("result");
+    let generated = bootstrap(fixtures("kernel"), &out_dir, &options).expect("Bootstrapping should succeed");
+
+    assert!(generated.file("Cargo.toml").unwrap().contains("name = \"shen-result\""));
+    assert!(generated
+        .file("src/main.rs")
+        .unwrap()
+        .contains("kernel::init().and_then(|_| kernel::shen_shen())"));
+    assert!(generated
+        .file("src/kernel.rs")
+        .unwrap()
+        .contains("pub fn init() -> ShenResult<()> {"));
+
+    fs::remove_dir_all(&out_dir).unwrap();
+}
+
+fn build_target_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/bootstrap-tests")
+}
+
+/// Build a generated crate with cargo, sharing one target directory between
+/// runs so that the runtime is only compiled once.
+fn cargo_build(crate_dir: &Path) -> std::process::Output {
+    Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .arg("build")
+        .arg("--quiet")
+        .current_dir(crate_dir)
+        .env("CARGO_TARGET_DIR", build_target_dir())
+        .output()
+        .expect("cargo should run")
+}
+
+#[test]
+fn test_generated_kernel_crate_builds() {
+    for error_strategy in [ErrorStrategy::Unwind, ErrorStrategy::Result] {
+        let options = BootstrapOptions {
+            crate_name: format!("shen-kernel-{:?}", error_strategy).to_lowercase(),
+            codegen: CodegenOptions {
+                error_strategy,
+                ..CodegenOptions::default()
+            },
+            ..BootstrapOptions::default()
+        };
+        let out_dir = scratch_dir(&format!("build-{:?}", error_strategy));
+        bootstrap(fixtures("kernel"), &out_dir, &options).expect("Bootstrapping should succeed");
+
+        let output = cargo_build(&out_dir);
+        assert!(
+            output.status.success(),
+            "The {:?} kernel crate should build:\n{}",
+            error_strategy,
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        // The fixture defines no shen.shen, which the binary reports
+        let run = Command::new(build_target_dir().join("debug").join(&options.crate_name))
+            .output()
+            .expect("The kernel binary should run");
+        assert!(!run.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&run.stderr),
+            "this kernel does not define shen.shen\n"
+        );
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
+
+#[test]
+fn test_check_fixtures_compares_transcripts() {
+    // cat echoes its input, which is not what the reference REPL printed
+    let failures = check_fixtures("cat", fixtures("repl")).expect("Running the fixtures should succeed");
+    assert_eq!(
+        failures.iter().map(|failure| failure.name.as_str()).collect::<Vec<_>>(),
+        vec!["arithmetic", "errors", "lists"]
+    );
+    assert_eq!(failures[0].actual, "(+ 1 2)\n(* 6 7)\n");
+
+    // Banners and trailing whitespace are ignored
+    let dir = scratch_dir("fixtures");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("echo.shen"), "Shen, some port\n\n(0-) 3   \n\n(1-) ").unwrap();
+    fs::write(dir.join("echo.expected"), "(0-) 3\n\n(1-)\n").unwrap();
+    assert_eq!(check_fixtures("cat", &dir).unwrap(), vec![]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
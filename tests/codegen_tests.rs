@@ -1,7 +1,16 @@
 //! Tests for Shen to Rust code generation
 
-use shen_transpiler::codegen::generate_rust_code;
-use shen_transpiler::parser::parse_shen_source;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use shen_transpiler::codegen::{
+    generate_klambda_code, generate_rust_code, generate_rust_code_with_options, CodegenOptions,
+    ErrorStrategy, GlobalsMode,
+};
+use shen_transpiler::ast::{ShenNode, ShenType, Symbol};
+use shen_transpiler::error::TranspilerError;
+use shen_transpiler::parser::{parse_klambda, parse_shen_program, parse_shen_source};
 use pretty_assertions::assert_eq;
 
 // Add a helper function to simplify test code
@@ -66,3 +75,439 @@ fn test_generate_binary_operation() {
         "1.0 + 2.0"
     );
 }
+
+#[test]
+fn test_generate_curried_lambda() {
+    let input = "(/. X Y (+ X Y))";
+    assert_rust_code_generation(
+        input, 
+        "|X| { move |Y| { X + Y } }"
+    );
+}
+
+#[test]
+fn test_generate_freeze_and_thaw() {
+    assert_rust_code_generation(
+        "(freeze (+ 1 2))", 
+        "Lazy::new(move || { 1.0 + 2.0 })"
+    );
+    assert_rust_code_generation(
+        "(thaw F)", 
+        "F.thaw()"
+    );
+}
+
+#[test]
+fn test_generate_let_do_and_applied_lambda() {
+    assert_rust_code_generation("(let X 1 (+ X 2))", "{\n    let X = 1.0;\n    X + 2\n}");
+    assert_rust_code_generation("(do (print 1) 2)", "{\n    print(1.0);\n    2.0\n}");
+    assert_rust_code_generation("((/. X (+ X 1)) 2)", "(|X| { X + 1 })(2.0)");
+}
+
+#[test]
+fn test_generate_klambda_over_values() {
+    let program = parse_klambda(
+        "(defun shen.apply-twice (F X) ((lambda Y (F (F Y))) X))
+         (defun shen.adder (N) (lambda X (+ X N)))
+         (defun shen.arrow? (X) (if (cons? X) (= (hd X) ->) false))",
+    )
+    .expect("Parsing should succeed");
+    let generate = |node, options| generate_klambda_code(node, options).expect("Code generation should succeed");
+    let options = CodegenOptions::default();
+
+    assert_eq!(
+        generate(&program[0], &options),
+        "fn shen_apply_twice(F: Value, X: Value) -> Value {
+    {
+        let Y = X.clone();
+        apply(&F, apply(&F, Y.clone()).or_raise()).or_raise()
+    }
+}"
+    );
+    // Closures capture copies of the variables they use
+    assert_eq!(
+        generate(&program[1], &options),
+        "fn shen_adder(N: Value) -> Value {
+    {
+        let N = N.clone();
+        Value::Function(Function::new(move |X| Ok(add(&X, &N).or_raise())))
+    }
+}"
+    );
+    // Symbols that are not variables are data
+    let options = CodegenOptions {
+        error_strategy: ErrorStrategy::Result,
+        ..CodegenOptions::default()
+    };
+    assert_eq!(
+        generate(&program[2], &options),
+        "fn shen_arrow_p(X: Value) -> ShenResult<Value> {
+    Ok(if is_cons(&X) {
+        Value::from(hd(&X)? == Value::symbol(\"->\"))
+    } else {
+        Value::Boolean(false)
+    })
+}"
+    );
+}
+
+#[test]
+fn test_nested_errors_are_not_swallowed() {
+    let broken = || ShenNode::Error { message: "unexpected )".to_string() };
+    let symbol = |name: &str| ShenNode::Symbol { name: Symbol::intern(name), type_hint: ShenType::Symbol };
+    let nodes = [
+        ShenNode::Application { func: Box::new(symbol("f")), args: vec![symbol("X"), broken()] },
+        ShenNode::Conditional {
+            condition: Box::new(symbol("true")),
+            true_branch: Box::new(symbol("X")),
+            false_branch: Some(Box::new(broken())),
+        },
+        ShenNode::List { elements: vec![symbol("X"), broken()], element_type: ShenType::Symbol },
+        ShenNode::Lambda {
+            arg: (Symbol::intern("X"), ShenType::Symbol),
+            return_type: ShenType::Symbol,
+            body: Box::new(ShenNode::Freeze {
+                body: Box::new(ShenNode::Application { func: Box::new(symbol("f")), args: vec![broken()] }),
+            }),
+        },
+    ];
+    for node in &nodes {
+        assert!(
+            matches!(generate_rust_code(node), Err(TranspilerError::CodegenError(message)) if message.contains("unexpected )")),
+            "{:?} should fail to generate",
+            node
+        );
+    }
+}
+
+#[test]
+fn test_generate_trap_error_with_each_strategy() {
+    let input = "(trap-error (safe-div X) (/. E (error-to-string E)))";
+    assert_rust_code_generation(
+        input,
+        "trap_error(|| { safe_div(X) }, |E| { error_to_string(&E) })"
+    );
+
+    let parsed_node = parse_shen_source(input).expect("Parsing should succeed");
+    let options = CodegenOptions {
+        error_strategy: ErrorStrategy::Result,
+        ..CodegenOptions::default()
+    };
+    assert_eq!(
+        generate_rust_code_with_options(&parsed_node, &options).unwrap(),
+        "trap_error_result(|| -> ShenResult<_> { Ok(safe_div(X)?) }, \
+         |E| -> ShenResult<_> { Ok(error_to_string(&E)) })?"
+    );
+}
+
+#[test]
+fn test_generate_simple_error_with_result_strategy() {
+    let parsed_node = parse_shen_source("(defun fail (x) (simple-error \"boom\"))")
+        .expect("Parsing should succeed");
+    let options = CodegenOptions {
+        error_strategy: ErrorStrategy::Result,
+        ..CodegenOptions::default()
+    };
+    assert_eq!(
+        generate_rust_code_with_options(&parsed_node, &options).unwrap(),
+        "fn fail(x: impl Clone) -> ShenResult<impl Clone> {\n    Ok(return Err(simple_error(\"boom\")))\n}"
+    );
+    assert_rust_code_generation(
+        "(simple-error \"boom\")",
+        "raise(simple_error(\"boom\"))"
+    );
+}
+
+#[test]
+fn test_generate_global_variable_access() {
+    assert_rust_code_generation(
+        "(set *counter* (+ 1 2))",
+        "globals().set(\"*counter*\", 1.0 + 2.0)"
+    );
+    assert_rust_code_generation(
+        "(value *counter*)",
+        "globals().value(\"*counter*\").or_raise()"
+    );
+    assert_rust_code_generation(
+        "(bound? *counter*)",
+        "globals().is_bound(\"*counter*\")"
+    );
+
+    let parsed_node = parse_shen_source("(value *counter*)").expect("Parsing should succeed");
+    let options = CodegenOptions {
+        error_strategy: ErrorStrategy::Result,
+        globals: GlobalsMode::ThreadLocal,
+    };
+    assert_eq!(
+        generate_rust_code_with_options(&parsed_node, &options).unwrap(),
+        "with_thread_globals(|globals| globals.value(\"*counter*\"))?"
+    );
+}
+
+/// Build a binary from the code generated for `source` and the statements of
+/// `main`, run it, and panic with rustc's or the binary's output if either
+/// fails.
+fn assert_generated_code_runs(name: &str, source: &str, main: &str, options: &CodegenOptions) {
+    let items = parse_shen_program(source)
+        .expect("Parsing should succeed")
+        .iter()
+        .map(|node| generate_rust_code_with_options(node, options))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("Code generation should succeed");
+
+    let crate_name = format!("shen-codegen-{}", name);
+    let dir = std::env::temp_dir().join(format!("{}-{}", crate_name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(
+        dir.join("Cargo.toml"),
+        format!(
+            "[package]\nname = {:?}\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\nshen_transpiler = {{ path = {:?} }}\n",
+            crate_name,
+            env!("CARGO_MANIFEST_DIR")
+        ),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/main.rs"),
+        format!(
+            "#![allow(non_snake_case, unused, unreachable_code)]\n\n\
+             use shen_transpiler::runtime::prelude::*;\n\n{}\n\nfn main() {{\n{}\n}}\n",
+            items.join("\n\n"),
+            main
+        ),
+    )
+    .unwrap();
+
+    // Shares the target directory of the bootstrap tests, so the runtime is
+    // only compiled once
+    let target_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/bootstrap-tests");
+    let build = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .args(["build", "--quiet"])
+        .current_dir(&dir)
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .output()
+        .expect("cargo should run");
+    assert!(
+        build.status.success(),
+        "The generated code should build:\n{}",
+        String::from_utf8_lossy(&build.stderr)
+    );
+    let run = Command::new(target_dir.join("debug").join(&crate_name))
+        .output()
+        .expect("The generated binary should run");
+    assert!(
+        run.status.success(),
+        "The generated binary should succeed:\n{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_generated_define_builds_and_runs() {
+    let source = r#"
+        (define inc X -> (+ X 1))
+        (define pair X -> (@p 1 2))
+        (define sym X -> a)
+        (define swap (@p A B) -> (@p B A))
+        (define len [] -> 0 [_ | T] -> (+ 1 (len T)))
+        (define size X -> small where (< X 10) X -> big)
+        (define drop-a (@s "a" Rest) -> Rest)
+        (define first-two [X Y | _] -> [X Y])
+    "#;
+    for error_strategy in [ErrorStrategy::Unwind, ErrorStrategy::Result] {
+        let call = |call: &str| match error_strategy {
+            ErrorStrategy::Unwind => call.to_string(),
+            ErrorStrategy::Result => format!("{}.unwrap()", call),
+        };
+        let checks = [
+            (call("inc(Value::Integer(1))"), "Value::Integer(2)"),
+            (call("pair(Value::Nil)"), "Value::tuple(Value::Integer(1), Value::Integer(2))"),
+            (call("sym(Value::Nil)"), "Value::symbol(\"a\")"),
+            (
+                call("swap(Value::tuple(Value::Integer(1), Value::symbol(\"b\")))"),
+                "Value::tuple(Value::symbol(\"b\"), Value::Integer(1))",
+            ),
+            (call("len(Value::list([Value::Nil, Value::Nil]))"), "Value::Integer(2)"),
+            (call("size(Value::Integer(3))"), "Value::symbol(\"small\")"),
+            (call("size(Value::Integer(30))"), "Value::symbol(\"big\")"),
+            (call("drop_a(Value::from(\"abc\"))"), "Value::from(\"bc\")"),
+            (
+                call("first_two(Value::list([Value::Integer(1), Value::Integer(2), Value::Integer(3)]))"),
+                "Value::list([Value::Integer(1), Value::Integer(2)])",
+            ),
+        ];
+        let main = checks
+            .iter()
+            .map(|(actual, expected)| format!("    assert_eq!({}, {});", actual, expected))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let options = CodegenOptions {
+            error_strategy,
+            ..CodegenOptions::default()
+        };
+        assert_generated_code_runs(
+            &format!("define-{:?}", error_strategy).to_lowercase(),
+            source,
+            &main,
+            &options,
+        );
+    }
+}
+
+#[test]
+fn test_generate_tuples_native_or_runtime() {
+    assert_rust_code_generation("(@p 1 \"a\")", "(1.0, \"a\")");
+    assert_rust_code_generation("(@p X \"a\")", "Value::tuple(X, \"a\")");
+    assert_rust_code_generation("(fst (@p 1 \"a\"))", "(1.0, \"a\").0");
+    assert_rust_code_generation("(snd X)", "snd(&X).or_raise()");
+    assert_rust_code_generation("(tuple? X)", "is_tuple(&X)");
+}
+
+#[test]
+fn test_generate_define_with_tuple_pattern() {
+    assert_rust_code_generation(
+        "(define swap (@p A B) -> (@p B A))",
+        r#"fn swap(V1: Value) -> Value {
+    if let Some((V1_1, V1_2)) = V1.as_tuple() {
+        let A = V1_1.clone();
+        let B = V1_2.clone();
+        return Value::tuple(B.clone(), A.clone());
+    }
+    raise(simple_error("partial function swap"))
+}"#
+    );
+}
+
+#[test]
+fn test_generate_vector_primitives() {
+    assert_rust_code_generation("(vector 3)", "vector(3.0).or_raise()");
+    assert_rust_code_generation("(<-vector V 1)", "vector_get(&V, 1.0).or_raise()");
+    assert_rust_code_generation("(vector-> V 1 X)", "vector_set(&V, 1.0, X).or_raise()");
+    assert_rust_code_generation("(<-address V 0)", "address_get(&V, 0.0).or_raise()");
+    assert_rust_code_generation("(vector? V)", "is_vector(&V)");
+    assert_rust_code_generation(
+        "(@v 1 <>)",
+        "Value::vector(vec![Value::from(1.0)])"
+    );
+    assert_rust_code_generation("(@v X V)", "vector_cons(X, &V).or_raise()");
+}
+
+#[test]
+fn test_generate_string_primitives_and_patterns() {
+    assert_rust_code_generation("(pos S 0)", "pos(&S, 0.0).or_raise()");
+    assert_rust_code_generation("(string->n \"a\")", "string_to_n(&Value::from(\"a\")).or_raise()");
+    assert_rust_code_generation("(@s \"a\" \"b\")", "format!(\"{}{}\", \"a\", \"b\")");
+    assert_rust_code_generation("(@s \"a\" S)", "cn(&Value::from(\"a\"), &S).or_raise()");
+    assert_rust_code_generation(
+        "(define drop-a (@s \"a\" Rest) -> Rest)",
+        r#"fn drop_a(V1: Value) -> Value {
+    if let Some(V1_2) = V1.strip_string_prefix("a") {
+        let Rest = V1_2.clone();
+        return Rest.clone();
+    }
+    raise(simple_error("partial function drop-a"))
+}"#
+    );
+}
+
+#[test]
+fn test_generate_defprolog() {
+    assert_rust_code_generation(
+        "(defprolog member X [X | _] <--; X [_ | Y] <-- (member X Y);)",
+        r#"pub fn register_member() {
+    define_prolog("member", vec![
+        Clause::new(2, vec![Term::var(0), Term::cons(Term::var(0), Term::var(1))], vec![]),
+        Clause::new(3, vec![Term::var(0), Term::cons(Term::var(1), Term::var(2))], vec![Goal::Call("member".to_string(), vec![Term::var(0), Term::var(2)])]),
+    ]);
+}"#
+    );
+    assert_rust_code_generation(
+        "(prolog? (is X (+ Y 1)) ! (return X))",
+        "prolog_query(2, vec![Goal::Is(Term::var(0), Expr::new(|env| { let Y = env.value(1); Ok(add(&Y, &Value::Float(1.0))?) })), Goal::Cut, Goal::Return(Term::var(0))]).or_raise()"
+    );
+    // Goal expressions run over Values, so operators lower to runtime calls
+    assert_rust_code_generation(
+        "(prolog? (when (> (* X X) 1)))",
+        "prolog_query(1, vec![Goal::When(Expr::new(|env| { let X = env.value(0); Ok(greater(&multiply(&X, &X)?, &Value::Float(1.0))?) }))]).or_raise()"
+    );
+}
+
+#[test]
+fn test_generated_defprolog_builds_and_runs() {
+    let source = r#"
+        (defprolog member X [X | _] <--; X [_ | Y] <-- (member X Y);)
+        (define has-one L -> (prolog? (member 1 [2 1])))
+        (define first-member L -> (prolog? (member X [a b]) (return X)))
+    "#;
+    for error_strategy in [ErrorStrategy::Unwind, ErrorStrategy::Result] {
+        let call = |call: &str| match error_strategy {
+            ErrorStrategy::Unwind => call.to_string(),
+            ErrorStrategy::Result => format!("{}.unwrap()", call),
+        };
+        let main = format!(
+            "    register_member();\n    assert_eq!({}, Value::Boolean(true));\n    assert_eq!({}, Value::symbol(\"a\"));",
+            call("has_one(Value::Nil)"),
+            call("first_member(Value::Nil)")
+        );
+        let options = CodegenOptions {
+            error_strategy,
+            ..CodegenOptions::default()
+        };
+        assert_generated_code_runs(
+            &format!("defprolog-{:?}", error_strategy).to_lowercase(),
+            source,
+            &main,
+            &options,
+        );
+    }
+}
+
+#[test]
+fn test_generate_defcc() {
+    assert_rust_code_generation(
+        "(defcc <as> a X <as> := [X | <as>]; <e>;)",
+        r#"fn _as_(input: &Value) -> ShenResult<Option<(Value, Value)>> {
+    if let Some(input_1) = yacc_terminal(&input, &Value::symbol("a")) {
+        if let Some((X, input_2)) = input_1.as_cons() {
+            if let Some((input_3, _as_)) = _as_(&input_2)? {
+                return Ok(Some((input_3, Value::from(Value::cons(Value::from(X), Value::from(_as_))))));
+            }
+        }
+    }
+    let input_1 = input.clone();
+    return Ok(Some((input_1, yacc_consumed(input, &input_1))));
+    Ok(None)
+}"#
+    );
+    assert_rust_code_generation("(compile <as> Input)", "compile(_as_, &Input).or_raise()");
+}
+
+#[test]
+fn test_generate_package() {
+    assert_rust_code_generation(
+        "(package stack [push]
+           (define push X S -> (check [X | S]))
+           (define check S -> S))",
+        r#"mod stack {
+    use super::*;
+
+    pub fn push(V1: Value, V2: Value) -> Value {
+        let X = V1.clone();
+        let S = V2.clone();
+        return check(Value::cons(X.clone(), S.clone()));
+        raise(simple_error("partial function push"))
+    }
+
+    fn check(V1: Value) -> Value {
+        let S = V1.clone();
+        return S.clone();
+        raise(simple_error("partial function check"))
+    }
+}
+
+pub use stack::{push};"#,
+    );
+}
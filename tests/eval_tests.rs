@@ -0,0 +1,122 @@
+//! Tests for the tree-walking interpreter
+
+use pretty_assertions::assert_eq;
+use shen_transpiler::eval::Evaluator;
+use shen_transpiler::parser::{parse_klambda, parse_shen_program};
+use shen_transpiler::runtime::{ShenResult, Value};
+
+fn run(evaluator: &Evaluator, source: &str) -> ShenResult<Value> {
+    evaluator.eval_program(&parse_shen_program(source).expect("Parsing should succeed"))
+}
+
+fn eval(source: &str) -> ShenResult<Value> {
+    run(&Evaluator::new(), source)
+}
+
+#[test]
+fn test_eval_defines_and_calls_functions() {
+    let evaluator = Evaluator::new();
+    assert_eq!(
+        run(&evaluator, "(define fact 0 -> 1 N -> (* N (fact (- N 1))))").unwrap(),
+        Value::symbol("fact")
+    );
+    assert_eq!(run(&evaluator, "(fact 10)").unwrap(), Value::Integer(3628800));
+    assert_eq!(run(&evaluator, "(fact 5)").unwrap().to_string(), "120");
+
+    // Definitions persist across programs evaluated in one session
+    run(&evaluator, "(define swap (@p A B) -> (@p B A))").unwrap();
+    assert_eq!(run(&evaluator, "(swap (@p 1 a))").unwrap().to_string(), "(@p a 1)");
+    assert!(run(&evaluator, "(swap 1)").is_err());
+}
+
+#[test]
+fn test_eval_lambdas_and_currying() {
+    assert_eq!(eval("((/. X Y (- X Y)) 10 3)").unwrap(), Value::Integer(7));
+    assert_eq!(
+        eval("(define add X Y -> (+ X Y)) (map (add 1) [1 2 3])").unwrap().to_string(),
+        "[2 3 4]"
+    );
+    assert_eq!(eval("(map (/. X [X]) [a b])").unwrap().to_string(), "[[a] [b]]");
+    assert_eq!(eval("(let F (/. X (* X X)) (F 4))").unwrap(), Value::Integer(16));
+    assert!(eval("(1 2)").is_err());
+}
+
+#[test]
+fn test_eval_negative_numbers() {
+    assert_eq!(eval("(+ -1 2)").unwrap(), Value::Integer(1));
+    assert_eq!(eval("(* -2.5 2)").unwrap(), Value::Integer(-5));
+    assert_eq!(eval("(- 3 -1)").unwrap(), Value::Integer(4));
+    assert_eq!(eval("[-1 -2]").unwrap().to_string(), "[-1 -2]");
+}
+
+#[test]
+fn test_eval_cond() {
+    assert_eq!(eval("(cond ((= 1 2) a) (true b))").unwrap(), Value::symbol("b"));
+    assert_eq!(
+        eval("(define sign X -> (cond ((< X 0) -1) ((= X 0) 0) (true 1))) (map (/. X (sign X)) [-5 0 5])")
+            .unwrap()
+            .to_string(),
+        "[-1 0 1]"
+    );
+    // Only the clauses up to the first that holds are evaluated
+    assert_eq!(eval("(cond (true ok) ((hd []) never))").unwrap(), Value::symbol("ok"));
+    assert_eq!(
+        eval("(trap-error (cond ((= 1 2) a)) (/. E (error-to-string E)))").unwrap(),
+        Value::from("cond failure")
+    );
+}
+
+#[test]
+fn test_eval_errors_and_freeze() {
+    assert_eq!(
+        eval("(trap-error (simple-error \"oops\") (/. E (error-to-string E)))").unwrap(),
+        Value::from("oops")
+    );
+    assert_eq!(
+        eval("(trap-error (hd []) (/. E caught))").unwrap(),
+        Value::symbol("caught")
+    );
+    assert_eq!(
+        eval("(let F (freeze (+ 1 2)) (thaw F))").unwrap(),
+        Value::Integer(3)
+    );
+    assert_eq!(
+        eval("(trap-error (thaw (freeze (error \"~A failed\" x))) (/. E (error-to-string E)))")
+            .unwrap(),
+        Value::from("x failed")
+    );
+    assert!(eval("(simple-error \"uncaught\")").is_err());
+}
+
+#[test]
+fn test_eval_globals_strings_and_vectors() {
+    let evaluator = Evaluator::new();
+    run(&evaluator, "(set *count* 1)").unwrap();
+    assert_eq!(run(&evaluator, "(set *count* (+ (value *count*) 1))").unwrap(), Value::Integer(2));
+    assert_eq!(evaluator.globals().get("*count*"), Some(Value::Integer(2)));
+    assert_eq!(run(&evaluator, "(bound? *missing*)").unwrap(), Value::Boolean(false));
+
+    assert_eq!(
+        eval("(define greet (@s \"hi \" Name) -> Name) (greet \"hi bob\")").unwrap(),
+        Value::from("bob")
+    );
+    assert_eq!(eval("(make-string \"~A is ~S\" a \"b\")").unwrap(), Value::from("a is \"b\""));
+    assert_eq!(eval("(@v 1 2 <>)").unwrap().to_string(), "<1 2>");
+    assert_eq!(
+        eval("(let V (vector 2) (do (vector-> V 1 a) (<-vector V 1)))").unwrap(),
+        Value::symbol("a")
+    );
+    assert_eq!(eval("(/ 7 2)").unwrap(), Value::Float(3.5));
+    assert_eq!(eval("(/ 8 2)").unwrap(), Value::Integer(4));
+}
+
+#[test]
+fn test_eval_klambda() {
+    let evaluator = Evaluator::new();
+    let kernel = parse_klambda(
+        "(defun shen.len (V1 V2) (cond ((= () V1) V2) (true (shen.len (tl V1) (+ V2 1)))))",
+    )
+    .unwrap();
+    evaluator.eval_program(&kernel).unwrap();
+    assert_eq!(run(&evaluator, "(shen.len [a b c] 0)").unwrap(), Value::Integer(3));
+}
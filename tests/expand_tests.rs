@@ -0,0 +1,162 @@
+//! Tests for macro expansion and the interpreter it runs macros with
+
+use shen_transpiler::ast::ShenNode;
+use shen_transpiler::expand::{expand_program, to_source};
+use shen_transpiler::eval::Evaluator;
+use shen_transpiler::parser::{parse_shen_program, parse_shen_source};
+use shen_transpiler::runtime::Value;
+use pretty_assertions::assert_eq;
+
+fn expand_source(input: &str) -> Vec<String> {
+    let program = parse_shen_program(input).expect("Parsing should succeed");
+    expand_program(program)
+        .expect("Expansion should succeed")
+        .iter()
+        .map(to_source)
+        .collect()
+}
+
+#[test]
+fn test_defmacro_rewrites_matching_forms() {
+    let expanded = expand_source(
+        "(defmacro my-macro [foo X] -> [bar X X])
+         (define f Y -> (foo (+ Y 1)))",
+    );
+    assert_eq!(expanded, vec!["(define f Y -> (bar (+ Y 1) (+ Y 1)))"]);
+}
+
+#[test]
+fn test_expansion_reaches_a_fixpoint_inside_nested_forms() {
+    // unless expands to if, and twice expands to nested unless forms
+    let expanded = expand_source(
+        "(defmacro unless-macro [unless C X] -> [if C [] X])
+         (defmacro twice-macro [twice X] -> [unless false [unless false X]])
+         (define g -> (cons 1 (twice 2)))",
+    );
+    assert_eq!(expanded, vec!["(define g -> (cons 1 (if false [] (if false [] 2))))"]);
+}
+
+#[test]
+fn test_macros_can_call_helper_functions() {
+    let expanded = expand_source(
+        "(define swap-args [F A B] -> [F B A])
+         (defmacro swap-macro [swap Call] -> (swap-args Call))
+         (swap (- 10 3))",
+    );
+    assert_eq!(expanded.len(), 2);
+    assert_eq!(expanded[1], "(- 3 10)");
+}
+
+#[test]
+fn test_macros_can_produce_strings_with_quotes() {
+    // Shen source cannot spell a string holding ", so the result must not be
+    // printed and read back
+    let program = parse_shen_program(
+        "(defmacro quote-macro [quote X] -> (cn (n->string 34) X))
+         (quote \"a\")",
+    )
+    .expect("Parsing should succeed");
+    let expanded = expand_program(program).expect("Expansion should succeed");
+    assert_eq!(Evaluator::new().eval_program(&expanded).unwrap(), Value::from("\"a"));
+}
+
+#[test]
+fn test_macros_rewrite_every_level_of_nested_forms() {
+    let depth = 100;
+    let source = format!(
+        "(defmacro inc-macro [inc X] -> [+ X 1]) (define f X -> {}X{})",
+        "(inc ".repeat(depth),
+        ")".repeat(depth)
+    );
+    let program = parse_shen_program(&source).expect("Parsing should succeed");
+    let expanded = expand_program(program).expect("Expansion should succeed");
+    let evaluator = Evaluator::new();
+    evaluator.eval_program(&expanded).unwrap();
+    assert_eq!(
+        evaluator.eval_program(&parse_shen_program("(f 0)").unwrap()).unwrap(),
+        Value::Integer(depth as i64)
+    );
+}
+
+#[test]
+fn test_macros_can_produce_negative_numbers() {
+    let program = parse_shen_program(
+        "(defmacro negate-macro [negate N] -> (- 0 N))
+         (define f X -> (+ X (negate 2)))
+         (f 5)",
+    )
+    .expect("Parsing should succeed");
+    let expanded = expand_program(program).expect("Expansion should succeed");
+    assert_eq!(to_source(&expanded[0]), "(define f X -> (+ X -2))");
+    // -2 is read back as a number, not a symbol
+    assert_eq!(Evaluator::new().eval_program(&expanded).unwrap(), Value::Integer(3));
+}
+
+#[test]
+fn test_non_terminating_macros_are_reported() {
+    let program = parse_shen_program("(defmacro loop-macro [loop X] -> [loop [loop X]]) (loop 1)")
+        .expect("Parsing should succeed");
+    assert!(expand_program(program).is_err());
+}
+
+#[test]
+fn test_interpreter_evaluates_shen_code() {
+    let mut interpreter = Evaluator::new();
+    let node = parse_shen_source("(define len [] -> 0 [_ | T] -> (+ 1 (len T)))").unwrap();
+    if let ShenNode::Define { name, rules } = node {
+        interpreter.define(name, rules);
+    }
+
+    let eval = |source: &str| {
+        interpreter.eval(&parse_shen_source(source).unwrap(), &Default::default())
+    };
+    assert_eq!(eval("(len [a b c])").unwrap(), Value::Integer(3));
+    assert_eq!(eval("(let X 2 Y (* X 3) [X Y])").unwrap(), Value::from(vec![2, 6]));
+    assert_eq!(eval("(if (and true (cons? [a])) (hd [a]) b)").unwrap(), Value::symbol("a"));
+    assert!(eval("(len a)").is_err());
+}
+
+fn standard(input: &str) -> String {
+    to_source(&parse_shen_source(input).expect("Parsing should succeed"))
+}
+
+#[test]
+fn test_standard_macros_nest_variadic_forms() {
+    assert_eq!(standard("(@p 1 2 3)"), "(@p 1 (@p 2 3))");
+    assert_eq!(standard("(@s \"a\" \"b\" S)"), "(@s \"a\" (@s \"b\" S))");
+    assert_eq!(standard("(@v 1 2 V)"), "(@v 1 (@v 2 V))");
+    assert_eq!(standard("(@v 1 2 <>)"), "(@v 1 (@v 2 <>))");
+    assert!(matches!(
+        parse_shen_source("(@v 1 2 <>)"),
+        Ok(ShenNode::Vector { ref elements }) if elements.len() == 2
+    ));
+    assert_eq!(standard("(/. X Y Z (+ X Y))"), "(/. X (/. Y (/. Z (+ X Y))))");
+    assert_eq!(standard("(let X 1 Y 2 (+ X Y))"), "(let X 1 (let Y 2 (+ X Y)))");
+    assert!(parse_shen_source("(let X 1 Y (+ X Y))").is_err());
+}
+
+#[test]
+fn test_standard_macros_for_io_errors_and_properties() {
+    assert_eq!(standard("(input+ number)"), "(input+ number (stinput))");
+    assert_eq!(
+        standard("(output \"x is ~A~%\" X)"),
+        "(prhush (cn \"x is \" (shen.app X \"\n\" shen.a)) (stoutput))"
+    );
+    assert_eq!(
+        standard("(error \"~S and ~A\" X Y)"),
+        "(simple-error (shen.app X (cn \" and \" (shen.app Y \"\" shen.a)) shen.s))"
+    );
+    assert_eq!(standard("(make-string \"plain\")"), "\"plain\"");
+    assert!(parse_shen_source("(make-string \"~A ~A\" X)").is_err());
+    assert_eq!(standard("(put X colour red)"), "(put X colour red (value *property-vector*))");
+    assert_eq!(standard("(get X colour)"), "(get X colour (value *property-vector*))");
+}
+
+#[test]
+fn test_standard_cases_macro() {
+    assert_eq!(
+        standard("(cases (= X 1) a (= X 2) b)"),
+        "(if (= X 1) a (if (= X 2) b (simple-error \"error: cases exhausted\")))"
+    );
+    assert!(parse_shen_source("(cases (= X 1))").is_err());
+}
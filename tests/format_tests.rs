@@ -0,0 +1,175 @@
+//! Tests for formatting Shen source
+
+use std::fs;
+
+use pretty_assertions::assert_eq;
+use shen_transpiler::error::TranspilerError;
+use shen_transpiler::formatter::{format_file, format_source};
+use shen_transpiler::parser::parse_shen_program;
+use shen_transpiler::printer::DEFAULT_WIDTH;
+
+fn format(source: &str, width: usize) -> String {
+    format_source(source, width).unwrap_or_else(|err| panic!("Formatting should succeed: {}\n{}", err, source))
+}
+
+#[test]
+fn test_define_rules_are_aligned() {
+    let source = "(define rev {(list A) --> (list A)} [] -> [] [X | Y] -> (append (rev Y) [X]))";
+    assert_eq!(
+        format(source, DEFAULT_WIDTH),
+        "\
+(define rev
+  {(list A) --> (list A)}
+  []      -> []
+  [X | Y] -> (append (rev Y) [X]))
+"
+    );
+
+    // Long rules break after the arrow, and the guard goes under the body
+    let source = "(define fib 0 -> 0 1 -> 1 N -> (+ (fib (- N 1)) (fib (- N 2))) where (> N 1))";
+    assert_eq!(
+        format(source, 40),
+        "\
+(define fib
+  0 -> 0
+  1 -> 1
+  N ->
+    (+ (fib (- N 1)) (fib (- N 2)))
+    where (> N 1))
+"
+    );
+}
+
+#[test]
+fn test_forms_are_laid_out_by_head() {
+    let source = "\
+(defprolog member X [X | _] <--; X [_ | Y] <-- ! (member X Y);)
+(defcc <as> a X <as> := [X | <as>] where (number? X); <e>;)
+(let X 1 Y [2 3] (if (> X 0) (cons X Y) (some-long-function-name X Y \"a string\")))
+(cond ((= X 1) one) ((= X 2) two) (true many))
+(defun twice (F X) (F (F X)))
+(/. X Y (+ X Y))
+(package stack [push] (define push X S -> [X | S]) (define pop [_ | S] -> S))";
+    assert_eq!(
+        format(source, 40),
+        "\
+(defprolog member
+  X [X | _] <--;
+  X [_ | Y] <-- ! (member X Y);)
+(defcc <as>
+  a X <as> :=
+    [X | <as>]
+    where (number? X);
+  <e>;)
+(let X 1
+     Y [2 3]
+  (if (> X 0)
+      (cons X Y)
+      (some-long-function-name X
+                               Y
+                               \"a string\")))
+(cond ((= X 1) one)
+      ((= X 2) two)
+      (true many))
+(defun twice (F X) (F (F X)))
+(/. X Y (+ X Y))
+(package stack [push]
+  (define push
+    X S -> [X | S])
+  (define pop
+    [_ | S] -> S))
+"
+    );
+}
+
+#[test]
+fn test_comments_are_kept_in_place() {
+    let source = "\
+\\* A header
+   over two lines *\\
+
+
+(define rev   \\\\ reverse a list
+  [] -> []
+
+  \\\\ the recursive case
+  [X | Y] -> (append (rev Y) [X])) \\\\ trailing
+\\\\ before
+(f a \\\\ first
+   b \\* inline *\\ c
+   \\\\ dangling
+   )
+\\\\ the end
+";
+    assert_eq!(
+        format(source, DEFAULT_WIDTH),
+        "\
+\\* A header
+   over two lines *\\
+
+(define rev \\\\ reverse a list
+  []      -> []
+
+  \\\\ the recursive case
+  [X | Y] -> (append (rev Y) [X])) \\\\ trailing
+\\\\ before
+(f a \\\\ first
+   b \\* inline *\\
+   c
+   \\\\ dangling
+)
+\\\\ the end
+"
+    );
+}
+
+#[test]
+fn test_formatting_is_idempotent_and_keeps_the_program() {
+    let mut sources = vec![fs::read_to_string("tests/fixtures/kernel/sys.kl").unwrap()];
+    for entry in fs::read_dir("tests/fixtures/repl").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "shen") {
+            sources.push(fs::read_to_string(path).unwrap());
+        }
+    }
+
+    for source in &sources {
+        let program = format!("{:?}", parse_shen_program(source).unwrap());
+        for width in [1, 20, 40, DEFAULT_WIDTH, 1000] {
+            let formatted = format(source, width);
+            assert_eq!(format(&formatted, width), formatted);
+            assert_eq!(format!("{:?}", parse_shen_program(&formatted).unwrap()), program);
+        }
+    }
+}
+
+#[test]
+fn test_unreadable_source_is_not_formatted() {
+    assert!(matches!(
+        format_source("(define f X -> [X)", DEFAULT_WIDTH),
+        Err(TranspilerError::ParseError { .. })
+    ));
+    assert!(matches!(
+        format_source("(f \\* unterminated", DEFAULT_WIDTH),
+        Err(TranspilerError::ParseError { .. })
+    ));
+}
+
+#[test]
+fn test_format_file_checks_or_rewrites() {
+    let path = std::env::temp_dir().join(format!("shen-format-{}.shen", std::process::id()));
+    fs::write(&path, "(define inc X -> (+ X 1))").unwrap();
+
+    assert!(format_file(&path, DEFAULT_WIDTH, true).unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "(define inc X -> (+ X 1))");
+
+    assert!(format_file(&path, DEFAULT_WIDTH, false).unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "(define inc\n  X -> (+ X 1))\n");
+    assert!(!format_file(&path, DEFAULT_WIDTH, true).unwrap());
+
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(
+        format_file(&path, DEFAULT_WIDTH, true),
+        Err(TranspilerError::IoError { .. })
+    ));
+}
@@ -1,3 +1,11 @@
 mod parser_tests;
 mod codegen_tests;
+mod runtime_tests;
+mod expand_tests;
+mod bootstrap_tests;
+mod eval_tests;
+mod repl_tests;
+mod ast_tests;
+mod printer_tests;
 extern crate shen_transpiler;
+mod format_tests;
@@ -1,8 +1,12 @@
 //! Tests for Shen language parsing
 
 use pretty_assertions::assert_eq;
-use shen_transpiler::ast::{ShenNode, ShenType, ShenValue};
-use shen_transpiler::parser::parse_shen_source;
+use shen_transpiler::ast::{Pattern, ShenNode, ShenType, ShenValue, Symbol, YaccItem};
+use shen_transpiler::error::TranspilerError;
+use shen_transpiler::parser::{
+    is_unfinished, load_kernel, parse_klambda, parse_shen_program, parse_shen_program_partial,
+    comments, parse_shen_source, tokenize, tokenize_partial, Span, Token,
+};
 
 #[test]
 fn test_parse_simple_function() {
@@ -100,7 +104,7 @@ fn test_parse_function_with_multiple_args() {
 
             // Check return type inference
             match return_type {
-                ShenType::Float | ShenType::Integer => assert!(true),
+                ShenType::Float | ShenType::Integer => {}
                 _ => panic!("Expected Float or Integer return type"),
             }
         }
@@ -118,14 +122,13 @@ fn test_parse_lambda_expression() {
     let node = result.unwrap();
     match node {
         ShenNode::Lambda {
-            args,
+            arg,
             body,
             return_type,
             ..
         } => {
-            assert_eq!(args.len(), 1);
-            assert_eq!(args[0].0, "x");
-            assert_eq!(args[0].1, ShenType::Symbol);
+            assert_eq!(arg.0, "x");
+            assert_eq!(arg.1, ShenType::Symbol);
 
             // Check body is a binary operation
             match *body {
@@ -294,3 +297,708 @@ fn test_parse_list_expression() {
         _ => panic!("Expected a list node"),
     }
 }
+
+#[test]
+fn test_parse_shen_lambda_is_curried() {
+    let input = "(/. X Y (+ X Y))";
+    let result = parse_shen_source(input);
+
+    assert!(result.is_ok(), "Parsing should succeed");
+
+    let node = result.unwrap();
+    match node {
+        ShenNode::Lambda {
+            arg,
+            body,
+            return_type,
+        } => {
+            assert_eq!(arg.0, "X");
+            assert_eq!(return_type, ShenType::Function);
+
+            match *body {
+                ShenNode::Lambda {
+                    arg,
+                    body,
+                    return_type,
+                } => {
+                    assert_eq!(arg.0, "Y");
+                    assert_eq!(return_type, ShenType::Float);
+                    assert!(matches!(*body, ShenNode::BinaryOperation { .. }));
+                }
+                _ => panic!("Expected a nested lambda"),
+            }
+        }
+        _ => panic!("Expected a lambda node"),
+    }
+}
+
+#[test]
+fn test_parse_lambda_forms_normalize_to_same_shape() {
+    let param_names = |input: &str| {
+        let node = parse_shen_source(input).expect("Parsing should succeed");
+        let (args, _body) = node.uncurry_lambda().expect("Expected a lambda node");
+        args.iter().map(|(name, _)| *name).collect::<Vec<_>>()
+    };
+
+    assert_eq!(param_names("(lambda X X)"), vec!["X"]);
+    assert_eq!(param_names("(lambda (x y) (+ x y))"), vec!["x", "y"]);
+    assert_eq!(param_names("(/. X Y (+ X Y))"), vec!["X", "Y"]);
+    assert!(parse_shen_source("(/. (+ 1 2))").is_err());
+}
+
+#[test]
+fn test_parse_freeze_and_thaw_types() {
+    let frozen = parse_shen_source("(freeze (+ 1 2))").expect("Parsing should succeed");
+    match &frozen {
+        ShenNode::Freeze { body } => {
+            assert!(matches!(**body, ShenNode::BinaryOperation { .. }));
+        }
+        _ => panic!("Expected a freeze node"),
+    }
+    assert_eq!(frozen.get_type(), ShenType::Lazy(Box::new(ShenType::Float)));
+
+    let thawed = parse_shen_source("(thaw (freeze (+ 1 2)))").expect("Parsing should succeed");
+    assert_eq!(thawed.get_type(), ShenType::Float);
+}
+
+#[test]
+fn test_parse_trap_error() {
+    let input = "(trap-error (simple-error \"boom\") (/. E (error-to-string E)))";
+    let node = parse_shen_source(input).expect("Parsing should succeed");
+
+    match node {
+        ShenNode::TrapError { body, handler } => {
+            match *body {
+                ShenNode::Application { func, args } => {
+                    assert!(matches!(*func, ShenNode::Symbol { ref name, .. } if name == "simple-error"));
+                    assert_eq!(args.len(), 1);
+                }
+                _ => panic!("Expected an application body"),
+            }
+            assert!(matches!(*handler, ShenNode::Lambda { ref arg, .. } if arg.0 == "E"));
+        }
+        _ => panic!("Expected a trap-error node"),
+    }
+}
+
+#[test]
+fn test_parse_nested_tuple_sugar() {
+    let node = parse_shen_source("(@p 1 \"a\" 2)").expect("Parsing should succeed");
+
+    match node {
+        ShenNode::Tuple { first, second } => {
+            assert!(matches!(*first, ShenNode::Literal { value: ShenValue::Float(_) }));
+            assert!(matches!(*second, ShenNode::Tuple { .. }));
+        }
+        _ => panic!("Expected a tuple node"),
+    }
+}
+
+#[test]
+fn test_parse_define_with_tuple_patterns() {
+    let input = "(define swap (@p A B) -> (@p B A) _ -> (simple-error \"not a pair\"))";
+    let node = parse_shen_source(input).expect("Parsing should succeed");
+
+    match node {
+        ShenNode::Define { name, rules } => {
+            assert_eq!(name, "swap");
+            assert_eq!(rules.len(), 2);
+            assert_eq!(
+                rules[0].patterns,
+                vec![Pattern::Tuple(
+                    Box::new(Pattern::Variable("A".into())),
+                    Box::new(Pattern::Variable("B".into())),
+                )]
+            );
+            assert!(matches!(rules[0].body, ShenNode::Tuple { .. }));
+            assert_eq!(rules[1].patterns, vec![Pattern::Wildcard]);
+        }
+        _ => panic!("Expected a define node"),
+    }
+}
+
+#[test]
+fn test_parse_vector_literals_and_patterns() {
+    match parse_shen_source("(@v 1 2 <>)").expect("Parsing should succeed") {
+        ShenNode::Vector { elements } => assert_eq!(elements.len(), 2),
+        _ => panic!("Expected a vector literal"),
+    }
+
+    match parse_shen_source("(@v X V)").expect("Parsing should succeed") {
+        ShenNode::VectorCons { head, tail } => {
+            assert!(matches!(*head, ShenNode::Symbol { ref name, .. } if name == "X"));
+            assert!(matches!(*tail, ShenNode::Symbol { ref name, .. } if name == "V"));
+        }
+        _ => panic!("Expected a vector cons"),
+    }
+
+    let node = parse_shen_source("(define vsum <> -> 0 (@v X Y) -> (+ X (vsum Y)))")
+        .expect("Parsing should succeed");
+    match node {
+        ShenNode::Define { rules, .. } => {
+            assert_eq!(rules[0].patterns, vec![Pattern::EmptyVector]);
+            assert_eq!(
+                rules[1].patterns,
+                vec![Pattern::VectorCons(
+                    Box::new(Pattern::Variable("X".into())),
+                    Box::new(Pattern::Variable("Y".into())),
+                )]
+            );
+        }
+        _ => panic!("Expected a define node"),
+    }
+}
+
+#[test]
+fn test_parse_string_patterns() {
+    let input = "(define strip-a (@s \"a\" Rest) -> Rest (@s C Rest) -> (@s C (strip-a Rest)))";
+    let node = parse_shen_source(input).expect("Parsing should succeed");
+
+    match node {
+        ShenNode::Define { rules, .. } => {
+            assert_eq!(
+                rules[0].patterns,
+                vec![Pattern::StringCons(
+                    Box::new(Pattern::Literal(ShenValue::String("a".to_string()))),
+                    Box::new(Pattern::Variable("Rest".into())),
+                )]
+            );
+            assert!(matches!(rules[1].body, ShenNode::StringCons { .. }));
+        }
+        _ => panic!("Expected a define node"),
+    }
+}
+
+#[test]
+fn test_parse_bracket_lists() {
+    assert!(matches!(parse_shen_source("[]"), Ok(ShenNode::Nil)));
+    assert!(matches!(
+        parse_shen_source("[1 2 3]"),
+        Ok(ShenNode::List { ref elements, .. }) if elements.len() == 3
+    ));
+
+    match parse_shen_source("[X Y | T]").expect("Parsing should succeed") {
+        ShenNode::Cons { tail, .. } => assert!(matches!(*tail, ShenNode::Cons { .. })),
+        _ => panic!("Expected a cons"),
+    }
+
+    assert!(parse_shen_source("[X | Y Z]").is_err());
+    assert!(parse_shen_source("[X)").is_err());
+}
+
+#[test]
+fn test_parse_defprolog() {
+    let input = "(defprolog member X [X | _] <--; X [_ | Y] <-- ! (member X Y);)";
+    let node = parse_shen_source(input).expect("Parsing should succeed");
+
+    match node {
+        ShenNode::Defprolog { name, clauses } => {
+            assert_eq!(name, "member");
+            assert_eq!(clauses.len(), 2);
+            assert_eq!(
+                clauses[0].head,
+                vec![
+                    Pattern::Variable("X".into()),
+                    Pattern::Cons(
+                        Box::new(Pattern::Variable("X".into())),
+                        Box::new(Pattern::Wildcard),
+                    ),
+                ]
+            );
+            assert!(clauses[0].body.is_empty());
+            assert_eq!(clauses[1].body.len(), 2);
+            assert!(matches!(&clauses[1].body[0], ShenNode::Symbol { name, .. } if name == "!"));
+        }
+        _ => panic!("Expected a defprolog node"),
+    }
+
+    assert!(matches!(
+        parse_shen_source("(prolog? (member X [1 2]) (return X))"),
+        Ok(ShenNode::PrologQuery { ref goals }) if goals.len() == 2
+    ));
+    assert!(parse_shen_source("(defprolog p X <-- (q X))").is_err());
+}
+
+#[test]
+fn test_parse_defcc() {
+    let input = "(defcc <digits> <digit> <digits> := [<digit> | <digits>]; <digit> := [<digit>];)";
+    let node = parse_shen_source(input).expect("Parsing should succeed");
+
+    match node {
+        ShenNode::Defcc { name, rules } => {
+            assert_eq!(name, "<digits>");
+            assert_eq!(
+                rules[0].items,
+                vec![
+                    YaccItem::NonTerminal(Symbol::intern("<digit>")),
+                    YaccItem::NonTerminal(Symbol::intern("<digits>")),
+                ]
+            );
+            assert!(matches!(rules[0].action, Some(ShenNode::Cons { .. })));
+            assert!(rules[1].guard.is_none());
+        }
+        _ => panic!("Expected a defcc node"),
+    }
+
+    match parse_shen_source("(defcc <rest> a X <!> := X where (number? X); <e>;)") {
+        Ok(ShenNode::Defcc { rules, .. }) => {
+            assert_eq!(
+                rules[0].items,
+                vec![
+                    YaccItem::Symbol(Symbol::intern("a")),
+                    YaccItem::Variable(Symbol::intern("X")),
+                    YaccItem::Rest,
+                ]
+            );
+            assert!(rules[0].guard.is_some());
+            assert_eq!(rules[1].items, vec![YaccItem::Empty]);
+            assert!(rules[1].action.is_none());
+        }
+        other => panic!("Expected a defcc node, got {:?}", other),
+    }
+
+    assert!(parse_shen_source("(defcc digits X := X;)").is_err());
+    assert!(parse_shen_source("(defcc <x> X := X Y;)").is_err());
+}
+
+#[test]
+fn test_parse_defmacro_and_programs() {
+    let program = parse_shen_program("(defmacro m [foo X] -> [bar X]) (foo 1)")
+        .expect("Parsing should succeed");
+    assert_eq!(program.len(), 2);
+
+    match &program[0] {
+        ShenNode::Defmacro { name, rules } => {
+            assert_eq!(name, "m");
+            assert_eq!(
+                rules[0].patterns,
+                vec![Pattern::Cons(
+                    Box::new(Pattern::Symbol("foo".into())),
+                    Box::new(Pattern::Cons(
+                        Box::new(Pattern::Variable("X".into())),
+                        Box::new(Pattern::EmptyList),
+                    )),
+                )]
+            );
+        }
+        _ => panic!("Expected a defmacro node"),
+    }
+
+    assert!(parse_shen_source("(defmacro m X Y -> X)").is_err());
+    assert!(matches!(parse_shen_source("(cons 1 [])"), Ok(ShenNode::Cons { .. })));
+}
+
+#[test]
+fn test_parse_define_with_type_signature() {
+    let with_signature =
+        parse_shen_source("(define double {number --> number} X -> (* 2 X))").expect("Parsing should succeed");
+    let without_signature = parse_shen_source("(define double X -> (* 2 X))").unwrap();
+    assert_eq!(format!("{:?}", with_signature), format!("{:?}", without_signature));
+
+    assert!(parse_shen_source("(define f X -> {X})").is_err());
+    assert!(parse_shen_source("(define f {number --> number)").is_err());
+    match parse_shen_source("(f 1)\n  (g 2)") {
+        Err(TranspilerError::ParseError { token, line, column, .. }) => {
+            assert_eq!((token.as_str(), line, column), ("(g 2)", 2, 3))
+        }
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_errors_say_what_was_expected() {
+    let error = |source: &str| match parse_shen_source(source) {
+        Err(TranspilerError::ParseError {
+            token,
+            message,
+            line,
+            column,
+        }) => (token, message, line, column),
+        other => panic!("Expected a parse error for {}, got {:?}", source, other),
+    };
+
+    assert_eq!(
+        error("(define f X)"),
+        (
+            ")".to_string(),
+            "unexpected end of form; expected `->`; in a rule of f".to_string(),
+            1,
+            12
+        )
+    );
+    assert_eq!(
+        error("(defun f x y)"),
+        (
+            "x".to_string(),
+            "unexpected token; expected a parameter list in parentheses".to_string(),
+            1,
+            10
+        )
+    );
+    assert_eq!(
+        error("(f\n  [X)"),
+        (
+            ")".to_string(),
+            "mismatched delimiter; expected `]`".to_string(),
+            2,
+            5
+        )
+    );
+    assert_eq!(
+        parse_shen_source("(f\n  [X)").unwrap_err().to_string(),
+        "Parsing error at 2:5, token ): mismatched delimiter; expected `]`"
+    );
+    assert_eq!(error("(if)").1, "unexpected end of form; expected a condition");
+    assert_eq!(error("(freeze)").1, "unexpected end of form; expected an expression");
+    assert_eq!(error("(trap-error a b c)").0, "c");
+    assert_eq!(error("(define f (g X) -> X)").1, "bad pattern; only `cons`, `@p`, `@v` and `@s` forms are patterns");
+    assert_eq!(error("(define f X -> X Y Z -> Y)").1.split(';').next(), Some("arity mismatch"));
+    assert_eq!(error("").0, "end of input");
+}
+
+#[test]
+fn test_parse_program_recovers_from_errors() {
+    let source = "\
+(define inc X -> (+ X 1))
+(define broken X)
+(define open X -> (* X 2)
+(define twice F X -> (F (F X)))
+(f ])
+(g 1 %)
+";
+    let program = parse_shen_program_partial(source);
+
+    let positions = program
+        .errors
+        .iter()
+        .map(|error| match error {
+            TranspilerError::ParseError { line, column, .. } => (*line, *column),
+            error => panic!("Expected a parse error, got {:?}", error),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(positions, vec![(2, 17), (3, 1), (5, 4), (6, 6)]);
+
+    let shapes = program
+        .forms
+        .iter()
+        .map(|form| match form {
+            ShenNode::Define { name, .. } => name.as_str(),
+            ShenNode::Error { .. } => "error",
+            ShenNode::Application { .. } => "application",
+            _ => "other",
+        })
+        .collect::<Vec<_>>();
+    // The unclosed define is closed before the next one and still parses
+    assert_eq!(shapes, vec!["inc", "error", "open", "twice", "application", "application"]);
+
+    let clean = parse_shen_program_partial("(define inc X -> (+ X 1))");
+    assert!(clean.errors.is_empty());
+    assert_eq!(clean.forms.len(), 1);
+}
+
+#[test]
+fn test_parse_package() {
+    let node = parse_shen_source(
+        "(package stack [push]
+           (define push X S -> (check [X | S]))
+           (define check S -> (if (empty? S) empty S)))",
+    )
+    .expect("Parsing should succeed");
+
+    let (name, exports, body) = match node {
+        ShenNode::Package {
+            name,
+            exports,
+            body,
+        } => (name, exports, body),
+        _ => panic!("Expected a package node"),
+    };
+    assert_eq!(name, "stack");
+    assert_eq!(exports, vec![Symbol::intern("push")]);
+
+    let names = body
+        .iter()
+        .map(|form| match form {
+            ShenNode::Define { name, .. } => name.as_str(),
+            _ => panic!("Expected a define node"),
+        })
+        .collect::<Vec<_>>();
+    // Exported and system symbols keep their names, variables are untouched
+    assert_eq!(names, vec!["push", "stack.check"]);
+    match &body[1] {
+        ShenNode::Define { rules, .. } => {
+            assert_eq!(rules[0].patterns, vec![Pattern::Variable("S".into())]);
+            match &rules[0].body {
+                ShenNode::Conditional {
+                    condition,
+                    true_branch,
+                    ..
+                } => {
+                    assert!(matches!(
+                        condition.as_ref(),
+                        ShenNode::Application { func, .. }
+                            if matches!(func.as_ref(), ShenNode::Symbol { name, .. } if name == "empty?")
+                    ));
+                    assert!(matches!(
+                        true_branch.as_ref(),
+                        ShenNode::Symbol { name, .. } if name == "stack.empty"
+                    ));
+                }
+                _ => panic!("Expected a conditional"),
+            }
+        }
+        _ => panic!("Expected a define node"),
+    }
+
+    // The null package prefixes nothing
+    match parse_shen_source("(package null [] (define f X -> g))") {
+        Ok(ShenNode::Package { body, .. }) => {
+            assert!(matches!(&body[0], ShenNode::Define { name, .. } if name == "f"));
+        }
+        _ => panic!("Expected a package node"),
+    }
+    assert!(parse_shen_source("(package stack push)").is_err());
+    assert!(parse_shen_source("(package stack [(push)])").is_err());
+}
+
+#[test]
+fn test_parse_klambda() {
+    let forms = parse_klambda(
+        "(defun shen.f (V1 V2) (cond ((= V1 ()) V2) (true (cons | (shen.f (tl V1) 1.5)))))",
+    )
+    .expect("Reading should succeed");
+    assert_eq!(forms.len(), 1);
+
+    let (name, args, body) = match &forms[0] {
+        ShenNode::Function { name, args, body, .. } => (name, args, body),
+        _ => panic!("Expected a function node"),
+    };
+    assert_eq!(name, "shen.f");
+    assert_eq!(args.iter().map(|(arg, _)| arg.as_str()).collect::<Vec<_>>(), vec!["V1", "V2"]);
+
+    // cond becomes a chain of ifs ending in an error
+    let ShenNode::Conditional { false_branch: Some(rest), .. } = body.as_ref() else {
+        panic!("Expected a conditional");
+    };
+    let ShenNode::Conditional { true_branch, false_branch: Some(failure), .. } = rest.as_ref() else {
+        panic!("Expected a conditional");
+    };
+    match true_branch.as_ref() {
+        ShenNode::Cons { head, tail } => {
+            assert!(matches!(head.as_ref(), ShenNode::Symbol { name, .. } if name == "|"));
+            assert!(matches!(
+                tail.as_ref(),
+                ShenNode::Application { args, .. }
+                    if matches!(args[1], ShenNode::Literal { value: ShenValue::Float(value) } if value == 1.5)
+            ));
+        }
+        _ => panic!("Expected a cons"),
+    }
+    assert!(matches!(
+        failure.as_ref(),
+        ShenNode::Application { func, .. }
+            if matches!(func.as_ref(), ShenNode::Symbol { name, .. } if name == "simple-error")
+    ));
+
+    // Shen's delimiters and comment markers are symbols in KLambda
+    match &parse_klambda("(f ; := \\\\ -1 1+)").expect("Reading should succeed")[0] {
+        ShenNode::Application { args, .. } => {
+            let symbols = args
+                .iter()
+                .filter_map(|arg| match arg {
+                    ShenNode::Symbol { name, .. } => Some(name.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(symbols, vec![";", ":=", "\\\\", "1+"]);
+            assert!(matches!(args[3], ShenNode::Literal { value: ShenValue::Integer(-1) }));
+        }
+        other => panic!("Expected an application, got {:?}", other),
+    }
+
+    match parse_klambda("(defun f (X)\n  (lambda))") {
+        Err(TranspilerError::ParseError { line, column, .. }) => assert_eq!((line, column), (2, 3)),
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+    assert!(parse_klambda("(f \"unterminated)").is_err());
+    assert!(parse_klambda("(f (g x)").is_err());
+    assert!(parse_klambda("f)").is_err());
+}
+
+#[test]
+fn test_load_kernel() {
+    // Hand-written stand-ins shaped like the official kernel files, not the
+    // files themselves
+    let forms = load_kernel(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/kernel"))
+        .expect("Loading the kernel should succeed");
+
+    let names = forms
+        .iter()
+        .filter_map(|form| match form {
+            ShenNode::Function { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            "shen.compose",
+            "shen.f-error",
+            "shen.rule-arrow?",
+            "shen.sum",
+            "shen.safe-div",
+            "shen.delay",
+            "shen.typed",
+            "shen.apply-twice",
+            "shen.<-vector",
+            "shen.nothing",
+        ]
+    );
+    // The licence strings are dropped; other top-level forms are kept
+    assert_eq!(forms.len(), names.len() + 1);
+
+    assert!(matches!(
+        load_kernel("/nonexistent/kernel"),
+        Err(TranspilerError::IoError { .. })
+    ));
+}
+
+#[test]
+fn test_tokenize_partial_input() {
+    let (tokens, errors) = tokenize("(cn \"a\" <-vector)");
+    assert!(errors.is_empty());
+    assert_eq!(
+        tokens,
+        vec![
+            (Token::OpenParen, Span { start: 0, end: 1 }),
+            (Token::Identifier("cn"), Span { start: 1, end: 3 }),
+            (Token::Literal("a"), Span { start: 4, end: 7 }),
+            (Token::Identifier("<-vector"), Span { start: 8, end: 16 }),
+            (Token::CloseParen, Span { start: 16, end: 17 }),
+        ]
+    );
+
+    // A string still open needs more input, and is an error once there is none
+    assert!(tokenize_partial("(cn \"unfinished").is_err());
+    assert_eq!(
+        tokenize("(cn \"unfinished").1,
+        vec![("Unterminated string".to_string(), Span { start: 4, end: 15 })]
+    );
+
+    assert!(is_unfinished("(define f\n  X -> [X"));
+    assert!(is_unfinished("(cn \"(\" \"unfinished"));
+    assert!(!is_unfinished("(cn \"(\" \"[\")"));
+    assert!(!is_unfinished("(+ 1 2))"));
+}
+
+#[test]
+fn test_signed_numbers_are_numbers() {
+    let tokens = |input| tokenize(input).0.into_iter().map(|(token, _)| token).collect::<Vec<_>>();
+    assert_eq!(
+        tokens("(+ -1 2 -2.5 +3)"),
+        vec![
+            Token::OpenParen,
+            Token::Operator("+"),
+            Token::Number(-1.0),
+            Token::Number(2.0),
+            Token::Number(-2.5),
+            Token::Number(3.0),
+            Token::CloseParen,
+        ]
+    );
+    // Without a digit after the sign these stay operators and symbols
+    assert_eq!(
+        tokens("(- 1) -> -x --1"),
+        vec![
+            Token::OpenParen,
+            Token::Operator("-"),
+            Token::Number(1.0),
+            Token::CloseParen,
+            Token::Operator("->"),
+            Token::Identifier("-x"),
+            Token::Identifier("--1"),
+        ]
+    );
+}
+
+#[test]
+fn test_comments_are_skipped_and_kept() {
+    let input = "\\* two\n   lines *\\ (+ 1 \\\\ one\n 2)";
+    assert_eq!(
+        tokenize(input).0.into_iter().map(|(token, _)| token).collect::<Vec<_>>(),
+        vec![
+            Token::OpenParen,
+            Token::Operator("+"),
+            Token::Number(1.0),
+            Token::Number(2.0),
+            Token::CloseParen,
+        ]
+    );
+    assert_eq!(
+        comments(input),
+        vec![
+            ("\\* two\n   lines *\\", Span { start: 0, end: 18 }),
+            ("\\\\ one", Span { start: 24, end: 30 }),
+        ]
+    );
+    assert_eq!(
+        format!("{:?}", parse_shen_source(input).unwrap()),
+        format!("{:?}", parse_shen_source("(+ 1 2)").unwrap())
+    );
+
+    // A block comment still open waits for more input, and is an error once
+    // there is none
+    assert!(is_unfinished("(+ 1 \\* not yet"));
+    assert_eq!(
+        tokenize("(+ 1 \\* not yet").1,
+        vec![("Unterminated comment".to_string(), Span { start: 5, end: 15 })]
+    );
+}
+
+#[test]
+fn test_parsed_symbols_are_interned() {
+    let symbols = match parse_shen_source("(append Xs Xs)").unwrap() {
+        ShenNode::Application { func, args } => std::iter::once(*func)
+            .chain(args)
+            .map(|node| match node {
+                ShenNode::Symbol { name, .. } => name,
+                node => panic!("Expected a symbol, got {:?}", node),
+            })
+            .collect::<Vec<_>>(),
+        node => panic!("Expected an application, got {:?}", node),
+    };
+
+    assert_eq!(symbols[0], Symbol::intern("append"));
+    assert_eq!(symbols[1].id(), symbols[2].id());
+    assert_ne!(symbols[0], symbols[1]);
+    assert_eq!(symbols[1].as_str(), "Xs");
+    assert_eq!(format!("{:?} {}", symbols[0], symbols[0]), "\"append\" append");
+}
+
+#[test]
+fn test_symbols_order_by_name() {
+    // Interned in the opposite order to their names
+    let later = Symbol::intern("zz-symbols-order-by-name");
+    let earlier = Symbol::intern("aa-symbols-order-by-name");
+    assert!(later.id() < earlier.id());
+    assert!(earlier < later);
+
+    let mut names = vec![later, earlier];
+    names.sort();
+    assert_eq!(names, vec![earlier, later]);
+}
+
+#[test]
+fn test_definition_names_are_interned() {
+    let program = parse_shen_program("(define inc X -> (+ X 1)) (inc 1)").unwrap();
+    let ShenNode::Define { name, .. } = &program[0] else {
+        panic!("Expected a define, got {:?}", program[0]);
+    };
+    let ShenNode::Application { func, .. } = &program[1] else {
+        panic!("Expected an application, got {:?}", program[1]);
+    };
+    let ShenNode::Symbol { name: called, .. } = func.as_ref() else {
+        panic!("Expected a symbol, got {:?}", func);
+    };
+    assert_eq!(name.id(), called.id());
+}
@@ -0,0 +1,122 @@
+//! Tests for printing the AST as Shen source
+
+use std::fs;
+
+use pretty_assertions::assert_eq;
+use shen_transpiler::ast::{ShenNode, ShenValue};
+use shen_transpiler::parser::{parse_shen_program, parse_shen_source};
+use shen_transpiler::printer::{print_node, print_program, DEFAULT_WIDTH};
+
+const PROGRAM: &str = "\
+(define swap (@p A B) -> (@p B A) _ -> (simple-error \"not a pair\"))
+(define vsum <> -> 0 (@v X Y) -> (+ X (vsum Y)) where (number? X))
+(define split (@s \"a\" S) -> [a | (split S)] [X Y | Z] -> Z (@p A B C) -> C)
+(defmacro unless-macro [unless C E] -> [if C [] E])
+(defun twice (f x) (f (f x)))
+(defprolog member X [X | _] <--; X [_ | Y] <-- ! (member X Y);)
+(defcc <as> a X <as> := [X | <as>] where (number? X); <e>; \"b\" 2 <!> := <!>;)
+(package stack [push] (define push X S -> [X | S]) (define pop [_ | S] -> S))
+(let X 1 Y [2 3] (if (> X 0) (cons X Y) (@s \"a\" \"b\" \"c\")))
+(cond ((= X 1) one) ((= X 2) two) (true many))
+(trap-error (thaw (freeze (/. E F (E F)))) (/. E (error-to-string E)))
+(prolog? (member X [1 2]) (return X))
+(@v 1 2 <>)
+(@v 1 (@v 2 V))
+(@p 1.5 (@p \"s\" []))
+(+ -1 (- -2.5 3))
+[f [1 2 | T] (g) []]";
+
+/// Parse `source`, print it at `width` and parse the result again.
+fn round_trip(source: &str, width: usize) {
+    let program = parse_shen_program(source).expect("Parsing should succeed");
+    let printed = print_program(&program, width);
+    let reparsed = parse_shen_program(&printed)
+        .unwrap_or_else(|err| panic!("Printed source should parse: {}\n{}", err, printed));
+    assert_eq!(format!("{:#?}", reparsed), format!("{:#?}", program), "printed as\n{}", printed);
+}
+
+#[test]
+fn test_printed_source_parses_to_the_same_tree() {
+    for width in [1, 20, 40, DEFAULT_WIDTH, 1000] {
+        round_trip(PROGRAM, width);
+    }
+
+    // Negative numbers print as numbers that read back as numbers
+    let literal = ShenNode::Literal { value: ShenValue::Float(-2.5) };
+    let printed = print_node(&literal, DEFAULT_WIDTH);
+    assert_eq!(printed, "-2.5");
+    assert_eq!(format!("{:?}", parse_shen_source(&printed).unwrap()), format!("{:?}", literal));
+}
+
+#[test]
+fn test_printed_fixtures_parse_to_the_same_trees() {
+    let mut sources = vec![fs::read_to_string("tests/fixtures/kernel/sys.kl").unwrap()];
+    for entry in fs::read_dir("tests/fixtures/repl").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "shen") {
+            sources.push(fs::read_to_string(path).unwrap());
+        }
+    }
+    for source in &sources {
+        round_trip(source, DEFAULT_WIDTH);
+    }
+}
+
+#[test]
+fn test_short_forms_stay_on_one_line() {
+    let print = |source| print_node(&parse_shen_source(source).unwrap(), DEFAULT_WIDTH);
+    assert_eq!(print("(f  X\n  [1 2 | Y])"), "(f X [1 2 | Y])");
+    assert_eq!(print("(/. X (/. Y (+ X Y)))"), "(/. X Y (+ X Y))");
+    assert_eq!(print("(let X 1 (let Y 2 (+ X Y)))"), "(let X 1 Y 2 (+ X Y))");
+    assert_eq!(print("(@p 1 (@p 2 3))"), "(@p 1 2 3)");
+    assert_eq!(print("(@v 1 2 <>)"), "(@v 1 2 <>)");
+}
+
+#[test]
+fn test_definitions_put_each_rule_on_its_own_line() {
+    let program = parse_shen_program(
+        "(define fib 0 -> 0 1 -> 1 N -> (+ (fib (- N 1)) (fib (- N 2))) where (> N 1))
+         (defprolog app [] X X <--; [X | Y] Z [X | W] <-- (app Y Z W);)",
+    )
+    .unwrap();
+    assert_eq!(
+        print_program(&program, DEFAULT_WIDTH),
+        "\
+(define fib
+  0 -> 0
+  1 -> 1
+  N -> (+ (fib (- N 1)) (fib (- N 2))) where (> N 1))
+
+(defprolog app
+  [] X X <--;
+  [X | Y] Z [X | W] <-- (app Y Z W);)
+"
+    );
+}
+
+#[test]
+fn test_long_forms_break_to_fit_the_width() {
+    let node = parse_shen_source(
+        "(define walk [X | Y] -> (let Head (process-element X) Tail (walk Y) (cond ((empty? Tail) [Head]) (true [Head | Tail]))))",
+    )
+    .unwrap();
+    assert_eq!(
+        print_node(&node, 40),
+        "\
+(define walk
+  [X | Y] ->
+    (let Head (process-element X)
+         Tail (walk Y)
+      (cond ((empty? Tail) [Head])
+            (true [Head | Tail]))))"
+    );
+
+    let node = parse_shen_source("(some-function [first-element second-element] (g third-argument))").unwrap();
+    assert_eq!(
+        print_node(&node, 30),
+        "\
+(some-function [first-element
+                second-element]
+               (g third-argument))"
+    );
+}
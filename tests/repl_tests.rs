@@ -0,0 +1,65 @@
+//! Tests for the interactive REPL
+
+use std::fs;
+
+use pretty_assertions::assert_eq;
+use shen_transpiler::repl::{is_complete, Repl, ReplOutput};
+
+fn text(repl: &mut Repl, input: &str) -> String {
+    match repl.handle(input) {
+        Ok(ReplOutput::Text(text)) => text,
+        other => panic!("Expected output for {:?}, got {:?}", input, other),
+    }
+}
+
+#[test]
+fn test_repl_evaluates_and_prints_like_shen() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.prompt(), "(0-) ");
+    assert_eq!(text(&mut repl, "(define double X -> (* X 2))"), "double");
+    assert_eq!(text(&mut repl, "(map double [1 2 3])"), "[2 4 6]");
+    assert_eq!(text(&mut repl, "(@p \"a\" b)"), "(@p \"a\" b)");
+    assert_eq!(text(&mut repl, "1 2"), "1\n2");
+    assert_eq!(repl.prompt(), "(4-) ");
+
+    assert_eq!(repl.handle("(hd [])").unwrap_err().to_string(), "[] has no head");
+    assert!(repl.handle(":nonsense").is_err());
+    assert_eq!(repl.handle(":quit").unwrap(), ReplOutput::Quit);
+}
+
+#[test]
+fn test_repl_commands() {
+    let mut repl = Repl::new();
+    assert_eq!(text(&mut repl, ":type (@p 1 \"a\")"), "(number * string)");
+    assert_eq!(text(&mut repl, ":type (@v 1 2 <>)"), "(vector number)");
+    assert_eq!(text(&mut repl, ":rust (cons 1 [])"), "Value::cons(Value::from(1.0), Value::from(None))");
+    assert!(text(&mut repl, ":ast (f X)").starts_with("Application {"));
+    assert!(text(&mut repl, ":help").contains(":load <file>"));
+
+    // Macros entered earlier apply to later inputs and commands
+    assert_eq!(text(&mut repl, "(defmacro twice-macro [twice X] -> [+ X X])"), "twice-macro");
+    assert_eq!(text(&mut repl, "(twice 4)"), "8");
+    assert_eq!(text(&mut repl, ":rust (twice 4)"), "4.0 + 4.0");
+    assert_eq!(text(&mut repl, ":expand (twice (@p 1 2 3))"), "(+ (@p 1 2 3) (@p 1 2 3))");
+}
+
+#[test]
+fn test_repl_loads_files() {
+    let path = std::env::temp_dir().join(format!("shen-repl-{}.shen", std::process::id()));
+    fs::write(&path, "(define inc X -> (+ X 1))\n(inc 1)\n").unwrap();
+
+    let mut repl = Repl::new();
+    assert_eq!(text(&mut repl, &format!(":load {}", path.display())), "inc\n2");
+    assert_eq!(text(&mut repl, "(inc 41)"), "42");
+    assert!(repl.handle(":load /nonexistent/file.shen").is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_repl_input_completeness() {
+    assert!(is_complete("(+ 1 2)"));
+    assert!(!is_complete("(define f\n  X -> [X"));
+    assert!(is_complete("(cn \"(\" \"[\")"));
+    assert!(!is_complete("(cn \"unfinished"));
+}
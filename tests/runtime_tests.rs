@@ -0,0 +1,404 @@
+//! Tests for the runtime support library used by generated code
+
+use shen_transpiler::runtime::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn test_thaw_reevaluates_frozen_expression() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&calls);
+    let frozen = Lazy::new(move || counter.fetch_add(1, Ordering::SeqCst) + 1);
+
+    assert_eq!(frozen.thaw(), 1);
+    assert_eq!(thaw(&frozen.clone()), 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+fn checked_div(x: f64, y: f64) -> f64 {
+    if y == 0.0 {
+        raise(simple_error("division by zero"))
+    }
+    x / y
+}
+
+fn checked_div_result(x: f64, y: f64) -> ShenResult<f64> {
+    if y == 0.0 {
+        return Err(simple_error("division by zero"));
+    }
+    Ok(x / y)
+}
+
+#[test]
+fn test_trap_error_catches_errors_from_nested_calls() {
+    let caught = trap_error(
+        || checked_div(1.0, 2.0) + checked_div(1.0, 0.0),
+        |e| {
+            assert_eq!(error_to_string(&e), "division by zero");
+            -1.0
+        },
+    );
+    assert_eq!(caught, -1.0);
+
+    let inner_handled = trap_error(
+        || trap_error(|| checked_div(1.0, 0.0), |_| 0.0) + 1.0,
+        |_| -1.0,
+    );
+    assert_eq!(inner_handled, 1.0);
+}
+
+#[test]
+fn test_trap_error_result_propagates_with_question_mark() {
+    let outer = || -> ShenResult<f64> { Ok(checked_div_result(1.0, 0.0)? + 1.0) };
+    let caught = trap_error_result(outer, |e| Ok(error_to_string(&e).len() as f64));
+    assert_eq!(caught, Ok(16.0));
+
+    let rethrown = trap_error_result(outer, Err);
+    assert_eq!(rethrown, Err(simple_error("division by zero")));
+}
+
+#[test]
+fn test_globals_set_value_and_bound() {
+    let env = Globals::new();
+    assert!(!env.is_bound("*counter*"));
+    assert_eq!(
+        env.value("*counter*"),
+        Err(simple_error("variable *counter* has no value"))
+    );
+
+    assert_eq!(env.set("*counter*", 0), Value::Integer(0));
+    assert!(env.is_bound("*counter*"));
+    assert_eq!(env.value("*counter*"), Ok(Value::Integer(0)));
+}
+
+#[test]
+fn test_default_globals_are_seeded() {
+    assert!(globals().is_bound("*stoutput*"));
+    assert!(globals().is_bound("*home-directory*"));
+    assert_eq!(globals().get("*language*"), Some(Value::from("Rust")));
+}
+
+#[test]
+fn test_thread_globals_are_isolated_per_thread() {
+    with_thread_globals(|env| env.set("*thread-test*", "main"));
+
+    let seen_by_other_thread =
+        std::thread::spawn(|| with_thread_globals(|env| env.is_bound("*thread-test*")))
+            .join()
+            .unwrap();
+
+    assert!(!seen_by_other_thread);
+    assert_eq!(
+        with_thread_globals(|env| env.get("*thread-test*")),
+        Some(Value::from("main"))
+    );
+}
+
+#[test]
+fn test_runtime_tuples() {
+    let pair = Value::tuple(1, (2.5, "b"));
+    assert!(is_tuple(&pair));
+    assert_eq!(fst(&pair), Ok(Value::Integer(1)));
+    assert_eq!(snd(&pair), Ok(Value::tuple(2.5, "b")));
+    assert_eq!(pair.to_string(), "(@p 1 (@p 2.5 \"b\"))");
+
+    assert!(!is_tuple(&Value::Nil));
+    assert_eq!(fst(&Value::Nil), Err(simple_error("[] is not a tuple")));
+}
+
+#[test]
+fn test_list_and_number_primitives_over_values() {
+    let list = Value::list(vec![Value::Integer(1), Value::symbol("a")]);
+    assert_eq!(hd(&list), Ok(Value::Integer(1)));
+    assert_eq!(tl(&list), Ok(Value::list(vec![Value::symbol("a")])));
+    assert!(is_cons(&list) && !is_empty(&list) && is_empty(&Value::Nil));
+    assert_eq!(is_element(&Value::symbol("a"), &list), Ok(true));
+    assert_eq!(hd(&Value::Nil), Err(simple_error("[] has no head")));
+
+    assert_eq!(add(&Value::Integer(1), &Value::Integer(2)), Ok(Value::Integer(3)));
+    assert_eq!(divide(&Value::Integer(1), &Value::Integer(2)), Ok(Value::Float(0.5)));
+    assert_eq!(less(&Value::Integer(1), &Value::Float(1.5)), Ok(Value::Boolean(true)));
+    assert_eq!(
+        divide(&Value::Integer(1), &Value::Integer(0)),
+        Err(simple_error("division by zero"))
+    );
+    assert_eq!(
+        subtract(&Value::symbol("a"), &Value::Integer(1)),
+        Err(simple_error("a is not a number"))
+    );
+
+    let increment = Value::Function(Function::new(|x| add(&x, &Value::Integer(1))));
+    assert_eq!(apply(&increment, Value::Integer(1)), Ok(Value::Integer(2)));
+    assert_eq!(apply(&Value::Nil, Value::Integer(1)), Err(simple_error("[] is not a function")));
+}
+
+#[test]
+fn test_vectors_are_one_based_with_fail_sentinel() {
+    let v = vector(2).unwrap();
+    assert_eq!(limit(&v), Ok(2));
+    assert_eq!(address_get(&v, 0), Ok(Value::Integer(2)));
+    assert_eq!(address_get(&v, 1), Ok(Value::fail()));
+    assert_eq!(
+        vector_get(&v, 1),
+        Err(simple_error("vector element 1 not found"))
+    );
+
+    vector_set(&v, 1, "a").unwrap();
+    vector_set(&v, 2, 2.5).unwrap();
+    assert_eq!(vector_get(&v, 1), Ok(Value::from("a")));
+    assert_eq!(v.to_string(), "<\"a\" 2.5>");
+    assert!(is_vector(&v));
+}
+
+#[test]
+fn test_vector_bounds_errors_are_shen_exceptions() {
+    let v = Value::vector(vec![Value::from(1), Value::from(2)]);
+    assert!(vector_get(&v, 0).is_err());
+    assert!(vector_set(&v, 3, 0).is_err());
+
+    let message = trap_error(
+        || vector_get(&v, 3).or_raise().to_string(),
+        |e| error_to_string(&e),
+    );
+    assert_eq!(message, "index 3 is out of bounds for a vector of limit 2");
+}
+
+#[test]
+fn test_absvectors_and_vector_cons() {
+    let raw = absvector(2).unwrap();
+    address_set(&raw, 0, "x").unwrap();
+    assert!(is_absvector(&raw));
+    assert!(!is_vector(&raw));
+    assert!(address_get(&raw, 2).is_err());
+
+    let v = vector_cons(0, &Value::vector(vec![Value::from(1)])).unwrap();
+    assert_eq!(v.to_string(), "<0 1>");
+    assert_eq!(
+        v.as_vector_cons(),
+        Some((Value::Integer(0), Value::vector(vec![Value::from(1)])))
+    );
+    assert!(Value::vector(vec![]).is_empty_vector());
+}
+
+#[test]
+fn test_string_primitives_handle_multibyte_characters() {
+    let word = Value::from("héllo");
+    assert_eq!(pos(&word, 1), Ok(Value::from("é")));
+    assert_eq!(tlstr(&word), Ok(Value::from("éllo")));
+    assert_eq!(string_to_n(&Value::from("é")), Ok(Value::Integer(0xE9)));
+    assert_eq!(n_to_string(0x1F600), Ok(Value::from("😀")));
+    assert_eq!(cn(&Value::from("日本"), &Value::from("語")), Ok(Value::from("日本語")));
+    assert_eq!(str(&Value::Integer(42)), Ok(Value::from("42")));
+    assert!(pos(&word, 5).is_err());
+    assert!(n_to_string(0xD800).is_err());
+}
+
+#[test]
+fn test_string_patterns_respect_grapheme_clusters() {
+    // "e" followed by a combining acute accent, and a two-code-point flag
+    let decomposed = Value::from("e\u{301}t\u{1F1EB}\u{1F1F7}");
+    assert_eq!(
+        decomposed.as_string_cons(),
+        Some((Value::from("e\u{301}"), Value::from("t\u{1F1EB}\u{1F1F7}")))
+    );
+    assert_eq!(pos(&decomposed, 2), Ok(Value::from("\u{1F1EB}\u{1F1F7}")));
+    assert_eq!(decomposed.strip_string_prefix("e"), None);
+    assert_eq!(
+        decomposed.strip_string_prefix("e\u{301}"),
+        Some(Value::from("t\u{1F1EB}\u{1F1F7}"))
+    );
+    assert_eq!(Value::from("").as_string_cons(), None);
+}
+
+fn member_clauses() -> Vec<Clause> {
+    // member X [X | _] <--;
+    // member X [_ | Y] <-- (member X Y);
+    vec![
+        Clause::new(2, vec![Term::var(0), Term::cons(Term::var(0), Term::var(1))], vec![]),
+        Clause::new(
+            3,
+            vec![Term::var(0), Term::cons(Term::var(1), Term::var(2))],
+            vec![Goal::Call("member".to_string(), vec![Term::var(0), Term::var(2)])],
+        ),
+    ]
+}
+
+#[test]
+fn test_prolog_backtracks_through_all_solutions() {
+    let mut database = PrologDatabase::new();
+    database.define("member", member_clauses());
+    let list = Value::list([Value::from(1), Value::from(2), Value::from(3)]);
+
+    let mut found = Vec::new();
+    database
+        .solve(
+            1,
+            &[Goal::Call("member".to_string(), vec![Term::var(0), Term::value(list.clone())])],
+            |env| {
+                found.push(env.value(0));
+                Ok(true)
+            },
+        )
+        .unwrap();
+    assert_eq!(found, vec![Value::from(1), Value::from(2), Value::from(3)]);
+
+    let query = |x: i64| {
+        database.query(0, &[Goal::Call("member".to_string(), vec![Term::value(x), Term::value(list.clone())])])
+    };
+    assert_eq!(query(2), Ok(Value::Boolean(true)));
+    assert_eq!(query(4), Ok(Value::Boolean(false)));
+}
+
+#[test]
+fn test_prolog_cut_is_when_and_call() {
+    let mut database = PrologDatabase::new();
+    database.define("member", member_clauses());
+    // first X L <-- (member X L) !;
+    database.define(
+        "first",
+        vec![Clause::new(
+            2,
+            vec![Term::var(0), Term::var(1)],
+            vec![Goal::Call("member".to_string(), vec![Term::var(0), Term::var(1)]), Goal::Cut],
+        )],
+    );
+    let list = Value::list([Value::from(5), Value::from(6)]);
+
+    let mut found = Vec::new();
+    database
+        .solve(
+            1,
+            &[Goal::Call("first".to_string(), vec![Term::var(0), Term::value(list.clone())])],
+            |env| {
+                found.push(env.value(0));
+                Ok(true)
+            },
+        )
+        .unwrap();
+    assert_eq!(found, vec![Value::from(5)]);
+
+    // (prolog? (call [member X L]) (when (> X 5)) (is Y (+ X 1)) (return Y))
+    let greater_than_five = Expr::new(|env| match env.value(0) {
+        Value::Integer(x) => Ok(Value::from(x > 5)),
+        _ => Ok(Value::from(false)),
+    });
+    let successor = Expr::new(|env| match env.value(0) {
+        Value::Integer(x) => Ok(Value::from(x + 1)),
+        other => Err(simple_error(format!("{} is not a number", other))),
+    });
+    let goal = Term::cons(
+        Term::value(Value::symbol("member")),
+        Term::cons(Term::var(0), Term::cons(Term::value(list), Term::value(Value::Nil))),
+    );
+    let result = database.query(
+        2,
+        &[
+            Goal::CallTerm(goal),
+            Goal::When(greater_than_five),
+            Goal::Is(Term::var(1), successor.clone()),
+            Goal::Return(Term::var(1)),
+        ],
+    );
+    assert_eq!(result, Ok(Value::from(7)));
+
+    let bound_twice = database.query(
+        1,
+        &[Goal::Bind(Term::var(0), successor.clone()), Goal::Bind(Term::var(0), successor)],
+    );
+    assert!(bound_twice.is_err());
+}
+
+#[test]
+fn test_prolog_expressions_use_runtime_arithmetic() {
+    // (prolog? (is Y 2) (is X (+ Y 1)) (return X))
+    let found = prolog_query(
+        2,
+        vec![
+            Goal::Is(Term::var(1), Expr::new(|_| Ok(Value::Float(2.0)))),
+            Goal::Is(
+                Term::var(0),
+                Expr::new(|env| {
+                    let y = env.value(1);
+                    add(&y, &Value::Float(1.0))
+                }),
+            ),
+            Goal::Return(Term::var(0)),
+        ],
+    );
+    assert_eq!(found, Ok(Value::Float(3.0)));
+}
+
+#[test]
+fn test_prolog_query_uses_the_shared_database() {
+    define_prolog("runtime-test-member", {
+        let mut clauses = member_clauses();
+        clauses[1] = Clause::new(
+            3,
+            vec![Term::var(0), Term::cons(Term::var(1), Term::var(2))],
+            vec![Goal::Call("runtime-test-member".to_string(), vec![Term::var(0), Term::var(2)])],
+        );
+        clauses
+    });
+
+    let result = prolog_query(
+        1,
+        vec![
+            Goal::Call(
+                "runtime-test-member".to_string(),
+                vec![Term::var(0), Term::value(Value::list([Value::from("a"), Value::from("b")]))],
+            ),
+            Goal::Return(Term::var(0)),
+        ],
+    );
+    assert_eq!(result, Ok(Value::from("a")));
+    assert!(prolog_query(0, vec![Goal::Call("no-such-predicate".to_string(), vec![])]).is_err());
+}
+
+// What (defcc <digits> <digit> <digits> := [<digit> | <digits>]; <digit> := [<digit>];)
+// and (defcc <digit> X := X where (integer? X);) compile to
+#[allow(non_snake_case)]
+fn _digits_(input: &Value) -> ShenResult<Option<(Value, Value)>> {
+    if let Some((input_1, _digit_)) = _digit_(input)? {
+        if let Some((input_2, _digits_)) = _digits_(&input_1)? {
+            return Ok(Some((input_2, Value::cons(_digit_, _digits_))));
+        }
+    }
+    if let Some((input_1, _digit_)) = _digit_(input)? {
+        return Ok(Some((input_1, Value::from(vec![_digit_]))));
+    }
+    Ok(None)
+}
+
+#[allow(non_snake_case)]
+fn _digit_(input: &Value) -> ShenResult<Option<(Value, Value)>> {
+    if let Some((X, input_1)) = input.as_cons() {
+        if matches!(X, Value::Integer(_)) {
+            return Ok(Some((input_1, X)));
+        }
+    }
+    Ok(None)
+}
+
+#[test]
+fn test_yacc_compile_parses_whole_input() {
+    let digits = Value::from(vec![1, 2, 3]);
+    assert_eq!(compile(_digits_, &digits), Ok(digits.clone()));
+    assert_eq!(
+        compile(_digits_, &Value::from(vec![Value::from(1), Value::symbol("x")])),
+        Err(simple_error("parse error here: [x]"))
+    );
+    assert_eq!(compile(_digits_, &Value::Nil), Err(simple_error("parse error")));
+
+    assert_eq!(yacc_terminal(&digits, &Value::from(1)), Some(Value::from(vec![2, 3])));
+    assert_eq!(yacc_terminal(&digits, &Value::from(2)), None);
+    assert_eq!(yacc_consumed(&digits, &Value::from(vec![3])), Value::from(vec![1, 2]));
+}
+
+#[test]
+fn test_make_string_printing() {
+    let (a, s) = (Value::symbol("shen.a"), Value::symbol("shen.s"));
+    assert_eq!(app(&Value::from("hi"), &Value::from("!"), &a), Ok(Value::from("hi!")));
+    assert_eq!(app(&Value::from("hi"), &Value::from(""), &s), Ok(Value::from("\"hi\"")));
+    assert_eq!(app(&Value::from(vec![1, 2]), &Value::from(""), &a), Ok(Value::from("[1 2]")));
+    assert!(app(&Value::from(1), &Value::symbol("x"), &a).is_err());
+}